@@ -0,0 +1,36 @@
+//! Compares owned parsing with the borrowed `IdentityView`.
+//!
+//! Run with `cargo bench --bench identity_view`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use getmyid::{Identity, IdentityView};
+
+const ITERATIONS: u32 = 200_000;
+
+const RESPONSE: &str = r#"{"status":"ok","identity":"TRUSTEE_AGENT","idm_url":"https://auth.example.com/oauth2/trustee","config_url":"https://config.example.com/api/trustee","token":"tok_trustee_xxx","runner":{"instance_id":42,"timestamp":1738512000,"identity":"TRUSTEE_AGENT","hostname":"worker-03","process":"trustee","pid":26567,"uid":1000,"gid":1000,"region":"eu-west-1","zone":"b"}}"#;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_iter = start.elapsed() / ITERATIONS;
+    println!("{name:<24} {per_iter:?}/iter");
+}
+
+fn main() {
+    bench("Identity (owned)", || {
+        let identity: Identity = serde_json::from_str(black_box(RESPONSE)).unwrap();
+        black_box(identity.identity.len());
+    });
+    bench("IdentityView", || {
+        let view = IdentityView::parse(black_box(RESPONSE)).unwrap();
+        black_box(view.identity.len());
+    });
+    bench("IdentityView::to_owned", || {
+        let identity = IdentityView::parse(black_box(RESPONSE)).unwrap().to_owned();
+        black_box(identity.identity.len());
+    });
+}
@@ -0,0 +1,50 @@
+//! An identity-gated worker: waits for the whoami daemon, fetches its
+//! configuration from the identity's `config_url` and processes jobs with
+//! the current token until Ctrl-C.
+//!
+//! ```sh
+//! cargo run --example worker --features tokio,http
+//! ```
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use getmyid::scaffold::IdentityRuntime;
+use getmyid::RunnerRequest;
+
+#[tokio::main]
+async fn main() -> Result<(), getmyid::GetMyIdError> {
+    let runtime = IdentityRuntime::builder()
+        .runner(RunnerRequest::new().with_region("eu-west-1"))
+        .start()
+        .await?;
+    let config: HashMap<String, serde_json::Value> = runtime.fetch_config().await?;
+    println!(
+        "running as {} with {} settings",
+        runtime.identity().identity,
+        config.len()
+    );
+
+    let identity = &runtime;
+    let signal = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let jobs = runtime
+        .run(signal, |shutdown| async move {
+            let mut jobs = 0u64;
+            while !shutdown.is_requested() {
+                process_job(&identity.identity().token).await;
+                jobs += 1;
+            }
+            jobs
+        })
+        .await;
+    println!("shut down after {jobs} jobs");
+    Ok(())
+}
+
+/// Stand-in for real work authenticated with `token`.
+async fn process_job(token: &str) {
+    let _ = token;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+}
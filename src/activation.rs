@@ -0,0 +1,246 @@
+//! Locating the daemon socket from what a service manager hands down.
+//!
+//! Sandboxed services (systemd `PrivateTmp=`, `InaccessiblePaths=/var/run`,
+//! minimal containers) may not be able to reach the daemon socket by its
+//! usual path. A launcher can instead pass a file descriptor referring to the
+//! socket file, or a credential naming the socket to use:
+//!
+//! - **Inherited file descriptor**: an fd listed through the `LISTEN_FDS` /
+//!   `LISTEN_FDNAMES` protocol under the name `whoami`. The fd must refer to
+//!   the socket file itself (for example opened with `O_PATH`); it is kept
+//!   open and every connection is made through `/proc/self/fd/<fd>`, so it
+//!   can be reused for any number of requests.
+//! - **Credential**: a file named `whoami.socket` in `$CREDENTIALS_DIRECTORY`
+//!   (systemd `LoadCredential=` / `SetCredential=`) containing the socket
+//!   path, e.g. an abstract socket name such as `@whoami`.
+//!
+//! Hosts that place the socket in different locations can instead probe a
+//! list of candidate paths with [`discover_socket_path`]. A socket already
+//! connected to the daemon can be used with
+//! [`Client::from_fd`](crate::Client::from_fd) for a single request.
+
+use std::env;
+use std::fs;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::client::DEFAULT_SOCKET_PATH;
+use crate::transport::{SocketType, UnixTransport};
+
+/// Name under which the daemon socket is looked up in `LISTEN_FDNAMES`.
+pub const INHERITED_SOCKET_NAME: &str = "whoami";
+
+/// First file descriptor passed by the `LISTEN_FDS` protocol.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Credential file holding the socket path.
+const SOCKET_CREDENTIAL: &str = "whoami.socket";
+
+/// How long [`discover_socket_path`] waits for each candidate to accept.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Find the daemon socket handed down by the service manager.
+///
+/// An inherited file descriptor named [`INHERITED_SOCKET_NAME`] (or the only
+/// inherited descriptor, when none are named) takes precedence over a
+/// `whoami.socket` credential. Returns `None` when neither is present.
+pub fn inherited_socket_path() -> Option<PathBuf> {
+    let fd = find_listen_fd(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        env::var("LISTEN_FDNAMES").ok().as_deref(),
+        std::process::id(),
+    );
+    if let Some(fd) = fd {
+        let path = fd_path(fd);
+        if path.exists() {
+            return Some(path);
+        }
+    }
+
+    let dir = env::var_os("CREDENTIALS_DIRECTORY")?;
+    read_socket_credential(Path::new(&dir))
+}
+
+/// Socket paths probed when discovery is enabled on the builders, in order:
+/// `/var/run/whoami.sock`, `/run/whoami.sock` and, if set,
+/// `$XDG_RUNTIME_DIR/whoami.sock`.
+pub fn default_socket_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![
+        PathBuf::from(DEFAULT_SOCKET_PATH),
+        PathBuf::from("/run/whoami.sock"),
+    ];
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        candidates.push(PathBuf::from(dir).join("whoami.sock"));
+    }
+    candidates
+}
+
+/// Return the first of `candidates` accepting a stream connection.
+///
+/// Stale socket files left behind by a stopped daemon are skipped. Each
+/// probe opens and immediately closes a connection without sending a
+/// request. Returns `None` when no candidate is live.
+///
+/// # Example
+///
+/// ```no_run
+/// let path = getmyid::discover_socket_path(getmyid::default_socket_candidates());
+/// println!("daemon socket: {path:?}");
+/// ```
+pub fn discover_socket_path<I>(candidates: I) -> Option<PathBuf>
+where
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    probe_candidates(candidates, SocketType::Stream, Some(PROBE_TIMEOUT))
+}
+
+/// Return the first of `candidates` accepting a connection of `socket_type`
+/// within `timeout` (bounded by [`PROBE_TIMEOUT`]).
+pub(crate) fn probe_candidates<I>(
+    candidates: I,
+    socket_type: SocketType,
+    timeout: Option<Duration>,
+) -> Option<PathBuf>
+where
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    let timeout = timeout.map_or(PROBE_TIMEOUT, |timeout| timeout.min(PROBE_TIMEOUT));
+    candidates.into_iter().find_map(|path| {
+        UnixTransport::new(path.as_ref())
+            .with_socket_type(socket_type)
+            .with_connect_timeout(timeout)
+            .connect()
+            .ok()
+            .map(|_| path.as_ref().to_path_buf())
+    })
+}
+
+/// Path through which a connection can be made to the socket file `fd`
+/// refers to.
+pub(crate) fn fd_path(fd: RawFd) -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{fd}"))
+}
+
+/// Select the inherited descriptor for the daemon socket.
+///
+/// Descriptors are ignored unless `LISTEN_PID` names this process, so
+/// variables leaked to child processes are not misinterpreted.
+fn find_listen_fd(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    names: Option<&str>,
+    pid: u32,
+) -> Option<RawFd> {
+    if listen_pid?.parse::<u32>().ok()? != pid {
+        return None;
+    }
+    let count: RawFd = listen_fds?.parse().ok()?;
+    if count <= 0 {
+        return None;
+    }
+
+    match names {
+        Some(names) if !names.is_empty() => names
+            .split(':')
+            .take(count as usize)
+            .position(|name| name == INHERITED_SOCKET_NAME)
+            .map(|index| LISTEN_FDS_START + index as RawFd),
+        _ if count == 1 => Some(LISTEN_FDS_START),
+        _ => None,
+    }
+}
+
+fn read_socket_credential(dir: &Path) -> Option<PathBuf> {
+    let contents = fs::read_to_string(dir.join(SOCKET_CREDENTIAL)).ok()?;
+    let path = contents.trim();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::io::{Read, Write};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixListener;
+
+    use crate::transport::{Transport, UnixTransport};
+
+    #[test]
+    fn test_find_listen_fd() {
+        let pid = 4242;
+        assert_eq!(find_listen_fd(Some("4242"), Some("1"), None, pid), Some(3));
+        assert_eq!(
+            find_listen_fd(Some("4242"), Some("3"), Some("log:whoami:metrics"), pid),
+            Some(4)
+        );
+        assert_eq!(
+            find_listen_fd(Some("4242"), Some("2"), Some("log:metrics"), pid),
+            None
+        );
+        assert_eq!(find_listen_fd(Some("4242"), Some("2"), None, pid), None);
+        assert_eq!(find_listen_fd(Some("1"), Some("1"), None, pid), None);
+        assert_eq!(find_listen_fd(None, Some("1"), None, pid), None);
+    }
+
+    #[test]
+    fn test_read_socket_credential() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(read_socket_credential(dir.path()), None);
+
+        fs::write(dir.path().join(SOCKET_CREDENTIAL), "@whoami\n").unwrap();
+        assert_eq!(
+            read_socket_credential(dir.path()),
+            Some(PathBuf::from("@whoami"))
+        );
+    }
+
+    #[test]
+    fn test_discover_socket_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = dir.path().join("stale.sock");
+        drop(UnixListener::bind(&stale).unwrap());
+        let live = dir.path().join("live.sock");
+        let _listener = UnixListener::bind(&live).unwrap();
+
+        let candidates = [dir.path().join("missing.sock"), stale, live.clone()];
+        assert_eq!(discover_socket_path(&candidates), Some(live));
+        assert_eq!(discover_socket_path(&candidates[..2]), None);
+    }
+
+    #[test]
+    fn test_default_socket_candidates() {
+        let candidates = default_socket_candidates();
+        assert_eq!(candidates[0], Path::new(DEFAULT_SOCKET_PATH));
+        assert_eq!(candidates[1], Path::new("/run/whoami.sock"));
+    }
+
+    #[test]
+    fn test_connect_through_inherited_fd() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("whoami.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        assert!(fd >= 0);
+
+        let transport = UnixTransport::new(fd_path(fd));
+        for _ in 0..2 {
+            let mut conn = transport.dial().unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            server.write_all(b"pong").unwrap();
+            drop(server);
+
+            let mut response = String::new();
+            conn.read_to_string(&mut response).unwrap();
+            assert_eq!(response, "pong");
+        }
+
+        unsafe { libc::close(fd) };
+    }
+}
@@ -0,0 +1,142 @@
+//! `actix-web` middleware providing the identity to handlers (requires
+//! `actix` feature).
+
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::HttpMessage;
+
+use crate::managed::ManagedIdentity;
+
+/// Middleware inserting the process identity into the request extensions as
+/// an `Arc<Identity>`.
+///
+/// The identity comes from a [`ManagedIdentity`], resolved once at startup
+/// and refreshed in the background, so requests never wait for the daemon.
+/// Handlers take it as a `web::ReqData<Arc<Identity>>` parameter.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use actix_web::{web, App, HttpServer};
+/// use getmyid::{AsyncClient, Identity, IdentityMiddleware, ManagedIdentity};
+///
+/// async fn handler(identity: web::ReqData<Arc<Identity>>) -> String {
+///     format!("serving as {}", identity.identity)
+/// }
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let identity = ManagedIdentity::start(AsyncClient::new(), Duration::from_secs(60)).await?;
+/// let middleware = IdentityMiddleware::from(identity);
+/// HttpServer::new(move || {
+///     App::new()
+///         .wrap(middleware.clone())
+///         .route("/", web::get().to(handler))
+/// })
+/// .bind(("127.0.0.1", 8080))?
+/// .run()
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct IdentityMiddleware {
+    identity: Arc<ManagedIdentity>,
+}
+
+impl IdentityMiddleware {
+    /// Create a middleware providing the identity held by `identity`.
+    pub fn new(identity: Arc<ManagedIdentity>) -> Self {
+        Self { identity }
+    }
+
+    /// Get the identity provided to handlers.
+    pub fn identity(&self) -> &Arc<ManagedIdentity> {
+        &self.identity
+    }
+}
+
+impl From<ManagedIdentity> for IdentityMiddleware {
+    fn from(identity: ManagedIdentity) -> Self {
+        Self::new(Arc::new(identity))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IdentityMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = IdentityMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdentityMiddlewareService {
+            service,
+            identity: Arc::clone(&self.identity),
+        }))
+    }
+}
+
+/// Service created by [`IdentityMiddleware`] around the wrapped service.
+#[derive(Debug)]
+pub struct IdentityMiddlewareService<S> {
+    service: S,
+    identity: Arc<ManagedIdentity>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdentityMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        req.extensions_mut().insert(self.identity.current());
+        self.service.call(req)
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::async_client::AsyncClient;
+    use crate::testing::FakeDaemon;
+    use crate::types::Identity;
+    use actix_web::{test, web, App};
+    use std::time::Duration;
+
+    async fn handler(identity: web::ReqData<Arc<Identity>>) -> String {
+        identity.identity.clone()
+    }
+
+    #[tokio::test]
+    async fn test_identity_middleware() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let managed = ManagedIdentity::start(client, Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(IdentityMiddleware::from(managed))
+                .route("/", web::get().to(handler)),
+        )
+        .await;
+        let body = test::call_and_read_body(&app, test::TestRequest::get().to_request()).await;
+        assert_eq!(body, FakeDaemon::sample_identity().identity);
+        assert_eq!(daemon.requests().len(), 1);
+    }
+}
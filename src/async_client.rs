@@ -1,14 +1,47 @@
 //! Asynchronous client for the whoami daemon (requires `tokio` feature).
 
+use std::future::{poll_fn, Future};
+use std::os::unix::io::RawFd;
+use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
 
+use serde::Serialize;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixStream;
 
-use crate::client::{parse_response, DEFAULT_SOCKET_PATH, DEFAULT_TIMEOUT};
+use crate::activation::{
+    default_socket_candidates, fd_path, inherited_socket_path, probe_candidates,
+};
+use crate::async_transport::{
+    AsyncConnectedTransport, AsyncConnection, AsyncTransport, AsyncUnixTransport,
+};
+use crate::client::{
+    read_limit, response_text, DEFAULT_MAX_RESPONSE_BYTES, DEFAULT_SOCKET_PATH, DEFAULT_TIMEOUT,
+};
+use crate::compatibility::DaemonInfo;
+use crate::config::Settings;
 use crate::error::{GetMyIdError, Result};
-use crate::types::{Identity, RunnerRequest};
+use crate::limits::JsonLimits;
+use crate::logging::{log_debug, log_warn};
+use crate::metrics::{Metrics, MetricsSink, RequestTimer};
+#[cfg(feature = "otel")]
+use crate::otel::TraceContext;
+use crate::pressure::{Pressure, PressureTracker};
+use crate::procfs;
+#[cfg(feature = "admin")]
+use crate::protocol::check_target_pid;
+use crate::protocol::{
+    check_alive, check_known_fields, check_nonce, parse_response, Request, STATUS_INFO, STATUS_PONG,
+};
+use crate::retry::{CacheLimits, IdentityCache, NegativeCache, RetryPolicy};
+use crate::singleflight::AsyncSingleFlight;
+use crate::soft::SoftErrorCodes;
+use crate::transport::{absolute_socket_path, abstract_path, SocketType, SymlinkPolicy};
+use crate::types::{HostnamePolicy, Identity, KeyCase, RawIdentity, RunnerRequest};
+use crate::watch::IdentityWatch;
 
 /// Asynchronous client for communicating with the whoami daemon.
 ///
@@ -30,7 +63,31 @@ use crate::types::{Identity, RunnerRequest};
 #[derive(Debug, Clone)]
 pub struct AsyncClient {
     socket_path: PathBuf,
-    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    extras_key_case: KeyCase,
+    hostname_policy: HostnamePolicy,
+    json_limits: JsonLimits,
+    strict_parsing: bool,
+    soft_errors: SoftErrorCodes,
+    max_response_bytes: usize,
+    default_runner: Option<RunnerRequest>,
+    retry: RetryPolicy,
+    cache: Option<Arc<IdentityCache>>,
+    negative_cache: Option<Arc<NegativeCache>>,
+    pressure: Arc<PressureTracker>,
+    flights: Arc<AsyncSingleFlight>,
+    transport: Arc<dyn AsyncTransport>,
+    failover: Vec<(PathBuf, Arc<dyn AsyncTransport>)>,
+    hedge_after: Option<Duration>,
+    #[cfg(feature = "signing")]
+    daemon_public_key: Option<Arc<[u8]>>,
+    require_nonce: bool,
+    request_ids: bool,
+    metrics: Option<Metrics>,
+    #[cfg(feature = "otel")]
+    trace_context: Option<TraceContext>,
 }
 
 impl Default for AsyncClient {
@@ -42,10 +99,7 @@ impl Default for AsyncClient {
 impl AsyncClient {
     /// Create a new async client with default settings.
     pub fn new() -> Self {
-        Self {
-            socket_path: PathBuf::from(DEFAULT_SOCKET_PATH),
-            timeout: Some(DEFAULT_TIMEOUT),
-        }
+        AsyncClientBuilder::new().build()
     }
 
     /// Create an async client builder for custom configuration.
@@ -53,6 +107,61 @@ impl AsyncClient {
         AsyncClientBuilder::new()
     }
 
+    /// Create a client using `stream`, a socket already connected to the
+    /// daemon, e.g. by a supervisor that handed it down to this process.
+    ///
+    /// The daemon identifies the process that connected, and answers a
+    /// single request per connection: the client can resolve the identity
+    /// once, and later requests fail with [`GetMyIdError::ConnectionFailed`].
+    /// Use [`AsyncClientBuilder::transport`] with an
+    /// [`AsyncConnectedTransport`] to configure the client further.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::os::unix::io::FromRawFd;
+    /// use std::os::unix::net::UnixStream;
+    /// use getmyid::AsyncClient;
+    ///
+    /// # async fn example() -> getmyid::Result<()> {
+    /// // SAFETY: the supervisor passed the connected socket as fd 3
+    /// let stream = unsafe { UnixStream::from_raw_fd(3) };
+    /// let identity = AsyncClient::from_std(stream).get_identity().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_std(stream: UnixStream) -> Self {
+        AsyncClientBuilder::new()
+            .transport(AsyncConnectedTransport::new(stream))
+            .build()
+    }
+
+    /// Create a client configured from the `GETMYID_*` environment variables.
+    ///
+    /// See the [`config`](crate::config) module for the variables read;
+    /// settings without a variable keep their defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidEnvVar`] if a variable cannot be parsed.
+    pub fn from_env() -> Result<Self> {
+        Ok(AsyncClientBuilder::from_env()?.build())
+    }
+
+    /// Create a client configured from the TOML file at `path`, overridden
+    /// by the `GETMYID_*` environment variables (requires `config-file`
+    /// feature).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidConfig`] if the file cannot be read or
+    /// parsed, or [`GetMyIdError::InvalidEnvVar`] if a variable cannot be
+    /// parsed.
+    #[cfg(feature = "config-file")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(AsyncClientBuilder::from_file(path)?.build())
+    }
+
     /// Get the identity of the current process asynchronously.
     ///
     /// # Errors
@@ -88,63 +197,574 @@ impl AsyncClient {
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
-        // Check socket exists
-        if !self.socket_path.exists() {
-            return Err(GetMyIdError::SocketNotFound(self.socket_path.clone()));
-        }
-
-        let get_identity_inner = async {
-            // Connect to the socket
-            let mut stream = UnixStream::connect(&self.socket_path).await.map_err(|e| {
-                GetMyIdError::ConnectionFailed {
-                    path: self.socket_path.clone(),
-                    source: e,
+    pub async fn get_identity_with_runner(
+        &self,
+        runner: Option<RunnerRequest>,
+    ) -> Result<Identity> {
+        let runner = self.prepare_runner(runner);
+        let key = IdentityCache::key(runner.as_ref());
+        if let Some(identity) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            return Ok(identity);
+        }
+        // Concurrent lookups with the same runner context share one request
+        let lookup = || async {
+            self.lookup(runner.as_ref())
+                .await
+                .map(RawIdentity::into_identity)
+        };
+        let identity = match self.flights.run(key.clone(), lookup).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                let cached = self.cache.as_deref().map(|cache| (cache, key.as_str()));
+                return self.soft_errors.recover(cached, e);
+            }
+        };
+        if let Some(cache) = &self.cache {
+            cache.insert(key, identity.clone());
+        }
+        Ok(identity)
+    }
+
+    /// Get the identity with an application's own runner context type.
+    ///
+    /// `context` is converted with [`RunnerRequest::from_context`] and
+    /// sent as by [`get_identity_with_runner`](AsyncClient::get_identity_with_runner).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidJson`] if `context` does not serialize
+    /// to a runner request, and the errors of
+    /// [`get_identity`](AsyncClient::get_identity).
+    pub async fn get_identity_with_context<T: Serialize + ?Sized>(
+        &self,
+        context: &T,
+    ) -> Result<Identity> {
+        let runner = RunnerRequest::from_context(context)?;
+        self.get_identity_with_runner(Some(runner)).await
+    }
+
+    /// Get the identity together with the daemon response it was parsed
+    /// from.
+    ///
+    /// Retries, failover daemons and the negative cache apply as for
+    /// [`get_identity`](AsyncClient::get_identity), but the identity cache is
+    /// bypassed since it keeps no responses; the fresh identity still
+    /// refreshes it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::AsyncClient;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), getmyid::GetMyIdError> {
+    /// let client = AsyncClient::new();
+    /// let raw = client.get_identity_raw().await?;
+    /// println!("Identity: {}", raw.identity.identity);
+    /// audit_log(&raw.raw);
+    /// # Ok(())
+    /// # }
+    /// # fn audit_log(_: &str) {}
+    /// ```
+    pub async fn get_identity_raw(&self) -> Result<RawIdentity> {
+        self.get_identity_raw_with_runner(None).await
+    }
+
+    /// Like [`get_identity_raw`](AsyncClient::get_identity_raw), sending `runner`
+    /// as context.
+    pub async fn get_identity_raw_with_runner(
+        &self,
+        runner: Option<RunnerRequest>,
+    ) -> Result<RawIdentity> {
+        let runner = self.prepare_runner(runner);
+        let raw = self.lookup(runner.as_ref()).await?;
+        if let Some(cache) = &self.cache {
+            cache.insert(IdentityCache::key(runner.as_ref()), raw.identity.clone());
+        }
+        Ok(raw)
+    }
+
+    /// Ask the daemons, retrying transient failures, unless the negative
+    /// cache holds a recent failure.
+    async fn lookup(&self, runner: Option<&RunnerRequest>) -> Result<RawIdentity> {
+        if let Some(error) = self.negative_cache.as_ref().and_then(|cache| cache.get()) {
+            return Err(error);
+        }
+        self.lookup_uncached(runner).await
+    }
+
+    /// Ask the daemons, retrying transient failures.
+    async fn lookup_uncached(&self, runner: Option<&RunnerRequest>) -> Result<RawIdentity> {
+        // One request ID for every attempt, failover daemon and hedge
+        let runner = self.with_request_id(runner.cloned());
+        let runner = runner.as_ref();
+        let _in_flight = self.pressure.start();
+        let mut attempt = 0;
+        let raw = loop {
+            match self.request(runner).await {
+                Ok(raw) => break raw,
+                Err(e) => match self.retry.delay(attempt, &e) {
+                    Some(delay) => {
+                        log_warn!("identity lookup failed, retrying in {delay:?}: {e}");
+                        let _queued = self.pressure.queue();
+                        tokio::time::sleep(delay).await;
+                    }
+                    None => {
+                        log_warn!("identity lookup failed: {e}");
+                        self.pressure.record(false);
+                        if let Some(cache) = &self.negative_cache {
+                            cache.record(&e);
+                        }
+                        return Err(e);
+                    }
+                },
+            }
+            attempt += 1;
+        };
+        self.pressure.record(true);
+        let name = &raw.identity.identity;
+        match raw.identity.runner.request_id() {
+            Some(id) => log_debug!("resolved identity {name} for request {id}"),
+            None => log_debug!("resolved identity {name}"),
+        }
+        Ok(raw)
+    }
+
+    /// Ask the daemon for the identity, bypassing the identity cache, the
+    /// negative cache and soft-error recovery, and cache the answer.
+    pub(crate) async fn refresh_identity(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        let runner = self.prepare_runner(runner);
+        let identity = self.lookup_uncached(runner.as_ref()).await?.into_identity();
+        if let Some(cache) = &self.cache {
+            cache.insert(IdentityCache::key(runner.as_ref()), identity.clone());
+        }
+        Ok(identity)
+    }
+
+    /// Poll the daemon every `interval` and get notified when the identity,
+    /// its uid, its URLs or its token change.
+    ///
+    /// See [`IdentityWatch`] for how failures are reported.
+    pub fn watch_identity(&self, interval: Duration) -> IdentityWatch {
+        self.watch_identity_with_runner(interval, None)
+    }
+
+    /// Like [`watch_identity`](AsyncClient::watch_identity), sending
+    /// `runner` as context with every poll.
+    pub fn watch_identity_with_runner(
+        &self,
+        interval: Duration,
+        runner: Option<RunnerRequest>,
+    ) -> IdentityWatch {
+        IdentityWatch::new(self.clone(), runner, interval)
+    }
+
+    /// Normalize the extras keys of `runner` and merge the default runner
+    /// request under it.
+    pub(crate) fn prepare_runner(&self, runner: Option<RunnerRequest>) -> Option<RunnerRequest> {
+        self.with_default_runner(runner.map(|r| r.normalize_keys(self.extras_key_case)))
+    }
+
+    /// Merge the default runner request under `runner`.
+    fn with_default_runner(&self, runner: Option<RunnerRequest>) -> Option<RunnerRequest> {
+        match (runner, &self.default_runner) {
+            (Some(runner), Some(defaults)) => Some(runner.with_defaults(defaults)),
+            (runner, defaults) => runner.or_else(|| defaults.clone()),
+        }
+    }
+
+    /// Ask the primary daemon, then each failover daemon in turn while the
+    /// previous one fails transiently.
+    async fn request(&self, runner: Option<&RunnerRequest>) -> Result<RawIdentity> {
+        if self.failover.is_empty() {
+            log_debug!("asking the whoami daemon at {}", self.socket_path.display());
+            return self.hedged_exchange(self.transport.as_ref(), runner).await;
+        }
+
+        let failover = self.failover.iter().map(|(path, t)| (path, t));
+        let endpoints = std::iter::once((&self.socket_path, &self.transport)).chain(failover);
+        let mut failures = Vec::new();
+        for (path, transport) in endpoints {
+            log_debug!("asking the whoami daemon at {}", path.display());
+            match self.hedged_exchange(transport.as_ref(), runner).await {
+                Ok(raw) => return Ok(raw),
+                Err(e) if e.is_transient() => {
+                    log_warn!("whoami daemon at {} failed: {e}", path.display());
+                    failures.push((path.clone(), e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(GetMyIdError::AllEndpointsFailed { failures })
+    }
+
+    /// Exchange with `transport`, sending a second request on a fresh
+    /// connection if the first has not completed after `hedge_after`.
+    ///
+    /// Returns whichever request succeeds first, or the first request's error
+    /// if both fail.
+    async fn hedged_exchange(
+        &self,
+        transport: &dyn AsyncTransport,
+        runner: Option<&RunnerRequest>,
+    ) -> Result<RawIdentity> {
+        let Some(delay) = self.hedge_after else {
+            return self.exchange(transport, runner).await;
+        };
+
+        let mut first = pin!(self.exchange(transport, runner));
+        if let Ok(result) = tokio::time::timeout(delay, first.as_mut()).await {
+            return result;
+        }
+
+        let mut second = pin!(self.exchange(transport, runner));
+        let mut first_error = None;
+        let mut second_failed = false;
+        poll_fn(|cx| {
+            if first_error.is_none() {
+                match first.as_mut().poll(cx) {
+                    Poll::Ready(Ok(raw)) => return Poll::Ready(Ok(raw)),
+                    Poll::Ready(Err(e)) => first_error = Some(e),
+                    Poll::Pending => {}
+                }
+            }
+            if !second_failed {
+                match second.as_mut().poll(cx) {
+                    Poll::Ready(Ok(raw)) => return Poll::Ready(Ok(raw)),
+                    Poll::Ready(Err(_)) => second_failed = true,
+                    Poll::Pending => {}
+                }
+            }
+            match first_error.take() {
+                Some(e) if second_failed => Poll::Ready(Err(e)),
+                error => {
+                    first_error = error;
+                    Poll::Pending
                 }
-            })?;
+            }
+        })
+        .await
+    }
+
+    /// Perform a single request/response exchange over `transport`.
+    async fn exchange(
+        &self,
+        transport: &dyn AsyncTransport,
+        runner: Option<&RunnerRequest>,
+    ) -> Result<RawIdentity> {
+        // Reject reserved fields before connecting to the daemon
+        if let Some(runner) = runner {
+            runner.check()?;
+        }
+        let mut timer = RequestTimer::start();
+        let mut raw = String::new();
+        let identity = match with_timeout(self.connect_timeout, transport.dial()).await {
+            Ok(stream) => {
+                timer.connected();
+                let request = self.new_request(runner.cloned());
+                self.fetch_over(stream, &request, &mut raw).await
+            }
+            Err(e) => Err(e),
+        }
+        .and_then(|()| self.identity_from_response(&raw, runner));
+        timer.finish(self.metrics.as_ref(), raw.len(), identity.as_ref().err());
+        Ok(RawIdentity::new(identity?, raw))
+    }
 
-            // Send runner request if provided
-            if let Some(ref runner_req) = runner {
-                let request = serde_json::json!({ "runner": runner_req });
-                let request_str = serde_json::to_string(&request).map_err(GetMyIdError::InvalidJson)?;
+    /// Send `request` over the connection `stream` and read the raw
+    /// response into `raw`.
+    async fn fetch_over(
+        &self,
+        mut stream: Box<dyn AsyncConnection>,
+        request: &Request,
+        raw: &mut String,
+    ) -> Result<()> {
+        // Send the request unless it is empty
+        if *request != Request::default() {
+            let request_str = request.to_json()?;
+            with_timeout(self.write_timeout, async {
                 stream
                     .write_all(request_str.as_bytes())
                     .await
                     .map_err(GetMyIdError::WriteError)?;
-                stream.flush().await.map_err(GetMyIdError::WriteError)?;
-                // Shutdown write side to signal we're done sending
-                stream.shutdown().await.ok();
-            }
+                stream.flush().await.map_err(GetMyIdError::WriteError)
+            })
+            .await?;
+            // Shutdown write side to signal we're done sending
+            stream.shutdown().await.ok();
+        }
 
-            // Read the response
-            let mut response = String::new();
-            stream
-                .read_to_string(&mut response)
+        // Read the response
+        let mut response = Vec::new();
+        with_timeout(self.read_timeout, async {
+            (&mut stream)
+                .take(read_limit(self.max_response_bytes))
+                .read_to_end(&mut response)
                 .await
-                .map_err(GetMyIdError::ReadError)?;
+                .map_err(GetMyIdError::ReadError)
+        })
+        .await?;
+        *raw = response_text(response, self.max_response_bytes)?;
+        self.open_response(raw)?;
+        check_nonce(request.nonce.as_deref(), raw)
+    }
 
-            // Parse response
-            parse_response(&response)
-        };
+    /// Give `runner` a request ID unless it carries one, if the client adds
+    /// them.
+    fn with_request_id(&self, runner: Option<RunnerRequest>) -> Option<RunnerRequest> {
+        match runner {
+            runner if self.request_ids => Some(runner.unwrap_or_default().ensure_request_id()),
+            runner => runner,
+        }
+    }
 
-        // Apply timeout if configured
-        if let Some(timeout) = self.timeout {
-            tokio::time::timeout(timeout, get_identity_inner)
-                .await
-                .map_err(|_| GetMyIdError::Timeout(timeout))?
+    /// Create the request carrying `runner`, completed with a request ID
+    /// and the trace context if the client adds them, asking for a signed
+    /// answer if the client verifies signatures and with a nonce if it
+    /// requires one.
+    pub(crate) fn new_request(&self, runner: Option<RunnerRequest>) -> Request {
+        let runner = self.with_request_id(runner);
+        #[cfg(feature = "otel")]
+        let runner = match &self.trace_context {
+            Some(source) => source.inject(runner),
+            None => runner,
+        };
+        let request = Request {
+            #[cfg(feature = "signing")]
+            sign: self.daemon_public_key.is_some(),
+            ..Request::new(runner)
+        };
+        if self.require_nonce {
+            request.with_nonce()
         } else {
-            get_identity_inner.await
+            request
+        }
+    }
+
+    /// Replace the signed daemon answer `response` with its verified
+    /// payload if the client verifies signatures.
+    pub(crate) fn open_response(&self, response: &mut String) -> Result<()> {
+        #[cfg(feature = "signing")]
+        if let Some(public_key) = &self.daemon_public_key {
+            *response = crate::signing::open(response, public_key)?;
+        }
+        #[cfg(not(feature = "signing"))]
+        let _ = response;
+        Ok(())
+    }
+
+    /// Parse and validate a raw daemon response to `runner`.
+    pub(crate) fn identity_from_response(
+        &self,
+        response: &str,
+        runner: Option<&RunnerRequest>,
+    ) -> Result<Identity> {
+        if self.strict_parsing {
+            check_known_fields(response, runner)?;
         }
+        let mut identity = parse_response(response)?;
+        self.json_limits.check(&identity.runner)?;
+        identity.runner.normalize_keys(self.extras_key_case);
+        identity
+            .runner
+            .apply_hostname_policy(self.hostname_policy, procfs::hostname());
+        Ok(identity)
     }
 
-    /// Get the configured socket path.
+    /// Open a connection to the primary daemon within the connect timeout.
+    pub(crate) async fn dial(&self) -> Result<Box<dyn AsyncConnection>> {
+        with_timeout(self.connect_timeout, self.transport.dial()).await
+    }
+
+    /// Check that the daemon is alive and measure the round trip.
+    ///
+    /// Sends a [ping](Request::ping) to the configured daemon, once: the
+    /// cache, retries and failover daemons are bypassed. Daemons without
+    /// ping support answer with an identity, which counts as alive, as does
+    /// the rejection of this process such as `E_NO_MATCH`. Meant for
+    /// readiness probes and monitoring agents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon cannot be reached, does not answer in
+    /// time, is degraded or fails with `E_INTERNAL`.
+    pub async fn ping(&self) -> Result<Duration> {
+        let started = Instant::now();
+        let request = self.new_request(None).with_ping();
+        let mut response = String::new();
+        self.fetch_over(self.dial().await?, &request, &mut response)
+            .await?;
+        check_alive(&response, STATUS_PONG)?;
+        Ok(started.elapsed())
+    }
+
+    /// Ask the daemon for its versions and the protocol features it
+    /// supports.
+    ///
+    /// Like [`ping`](Self::ping), asks the configured daemon once,
+    /// bypassing the cache, retries and failover daemons. Daemons without
+    /// info support answer with an identity or reject this process; the
+    /// returned [`DaemonInfo`] then only carries the versions of that
+    /// answer, with no [`features`](DaemonInfo::features).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # async fn run() -> getmyid::Result<()> {
+    /// let client = getmyid::AsyncClient::new();
+    /// let info = client.daemon_info().await?;
+    /// if !info.compatibility().is_tested() || !info.supports("keep_alive") {
+    ///     eprintln!("whoami daemon {:?} not fully supported", info.version.release);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon cannot be reached, does not answer in
+    /// time, is degraded or fails with `E_INTERNAL`.
+    pub async fn daemon_info(&self) -> Result<DaemonInfo> {
+        let request = self.new_request(None).with_info();
+        let mut response = String::new();
+        self.fetch_over(self.dial().await?, &request, &mut response)
+            .await?;
+        check_alive(&response, STATUS_INFO)?;
+        Ok(DaemonInfo::from_response(&response))
+    }
+
+    /// Resolve the identity another local process would get.
+    ///
+    /// For supervisors and debugging tools: the daemon resolves the process
+    /// `pid` instead of the caller, if it permits the caller such lookups.
+    /// Asks the configured daemon once, bypassing the cache, retries and
+    /// failover daemons.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`GetMyIdError::DaemonError`] with `E_ACCESS_DENIED` if the
+    /// daemon does not permit the caller to look up other processes, and
+    /// [`GetMyIdError::PidMismatch`] if it answered for another process,
+    /// as daemons without support for PID-targeted lookups do.
+    #[cfg(feature = "admin")]
+    pub async fn get_identity_for_pid(&self, pid: u32) -> Result<Identity> {
+        let request = self.new_request(None).with_target_pid(pid);
+        let mut response = String::new();
+        self.fetch_over(self.dial().await?, &request, &mut response)
+            .await?;
+        let identity = self.identity_from_response(&response, request.runner.as_ref())?;
+        check_target_pid(pid, &identity)?;
+        Ok(identity)
+    }
+
+    /// Get the configured socket path, or the one chosen by discovery.
+    ///
+    /// This is not used when a custom transport is configured.
     pub fn socket_path(&self) -> &Path {
         &self.socket_path
     }
 
+    /// Get the socket paths of the failover daemons, in the order they are
+    /// tried after the primary.
+    pub fn failover_sockets(&self) -> impl Iterator<Item = &Path> {
+        self.failover.iter().map(|(path, _)| path.as_path())
+    }
+
     /// Get the configured timeout.
+    ///
+    /// When the timeouts have been configured individually this returns the
+    /// read timeout, which bounds how long the client waits for the daemon.
     pub fn timeout(&self) -> Option<Duration> {
-        self.timeout
+        self.read_timeout
+    }
+
+    /// Get the configured connect timeout.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// Get the configured read timeout.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Get the configured write timeout.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// Get the runner request sent with every request, if any.
+    pub fn default_runner(&self) -> Option<&RunnerRequest> {
+        self.default_runner.as_ref()
+    }
+
+    /// Get the number of retries after a transient failure.
+    pub fn retries(&self) -> u32 {
+        self.retry.retries
+    }
+
+    /// Get how long resolved identities are cached, if caching is enabled.
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache.as_ref().map(|cache| cache.ttl())
+    }
+
+    /// Get which hostname returned runners report.
+    pub fn hostname_policy(&self) -> HostnamePolicy {
+        self.hostname_policy
+    }
+
+    /// Get the limits enforced on the runner extras of daemon responses.
+    pub fn json_limits(&self) -> JsonLimits {
+        self.json_limits
+    }
+
+    /// Check whether responses with unknown fields are rejected.
+    pub fn strict_parsing(&self) -> bool {
+        self.strict_parsing
+    }
+
+    /// Get the daemon error codes answered with a cached identity; changes
+    /// made through the handle apply to this client.
+    pub fn soft_error_codes(&self) -> &SoftErrorCodes {
+        &self.soft_errors
+    }
+
+    /// Get the maximum size of a daemon response, in bytes.
+    pub fn max_response_bytes(&self) -> usize {
+        self.max_response_bytes
+    }
+
+    /// Get how long a failure to reach the daemon is returned again without
+    /// contacting it, if negative caching is enabled.
+    pub fn negative_cache_ttl(&self) -> Option<Duration> {
+        self.negative_cache.as_ref().map(|cache| cache.ttl())
+    }
+
+    /// Get the current load and recent error rate of identity lookups, so
+    /// callers can shed or defer identity-dependent work while the daemon
+    /// struggles instead of discovering it through timeouts.
+    pub fn pressure(&self) -> Pressure {
+        self.pressure.snapshot()
+    }
+
+    /// Get the delay after which a hedged second request is sent, if
+    /// hedging is enabled (requires `unstable` feature).
+    #[cfg(feature = "unstable")]
+    pub fn hedge_after(&self) -> Option<Duration> {
+        self.hedge_after
+    }
+}
+
+/// Run `fut`, failing with [`GetMyIdError::Timeout`] if it exceeds `timeout`.
+pub(crate) async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut)
+            .await
+            .map_err(|_| GetMyIdError::Timeout(timeout))?,
+        None => fut.await,
     }
 }
 
@@ -164,7 +784,33 @@ impl AsyncClient {
 #[derive(Debug, Clone)]
 pub struct AsyncClientBuilder {
     socket_path: PathBuf,
-    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    extras_key_case: KeyCase,
+    hostname_policy: HostnamePolicy,
+    json_limits: JsonLimits,
+    strict_parsing: bool,
+    soft_errors: SoftErrorCodes,
+    max_response_bytes: usize,
+    default_runner: Option<RunnerRequest>,
+    symlink_policy: SymlinkPolicy,
+    retry: RetryPolicy,
+    cache_ttl: Option<Duration>,
+    cache_limits: CacheLimits,
+    negative_cache_ttl: Option<Duration>,
+    socket_candidates: Option<Vec<PathBuf>>,
+    failover_sockets: Vec<PathBuf>,
+    hedge_after: Option<Duration>,
+    peer_uid: Option<u32>,
+    #[cfg(feature = "signing")]
+    daemon_public_key: Option<Arc<[u8]>>,
+    require_nonce: bool,
+    request_ids: bool,
+    metrics: Option<Metrics>,
+    #[cfg(feature = "otel")]
+    trace_context: Option<TraceContext>,
+    transport: Option<Arc<dyn AsyncTransport>>,
 }
 
 impl Default for AsyncClientBuilder {
@@ -178,29 +824,558 @@ impl AsyncClientBuilder {
     pub fn new() -> Self {
         Self {
             socket_path: PathBuf::from(DEFAULT_SOCKET_PATH),
-            timeout: Some(DEFAULT_TIMEOUT),
+            connect_timeout: Some(DEFAULT_TIMEOUT),
+            read_timeout: Some(DEFAULT_TIMEOUT),
+            write_timeout: Some(DEFAULT_TIMEOUT),
+            extras_key_case: KeyCase::Preserve,
+            hostname_policy: HostnamePolicy::Daemon,
+            json_limits: JsonLimits::new(),
+            strict_parsing: false,
+            soft_errors: SoftErrorCodes::default(),
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            default_runner: None,
+            symlink_policy: SymlinkPolicy::Follow,
+            retry: RetryPolicy::default(),
+            cache_ttl: None,
+            cache_limits: CacheLimits::default(),
+            negative_cache_ttl: None,
+            socket_candidates: None,
+            failover_sockets: Vec::new(),
+            hedge_after: None,
+            peer_uid: None,
+            #[cfg(feature = "signing")]
+            daemon_public_key: None,
+            require_nonce: false,
+            request_ids: false,
+            metrics: None,
+            #[cfg(feature = "otel")]
+            trace_context: None,
+            transport: None,
+        }
+    }
+
+    /// Create a builder starting from the defaults overridden by the
+    /// `GETMYID_*` environment variables.
+    ///
+    /// Options set on the returned builder take precedence over the
+    /// environment.
+    ///
+    /// `GETMYID_SOCKET_TYPE` is ignored: the async client always uses stream
+    /// sockets.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidEnvVar`] if a variable cannot be parsed.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::new().apply(Settings::from_env()?))
+    }
+
+    /// Create a builder starting from the defaults overridden by the TOML
+    /// file at `path`, then by the `GETMYID_*` environment variables
+    /// (requires `config-file` feature).
+    ///
+    /// Options set on the returned builder take precedence over both. The
+    /// `socket_type` key is ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidConfig`] if the file cannot be read or
+    /// parsed, or [`GetMyIdError::InvalidEnvVar`] if a variable cannot be
+    /// parsed.
+    #[cfg(feature = "config-file")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let settings = Settings::from_file(path.as_ref())?.overlay(Settings::from_env()?);
+        Ok(Self::new().apply(settings))
+    }
+
+    /// Like [`from_file`](Self::from_file), reading `/etc/getmyid.toml` and
+    /// then the per-user `~/.config/getmyid.toml`, skipping files that do
+    /// not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidConfig`] if an existing file cannot be
+    /// read or parsed, or [`GetMyIdError::InvalidEnvVar`] if a variable
+    /// cannot be parsed.
+    #[cfg(feature = "config-file")]
+    pub fn from_default_files() -> Result<Self> {
+        let settings = Settings::from_default_files()?.overlay(Settings::from_env()?);
+        Ok(Self::new().apply(settings))
+    }
+
+    /// Override the options present in `settings`.
+    fn apply(mut self, settings: Settings) -> Self {
+        if let Some(path) = settings.socket_path {
+            self.socket_path = path;
+        }
+        if let Some(timeout) = settings.connect_timeout {
+            self.connect_timeout = timeout;
+        }
+        if let Some(timeout) = settings.read_timeout {
+            self.read_timeout = timeout;
+        }
+        if let Some(timeout) = settings.write_timeout {
+            self.write_timeout = timeout;
         }
+        if let Some(retries) = settings.retries {
+            self.retry.retries = retries;
+        }
+        if let Some(backoff) = settings.retry_backoff {
+            self.retry.backoff = backoff;
+        }
+        if let Some(ttl) = settings.cache_ttl {
+            self.cache_ttl = ttl;
+        }
+        if let Some(max) = settings.cache_max_entries {
+            self.cache_limits.max_entries = max;
+        }
+        if let Some(max) = settings.cache_max_bytes {
+            self.cache_limits.max_bytes = max;
+        }
+        self
     }
 
     /// Set the socket path.
+    ///
+    /// A path starting with `@` refers to a Linux abstract namespace socket.
     pub fn socket_path<P: AsRef<Path>>(mut self, path: P) -> Self {
         self.socket_path = path.as_ref().to_path_buf();
         self
     }
 
+    /// Connect to the Linux abstract namespace socket `name`.
+    ///
+    /// This is equivalent to `socket_path("@name")`.
+    pub fn abstract_socket(mut self, name: impl AsRef<std::ffi::OsStr>) -> Self {
+        self.socket_path = abstract_path(name);
+        self
+    }
+
+    /// Connect through an inherited file descriptor referring to the
+    /// daemon's socket file (for example opened with `O_PATH`).
+    ///
+    /// The descriptor must stay open for the lifetime of the client; every
+    /// connection is made through `/proc/self/fd/<fd>`.
+    pub fn socket_fd(mut self, fd: RawFd) -> Self {
+        self.socket_path = fd_path(fd);
+        self
+    }
+
+    /// Use the socket handed down by the service manager, if any.
+    ///
+    /// See [`inherited_socket_path`](crate::inherited_socket_path) for the
+    /// conventions detected. When nothing was handed down, the socket path
+    /// configured so far is kept.
+    pub fn inherited_socket(mut self) -> Self {
+        if let Some(path) = inherited_socket_path() {
+            self.socket_path = path;
+        }
+        self
+    }
+
+    /// Connect to the first live socket among the
+    /// [`default_socket_candidates`](crate::default_socket_candidates).
+    ///
+    /// See [`socket_candidates`](Self::socket_candidates).
+    pub fn discover_socket(mut self) -> Self {
+        self.socket_candidates = Some(default_socket_candidates());
+        self
+    }
+
+    /// Connect to the first of `candidates` accepting a connection.
+    ///
+    /// The candidates are probed in order once, when the client is built;
+    /// the chosen path is reported by the client's `socket_path()`. If none
+    /// is live, the socket path configured otherwise is kept.
+    pub fn socket_candidates<I>(mut self, candidates: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        self.socket_candidates = Some(
+            candidates
+                .into_iter()
+                .map(|path| path.as_ref().to_path_buf())
+                .collect(),
+        );
+        self
+    }
+
+    /// Add a failover daemon socket, tried after the primary socket and any
+    /// failover sockets added before it.
+    ///
+    /// A daemon is passed over when it is unreachable, times out or reports
+    /// being degraded; a rejection such as `E_NO_MATCH` is returned as is.
+    /// When every daemon fails, [`GetMyIdError::AllEndpointsFailed`] lists
+    /// each error. Failover daemons are always reached over Unix sockets.
+    pub fn failover_socket<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.failover_sockets.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add several failover daemon sockets; see
+    /// [`failover_socket`](Self::failover_socket).
+    pub fn failover_sockets<I>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        self.failover_sockets
+            .extend(paths.into_iter().map(|path| path.as_ref().to_path_buf()));
+        self
+    }
+
     /// Set the connection timeout.
     ///
+    /// This sets the connect, read and write timeouts to the same value.
     /// Pass `None` to disable timeouts.
     pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
-        self.timeout = timeout.into();
+        let timeout = timeout.into();
+        self.connect_timeout = timeout;
+        self.read_timeout = timeout;
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Set the timeout for establishing the connection.
+    ///
+    /// Pass `None` to wait indefinitely.
+    pub fn connect_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.connect_timeout = timeout.into();
+        self
+    }
+
+    /// Set the timeout for reading the daemon's response.
+    ///
+    /// Pass `None` to wait indefinitely.
+    pub fn read_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.read_timeout = timeout.into();
+        self
+    }
+
+    /// Set the timeout for sending the runner request.
+    ///
+    /// Pass `None` to wait indefinitely.
+    pub fn write_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.write_timeout = timeout.into();
+        self
+    }
+
+    /// Normalize the keys of runner extras to `case`.
+    ///
+    /// The convention is applied to the extras of every runner request
+    /// before it is sent and to the extras of every returned runner, so
+    /// callers see one convention regardless of what the daemon echoes back.
+    pub fn extras_key_case(mut self, case: KeyCase) -> Self {
+        self.extras_key_case = case;
+        self
+    }
+
+    /// Choose which hostname [`Runner::hostname`](crate::Runner::hostname)
+    /// holds when the client runs in a different UTS namespace than the
+    /// daemon, e.g. inside a container.
+    ///
+    /// Whenever the two differ, the client's hostname is also recorded in
+    /// [`Runner::client_hostname`](crate::Runner::client_hostname). Defaults
+    /// to [`HostnamePolicy::Daemon`].
+    pub fn hostname_policy(mut self, policy: HostnamePolicy) -> Self {
+        self.hostname_policy = policy;
+        self
+    }
+
+    /// Reject responses whose runner extras exceed `limits` with
+    /// [`GetMyIdError::MalformedResponse`].
+    ///
+    /// Defaults to [`JsonLimits::new`]; pass [`JsonLimits::unlimited`] to
+    /// accept any extras.
+    pub fn json_limits(mut self, limits: JsonLimits) -> Self {
+        self.json_limits = limits;
+        self
+    }
+
+    /// Reject responses carrying fields outside the daemon protocol with
+    /// [`GetMyIdError::UnknownField`] naming the field, e.g. `runner.color`.
+    ///
+    /// By default unknown top-level fields are ignored and unknown runner
+    /// fields end up in [`Runner::extra`](crate::Runner::extra). In strict
+    /// mode runner extras are only accepted if the runner request sent
+    /// carries them, so a protocol mismatch is an error instead. Responses
+    /// in the legacy flat format are rejected too.
+    pub fn strict_parsing(mut self, strict: bool) -> Self {
+        self.strict_parsing = strict;
+        self
+    }
+
+    /// Fail with [`GetMyIdError::ResponseTooLarge`] as soon as a daemon
+    /// response grows beyond `max` bytes, so a misbehaving or spoofed daemon
+    /// cannot make the client allocate unbounded memory.
+    ///
+    /// Defaults to [`DEFAULT_MAX_RESPONSE_BYTES`] (1 MiB).
+    pub fn max_response_bytes(mut self, max: usize) -> Self {
+        self.max_response_bytes = max;
+        self
+    }
+
+    /// Send `runner` with every request.
+    ///
+    /// Runner requests passed to
+    /// [`get_identity_with_runner`](AsyncClient::get_identity_with_runner) are
+    /// merged over it with [`RunnerRequest::with_defaults`], so per-call
+    /// fields win; [`get_identity`](AsyncClient::get_identity) sends it as is.
+    pub fn default_runner(mut self, runner: RunnerRequest) -> Self {
+        self.default_runner = Some(runner);
+        self
+    }
+
+    /// Retry up to `retries` times when the daemon is unreachable, times out
+    /// or reports being degraded.
+    ///
+    /// Rejections such as `E_NO_MATCH` are never retried. Defaults to 0.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retry.retries = retries;
+        self
+    }
+
+    /// Set the delay before the first retry; it doubles after every attempt.
+    ///
+    /// A `retry_after` hint from a degraded daemon is used instead when
+    /// present, up to 30 seconds.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry.backoff = backoff;
+        self
+    }
+
+    /// Reuse a resolved identity for `ttl` instead of asking the daemon again.
+    ///
+    /// Identities are cached per runner request and shared between clones of
+    /// the client. Pass `None` to disable caching (the default).
+    pub fn cache_ttl(mut self, ttl: impl Into<Option<Duration>>) -> Self {
+        self.cache_ttl = ttl.into();
+        self
+    }
+
+    /// Cache at most `max` identities, evicting the least recently used.
+    ///
+    /// Defaults to 1024. Expired identities are also swept out as new ones
+    /// are cached.
+    pub fn cache_max_entries(mut self, max: usize) -> Self {
+        self.cache_limits.max_entries = max;
+        self
+    }
+
+    /// Bound the approximate memory used by cached identities, evicting the
+    /// least recently used.
+    ///
+    /// Sizes are estimated from the serialized identity and its runner
+    /// context; a single identity larger than `max` is not cached. Pass
+    /// `None` to remove the bound (the default).
+    pub fn cache_max_bytes(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.cache_limits.max_bytes = max.into();
+        self
+    }
+
+    /// Answer daemon errors with these codes, such as `E_MAINTENANCE`, with
+    /// the identity cached earlier instead of failing.
+    ///
+    /// Keep a clone of `codes` to change them at runtime; see
+    /// [`SoftErrorCodes`]. Only lookups through the identity cache are
+    /// affected, so this needs `cache_ttl`. No code is soft by default.
+    pub fn soft_error_codes(mut self, codes: SoftErrorCodes) -> Self {
+        self.soft_errors = codes;
+        self
+    }
+
+    /// Return the last failure to reach the daemon again, without
+    /// contacting it, for `ttl` after it happened.
+    ///
+    /// Only failures showing the daemon is down are cached: a missing
+    /// socket, a refused connection or a timeout, once retries and failover
+    /// daemons are exhausted. Hot request paths then fail fast during an
+    /// outage instead of waiting for the connect timeout on every call.
+    /// Pass `None` to disable (the default).
+    pub fn negative_cache_ttl(mut self, ttl: impl Into<Option<Duration>>) -> Self {
+        self.negative_cache_ttl = ttl.into();
+        self
+    }
+
+    /// Set how symlinks in the socket path are treated.
+    ///
+    /// By default they are followed, so `/var/run/whoami.sock` reaches the
+    /// daemon at `/run/whoami.sock`; errors then name both paths. Relative
+    /// socket paths are made absolute when the client is built.
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// Only trust a daemon running as `uid`, e.g. `0` for a daemon run by
+    /// root.
+    ///
+    /// After connecting, the client reads the uid of the process serving
+    /// the socket with `SO_PEERCRED` and fails with
+    /// [`GetMyIdError::UntrustedDaemon`] before sending anything if it
+    /// differs, so a local process listening on a look-alike socket cannot
+    /// hand out fake identities. Applies to failover daemons too, but not to
+    /// custom transports. Pass `None` to disable (the default).
+    pub fn require_peer_uid(mut self, uid: impl Into<Option<u32>>) -> Self {
+        self.peer_uid = uid.into();
+        self
+    }
+
+    /// Send a fresh random nonce with every request and require the daemon
+    /// to echo it in its response.
+    ///
+    /// Responses without the nonce, e.g. a recorded answer replayed to the
+    /// client or one served from a cache between the client and the daemon,
+    /// are rejected with [`GetMyIdError::NonceMismatch`]. Requires a daemon
+    /// supporting nonces; disabled by default.
+    pub fn require_nonce(mut self, enabled: bool) -> Self {
+        self.require_nonce = enabled;
+        self
+    }
+
+    /// Give every request a fresh [request ID](RunnerRequest::with_request_id)
+    /// unless its runner context carries one already.
+    ///
+    /// The daemon echoes the ID in the identity's runner object, from where
+    /// it reaches the config server, so one lookup can be followed through
+    /// the logs of all three. Disabled by default.
+    pub fn request_ids(mut self, enabled: bool) -> Self {
+        self.request_ids = enabled;
+        self
+    }
+
+    /// Report every exchange with a daemon to `sink`, e.g. to count
+    /// requests and errors by [outcome](crate::RequestMetrics::outcome) and
+    /// record connect latencies and response sizes in histograms.
+    ///
+    /// Lookups through [`get_identity`](AsyncClient::get_identity) and its
+    /// variants are reported, once per daemon asked; exchanges abandoned
+    /// for a [hedged](AsyncClientBuilder::hedge_after) one and
+    /// [pooled connections](crate::AsyncClientPool) are not.
+    pub fn metrics(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.metrics = Some(Metrics::new(sink));
+        self
+    }
+
+    /// Send the trace context returned by `source` with every request, as
+    /// the [`TRACEPARENT_FIELD`](crate::TRACEPARENT_FIELD) runner field
+    /// (requires `otel` feature).
+    ///
+    /// `source` is called for each request and typically reads the current
+    /// span of the application's tracer; requests go out unchanged when it
+    /// returns `None`. The trace context is not part of the cache key, so
+    /// cached identities carry the one of the lookup that fetched them.
+    #[cfg(feature = "otel")]
+    pub fn trace_context(
+        mut self,
+        source: impl Fn() -> Option<crate::TraceParent> + Send + Sync + 'static,
+    ) -> Self {
+        self.trace_context = Some(TraceContext::new(source));
+        self
+    }
+
+    /// Require the daemon to sign its responses with the Ed25519 key whose
+    /// raw 32-byte public key is `public_key` (requires `signing` feature).
+    ///
+    /// See [`ClientBuilder::daemon_public_key`](crate::ClientBuilder::daemon_public_key).
+    #[cfg(feature = "signing")]
+    pub fn daemon_public_key(mut self, public_key: impl Into<Vec<u8>>) -> Self {
+        self.daemon_public_key = Some(public_key.into().into());
+        self
+    }
+
+    /// Send a second request on a fresh connection if the first has not
+    /// completed after `delay`, and use whichever succeeds first.
+    ///
+    /// Hedging trims tail latency when a daemon occasionally stalls, at the
+    /// cost of an extra request for slow lookups. Each failover daemon is
+    /// hedged the same way. Pass `None` to disable hedging (the default).
+    ///
+    /// Requires the `unstable` feature; the API may change in any release.
+    #[cfg(feature = "unstable")]
+    pub fn hedge_after(mut self, delay: impl Into<Option<Duration>>) -> Self {
+        self.hedge_after = delay.into();
+        self
+    }
+
+    /// Use a custom transport instead of the Unix socket.
+    ///
+    /// The configured timeouts still apply; the socket path does not.
+    pub fn transport(mut self, transport: impl AsyncTransport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
         self
     }
 
+    /// Replace the socket path with the first live candidate, if any.
+    fn discover(&mut self) {
+        if self.transport.is_some() {
+            return;
+        }
+        if let Some(candidates) = self.socket_candidates.take() {
+            if let Some(path) =
+                probe_candidates(candidates, SocketType::Stream, self.connect_timeout)
+            {
+                self.socket_path = path;
+            }
+        }
+    }
+
     /// Build the async client.
-    pub fn build(self) -> AsyncClient {
+    pub fn build(mut self) -> AsyncClient {
+        self.discover();
+        self.socket_path = absolute_socket_path(self.socket_path);
+        let unix_transport = |path: &Path| {
+            AsyncUnixTransport::new(path)
+                .with_symlink_policy(self.symlink_policy)
+                .with_required_peer_uid(self.peer_uid)
+        };
+        let transport = self
+            .transport
+            .unwrap_or_else(|| Arc::new(unix_transport(&self.socket_path)));
+        let failover = self
+            .failover_sockets
+            .into_iter()
+            .map(absolute_socket_path)
+            .map(|path| {
+                let transport: Arc<dyn AsyncTransport> = Arc::new(unix_transport(&path));
+                (path, transport)
+            })
+            .collect();
+
         AsyncClient {
             socket_path: self.socket_path,
-            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            extras_key_case: self.extras_key_case,
+            hostname_policy: self.hostname_policy,
+            json_limits: self.json_limits,
+            strict_parsing: self.strict_parsing,
+            soft_errors: self.soft_errors,
+            max_response_bytes: self.max_response_bytes,
+            default_runner: self
+                .default_runner
+                .map(|r| r.normalize_keys(self.extras_key_case)),
+            retry: self.retry,
+            cache: self
+                .cache_ttl
+                .map(|ttl| Arc::new(IdentityCache::new(ttl, self.cache_limits))),
+            negative_cache: self
+                .negative_cache_ttl
+                .map(|ttl| Arc::new(NegativeCache::new(ttl))),
+            pressure: Arc::default(),
+            flights: Arc::default(),
+            transport,
+            failover,
+            hedge_after: self.hedge_after,
+            #[cfg(feature = "signing")]
+            daemon_public_key: self.daemon_public_key,
+            require_nonce: self.require_nonce,
+            request_ids: self.request_ids,
+            metrics: self.metrics,
+            #[cfg(feature = "otel")]
+            trace_context: self.trace_context,
         }
     }
 }
@@ -225,6 +1400,21 @@ mod tests {
         let client = AsyncClient::builder().timeout(None).build();
 
         assert_eq!(client.timeout(), None);
+        assert_eq!(client.connect_timeout(), None);
+        assert_eq!(client.write_timeout(), None);
+    }
+
+    #[test]
+    fn test_async_client_builder_separate_timeouts() {
+        let client = AsyncClient::builder()
+            .timeout(Duration::from_secs(1))
+            .connect_timeout(Duration::from_secs(30))
+            .read_timeout(Duration::from_millis(500))
+            .build();
+
+        assert_eq!(client.connect_timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(client.read_timeout(), Some(Duration::from_millis(500)));
+        assert_eq!(client.write_timeout(), Some(Duration::from_secs(1)));
     }
 
     #[test]
@@ -234,4 +1424,347 @@ mod tests {
         assert_eq!(client.socket_path(), Path::new(DEFAULT_SOCKET_PATH));
         assert_eq!(client.timeout(), Some(DEFAULT_TIMEOUT));
     }
+
+    #[tokio::test]
+    async fn test_async_client_abstract_socket() {
+        let name = format!("getmyid-async-test-{}", std::process::id());
+        let listener = tokio::net::UnixListener::bind(format!("\0{name}")).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let response = r#"{"status":"ok","identity":"ABSTRACT","idm_url":"","config_url":"","token":"","runner":{}}"#;
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let client = AsyncClient::builder().abstract_socket(&name).build();
+        let identity = client.get_identity().await.unwrap();
+
+        assert_eq!(identity.identity, "ABSTRACT");
+    }
+
+    #[tokio::test]
+    async fn test_async_client_custom_transport() {
+        use crate::async_transport::{AsyncConnection, BoxFuture};
+
+        #[derive(Debug)]
+        struct Duplex;
+
+        impl AsyncTransport for Duplex {
+            fn dial(&self) -> BoxFuture<'_, Result<Box<dyn AsyncConnection>>> {
+                Box::pin(async {
+                    let (client, mut daemon) = tokio::io::duplex(1024);
+                    tokio::spawn(async move {
+                        let mut request = String::new();
+                        daemon.read_to_string(&mut request).await.unwrap();
+                        assert!(request.contains("\"instance_id\":9"));
+                        let response = r#"{"status":"ok","identity":"PIPE","idm_url":"","config_url":"","token":"","runner":{"instance_id":9}}"#;
+                        daemon.write_all(response.as_bytes()).await.unwrap();
+                    });
+                    Ok(Box::new(client) as Box<dyn AsyncConnection>)
+                })
+            }
+        }
+
+        let client = AsyncClient::builder().transport(Duplex).build();
+        let identity = client
+            .get_identity_with_runner(Some(RunnerRequest::new().with_instance_id(9)))
+            .await
+            .unwrap();
+
+        assert_eq!(identity.identity, "PIPE");
+        assert_eq!(identity.runner.instance_id, Some(9));
+    }
+
+    #[cfg(feature = "unstable")]
+    #[tokio::test]
+    async fn test_async_client_hedged_request() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+
+        use crate::async_transport::{AsyncConnection, BoxFuture};
+
+        /// Never answers the first connection; answers every later one.
+        #[derive(Debug, Default)]
+        struct Stalling {
+            dials: Arc<AtomicUsize>,
+            stalled: Mutex<Vec<tokio::io::DuplexStream>>,
+        }
+
+        impl AsyncTransport for Stalling {
+            fn dial(&self) -> BoxFuture<'_, Result<Box<dyn AsyncConnection>>> {
+                Box::pin(async {
+                    let (client, mut daemon) = tokio::io::duplex(1024);
+                    if self.dials.fetch_add(1, Ordering::SeqCst) == 0 {
+                        self.stalled.lock().unwrap().push(daemon);
+                    } else {
+                        tokio::spawn(async move {
+                            let response = r#"{"status":"ok","identity":"HEDGED","idm_url":"","config_url":"","token":"","runner":{}}"#;
+                            daemon.write_all(response.as_bytes()).await.unwrap();
+                        });
+                    }
+                    Ok(Box::new(client) as Box<dyn AsyncConnection>)
+                })
+            }
+        }
+
+        let transport = Stalling::default();
+        let dials = Arc::clone(&transport.dials);
+        let client = AsyncClient::builder()
+            .transport(transport)
+            .hedge_after(Duration::from_millis(20))
+            .build();
+
+        assert_eq!(client.hedge_after(), Some(Duration::from_millis(20)));
+        assert_eq!(client.get_identity().await.unwrap().identity, "HEDGED");
+        assert_eq!(dials.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_client_failover() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path("/nonexistent/whoami.sock")
+            .failover_socket(daemon.socket_path())
+            .build();
+
+        assert_eq!(
+            client.get_identity().await.unwrap().identity,
+            "TEST_IDENTITY"
+        );
+        assert_eq!(
+            client.failover_sockets().collect::<Vec<_>>(),
+            [daemon.socket_path()]
+        );
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_client_default_runner() {
+        use crate::testing::FakeDaemon;
+        use crate::KeyCase;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .extras_key_case(KeyCase::Snake)
+            .default_runner(RunnerRequest::new().with_field("deployRegion", "eu"))
+            .build();
+        assert!(client.default_runner().is_some());
+
+        client
+            .get_identity_with_runner(Some(RunnerRequest::new().with_field("deploy_region", "us")))
+            .await
+            .unwrap();
+
+        let sent = daemon.requests()[0].runner().unwrap();
+        assert_eq!(sent.extra.get("deploy_region").unwrap(), "us");
+        assert_eq!(sent.extra.len(), 1);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_client_against_fake_daemon() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .read_timeout(Duration::from_millis(200))
+            .build();
+
+        let identity = client.get_identity().await.unwrap();
+        assert_eq!(identity.identity, "TEST_IDENTITY");
+
+        daemon.respond_with(FakeResponse::default().delayed(Duration::from_secs(2)));
+        assert!(matches!(
+            client.get_identity().await,
+            Err(GetMyIdError::Timeout(d)) if d == Duration::from_millis(200)
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_metrics_sink() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+        use crate::RequestMetrics;
+        use std::sync::Mutex;
+
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&outcomes);
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .metrics(move |request: &RequestMetrics<'_>| {
+                assert!(request.connect_latency.unwrap() <= request.duration);
+                sink.lock().unwrap().push(request.outcome().to_string());
+            })
+            .build();
+
+        client.get_identity().await.unwrap();
+        daemon.respond_with(FakeResponse::malformed());
+        client.get_identity().await.unwrap_err();
+        assert_eq!(*outcomes.lock().unwrap(), ["ok", "invalid_response"]);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_ping() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        client.ping().await.unwrap();
+        assert_eq!(daemon.requests()[0].json().unwrap()["ping"], true);
+        daemon.respond_with(FakeResponse::error("E_INTERNAL", "database down"));
+        client.ping().await.unwrap_err();
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_daemon_info() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let info = r#"{"status":"info","daemon_version":"0.2.0","features":["keep_alive"]}"#;
+        let daemon = FakeDaemon::with_response(FakeResponse::Raw(info.to_string())).unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let info = client.daemon_info().await.unwrap();
+        assert!(info.supports("keep_alive"));
+        assert_eq!(info.version.release.as_deref(), Some("0.2.0"));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_get_identity_with_context() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let context = std::collections::BTreeMap::from([("tenant", "acme")]);
+        let identity = client.get_identity_with_context(&context).await.unwrap();
+        assert_eq!(identity.runner.extra["tenant"], "acme");
+
+        let result = client.get_identity_with_context(&["a", "b"]).await;
+        assert!(matches!(result, Err(GetMyIdError::InvalidJson { .. })));
+        assert_eq!(daemon.requests().len(), 1);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_require_peer_uid() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let uid = unsafe { libc::geteuid() };
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .require_peer_uid(uid)
+            .build();
+        assert!(client.get_identity().await.is_ok());
+
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .require_peer_uid(uid + 1)
+            .build();
+        assert!(matches!(
+            client.get_identity().await,
+            Err(GetMyIdError::UntrustedDaemon { uid: peer, .. }) if peer == uid
+        ));
+    }
+
+    #[cfg(all(feature = "signing", feature = "test-util"))]
+    #[tokio::test]
+    async fn test_async_daemon_public_key() {
+        use crate::protocol::DaemonResponse;
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let response = DaemonResponse::identity(FakeDaemon::sample_identity());
+        let response = serde_json::to_string(&response).unwrap();
+        let (signed, public_key) = crate::signing::sign(&response, &[7; 32]);
+        let daemon = FakeDaemon::with_response(FakeResponse::Raw(signed)).unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .daemon_public_key(public_key)
+            .build();
+        assert_eq!(client.get_identity().await.unwrap().token, "tok_test");
+        assert_eq!(daemon.requests()[0].json().unwrap()["sign"], true);
+
+        daemon.respond_with(FakeResponse::default());
+        assert!(matches!(
+            client.get_identity().await,
+            Err(GetMyIdError::InvalidSignature(_))
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_client_coalesces_lookups() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let delayed = FakeResponse::default().delayed(Duration::from_millis(100));
+        let daemon = FakeDaemon::with_response(delayed).unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+
+        let lookups: Vec<_> = (0..8)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.get_identity().await })
+            })
+            .collect();
+        for lookup in lookups {
+            assert_eq!(lookup.await.unwrap().unwrap().token, "tok_test");
+        }
+        assert_eq!(daemon.requests().len(), 1);
+
+        // Different runner context is not coalesced
+        let runner = RunnerRequest::new().with_instance_id(1);
+        let (a, b) = tokio::join!(
+            client.get_identity(),
+            client.get_identity_with_runner(Some(runner))
+        );
+        assert_eq!(a.unwrap().runner.instance_id, None);
+        assert_eq!(b.unwrap().runner.instance_id, Some(1));
+        assert_eq!(daemon.requests().len(), 3);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_client_from_std() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let stream = UnixStream::connect(daemon.socket_path()).unwrap();
+        let client = AsyncClient::from_std(stream);
+
+        let identity = client.get_identity().await.unwrap();
+        assert_eq!(identity.identity, "TEST_IDENTITY");
+        assert!(matches!(
+            client.get_identity().await,
+            Err(GetMyIdError::ConnectionFailed { .. })
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_async_get_identity_raw() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+
+        let raw = client.get_identity_raw().await.unwrap();
+        assert_eq!(raw.identity.identity, "TEST_IDENTITY");
+        assert_eq!(raw.json().unwrap()["token"], "tok_test");
+    }
 }
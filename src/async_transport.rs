@@ -0,0 +1,147 @@
+//! Asynchronous transports for the `AsyncClient`, mirroring the
+//! synchronous ones in the transport module.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::UnixStream;
+
+use crate::error::{GetMyIdError, Result};
+use crate::transport::{
+    check_peer_uid, connect_error, connect_path, resolve_socket_path, ConnectedTransport,
+    SymlinkPolicy,
+};
+
+/// A boxed future, as returned by [`AsyncTransport::dial`].
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A bidirectional asynchronous byte stream to the daemon.
+///
+/// Shutting down the writer (`AsyncWrite::poll_shutdown`) must signal the
+/// end of the request to the daemon. Implemented for every
+/// `AsyncRead + AsyncWrite + Send + Unpin` type, including
+/// `tokio::io::DuplexStream` for in-memory tests.
+pub trait AsyncConnection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncConnection for T {}
+
+/// Opens connections to the daemon for the `AsyncClient`.
+///
+/// The client applies its connect, read and write timeouts around the
+/// transport, so implementations do not need to.
+pub trait AsyncTransport: std::fmt::Debug + Send + Sync {
+    /// Open a new connection for a single request/response exchange.
+    fn dial(&self) -> BoxFuture<'_, Result<Box<dyn AsyncConnection>>>;
+}
+
+/// Asynchronous transport connecting to the daemon's Unix Domain Socket.
+///
+/// Paths starting with [`ABSTRACT_SOCKET_PREFIX`](crate::ABSTRACT_SOCKET_PREFIX)
+/// refer to Linux abstract namespace sockets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsyncUnixTransport {
+    path: PathBuf,
+    symlink_policy: SymlinkPolicy,
+    peer_uid: Option<u32>,
+}
+
+impl AsyncUnixTransport {
+    /// Create a transport for the socket at `path`.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            symlink_policy: SymlinkPolicy::Follow,
+            peer_uid: None,
+        }
+    }
+
+    /// Set how symlinks in the socket path are treated.
+    pub fn with_symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// Only talk to a daemon running as `uid`, checked with `SO_PEERCRED`
+    /// after connecting; see
+    /// [`AsyncClientBuilder::require_peer_uid`](crate::AsyncClientBuilder::require_peer_uid).
+    pub fn with_required_peer_uid(mut self, uid: impl Into<Option<u32>>) -> Self {
+        self.peer_uid = uid.into();
+        self
+    }
+
+    /// Get the socket path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the uid the daemon is required to run as, if any.
+    pub fn required_peer_uid(&self) -> Option<u32> {
+        self.peer_uid
+    }
+
+    /// Get how symlinks in the socket path are treated.
+    pub fn symlink_policy(&self) -> SymlinkPolicy {
+        self.symlink_policy
+    }
+}
+
+impl AsyncTransport for AsyncUnixTransport {
+    fn dial(&self) -> BoxFuture<'_, Result<Box<dyn AsyncConnection>>> {
+        Box::pin(async move {
+            // Check socket exists (abstract sockets have no filesystem entry)
+            let resolved = resolve_socket_path(&self.path, self.symlink_policy)?;
+            let path = resolved.as_deref().unwrap_or(&self.path);
+
+            let stream = UnixStream::connect(connect_path(path))
+                .await
+                .map_err(|e| connect_error(&self.path, resolved, e))?;
+            if let Some(expected) = self.peer_uid {
+                let uid = stream.peer_cred().map(|cred| cred.uid());
+                check_peer_uid(&self.path, uid, expected)?;
+            }
+            Ok(Box::new(stream) as Box<dyn AsyncConnection>)
+        })
+    }
+}
+
+/// Asynchronous transport using a connection to the daemon established
+/// by someone else; see [`ConnectedTransport`].
+///
+/// The connection serves a single exchange; later requests fail with
+/// [`GetMyIdError::ConnectionFailed`].
+#[derive(Debug)]
+pub struct AsyncConnectedTransport(ConnectedTransport);
+
+impl AsyncConnectedTransport {
+    /// Create a transport for the connected stream socket `stream`.
+    ///
+    /// The stream is registered with the tokio runtime when the client
+    /// first uses it, so this may be called outside a runtime.
+    pub fn new(stream: std::os::unix::net::UnixStream) -> Self {
+        Self(ConnectedTransport::new(stream))
+    }
+
+    /// Whether the connection has not been used yet.
+    pub fn is_unused(&self) -> bool {
+        self.0.is_unused()
+    }
+}
+
+impl AsyncTransport for AsyncConnectedTransport {
+    fn dial(&self) -> BoxFuture<'_, Result<Box<dyn AsyncConnection>>> {
+        Box::pin(async move {
+            let stream = self.0.take()?;
+            stream
+                .set_nonblocking(true)
+                .and_then(|()| UnixStream::from_std(stream))
+                .map(|stream| Box::new(stream) as Box<dyn AsyncConnection>)
+                .map_err(|source| GetMyIdError::ConnectionFailed {
+                    path: self.0.path.clone(),
+                    resolved: None,
+                    source,
+                })
+        })
+    }
+}
@@ -0,0 +1,178 @@
+//! `getmyid audit`: detect drift between the live identity and a manifest.
+
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use clap::Args;
+use getmyid::{Client, Identity};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Exit code when the live identity deviates from the expectations.
+const EXIT_DRIFT: u8 = 1;
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// Expected value, as `field=value`. Repeatable.
+    ///
+    /// Fields are top-level identity fields (`identity`, `idm_url`,
+    /// `config_url`, `token`), runner fields (`uid`, `hostname`, ...) or
+    /// dotted paths such as `runner.region`.
+    #[arg(long = "expect", value_name = "FIELD=VALUE", required = true)]
+    expectations: Vec<Expectation>,
+}
+
+/// A single `field=value` expectation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expectation {
+    field: String,
+    value: String,
+}
+
+impl FromStr for Expectation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((field, value)) if !field.is_empty() => Ok(Self {
+                field: field.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!("expected FIELD=VALUE, got '{s}'")),
+        }
+    }
+}
+
+/// A field whose live value differs from the expected one.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Mismatch {
+    field: String,
+    expected: String,
+    actual: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    status: &'static str,
+    checked: usize,
+    mismatches: Vec<Mismatch>,
+}
+
+pub fn run(client: &Client, args: &AuditArgs) -> super::CliResult {
+    let identity = client.get_identity()?;
+    let mismatches = compare(&identity, &args.expectations);
+
+    let report = Report {
+        status: if mismatches.is_empty() { "ok" } else { "drift" },
+        checked: args.expectations.len(),
+        mismatches,
+    };
+    println!("{}", super::to_json(&report));
+
+    Ok(if report.mismatches.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_DRIFT)
+    })
+}
+
+fn compare(identity: &Identity, expectations: &[Expectation]) -> Vec<Mismatch> {
+    let value = serde_json::to_value(identity).expect("identity serializes to JSON");
+
+    expectations
+        .iter()
+        .filter_map(|expectation| {
+            let actual = lookup(&value, &expectation.field).map(display);
+            (actual.as_deref() != Some(expectation.value.as_str())).then(|| Mismatch {
+                field: expectation.field.clone(),
+                expected: expectation.value.clone(),
+                actual,
+            })
+        })
+        .collect()
+}
+
+/// Resolve a field name against the identity, falling back to the runner
+/// object for bare runner field names.
+fn lookup<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    let path = |root: &'a Value| {
+        field
+            .split('.')
+            .try_fold(root, |value, segment| value.get(segment))
+    };
+    path(value).or_else(|| path(value.get("runner")?))
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use getmyid::Runner;
+
+    fn identity() -> Identity {
+        let mut runner = Runner {
+            identity: "BILLING_PROD".to_string(),
+            hostname: "worker-01".to_string(),
+            uid: 1001,
+            ..Runner::default()
+        };
+        runner.extra.insert("region".to_string(), "eu".into());
+        Identity::new("BILLING_PROD", "tok")
+            .with_idm_url("https://auth.example.com")
+            .with_config_url("https://config.example.com")
+            .with_runner(runner)
+    }
+
+    #[test]
+    fn test_parse_expectation() {
+        let e: Expectation = "uid=1001".parse().unwrap();
+        assert_eq!(e.field, "uid");
+        assert_eq!(e.value, "1001");
+        assert!("uid".parse::<Expectation>().is_err());
+        assert!("=1001".parse::<Expectation>().is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let expectations: Vec<Expectation> = [
+            "identity=BILLING_PROD",
+            "uid=1000",
+            "runner.region=eu",
+            "region=us",
+            "missing=x",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+
+        let mismatches = compare(&identity(), &expectations);
+
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch {
+                    field: "uid".to_string(),
+                    expected: "1000".to_string(),
+                    actual: Some("1001".to_string()),
+                },
+                Mismatch {
+                    field: "region".to_string(),
+                    expected: "us".to_string(),
+                    actual: Some("eu".to_string()),
+                },
+                Mismatch {
+                    field: "missing".to_string(),
+                    expected: "x".to_string(),
+                    actual: None,
+                },
+            ]
+        );
+    }
+}
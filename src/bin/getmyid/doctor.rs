@@ -0,0 +1,80 @@
+//! `getmyid doctor`: step-by-step diagnosis of daemon connectivity.
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::process::ExitCode;
+
+use getmyid::{capabilities, Client};
+
+use crate::style::Style;
+
+/// Exit code when at least one check fails.
+const EXIT_FAILED: u8 = 1;
+
+pub fn run(client: &Client, style: Style) -> ExitCode {
+    let path = client.socket_path();
+    let mut failed = false;
+    let mut check = |ok: bool, label: &str, detail: String| {
+        let mark = if ok {
+            style.green("ok  ")
+        } else {
+            style.red("FAIL")
+        };
+        println!("[{mark}] {label:<16} {detail}");
+        failed |= !ok;
+        ok
+    };
+
+    let features = capabilities().features();
+    check(
+        true,
+        "features",
+        style.dim(if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }),
+    );
+
+    let metadata = fs::metadata(path);
+    let exists = check(
+        metadata.is_ok(),
+        "socket exists",
+        path.display().to_string(),
+    );
+    if let Ok(metadata) = metadata {
+        let is_socket = metadata.file_type().is_socket();
+        check(
+            is_socket,
+            "is a socket",
+            style.dim(if is_socket { "socket" } else { "not a socket" }),
+        );
+        check(
+            true,
+            "ownership",
+            format!(
+                "uid={} gid={} mode={:o}",
+                metadata.uid(),
+                metadata.gid(),
+                metadata.permissions().mode() & 0o7777
+            ),
+        );
+    }
+
+    if exists {
+        match client.get_identity() {
+            Ok(identity) => {
+                check(true, "identity", style.green(&identity.identity));
+            }
+            Err(e) => {
+                check(false, "identity", style.red(e));
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::from(EXIT_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
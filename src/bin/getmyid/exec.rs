@@ -0,0 +1,88 @@
+//! `getmyid exec`: run a command with the identity in its environment.
+
+use std::ffi::OsString;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use clap::Args;
+use getmyid::{Client, Identity, RunnerRequest, DEFAULT_ENV_PREFIX};
+
+use crate::CliResult;
+
+#[derive(Debug, Args)]
+pub struct ExecArgs {
+    /// Prefix of the exported variables.
+    #[arg(long, default_value = DEFAULT_ENV_PREFIX)]
+    prefix: String,
+
+    /// Instance identifier sent as runner context.
+    #[arg(long, value_name = "ID")]
+    instance_id: Option<u64>,
+
+    /// Command to run, with its arguments.
+    #[arg(required = true, trailing_var_arg = true, value_name = "COMMAND")]
+    command: Vec<OsString>,
+}
+
+/// Resolve the identity, then replace this process with the command.
+///
+/// The command keeps this process's PID, so the identity it receives is the
+/// one the daemon resolved for that PID. Returns only if the command cannot
+/// be started.
+pub fn run(client: &Client, args: &ExecArgs) -> CliResult {
+    let runner = args
+        .instance_id
+        .map(|id| RunnerRequest::new().with_instance_id(id));
+    let identity = client.get_identity_with_runner(runner)?;
+
+    let (program, rest) = args.command.split_first().ok_or("no command given")?;
+    let err = Command::new(program)
+        .args(rest)
+        .envs(identity_vars(&identity, &args.prefix))
+        .exec();
+    Err(format!("cannot run {}: {err}", program.to_string_lossy()).into())
+}
+
+/// Variables exported to the command, readable back with `EnvProvider`.
+fn identity_vars(identity: &Identity, prefix: &str) -> Vec<(String, String)> {
+    let mut vars = vec![
+        ("IDENTITY", identity.identity.clone()),
+        ("IDM_URL", identity.idm_url.clone()),
+        ("CONFIG_URL", identity.config_url.clone()),
+        ("TOKEN", identity.token.to_string()),
+        ("HOSTNAME", identity.runner.hostname.clone()),
+    ];
+    if let Some(instance_id) = identity.runner.instance_id {
+        vars.push(("INSTANCE_ID", instance_id.to_string()));
+    }
+    if let Some(expires_at) = identity.expires_at {
+        vars.push(("EXPIRES_AT", expires_at.to_string()));
+    }
+    vars.into_iter()
+        .map(|(name, value)| (format!("{prefix}{name}"), value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use getmyid::Runner;
+
+    #[test]
+    fn test_identity_vars() {
+        let identity = Identity::new("BILLING_PROD", "tok_xxx")
+            .with_idm_url("https://auth.example.com")
+            .with_config_url("https://config.example.com")
+            .with_runner(Runner {
+                hostname: "worker-01".to_string(),
+                instance_id: Some(7),
+                ..Runner::default()
+            });
+
+        let vars = identity_vars(&identity, "APP_");
+        assert!(vars.contains(&("APP_IDENTITY".to_string(), "BILLING_PROD".to_string())));
+        assert!(vars.contains(&("APP_TOKEN".to_string(), "tok_xxx".to_string())));
+        assert!(vars.contains(&("APP_INSTANCE_ID".to_string(), "7".to_string())));
+        assert_eq!(vars.len(), 6);
+    }
+}
@@ -0,0 +1,204 @@
+//! Command-line interface for the whoami daemon (requires `cli` feature).
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use getmyid::{Client, Identity, RunnerRequest, DEFAULT_SOCKET_PATH};
+
+use crate::style::{mask_token, print_rows, ColorChoice, Style};
+
+mod audit;
+mod doctor;
+mod exec;
+mod ping;
+mod style;
+mod support;
+mod watch;
+
+/// Exit code for failures to obtain an identity.
+const EXIT_ERROR: u8 = 2;
+
+/// Outcome of a subcommand: its exit code, or an error reported on stderr.
+type CliResult = Result<ExitCode, Box<dyn std::error::Error>>;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "getmyid",
+    version,
+    about = "Query the whoami Identity-by-PID daemon"
+)]
+struct Cli {
+    /// Path to the whoami daemon socket.
+    #[arg(long, global = true, default_value = DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
+
+    /// Timeout in milliseconds for each phase of the exchange.
+    #[arg(long, global = true, value_name = "MS", default_value_t = 5000)]
+    timeout: u64,
+
+    /// When to color the output.
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the identity of this process.
+    Id(IdArgs),
+    /// Compare the live identity against expected values.
+    Audit(audit::AuditArgs),
+    /// Diagnose connectivity to the daemon step by step.
+    Doctor,
+    /// Check within a bounded time that the daemon resolves an identity.
+    Ping(ping::PingArgs),
+    /// Run a command with the identity exported as WHOAMI_* variables.
+    Exec(exec::ExecArgs),
+    /// Print a line whenever the identity, token or URLs change.
+    Watch(watch::WatchArgs),
+    /// Collect redacted diagnostics to attach to bug reports.
+    SupportBundle(support::SupportArgs),
+}
+
+#[derive(Debug, Args)]
+struct IdArgs {
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+
+    /// Instance identifier sent as runner context.
+    #[arg(long, value_name = "ID")]
+    instance_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Machine-readable JSON, including the full token.
+    Json,
+    /// Aligned, human-readable text with the token masked.
+    Text,
+    /// YAML, for configuration pipelines and Ansible.
+    Yaml,
+    /// TOML, for configuration pipelines and Terraform external data.
+    Toml,
+}
+
+impl Cli {
+    fn client(&self) -> Client {
+        Client::builder()
+            .socket_path(&self.socket)
+            .timeout(Duration::from_millis(self.timeout))
+            .build()
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let client = cli.client();
+    let style = Style::stdout(cli.color);
+
+    let result = match &cli.command {
+        Command::Id(args) => print_identity(&client, args, style),
+        Command::Audit(args) => audit::run(&client, args),
+        Command::Doctor => Ok(doctor::run(&client, style)),
+        Command::Ping(args) => Ok(ping::run(&cli.socket, args, style)),
+        Command::Exec(args) => exec::run(&client, args),
+        Command::Watch(args) => watch::run(&client, args, style),
+        Command::SupportBundle(args) => support::run(&client, args),
+    };
+
+    result.unwrap_or_else(|e| {
+        let style = Style::stderr(cli.color);
+        eprintln!("{} {e}", style.red("error:"));
+        ExitCode::from(EXIT_ERROR)
+    })
+}
+
+fn print_identity(client: &Client, args: &IdArgs, style: Style) -> CliResult {
+    let runner = args
+        .instance_id
+        .map(|id| RunnerRequest::new().with_instance_id(id));
+    let identity = client.get_identity_with_runner(runner)?;
+    match args.format {
+        Format::Json => println!("{}", to_json(&identity)),
+        Format::Text => print_identity_text(&identity, style),
+        Format::Yaml => print!("{}", serde_yaml::to_string(&identity)?),
+        Format::Toml => print!("{}", toml::to_string(&identity)?),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_identity_text(identity: &Identity, style: Style) {
+    let runner = &identity.runner;
+    print_rows(
+        0,
+        &[
+            ("Identity", style.bold(style.green(&identity.identity))),
+            ("IDM URL", identity.idm_url.clone()),
+            ("Config URL", identity.config_url.clone()),
+            ("Token", style.dim(mask_token(&identity.token))),
+        ],
+    );
+    println!("Runner:");
+    let mut rows = vec![
+        ("Hostname", runner.hostname.clone()),
+        ("Process", runner.process.clone()),
+        ("PID", runner.pid.to_string()),
+        ("UID", runner.uid.to_string()),
+        ("GID", runner.gid.to_string()),
+    ];
+    if let Some(client_hostname) = &runner.client_hostname {
+        rows.push(("Client hostname", client_hostname.clone()));
+    }
+    if let Some(instance_id) = runner.instance_id {
+        rows.push(("Instance ID", instance_id.to_string()));
+    }
+    if let Some(timestamp) = runner.timestamp {
+        rows.push(("Timestamp", timestamp.to_string()));
+    }
+    print_rows(2, &rows);
+}
+
+/// Parse a duration such as `250ms`, `2s` or `1m`; bare numbers are milliseconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let invalid = || format!("invalid duration '{s}'");
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+    match unit {
+        "" | "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => value
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(invalid),
+        _ => Err(format!("invalid duration unit in '{s}' (use ms, s or m)")),
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).expect("identity serializes to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("200ms"), Ok(Duration::from_millis(200)));
+        assert_eq!(parse_duration("150"), Ok(Duration::from_millis(150)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert!(parse_duration("fast").is_err());
+        assert!(parse_duration("5h").is_err());
+        assert_eq!(
+            parse_duration("400000000000000000m"),
+            Err("invalid duration '400000000000000000m'".to_string())
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! `getmyid ping`: bounded-time liveness check for container probes.
+
+use std::process::ExitCode;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use getmyid::Client;
+
+use crate::style::Style;
+
+/// Exit code when the daemon did not answer with an identity in time.
+const EXIT_UNHEALTHY: u8 = 1;
+
+#[derive(Debug, Args)]
+pub struct PingArgs {
+    /// Upper bound on the whole check, e.g. `200ms` or `1s`.
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = super::parse_duration)]
+    max_wait: Duration,
+
+    /// Print nothing; report only through the exit code.
+    #[arg(long, short)]
+    quiet: bool,
+}
+
+pub fn run(socket: &std::path::Path, args: &PingArgs, style: Style) -> ExitCode {
+    let client = Client::builder()
+        .socket_path(socket)
+        .timeout(args.max_wait)
+        .build();
+
+    // The per-phase timeouts could add up; the channel deadline bounds the
+    // check as a whole. A straggling exchange dies with the process.
+    let (tx, rx) = mpsc::sync_channel(1);
+    let start = Instant::now();
+    thread::spawn(move || {
+        let _ = tx.send(client.get_identity());
+    });
+    let result = rx.recv_timeout(args.max_wait);
+    let elapsed = start.elapsed();
+
+    let (healthy, detail) = match result {
+        Ok(Ok(identity)) => (true, identity.identity.clone()),
+        Ok(Err(e)) => (false, e.to_string()),
+        Err(_) => (false, format!("no answer within {:?}", args.max_wait)),
+    };
+
+    if !args.quiet {
+        if healthy {
+            println!("{} {detail} in {elapsed:.1?}", style.green("ok"));
+        } else {
+            println!("{} {detail}", style.red("unhealthy"));
+        }
+    }
+
+    if healthy {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_UNHEALTHY)
+    }
+}
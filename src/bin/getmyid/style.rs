@@ -0,0 +1,115 @@
+//! Terminal styling honoring `--color` and `NO_COLOR`.
+
+use std::env;
+use std::fmt::Display;
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+
+/// When to emit ANSI colors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal and `NO_COLOR` is unset.
+    #[default]
+    Auto,
+    /// Always color, even when piped or `NO_COLOR` is set.
+    Always,
+    /// Never color.
+    Never,
+}
+
+/// Styling for one output stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    /// Styling for standard output.
+    pub fn stdout(choice: ColorChoice) -> Self {
+        Self::new(choice, std::io::stdout().is_terminal())
+    }
+
+    /// Styling for standard error.
+    pub fn stderr(choice: ColorChoice) -> Self {
+        Self::new(choice, std::io::stderr().is_terminal())
+    }
+
+    fn new(choice: ColorChoice, is_terminal: bool) -> Self {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && !no_color,
+        };
+        Self { enabled }
+    }
+
+    pub fn green(self, text: impl Display) -> String {
+        self.paint("32", text)
+    }
+
+    pub fn red(self, text: impl Display) -> String {
+        self.paint("31", text)
+    }
+
+    pub fn bold(self, text: impl Display) -> String {
+        self.paint("1", text)
+    }
+
+    pub fn dim(self, text: impl Display) -> String {
+        self.paint("2", text)
+    }
+
+    fn paint(self, code: &str, text: impl Display) -> String {
+        if self.enabled {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Mask a token for display, keeping a short prefix for recognition.
+pub fn mask_token(token: &str) -> String {
+    const VISIBLE: usize = 4;
+    match token.char_indices().nth(VISIBLE) {
+        Some((end, _)) if token.chars().count() > 2 * VISIBLE => {
+            format!("{}{}", &token[..end], "*".repeat(8))
+        }
+        _ if token.is_empty() => String::new(),
+        _ => "*".repeat(8),
+    }
+}
+
+/// Print `label: value` rows with the values aligned.
+pub fn print_rows(indent: usize, rows: &[(&str, String)]) {
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0) + 1;
+    for (label, value) in rows {
+        println!("{:indent$}{:<width$} {value}", "", format!("{label}:"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_choice() {
+        assert!(Style::new(ColorChoice::Always, false).enabled);
+        assert!(!Style::new(ColorChoice::Never, true).enabled);
+        assert!(!Style::new(ColorChoice::Auto, false).enabled);
+        assert_eq!(Style::new(ColorChoice::Never, true).green("x"), "x");
+        assert_eq!(
+            Style::new(ColorChoice::Always, false).red("x"),
+            "\x1b[31mx\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_mask_token() {
+        assert_eq!(mask_token("tok_billing_xxx"), "tok_********");
+        assert_eq!(mask_token("short"), "********");
+        assert_eq!(mask_token(""), "");
+    }
+}
@@ -0,0 +1,31 @@
+//! `getmyid support-bundle`: collect diagnostics to attach to bug reports.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Args;
+use getmyid::{Client, SupportBundle};
+
+use crate::CliResult;
+
+#[derive(Debug, Args)]
+pub struct SupportArgs {
+    /// Write the bundle to this file instead of stdout.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+/// Print or save the bundle. A failing daemon is part of the report, so the
+/// exit code is nonzero only if the bundle cannot be written.
+pub fn run(client: &Client, args: &SupportArgs) -> CliResult {
+    let json = SupportBundle::collect(client).to_json();
+    match &args.output {
+        Some(path) => {
+            fs::write(path, json + "\n")?;
+            eprintln!("support bundle written to {}", path.display());
+        }
+        None => println!("{json}"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
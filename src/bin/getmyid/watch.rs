@@ -0,0 +1,200 @@
+//! `getmyid watch`: report changes to the resolved identity.
+
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::Args;
+use getmyid::{Client, GetMyIdError, Identity, RunnerRequest};
+
+use crate::style::Style;
+use crate::CliResult;
+
+/// Fields whose changes are reported.
+const WATCHED: [&str; 4] = ["identity", "idm_url", "config_url", "token"];
+
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+    /// Time between polls, e.g. `500ms`, `5s` or `1m`.
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = super::parse_duration)]
+    interval: Duration,
+
+    /// Print one JSON object per line instead of text.
+    #[arg(long)]
+    json: bool,
+
+    /// Instance identifier sent as runner context.
+    #[arg(long, value_name = "ID")]
+    instance_id: Option<u64>,
+}
+
+/// Poll the daemon every interval and print a line whenever the identity,
+/// its token or its URLs change, or the daemon starts failing.
+///
+/// Runs until interrupted.
+pub fn run(client: &Client, args: &WatchArgs, style: Style) -> CliResult {
+    let runner = args
+        .instance_id
+        .map(|id| RunnerRequest::new().with_instance_id(id));
+    let mut watcher = Watcher::default();
+    loop {
+        let result = client.get_identity_with_runner(runner.clone());
+        if let Some(event) = watcher.observe(result) {
+            if args.json {
+                println!("{}", event.to_json_line());
+            } else {
+                println!("{}", event.to_text(style));
+            }
+        }
+        thread::sleep(args.interval);
+    }
+}
+
+/// Something worth reporting about a poll.
+#[derive(Debug, PartialEq)]
+enum Event<'a> {
+    /// An identity was resolved for the first time, after a failure, or with
+    /// the listed fields changed.
+    Resolved {
+        identity: &'a Identity,
+        changed: Vec<&'static str>,
+    },
+    /// The daemon failed, with a different error than the previous poll.
+    Failed(String),
+}
+
+impl Event<'_> {
+    fn to_text(&self, style: Style) -> String {
+        match self {
+            Event::Resolved { identity, changed } if changed.len() == WATCHED.len() => {
+                format!("{} resolved", style.green(&identity.identity))
+            }
+            Event::Resolved { identity, changed } if changed.is_empty() => {
+                format!("{} resolved again", style.green(&identity.identity))
+            }
+            Event::Resolved { identity, changed } => format!(
+                "{} changed: {}",
+                style.green(&identity.identity),
+                changed.join(", ")
+            ),
+            Event::Failed(message) => format!("{} {message}", style.red("unavailable:")),
+        }
+    }
+
+    fn to_json_line(&self) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let line = match self {
+            Event::Resolved { identity, changed } => serde_json::json!({
+                "timestamp": timestamp,
+                "event": "resolved",
+                "changed": changed,
+                "identity": identity,
+            }),
+            Event::Failed(message) => serde_json::json!({
+                "timestamp": timestamp,
+                "event": "error",
+                "message": message,
+            }),
+        };
+        line.to_string()
+    }
+}
+
+/// Remembers the last poll to turn results into change events.
+#[derive(Debug, Default)]
+struct Watcher {
+    identity: Option<Identity>,
+    error: Option<String>,
+}
+
+impl Watcher {
+    fn observe(&mut self, result: Result<Identity, GetMyIdError>) -> Option<Event<'_>> {
+        match result {
+            Ok(identity) => {
+                let changed = match &self.identity {
+                    Some(previous) => changed_fields(previous, &identity),
+                    None => WATCHED.to_vec(),
+                };
+                let recovered = self.error.take().is_some();
+                let identity = self.identity.insert(identity);
+                (recovered || !changed.is_empty()).then_some(Event::Resolved { identity, changed })
+            }
+            Err(e) => {
+                let message = e.to_string();
+                if self.error.as_ref() == Some(&message) {
+                    return None;
+                }
+                self.error = Some(message.clone());
+                Some(Event::Failed(message))
+            }
+        }
+    }
+}
+
+/// Names of the watched fields that differ between `old` and `new`.
+fn changed_fields(old: &Identity, new: &Identity) -> Vec<&'static str> {
+    let pairs = [
+        (old.identity.as_str(), new.identity.as_str()),
+        (old.idm_url.as_str(), new.idm_url.as_str()),
+        (old.config_url.as_str(), new.config_url.as_str()),
+        (old.token.as_str(), new.token.as_str()),
+    ];
+    WATCHED
+        .into_iter()
+        .zip(pairs)
+        .filter(|(_, (old, new))| old != new)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(token: &str) -> Identity {
+        Identity::new("BILLING_PROD", token)
+            .with_idm_url("https://auth.example.com")
+            .with_config_url("https://config.example.com")
+    }
+
+    #[test]
+    fn test_watcher_reports_changes() {
+        let mut watcher = Watcher::default();
+
+        assert!(matches!(
+            watcher.observe(Ok(identity("a"))),
+            Some(Event::Resolved { changed, .. }) if changed == WATCHED
+        ));
+        assert_eq!(watcher.observe(Ok(identity("a"))), None);
+        assert!(matches!(
+            watcher.observe(Ok(identity("b"))),
+            Some(Event::Resolved { changed, .. }) if changed == ["token"]
+        ));
+
+        let unreachable = || Err(GetMyIdError::SocketNotFound("/nonexistent".into()));
+        assert!(matches!(
+            watcher.observe(unreachable()),
+            Some(Event::Failed(_))
+        ));
+        assert_eq!(watcher.observe(unreachable()), None);
+        assert!(matches!(
+            watcher.observe(Ok(identity("b"))),
+            Some(Event::Resolved { changed, .. }) if changed.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_event_json_line() {
+        let identity = identity("a");
+        let event = Event::Resolved {
+            identity: &identity,
+            changed: vec!["token"],
+        };
+        let line: serde_json::Value = serde_json::from_str(&event.to_json_line()).unwrap();
+
+        assert_eq!(line["event"], "resolved");
+        assert_eq!(line["changed"], serde_json::json!(["token"]));
+        assert_eq!(line["identity"]["identity"], "BILLING_PROD");
+    }
+}
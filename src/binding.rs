@@ -0,0 +1,208 @@
+//! Fingerprints binding a token to the process it was issued to.
+//!
+//! A client forwards [`Identity::binding_fingerprint`] alongside its token;
+//! a downstream service checks it with a [`BindingVerifier`]. A token
+//! replayed from another process or host arrives with a different
+//! fingerprint and is flagged. The fingerprint is not a secret and does not
+//! authenticate the caller: it only helps detect tokens that leaked.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::procfs;
+use crate::types::Identity;
+
+/// Default number of tokens a [`BindingVerifier`] remembers.
+pub const DEFAULT_BINDING_CAPACITY: usize = 10_000;
+
+/// Version prefix of fingerprints, changed whenever their inputs or hash
+/// change.
+const FINGERPRINT_VERSION: &str = "v1";
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+impl Identity {
+    /// Fingerprint of the process holding this identity, for leak
+    /// detection by downstream services.
+    ///
+    /// Combines the start time of the calling process with the pid, uid and
+    /// hostname reported by the daemon into a 64-bit FNV-1a hash, formatted
+    /// as `v1:` followed by 16 hex digits. The start time tells a restarted
+    /// process reusing the pid apart. Returns `None` if the start time
+    /// cannot be read from `/proc`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let identity = getmyid::get_identity()?;
+    /// if let Some(fingerprint) = identity.binding_fingerprint() {
+    ///     // Send as e.g. an `X-Token-Binding` header next to the token
+    ///     println!("{fingerprint}");
+    /// }
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn binding_fingerprint(&self) -> Option<String> {
+        Some(fingerprint(
+            procfs::start_time()?,
+            self.runner.pid,
+            self.runner.uid,
+            &self.runner.hostname,
+        ))
+    }
+}
+
+/// Hash the binding inputs into a fingerprint.
+fn fingerprint(start_time: u64, pid: u32, uid: u32, hostname: &str) -> String {
+    let input = format!("{start_time}\0{pid}\0{uid}\0{hostname}");
+    format!("{FINGERPRINT_VERSION}:{:016x}", fnv1a(input.as_bytes()))
+}
+
+/// 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Remembers the binding fingerprint each token was first presented with,
+/// for downstream services receiving forwarded fingerprints.
+///
+/// Tokens are kept as hashes, never in the clear. Once
+/// [`capacity`](BindingVerifier::with_capacity) tokens are remembered, the
+/// oldest is forgotten for each new one.
+///
+/// # Example
+///
+/// ```
+/// use getmyid::BindingVerifier;
+///
+/// let verifier = BindingVerifier::new();
+/// assert!(verifier.verify("tok_abc", "v1:0123456789abcdef"));
+/// assert!(verifier.verify("tok_abc", "v1:0123456789abcdef"));
+/// // The same token replayed from elsewhere
+/// assert!(!verifier.verify("tok_abc", "v1:fedcba9876543210"));
+/// ```
+#[derive(Debug)]
+pub struct BindingVerifier {
+    capacity: usize,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    fingerprints: HashMap<u64, String>,
+    /// Token hashes, oldest first.
+    order: VecDeque<u64>,
+}
+
+impl Default for BindingVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BindingVerifier {
+    /// Create a verifier remembering up to [`DEFAULT_BINDING_CAPACITY`]
+    /// tokens.
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_BINDING_CAPACITY)
+    }
+
+    /// Create a verifier remembering up to `capacity` tokens.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: Mutex::default(),
+        }
+    }
+
+    /// Check that `token` is presented with the fingerprint it was first
+    /// seen with.
+    ///
+    /// Returns `true` the first time a token is seen, remembering its
+    /// fingerprint, and whenever the fingerprint matches; `false` if the
+    /// token was first presented by another process or host.
+    pub fn verify(&self, token: &str, fingerprint: &str) -> bool {
+        let key = fnv1a(token.as_bytes());
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(expected) = seen.fingerprints.get(&key) {
+            return expected == fingerprint;
+        }
+        if self.capacity == 0 {
+            return true;
+        }
+        if seen.order.len() == self.capacity {
+            if let Some(oldest) = seen.order.pop_front() {
+                seen.fingerprints.remove(&oldest);
+            }
+        }
+        seen.order.push_back(key);
+        seen.fingerprints.insert(key, fingerprint.to_string());
+        true
+    }
+
+    /// Forget the fingerprint of `token`, e.g. once it was revoked.
+    pub fn forget(&self, token: &str) {
+        let key = fnv1a(token.as_bytes());
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if seen.fingerprints.remove(&key).is_some() {
+            seen.order.retain(|k| *k != key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Runner;
+
+    #[test]
+    fn test_binding_fingerprint() {
+        assert_eq!(fnv1a(b""), FNV_OFFSET);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        let base = fingerprint(98765, 1234, 1001, "worker-01");
+        assert!(base.starts_with("v1:"));
+        assert_eq!(base.len(), "v1:".len() + 16);
+        assert_eq!(base, fingerprint(98765, 1234, 1001, "worker-01"));
+        assert_ne!(base, fingerprint(98766, 1234, 1001, "worker-01"));
+        assert_ne!(base, fingerprint(98765, 1234, 0, "worker-01"));
+        assert_ne!(base, fingerprint(98765, 1234, 1001, "worker-02"));
+
+        let identity = Identity {
+            identity: "BILLING_PROD".to_string(),
+            idm_url: String::new(),
+            config_url: String::new(),
+            token: "tok".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner {
+                pid: std::process::id(),
+                hostname: "worker-01".to_string(),
+                ..Runner::default()
+            },
+        };
+        let fingerprint = identity.binding_fingerprint().unwrap();
+        assert_eq!(identity.binding_fingerprint(), Some(fingerprint));
+    }
+
+    #[test]
+    fn test_binding_verifier() {
+        let verifier = BindingVerifier::with_capacity(2);
+        assert!(verifier.verify("tok_a", "v1:a"));
+        assert!(verifier.verify("tok_a", "v1:a"));
+        assert!(!verifier.verify("tok_a", "v1:b"));
+
+        verifier.forget("tok_a");
+        assert!(verifier.verify("tok_a", "v1:b"));
+
+        assert!(verifier.verify("tok_b", "v1:b"));
+        assert!(verifier.verify("tok_c", "v1:c"));
+        // tok_a was evicted as the oldest
+        assert!(verifier.verify("tok_a", "v1:other"));
+        assert!(!verifier.verify("tok_c", "v1:other"));
+
+        assert!(BindingVerifier::with_capacity(0).verify("tok_a", "v1:a"));
+    }
+}
@@ -0,0 +1,199 @@
+//! Decoding of inlined bootstrap configuration (requires `bootstrap` feature).
+//!
+//! Daemons may inline a small configuration blob in the identity response so
+//! that services with tiny configs skip the round trip to `config_url`. The
+//! blob is JSON, gzip-compressed and base64-encoded, with a SHA-256 digest of
+//! the JSON; [`Identity::bootstrap_config`] undoes the encoding, checks the
+//! digest and deserializes the result.
+//!
+//! # Example
+//!
+//! ```no_run
+//! #[derive(serde::Deserialize)]
+//! struct AppConfig {
+//!     database_url: String,
+//! }
+//!
+//! let identity = getmyid::get_identity()?;
+//! if let Some(config) = identity.bootstrap_config::<AppConfig>()? {
+//!     println!("database: {}", config.database_url);
+//! }
+//! # Ok::<(), getmyid::GetMyIdError>(())
+//! ```
+
+use std::io::Read;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use ring::digest::{digest, SHA256};
+use serde::de::DeserializeOwned;
+
+use crate::error::{GetMyIdError, Result};
+use crate::types::{BootstrapPayload, Identity};
+
+/// Largest decompressed payload [`Identity::bootstrap_config`] accepts.
+pub const MAX_BOOTSTRAP_SIZE: usize = 64 * 1024;
+
+/// The only payload encoding defined by the protocol.
+const GZIP_BASE64: &str = "gzip+base64";
+
+impl Identity {
+    /// Decode, verify and deserialize the configuration inlined by the
+    /// daemon, accepting up to [`MAX_BOOTSTRAP_SIZE`] bytes once
+    /// decompressed.
+    ///
+    /// Returns `Ok(None)` if the daemon inlined no configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidBootstrapConfig`] if the encoding is
+    /// unknown, the payload cannot be decoded, is too large or fails the
+    /// checksum, and [`GetMyIdError::InvalidJson`] if it does not
+    /// deserialize into `T`.
+    pub fn bootstrap_config<T: DeserializeOwned>(&self) -> Result<Option<T>> {
+        self.bootstrap_config_with_limit(MAX_BOOTSTRAP_SIZE)
+    }
+
+    /// Like [`bootstrap_config`](Identity::bootstrap_config), accepting up
+    /// to `limit` bytes once decompressed.
+    ///
+    /// # Errors
+    ///
+    /// See [`bootstrap_config`](Identity::bootstrap_config).
+    pub fn bootstrap_config_with_limit<T: DeserializeOwned>(
+        &self,
+        limit: usize,
+    ) -> Result<Option<T>> {
+        let Some(payload) = &self.bootstrap else {
+            return Ok(None);
+        };
+        let json = decode(payload, limit)?;
+        serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(GetMyIdError::invalid_json)
+    }
+}
+
+/// Decode `payload` into the bytes it carries, checking its size and digest.
+fn decode(payload: &BootstrapPayload, limit: usize) -> Result<Vec<u8>> {
+    if payload.encoding != GZIP_BASE64 {
+        return Err(invalid(format!(
+            "unsupported encoding '{}'",
+            payload.encoding
+        )));
+    }
+    let compressed = STANDARD
+        .decode(payload.data.trim())
+        .map_err(|e| invalid(format!("invalid base64: {e}")))?;
+
+    // Read one byte past the limit to tell a full payload from an oversized one
+    let mut json = Vec::new();
+    GzDecoder::new(compressed.as_slice())
+        .take(limit as u64 + 1)
+        .read_to_end(&mut json)
+        .map_err(|e| invalid(format!("invalid gzip data: {e}")))?;
+    if json.len() > limit {
+        return Err(invalid(format!("payload exceeds the {limit} byte limit")));
+    }
+
+    let actual = hex(digest(&SHA256, &json).as_ref());
+    if !actual.eq_ignore_ascii_case(payload.sha256.trim()) {
+        return Err(invalid(format!(
+            "checksum mismatch: expected {}, got {actual}",
+            payload.sha256
+        )));
+    }
+    Ok(json)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn invalid(message: impl Into<String>) -> GetMyIdError {
+    GetMyIdError::InvalidBootstrapConfig(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::parse_response;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use serde::Deserialize;
+    use std::io::Write;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct AppConfig {
+        database_url: String,
+        pool_size: u32,
+    }
+
+    fn payload(json: &[u8]) -> BootstrapPayload {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json).unwrap();
+        BootstrapPayload {
+            encoding: GZIP_BASE64.to_string(),
+            data: STANDARD.encode(encoder.finish().unwrap()),
+            sha256: hex(digest(&SHA256, json).as_ref()),
+        }
+    }
+
+    fn response(payload: &BootstrapPayload) -> String {
+        serde_json::json!({
+            "status": "ok",
+            "identity": "BILLING_PROD",
+            "idm_url": "",
+            "config_url": "",
+            "token": "tok",
+            "bootstrap": payload,
+            "runner": {},
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_bootstrap_config() {
+        let json = br#"{"database_url":"postgres://db/billing","pool_size":4}"#;
+        let identity = parse_response(&response(&payload(json))).unwrap();
+
+        assert_eq!(
+            identity.bootstrap_config::<AppConfig>().unwrap(),
+            Some(AppConfig {
+                database_url: "postgres://db/billing".to_string(),
+                pool_size: 4,
+            })
+        );
+
+        let mut identity = identity;
+        identity.bootstrap = None;
+        assert_eq!(identity.bootstrap_config::<AppConfig>().unwrap(), None);
+    }
+
+    #[test]
+    fn test_bootstrap_config_rejected() {
+        let json = br#"{"database_url":"postgres://db/billing","pool_size":4}"#;
+        let decode_err = |payload: BootstrapPayload, limit| {
+            let identity = parse_response(&response(&payload)).unwrap();
+            identity
+                .bootstrap_config_with_limit::<AppConfig>(limit)
+                .unwrap_err()
+                .to_string()
+        };
+
+        let mut tampered = payload(json);
+        tampered.sha256 = hex(&[0; 32]);
+        assert!(decode_err(tampered, MAX_BOOTSTRAP_SIZE).contains("checksum mismatch"));
+
+        assert!(decode_err(payload(json), 16).contains("16 byte limit"));
+
+        let mut zstd = payload(json);
+        zstd.encoding = "zstd+base64".to_string();
+        assert!(decode_err(zstd, MAX_BOOTSTRAP_SIZE).contains("unsupported encoding"));
+
+        let mut garbage = payload(json);
+        garbage.data = STANDARD.encode(b"not gzip");
+        assert!(decode_err(garbage, MAX_BOOTSTRAP_SIZE).contains("invalid gzip"));
+    }
+}
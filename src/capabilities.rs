@@ -0,0 +1,181 @@
+//! What this build of the crate supports.
+
+use crate::error::{GetMyIdError, Result};
+
+/// Features and protocol modes compiled into this build.
+///
+/// Frameworks embedding this crate can use it to adapt their behavior, or
+/// to fail early with a clear message when an optional integration is
+/// missing.
+///
+/// # Example
+///
+/// ```
+/// let caps = getmyid::capabilities();
+/// if !caps.tokio {
+///     eprintln!("async identity lookups unavailable; enable the `tokio` feature");
+/// }
+/// assert!(caps.features().iter().all(|name| caps.require(name).is_ok()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// `AsyncClient` and the async transports (`tokio` feature).
+    pub tokio: bool,
+    /// `TcpTlsTransport` for remote daemons (`tls` feature).
+    pub tls: bool,
+    /// `CloudMetadataProvider` (`cloud` feature).
+    pub cloud: bool,
+    /// Loading settings from TOML files (`config-file` feature).
+    pub config_file: bool,
+    /// `testing::FakeDaemon` (`test-util` feature).
+    pub test_util: bool,
+    /// Built with the `cli` feature used by the `getmyid` binary.
+    pub cli: bool,
+    /// Experimental APIs outside the semver guarantees (`unstable` feature).
+    pub unstable: bool,
+    /// Inspecting JWT identity tokens (`jwt` feature).
+    pub jwt: bool,
+    /// Fetching configuration from `config_url` (`http` feature).
+    pub http: bool,
+    /// OAuth2 token exchange against the IDM (`idm` feature).
+    pub idm: bool,
+    /// Decoding configuration inlined by the daemon (`bootstrap` feature).
+    pub bootstrap: bool,
+    /// `TokenMiddleware` for `reqwest-middleware` clients (`reqwest` feature).
+    pub reqwest: bool,
+    /// `GetMyIdLayer` for `tower` service stacks (`tower` feature).
+    pub tower: bool,
+    /// The `WhoAmI` extractor for `axum` handlers (`axum` feature).
+    pub axum: bool,
+    /// `TokenInterceptor` for `tonic` gRPC clients (`tonic` feature).
+    pub tonic: bool,
+    /// `IdentityMiddleware` for `actix-web` apps (`actix` feature).
+    pub actix: bool,
+    /// `SecretIdentity` holding the token in a `secrecy` secret (`secrecy` feature).
+    pub secrecy: bool,
+    /// Wiping tokens from memory when identities are dropped (`zeroize` feature).
+    pub zeroize: bool,
+    /// `Identity::header_value` returning an `http::HeaderValue` (`headers` feature).
+    pub headers: bool,
+    /// Verification of signed daemon responses (`signing` feature).
+    pub signing: bool,
+    /// `NoiseTransport` encrypting the exchange (`noise` feature).
+    pub noise: bool,
+    /// Records of lookups, retries and failures for the `log` facade (`log` feature).
+    pub log: bool,
+    /// W3C `traceparent` propagation through the runner context (`otel` feature).
+    pub otel: bool,
+    /// `get_identity_for_pid` looking up other processes (`admin` feature).
+    pub admin: bool,
+    /// `SOCK_SEQPACKET` daemons through [`SocketType::SeqPacket`](crate::SocketType).
+    pub seqpacket: bool,
+    /// Linux abstract namespace socket paths (`@name`).
+    pub abstract_sockets: bool,
+    /// The original request-less exchange in [`compat::legacy`](crate::compat::legacy).
+    pub legacy_protocol: bool,
+}
+
+/// Cargo features of this crate, paired with whether they are enabled.
+const FEATURES: [(&str, bool); 24] = [
+    ("tokio", cfg!(feature = "tokio")),
+    ("tls", cfg!(feature = "tls")),
+    ("cloud", cfg!(feature = "cloud")),
+    ("config-file", cfg!(feature = "config-file")),
+    ("test-util", cfg!(feature = "test-util")),
+    ("cli", cfg!(feature = "cli")),
+    ("unstable", cfg!(feature = "unstable")),
+    ("jwt", cfg!(feature = "jwt")),
+    ("http", cfg!(feature = "http")),
+    ("idm", cfg!(feature = "idm")),
+    ("bootstrap", cfg!(feature = "bootstrap")),
+    ("reqwest", cfg!(feature = "reqwest")),
+    ("tower", cfg!(feature = "tower")),
+    ("axum", cfg!(feature = "axum")),
+    ("tonic", cfg!(feature = "tonic")),
+    ("actix", cfg!(feature = "actix")),
+    ("secrecy", cfg!(feature = "secrecy")),
+    ("zeroize", cfg!(feature = "zeroize")),
+    ("headers", cfg!(feature = "headers")),
+    ("signing", cfg!(feature = "signing")),
+    ("noise", cfg!(feature = "noise")),
+    ("log", cfg!(feature = "log")),
+    ("otel", cfg!(feature = "otel")),
+    ("admin", cfg!(feature = "admin")),
+];
+
+impl Capabilities {
+    /// Names of the Cargo features enabled in this build.
+    pub fn features(&self) -> Vec<&'static str> {
+        FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Check that the Cargo feature `feature` is enabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::FeatureDisabled`] if the feature is not
+    /// enabled or is not a feature of this crate.
+    pub fn require(&self, feature: &str) -> Result<()> {
+        if FEATURES.contains(&(feature, true)) {
+            Ok(())
+        } else {
+            Err(GetMyIdError::FeatureDisabled(feature.to_string()))
+        }
+    }
+}
+
+/// Report the features and protocol modes compiled into this build.
+pub const fn capabilities() -> Capabilities {
+    Capabilities {
+        tokio: cfg!(feature = "tokio"),
+        tls: cfg!(feature = "tls"),
+        cloud: cfg!(feature = "cloud"),
+        config_file: cfg!(feature = "config-file"),
+        test_util: cfg!(feature = "test-util"),
+        cli: cfg!(feature = "cli"),
+        unstable: cfg!(feature = "unstable"),
+        jwt: cfg!(feature = "jwt"),
+        http: cfg!(feature = "http"),
+        idm: cfg!(feature = "idm"),
+        bootstrap: cfg!(feature = "bootstrap"),
+        reqwest: cfg!(feature = "reqwest"),
+        tower: cfg!(feature = "tower"),
+        axum: cfg!(feature = "axum"),
+        tonic: cfg!(feature = "tonic"),
+        actix: cfg!(feature = "actix"),
+        secrecy: cfg!(feature = "secrecy"),
+        zeroize: cfg!(feature = "zeroize"),
+        headers: cfg!(feature = "headers"),
+        signing: cfg!(feature = "signing"),
+        noise: cfg!(feature = "noise"),
+        log: cfg!(feature = "log"),
+        otel: cfg!(feature = "otel"),
+        admin: cfg!(feature = "admin"),
+        seqpacket: true,
+        abstract_sockets: true,
+        legacy_protocol: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let caps = capabilities();
+
+        assert_eq!(caps.tokio, caps.features().contains(&"tokio"));
+        assert_eq!(caps.tokio, caps.require("tokio").is_ok());
+        assert!(caps.seqpacket);
+        assert!(matches!(
+            caps.require("openssl"),
+            Err(GetMyIdError::FeatureDisabled(name)) if name == "openssl"
+        ));
+    }
+}
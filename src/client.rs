@@ -1,19 +1,59 @@
 //! Synchronous client for the whoami daemon.
 
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{OwnedFd, RawFd};
 use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::activation::{
+    default_socket_candidates, fd_path, inherited_socket_path, probe_candidates,
+};
+use crate::compatibility::{Compatibility, DaemonInfo};
+use crate::config::Settings;
 use crate::error::{GetMyIdError, Result};
-use crate::types::{DaemonResponse, Identity, ResponseData, RunnerRequest};
+use crate::limits::JsonLimits;
+use crate::logging::{log_debug, log_warn};
+use crate::metrics::{Metrics, MetricsSink, RequestTimer};
+use crate::nonblocking::IdentityRequest;
+#[cfg(feature = "otel")]
+use crate::otel::TraceContext;
+use crate::pressure::{Pressure, PressureTracker};
+use crate::procfs;
+#[cfg(feature = "admin")]
+use crate::protocol::check_target_pid;
+use crate::protocol::{
+    check_alive, check_known_fields, check_nonce, parse_change, parse_response, Request,
+    STATUS_INFO, STATUS_PONG,
+};
+use crate::retry::{CacheLimits, IdentityCache, NegativeCache, RetryPolicy};
+use crate::session::Session;
+use crate::singleflight::SingleFlight;
+use crate::soft::SoftErrorCodes;
+use crate::transport::{
+    absolute_socket_path, abstract_path, ConnectedTransport, Connection, SocketType, SymlinkPolicy,
+    Transport, UnixTransport,
+};
+use crate::typed::TypedIdentity;
+use crate::types::{HostnamePolicy, Identity, IdentityChange, KeyCase, RawIdentity, RunnerRequest};
+use crate::view::IdentityView;
 
 /// Default socket path for the whoami daemon.
 pub const DEFAULT_SOCKET_PATH: &str = "/var/run/whoami.sock";
 
 /// Default timeout for connections.
+///
+/// Used for the connect, read and write phases unless they are configured
+/// individually.
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Default maximum size of a daemon response, in bytes.
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
 /// Synchronous client for communicating with the whoami daemon.
 ///
 /// # Example
@@ -29,7 +69,31 @@ pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 #[derive(Debug, Clone)]
 pub struct Client {
     socket_path: PathBuf,
-    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    extras_key_case: KeyCase,
+    hostname_policy: HostnamePolicy,
+    json_limits: JsonLimits,
+    strict_parsing: bool,
+    soft_errors: SoftErrorCodes,
+    max_response_bytes: usize,
+    default_runner: Option<RunnerRequest>,
+    retry: RetryPolicy,
+    cache: Option<Arc<IdentityCache>>,
+    negative_cache: Option<Arc<NegativeCache>>,
+    pressure: Arc<PressureTracker>,
+    flights: Arc<SingleFlight>,
+    transport: Arc<dyn Transport>,
+    unix_transport: Option<UnixTransport>,
+    failover: Vec<(PathBuf, Arc<dyn Transport>)>,
+    #[cfg(feature = "signing")]
+    daemon_public_key: Option<Arc<[u8]>>,
+    require_nonce: bool,
+    request_ids: bool,
+    metrics: Option<Metrics>,
+    #[cfg(feature = "otel")]
+    trace_context: Option<TraceContext>,
 }
 
 impl Default for Client {
@@ -43,10 +107,7 @@ impl Client {
     ///
     /// Uses the default socket path `/var/run/whoami.sock`.
     pub fn new() -> Self {
-        Self {
-            socket_path: PathBuf::from(DEFAULT_SOCKET_PATH),
-            timeout: Some(DEFAULT_TIMEOUT),
-        }
+        ClientBuilder::new().build()
     }
 
     /// Create a client builder for custom configuration.
@@ -54,6 +115,62 @@ impl Client {
         ClientBuilder::new()
     }
 
+    /// Create a client using `fd`, a stream socket already connected to the
+    /// daemon, e.g. by a supervisor that handed it down to this process.
+    ///
+    /// The daemon identifies the process that connected, and answers a
+    /// single request per connection: the client can resolve the identity
+    /// once, and later requests fail with [`GetMyIdError::ConnectionFailed`].
+    /// The default read and write timeouts are applied to the socket. Use
+    /// [`ClientBuilder::transport`] with a [`ConnectedTransport`] to
+    /// configure the client further.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::os::unix::io::{FromRawFd, OwnedFd};
+    /// use getmyid::Client;
+    ///
+    /// // SAFETY: the supervisor passed the connected socket as fd 3
+    /// let fd = unsafe { OwnedFd::from_raw_fd(3) };
+    /// let identity = Client::from_fd(fd).get_identity()?;
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn from_fd(fd: OwnedFd) -> Self {
+        let stream = UnixStream::from(fd);
+        stream.set_read_timeout(Some(DEFAULT_TIMEOUT)).ok();
+        stream.set_write_timeout(Some(DEFAULT_TIMEOUT)).ok();
+        ClientBuilder::new()
+            .transport(ConnectedTransport::new(stream))
+            .build()
+    }
+
+    /// Create a client configured from the `GETMYID_*` environment variables.
+    ///
+    /// See the [`config`](crate::config) module for the variables read;
+    /// settings without a variable keep their defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidEnvVar`] if a variable cannot be parsed.
+    pub fn from_env() -> Result<Self> {
+        Ok(ClientBuilder::from_env()?.build())
+    }
+
+    /// Create a client configured from the TOML file at `path`, overridden
+    /// by the `GETMYID_*` environment variables (requires `config-file`
+    /// feature).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidConfig`] if the file cannot be read or
+    /// parsed, or [`GetMyIdError::InvalidEnvVar`] if a variable cannot be
+    /// parsed.
+    #[cfg(feature = "config-file")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(ClientBuilder::from_file(path)?.build())
+    }
+
     /// Get the identity of the current process.
     ///
     /// Connects to the whoami daemon, which uses `SO_PEERCRED` to identify
@@ -90,59 +207,696 @@ impl Client {
     /// # Ok::<(), getmyid::GetMyIdError>(())
     /// ```
     pub fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
-        // Check socket exists
-        if !self.socket_path.exists() {
-            return Err(GetMyIdError::SocketNotFound(self.socket_path.clone()));
+        let runner = self.prepare_runner(runner);
+        let key = IdentityCache::key(runner.as_ref());
+        if let Some(identity) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            return Ok(identity);
+        }
+        // Concurrent lookups with the same runner context share one request
+        let lookup = || self.lookup(runner.as_ref()).map(RawIdentity::into_identity);
+        let identity = match self.flights.run(key.clone(), lookup) {
+            Ok(identity) => identity,
+            Err(e) => {
+                let cached = self.cache.as_deref().map(|cache| (cache, key.as_str()));
+                return self.soft_errors.recover(cached, e);
+            }
+        };
+        if let Some(cache) = &self.cache {
+            cache.insert(key, identity.clone());
+        }
+        Ok(identity)
+    }
+
+    /// Get the identity with an application's own runner context type.
+    ///
+    /// `context` is converted with [`RunnerRequest::from_context`] and
+    /// sent as by [`get_identity_with_runner`](Client::get_identity_with_runner).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// #[derive(serde::Serialize)]
+    /// struct Context<'a> {
+    ///     instance_id: u64,
+    ///     tenant: &'a str,
+    /// }
+    ///
+    /// let client = getmyid::Client::new();
+    /// let identity = client.get_identity_with_context(&Context {
+    ///     instance_id: 42,
+    ///     tenant: "acme",
+    /// })?;
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidJson`] if `context` does not serialize
+    /// to a runner request, and the errors of
+    /// [`get_identity`](Client::get_identity).
+    pub fn get_identity_with_context<T: Serialize + ?Sized>(
+        &self,
+        context: &T,
+    ) -> Result<Identity> {
+        self.get_identity_with_runner(Some(RunnerRequest::from_context(context)?))
+    }
+
+    /// Get the identity together with the daemon response it was parsed
+    /// from.
+    ///
+    /// Retries, failover daemons and the negative cache apply as for
+    /// [`get_identity`](Client::get_identity), but the identity cache is
+    /// bypassed since it keeps no responses; the fresh identity still
+    /// refreshes it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::Client;
+    ///
+    /// let client = Client::new();
+    /// let raw = client.get_identity_raw()?;
+    /// println!("Identity: {}", raw.identity.identity);
+    /// audit_log(&raw.raw);
+    /// # fn audit_log(_: &str) {}
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn get_identity_raw(&self) -> Result<RawIdentity> {
+        self.get_identity_raw_with_runner(None)
+    }
+
+    /// Like [`get_identity_raw`](Client::get_identity_raw), sending `runner`
+    /// as context.
+    pub fn get_identity_raw_with_runner(
+        &self,
+        runner: Option<RunnerRequest>,
+    ) -> Result<RawIdentity> {
+        let runner = self.prepare_runner(runner);
+        let raw = self.lookup(runner.as_ref())?;
+        if let Some(cache) = &self.cache {
+            cache.insert(IdentityCache::key(runner.as_ref()), raw.identity.clone());
+        }
+        Ok(raw)
+    }
+
+    /// Ask the daemons, retrying transient failures, unless the negative
+    /// cache holds a recent failure.
+    fn lookup(&self, runner: Option<&RunnerRequest>) -> Result<RawIdentity> {
+        if let Some(error) = self.negative_cache.as_ref().and_then(|cache| cache.get()) {
+            return Err(error);
         }
 
-        // Connect to the socket
-        let mut stream = UnixStream::connect(&self.socket_path).map_err(|e| {
-            GetMyIdError::ConnectionFailed {
-                path: self.socket_path.clone(),
-                source: e,
+        // One request ID for every attempt, failover daemon and hedge
+        let runner = self.with_request_id(runner.cloned());
+        let runner = runner.as_ref();
+        let _in_flight = self.pressure.start();
+        let mut attempt = 0;
+        let raw = loop {
+            match self.request(runner) {
+                Ok(raw) => break raw,
+                Err(e) => match self.retry.delay(attempt, &e) {
+                    Some(delay) => {
+                        log_warn!("identity lookup failed, retrying in {delay:?}: {e}");
+                        let _queued = self.pressure.queue();
+                        std::thread::sleep(delay);
+                    }
+                    None => {
+                        log_warn!("identity lookup failed: {e}");
+                        self.pressure.record(false);
+                        if let Some(cache) = &self.negative_cache {
+                            cache.record(&e);
+                        }
+                        return Err(e);
+                    }
+                },
             }
-        })?;
+            attempt += 1;
+        };
+        self.pressure.record(true);
+        let name = &raw.identity.identity;
+        match raw.identity.runner.request_id() {
+            Some(id) => log_debug!("resolved identity {name} for request {id}"),
+            None => log_debug!("resolved identity {name}"),
+        }
+        Ok(raw)
+    }
 
-        // Set timeouts if configured
-        if let Some(timeout) = self.timeout {
-            stream
-                .set_read_timeout(Some(timeout))
-                .map_err(GetMyIdError::ReadError)?;
-            stream
-                .set_write_timeout(Some(timeout))
-                .map_err(GetMyIdError::WriteError)?;
+    /// Normalize the extras keys of `runner` and merge the default runner
+    /// request under it.
+    pub(crate) fn prepare_runner(&self, runner: Option<RunnerRequest>) -> Option<RunnerRequest> {
+        self.with_default_runner(runner.map(|r| r.normalize_keys(self.extras_key_case)))
+    }
+
+    /// Merge the default runner request under `runner`.
+    fn with_default_runner(&self, runner: Option<RunnerRequest>) -> Option<RunnerRequest> {
+        match (runner, &self.default_runner) {
+            (Some(runner), Some(defaults)) => Some(runner.with_defaults(defaults)),
+            (runner, defaults) => runner.or_else(|| defaults.clone()),
+        }
+    }
+
+    /// Ask the primary daemon, then each failover daemon in turn while the
+    /// previous one fails transiently.
+    fn request(&self, runner: Option<&RunnerRequest>) -> Result<RawIdentity> {
+        if self.failover.is_empty() {
+            log_debug!("asking the whoami daemon at {}", self.socket_path.display());
+            return self.exchange(self.transport.as_ref(), runner);
+        }
+
+        let failover = self.failover.iter().map(|(path, t)| (path, t));
+        let endpoints = std::iter::once((&self.socket_path, &self.transport)).chain(failover);
+        let mut failures = Vec::new();
+        for (path, transport) in endpoints {
+            log_debug!("asking the whoami daemon at {}", path.display());
+            match self.exchange(transport.as_ref(), runner) {
+                Ok(raw) => return Ok(raw),
+                Err(e) if e.is_transient() => {
+                    log_warn!("whoami daemon at {} failed: {e}", path.display());
+                    failures.push((path.clone(), e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(GetMyIdError::AllEndpointsFailed { failures })
+    }
+
+    /// Get the identity as an [`IdentityView`] borrowing its strings from
+    /// `buf`.
+    ///
+    /// The response replaces the contents of `buf`, so one buffer can be
+    /// reused across calls. Only the primary daemon is asked, once: the
+    /// cache, retries, failover daemons and extras key normalization do not
+    /// apply, but the [default runner](ClientBuilder::default_runner) is
+    /// merged in and the [`JsonLimits`] are enforced.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::Client;
+    ///
+    /// let client = Client::new();
+    /// let mut buf = String::new();
+    /// let view = client.get_identity_view(None, &mut buf)?;
+    /// println!("Identity: {}", view.identity);
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn get_identity_view<'a>(
+        &self,
+        runner: Option<RunnerRequest>,
+        buf: &'a mut String,
+    ) -> Result<IdentityView<'a>> {
+        buf.clear();
+        let runner = self.with_default_runner(runner);
+        let request = self.new_request(runner.clone());
+        self.fetch(self.transport.as_ref(), &request, buf)?;
+        if self.strict_parsing {
+            check_known_fields(buf, runner.as_ref())?;
+        }
+        let view = IdentityView::parse(buf)?;
+        self.json_limits.check_response(buf)?;
+        Ok(view)
+    }
+
+    /// Get the identity with its runner object deserialized into `R`, a
+    /// type describing the runner the daemon returns for this service.
+    ///
+    /// The response is parsed straight into `R`. As for
+    /// [`get_identity_view`](Client::get_identity_view), only the primary
+    /// daemon is asked, once: the cache, retries, failover daemons and
+    /// extras key normalization do not apply, but the
+    /// [default runner](ClientBuilder::default_runner) is merged in and the
+    /// [`JsonLimits`] are enforced.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::{Client, RunnerRequest, TypedIdentity};
+    ///
+    /// #[derive(serde::Deserialize)]
+    /// struct Placement {
+    ///     hostname: String,
+    ///     region: String,
+    /// }
+    ///
+    /// let client = Client::new();
+    /// let runner = RunnerRequest::new().with_region("eu-west-1");
+    /// let identity: TypedIdentity<Placement> = client.get_identity_as(Some(runner))?;
+    /// println!("{} in {}", identity.runner.hostname, identity.runner.region);
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// See [`TypedIdentity::parse`].
+    pub fn get_identity_as<R: DeserializeOwned>(
+        &self,
+        runner: Option<RunnerRequest>,
+    ) -> Result<TypedIdentity<R>> {
+        let runner = self.with_default_runner(runner);
+        let request = self.new_request(runner.clone());
+        let mut response = String::new();
+        self.fetch(self.transport.as_ref(), &request, &mut response)?;
+        if self.strict_parsing {
+            check_known_fields(&response, runner.as_ref())?;
+        }
+        let identity = TypedIdentity::parse(&response)?;
+        self.json_limits.check_response(&response)?;
+        Ok(identity)
+    }
+
+    /// Wait up to `timeout` for the identity to change from version `since`.
+    ///
+    /// The daemon holds the connection open and answers as soon as the
+    /// identity differs from `since`, or with [`IdentityChange::Unchanged`]
+    /// once `timeout` passed, so rotations are noticed almost immediately
+    /// without polling. Pass an empty `since` to get the current identity
+    /// and its version. The read timeout is extended by `timeout`; custom
+    /// transports keep their own timeouts.
+    ///
+    /// Only the primary daemon is asked, once, with the
+    /// [default runner](ClientBuilder::default_runner): the cache, retries
+    /// and failover daemons do not apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::MissingField`] for `version` if the daemon
+    /// does not support long-polling and answered immediately.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use getmyid::{Client, IdentityChange};
+    ///
+    /// let client = Client::new();
+    /// let mut version = String::new();
+    /// loop {
+    ///     let change = client.wait_for_change(&version, Duration::from_secs(60))?;
+    ///     if let IdentityChange::Changed { identity, .. } = &change {
+    ///         println!("Token rotated for {}", identity.identity);
+    ///     }
+    ///     version = change.version().to_string();
+    /// }
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn wait_for_change(&self, since: &str, timeout: Duration) -> Result<IdentityChange> {
+        let runner = self.with_default_runner(None);
+        let request = self.new_request(runner.clone()).with_wait(since, timeout);
+        let long_poll = self.unix_transport.as_ref().map(|transport| {
+            let read_timeout = self.read_timeout.map(|read| read.saturating_add(timeout));
+            transport.clone().with_read_timeout(read_timeout)
+        });
+        let transport = match &long_poll {
+            Some(transport) => transport as &dyn Transport,
+            None => self.transport.as_ref(),
+        };
+
+        let mut response = String::new();
+        self.fetch(transport, &request, &mut response)?;
+        if self.strict_parsing {
+            check_known_fields(&response, runner.as_ref())?;
+        }
+        match parse_change(&response)? {
+            IdentityChange::Changed { identity, version } => Ok(IdentityChange::Changed {
+                identity: Box::new(self.localize(*identity)?),
+                version,
+            }),
+            unchanged => Ok(unchanged),
+        }
+    }
+
+    /// Ask the daemon for an identity and compare the versions it reports
+    /// to the range this crate is tested against.
+    ///
+    /// Meant to be called once at startup, logging a warning unless the
+    /// result [is tested](Compatibility::is_tested). The versions
+    /// themselves are on [`RawIdentity::daemon_version`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::{Client, Compatibility};
+    ///
+    /// let client = Client::new();
+    /// match client.check_compatibility()? {
+    ///     Compatibility::Older => eprintln!("whoami daemon older than tested"),
+    ///     Compatibility::Newer => eprintln!("whoami daemon newer than tested"),
+    ///     _ => {}
+    /// }
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn check_compatibility(&self) -> Result<Compatibility> {
+        Ok(self.get_identity_raw()?.daemon_version.compatibility())
+    }
+
+    /// Start a non-blocking identity lookup for an event loop.
+    ///
+    /// Equivalent to [`try_get_identity_with_runner(None)`](Client::try_get_identity_with_runner).
+    pub fn try_get_identity(&self) -> Result<IdentityRequest> {
+        self.try_get_identity_with_runner(None)
+    }
+
+    /// Start a non-blocking identity lookup with client-provided runner
+    /// context.
+    ///
+    /// The returned [`IdentityRequest`] is driven with
+    /// [`IdentityRequest::poll`], which reports `Poll::Pending` instead of
+    /// blocking, so the lookup fits into `mio`, `calloop` and similar event
+    /// loops. Only the primary daemon is asked, once: the cache, retries,
+    /// failover daemons and timeouts do not apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::ConnectionFailed`] with
+    /// [`io::ErrorKind::Unsupported`] if the client uses a custom transport
+    /// or a `SOCK_SEQPACKET` socket.
+    pub fn try_get_identity_with_runner(
+        &self,
+        runner: Option<RunnerRequest>,
+    ) -> Result<IdentityRequest> {
+        let transport =
+            self.unix_transport
+                .as_ref()
+                .ok_or_else(|| GetMyIdError::ConnectionFailed {
+                    path: self.socket_path.clone(),
+                    resolved: None,
+                    source: io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "non-blocking requests need a Unix socket transport",
+                    ),
+                })?;
+        let runner = self.prepare_runner(runner);
+        IdentityRequest::start(self.clone(), transport, runner)
+    }
+
+    /// Perform a single request/response exchange over `transport`.
+    fn exchange(
+        &self,
+        transport: &dyn Transport,
+        runner: Option<&RunnerRequest>,
+    ) -> Result<RawIdentity> {
+        // Reject reserved fields before connecting to the daemon
+        if let Some(runner) = runner {
+            runner.check()?;
+        }
+        let mut timer = RequestTimer::start();
+        let mut raw = String::new();
+        let identity = transport
+            .dial()
+            .and_then(|stream| {
+                timer.connected();
+                self.fetch_over(stream, &self.new_request(runner.cloned()), &mut raw)
+            })
+            .and_then(|()| self.identity_from_response(&raw, runner));
+        timer.finish(self.metrics.as_ref(), raw.len(), identity.as_ref().err());
+        Ok(RawIdentity::new(identity?, raw))
+    }
+
+    /// Give `runner` a request ID unless it carries one, if the client adds
+    /// them.
+    fn with_request_id(&self, runner: Option<RunnerRequest>) -> Option<RunnerRequest> {
+        match runner {
+            runner if self.request_ids => Some(runner.unwrap_or_default().ensure_request_id()),
+            runner => runner,
+        }
+    }
+
+    /// Create the request carrying `runner`, completed with a request ID
+    /// and the trace context if the client adds them, asking for a signed
+    /// answer if the client verifies signatures and with a nonce if it
+    /// requires one.
+    pub(crate) fn new_request(&self, runner: Option<RunnerRequest>) -> Request {
+        let runner = self.with_request_id(runner);
+        #[cfg(feature = "otel")]
+        let runner = match &self.trace_context {
+            Some(source) => source.inject(runner),
+            None => runner,
+        };
+        let request = Request {
+            #[cfg(feature = "signing")]
+            sign: self.daemon_public_key.is_some(),
+            ..Request::new(runner)
+        };
+        if self.require_nonce {
+            request.with_nonce()
+        } else {
+            request
+        }
+    }
+
+    /// Replace the signed daemon answer `response` with its verified
+    /// payload if the client verifies signatures.
+    pub(crate) fn open_response(&self, response: &mut String) -> Result<()> {
+        #[cfg(feature = "signing")]
+        if let Some(public_key) = &self.daemon_public_key {
+            *response = crate::signing::open(response, public_key)?;
+        }
+        #[cfg(not(feature = "signing"))]
+        let _ = response;
+        Ok(())
+    }
+
+    /// Parse and validate a raw daemon response to `runner`.
+    pub(crate) fn identity_from_response(
+        &self,
+        response: &str,
+        runner: Option<&RunnerRequest>,
+    ) -> Result<Identity> {
+        if self.strict_parsing {
+            check_known_fields(response, runner)?;
+        }
+        self.localize(parse_response(response)?)
+    }
+
+    /// Apply the client's extras limits, key case and hostname policy to an
+    /// identity parsed from a daemon response.
+    fn localize(&self, mut identity: Identity) -> Result<Identity> {
+        self.json_limits.check(&identity.runner)?;
+        identity.runner.normalize_keys(self.extras_key_case);
+        identity
+            .runner
+            .apply_hostname_policy(self.hostname_policy, procfs::hostname());
+        Ok(identity)
+    }
+
+    /// Send `request` over `transport` and read the raw response into
+    /// `response`.
+    fn fetch(
+        &self,
+        transport: &dyn Transport,
+        request: &Request,
+        response: &mut String,
+    ) -> Result<()> {
+        if let Some(runner) = &request.runner {
+            runner.check()?;
         }
+        self.fetch_over(transport.dial()?, request, response)
+    }
 
-        // Send runner request if provided
-        if let Some(ref runner_req) = runner {
-            let request = serde_json::json!({ "runner": runner_req });
-            let request_str = serde_json::to_string(&request).map_err(GetMyIdError::InvalidJson)?;
+    /// Send `request` over the connection `stream` and read the raw
+    /// response into `response`.
+    fn fetch_over(
+        &self,
+        mut stream: Box<dyn Connection>,
+        request: &Request,
+        response: &mut String,
+    ) -> Result<()> {
+        // Send the request unless it is empty; message-oriented connections
+        // always carry a request so the daemon knows when to reply
+        if *request != Request::default() || stream.is_message_oriented() {
+            let request_str = request.to_json()?;
             stream
                 .write_all(request_str.as_bytes())
                 .map_err(GetMyIdError::WriteError)?;
             stream.flush().map_err(GetMyIdError::WriteError)?;
             // Shutdown write side to signal we're done sending
-            stream.shutdown(std::net::Shutdown::Write).ok();
+            stream.finish_request().ok();
         }
 
-        // Read the response
+        read_response(&mut stream, response, self.max_response_bytes)?;
+        self.open_response(response)?;
+        check_nonce(request.nonce.as_deref(), response)
+    }
+
+    /// Open a connection to the daemon through the configured transport.
+    pub(crate) fn dial(&self) -> Result<Box<dyn Connection>> {
+        self.transport.dial()
+    }
+
+    /// Open a [`Session`] keeping a connection to the daemon open across
+    /// identity lookups.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon cannot be reached.
+    pub fn connect(&self) -> Result<Session> {
+        Session::open(self.clone())
+    }
+
+    /// Check that the daemon is alive and measure the round trip.
+    ///
+    /// Sends a [ping](Request::ping) to the configured daemon, once: the
+    /// cache, retries and failover daemons are bypassed. Daemons without
+    /// ping support answer with an identity, which counts as alive, as does
+    /// the rejection of this process such as `E_NO_MATCH`. Meant for
+    /// readiness probes and monitoring agents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon cannot be reached, does not answer in
+    /// time, is degraded or fails with `E_INTERNAL`.
+    pub fn ping(&self) -> Result<Duration> {
+        let started = Instant::now();
+        let request = self.new_request(None).with_ping();
+        let mut response = String::new();
+        self.fetch(self.transport.as_ref(), &request, &mut response)?;
+        check_alive(&response, STATUS_PONG)?;
+        Ok(started.elapsed())
+    }
+
+    /// Ask the daemon for its versions and the protocol features it
+    /// supports.
+    ///
+    /// Like [`ping`](Self::ping), asks the configured daemon once,
+    /// bypassing the cache, retries and failover daemons. Daemons without
+    /// info support answer with an identity or reject this process; the
+    /// returned [`DaemonInfo`] then only carries the versions of that
+    /// answer, with no [`features`](DaemonInfo::features).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let client = getmyid::Client::new();
+    /// let info = client.daemon_info()?;
+    /// if !info.compatibility().is_tested() || !info.supports("keep_alive") {
+    ///     eprintln!("whoami daemon {:?} not fully supported", info.version.release);
+    /// }
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon cannot be reached, does not answer in
+    /// time, is degraded or fails with `E_INTERNAL`.
+    pub fn daemon_info(&self) -> Result<DaemonInfo> {
+        let request = self.new_request(None).with_info();
         let mut response = String::new();
-        stream
-            .read_to_string(&mut response)
-            .map_err(GetMyIdError::ReadError)?;
+        self.fetch(self.transport.as_ref(), &request, &mut response)?;
+        check_alive(&response, STATUS_INFO)?;
+        Ok(DaemonInfo::from_response(&response))
+    }
 
-        // Parse and validate response
-        parse_response(&response)
+    /// Resolve the identity another local process would get.
+    ///
+    /// For supervisors and debugging tools: the daemon resolves the process
+    /// `pid` instead of the caller, if it permits the caller such lookups.
+    /// Asks the configured daemon once, bypassing the cache, retries and
+    /// failover daemons.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`GetMyIdError::DaemonError`] with `E_ACCESS_DENIED` if the
+    /// daemon does not permit the caller to look up other processes, and
+    /// [`GetMyIdError::PidMismatch`] if it answered for another process,
+    /// as daemons without support for PID-targeted lookups do.
+    #[cfg(feature = "admin")]
+    pub fn get_identity_for_pid(&self, pid: u32) -> Result<Identity> {
+        let request = self.new_request(None).with_target_pid(pid);
+        let mut response = String::new();
+        self.fetch(self.transport.as_ref(), &request, &mut response)?;
+        let identity = self.identity_from_response(&response, request.runner.as_ref())?;
+        check_target_pid(pid, &identity)?;
+        Ok(identity)
     }
 
-    /// Get the configured socket path.
+    /// Get the configured socket path, or the one chosen by discovery.
+    ///
+    /// This is not used when a custom transport is configured.
     pub fn socket_path(&self) -> &Path {
         &self.socket_path
     }
 
+    /// Get the socket paths of the failover daemons, in the order they are
+    /// tried after the primary.
+    pub fn failover_sockets(&self) -> impl Iterator<Item = &Path> {
+        self.failover.iter().map(|(path, _)| path.as_path())
+    }
+
     /// Get the configured timeout.
+    ///
+    /// When the timeouts have been configured individually this returns the
+    /// read timeout, which bounds how long the client waits for the daemon.
     pub fn timeout(&self) -> Option<Duration> {
-        self.timeout
+        self.read_timeout
+    }
+
+    /// Get the configured connect timeout.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// Get the configured read timeout.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Get the configured write timeout.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// Get the runner request sent with every request, if any.
+    pub fn default_runner(&self) -> Option<&RunnerRequest> {
+        self.default_runner.as_ref()
+    }
+
+    /// Get the number of retries after a transient failure.
+    pub fn retries(&self) -> u32 {
+        self.retry.retries
+    }
+
+    /// Get how long resolved identities are cached, if caching is enabled.
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache.as_ref().map(|cache| cache.ttl())
+    }
+
+    /// Get which hostname returned runners report.
+    pub fn hostname_policy(&self) -> HostnamePolicy {
+        self.hostname_policy
+    }
+
+    /// Get the limits enforced on the runner extras of daemon responses.
+    pub fn json_limits(&self) -> JsonLimits {
+        self.json_limits
+    }
+
+    /// Check whether responses with unknown fields are rejected.
+    pub fn strict_parsing(&self) -> bool {
+        self.strict_parsing
+    }
+
+    /// Get the daemon error codes answered with a cached identity; changes
+    /// made through the handle apply to this client.
+    pub fn soft_error_codes(&self) -> &SoftErrorCodes {
+        &self.soft_errors
+    }
+
+    /// Get the maximum size of a daemon response, in bytes.
+    pub fn max_response_bytes(&self) -> usize {
+        self.max_response_bytes
+    }
+
+    /// Get how long a failure to reach the daemon is returned again without
+    /// contacting it, if negative caching is enabled.
+    pub fn negative_cache_ttl(&self) -> Option<Duration> {
+        self.negative_cache.as_ref().map(|cache| cache.ttl())
+    }
+
+    /// Get the current load and recent error rate of identity lookups, so
+    /// callers can shed or defer identity-dependent work while the daemon
+    /// struggles instead of discovering it through timeouts.
+    pub fn pressure(&self) -> Pressure {
+        self.pressure.snapshot()
     }
 }
 
@@ -158,11 +912,43 @@ impl Client {
 ///     .socket_path("/tmp/whoami.sock")
 ///     .timeout(Duration::from_secs(10))
 ///     .build();
+///
+/// // Tolerate a slow daemon cold start but keep a tight read deadline
+/// let client = Client::builder()
+///     .connect_timeout(Duration::from_secs(30))
+///     .read_timeout(Duration::from_millis(500))
+///     .build();
 /// ```
 #[derive(Debug, Clone)]
 pub struct ClientBuilder {
     socket_path: PathBuf,
-    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    extras_key_case: KeyCase,
+    hostname_policy: HostnamePolicy,
+    json_limits: JsonLimits,
+    strict_parsing: bool,
+    soft_errors: SoftErrorCodes,
+    max_response_bytes: usize,
+    default_runner: Option<RunnerRequest>,
+    socket_type: SocketType,
+    symlink_policy: SymlinkPolicy,
+    retry: RetryPolicy,
+    cache_ttl: Option<Duration>,
+    cache_limits: CacheLimits,
+    negative_cache_ttl: Option<Duration>,
+    socket_candidates: Option<Vec<PathBuf>>,
+    failover_sockets: Vec<PathBuf>,
+    peer_uid: Option<u32>,
+    #[cfg(feature = "signing")]
+    daemon_public_key: Option<Arc<[u8]>>,
+    require_nonce: bool,
+    request_ids: bool,
+    metrics: Option<Metrics>,
+    #[cfg(feature = "otel")]
+    trace_context: Option<TraceContext>,
+    transport: Option<Arc<dyn Transport>>,
 }
 
 impl Default for ClientBuilder {
@@ -176,86 +962,644 @@ impl ClientBuilder {
     pub fn new() -> Self {
         Self {
             socket_path: PathBuf::from(DEFAULT_SOCKET_PATH),
-            timeout: Some(DEFAULT_TIMEOUT),
+            connect_timeout: Some(DEFAULT_TIMEOUT),
+            read_timeout: Some(DEFAULT_TIMEOUT),
+            write_timeout: Some(DEFAULT_TIMEOUT),
+            extras_key_case: KeyCase::Preserve,
+            hostname_policy: HostnamePolicy::Daemon,
+            json_limits: JsonLimits::new(),
+            strict_parsing: false,
+            soft_errors: SoftErrorCodes::default(),
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            default_runner: None,
+            socket_type: SocketType::Stream,
+            symlink_policy: SymlinkPolicy::Follow,
+            retry: RetryPolicy::default(),
+            cache_ttl: None,
+            cache_limits: CacheLimits::default(),
+            negative_cache_ttl: None,
+            socket_candidates: None,
+            failover_sockets: Vec::new(),
+            peer_uid: None,
+            #[cfg(feature = "signing")]
+            daemon_public_key: None,
+            require_nonce: false,
+            request_ids: false,
+            metrics: None,
+            #[cfg(feature = "otel")]
+            trace_context: None,
+            transport: None,
         }
     }
 
-    /// Set the socket path.
-    pub fn socket_path<P: AsRef<Path>>(mut self, path: P) -> Self {
-        self.socket_path = path.as_ref().to_path_buf();
-        self
+    /// Create a builder starting from the defaults overridden by the
+    /// `GETMYID_*` environment variables.
+    ///
+    /// Options set on the returned builder take precedence over the
+    /// environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidEnvVar`] if a variable cannot be parsed.
+    pub fn from_env() -> Result<Self> {
+        Ok(Self::new().apply(Settings::from_env()?))
     }
 
-    /// Set the connection timeout.
+    /// Create a builder starting from the defaults overridden by the TOML
+    /// file at `path`, then by the `GETMYID_*` environment variables
+    /// (requires `config-file` feature).
     ///
-    /// Pass `None` to disable timeouts.
-    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
-        self.timeout = timeout.into();
-        self
+    /// See the [`config`](crate::config) module for the keys read. Options
+    /// set on the returned builder take precedence over both.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidConfig`] if the file cannot be read or
+    /// parsed, or [`GetMyIdError::InvalidEnvVar`] if a variable cannot be
+    /// parsed.
+    #[cfg(feature = "config-file")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let settings = Settings::from_file(path.as_ref())?.overlay(Settings::from_env()?);
+        Ok(Self::new().apply(settings))
     }
 
-    /// Build the client.
-    pub fn build(self) -> Client {
-        Client {
-            socket_path: self.socket_path,
-            timeout: self.timeout,
-        }
+    /// Like [`from_file`](Self::from_file), reading `/etc/getmyid.toml` and
+    /// then the per-user `~/.config/getmyid.toml`, skipping files that do
+    /// not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidConfig`] if an existing file cannot be
+    /// read or parsed, or [`GetMyIdError::InvalidEnvVar`] if a variable
+    /// cannot be parsed.
+    #[cfg(feature = "config-file")]
+    pub fn from_default_files() -> Result<Self> {
+        let settings = Settings::from_default_files()?.overlay(Settings::from_env()?);
+        Ok(Self::new().apply(settings))
     }
-}
 
-/// Parse a response string from the daemon into an Identity.
-pub(crate) fn parse_response(response: &str) -> Result<Identity> {
-    let daemon_response: DaemonResponse =
-        serde_json::from_str(response).map_err(GetMyIdError::InvalidJson)?;
-
-    if !daemon_response.is_ok() {
-        match daemon_response.data {
-            ResponseData::Error {
-                error_code,
-                message,
-            } => {
-                return Err(GetMyIdError::DaemonError {
-                    code: error_code,
-                    message,
-                });
-            }
-            _ => {
-                return Err(GetMyIdError::MissingField { field: "error_code" });
-            }
+    /// Override the options present in `settings`.
+    pub(crate) fn apply(mut self, settings: Settings) -> Self {
+        if let Some(path) = settings.socket_path {
+            self.socket_path = path;
         }
-    }
+        if let Some(timeout) = settings.connect_timeout {
+            self.connect_timeout = timeout;
+        }
+        if let Some(timeout) = settings.read_timeout {
+            self.read_timeout = timeout;
+        }
+        if let Some(timeout) = settings.write_timeout {
+            self.write_timeout = timeout;
+        }
+        if let Some(socket_type) = settings.socket_type {
+            self.socket_type = socket_type;
+        }
+        if let Some(retries) = settings.retries {
+            self.retry.retries = retries;
+        }
+        if let Some(backoff) = settings.retry_backoff {
+            self.retry.backoff = backoff;
+        }
+        if let Some(ttl) = settings.cache_ttl {
+            self.cache_ttl = ttl;
+        }
+        if let Some(max) = settings.cache_max_entries {
+            self.cache_limits.max_entries = max;
+        }
+        if let Some(max) = settings.cache_max_bytes {
+            self.cache_limits.max_bytes = max;
+        }
+        self
+    }
+
+    /// Set the socket path.
+    ///
+    /// A path starting with `@` refers to a Linux abstract namespace socket.
+    pub fn socket_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.socket_path = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Connect to the Linux abstract namespace socket `name`.
+    ///
+    /// This is equivalent to `socket_path("@name")`.
+    pub fn abstract_socket(mut self, name: impl AsRef<std::ffi::OsStr>) -> Self {
+        self.socket_path = abstract_path(name);
+        self
+    }
+
+    /// Connect through an inherited file descriptor referring to the
+    /// daemon's socket file (for example opened with `O_PATH`).
+    ///
+    /// The descriptor must stay open for the lifetime of the client; every
+    /// connection is made through `/proc/self/fd/<fd>`.
+    pub fn socket_fd(mut self, fd: RawFd) -> Self {
+        self.socket_path = fd_path(fd);
+        self
+    }
+
+    /// Use the socket handed down by the service manager, if any.
+    ///
+    /// See [`inherited_socket_path`](crate::inherited_socket_path) for the
+    /// conventions detected. When nothing was handed down, the socket path
+    /// configured so far is kept.
+    pub fn inherited_socket(mut self) -> Self {
+        if let Some(path) = inherited_socket_path() {
+            self.socket_path = path;
+        }
+        self
+    }
+
+    /// Connect to the first live socket among the
+    /// [`default_socket_candidates`](crate::default_socket_candidates).
+    ///
+    /// See [`socket_candidates`](Self::socket_candidates).
+    pub fn discover_socket(mut self) -> Self {
+        self.socket_candidates = Some(default_socket_candidates());
+        self
+    }
+
+    /// Connect to the first of `candidates` accepting a connection.
+    ///
+    /// The candidates are probed in order once, when the client is built;
+    /// the chosen path is reported by the client's `socket_path()`. If none
+    /// is live, the socket path configured otherwise is kept.
+    pub fn socket_candidates<I>(mut self, candidates: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        self.socket_candidates = Some(
+            candidates
+                .into_iter()
+                .map(|path| path.as_ref().to_path_buf())
+                .collect(),
+        );
+        self
+    }
+
+    /// Add a failover daemon socket, tried after the primary socket and any
+    /// failover sockets added before it.
+    ///
+    /// A daemon is passed over when it is unreachable, times out or reports
+    /// being degraded; a rejection such as `E_NO_MATCH` is returned as is.
+    /// When every daemon fails, [`GetMyIdError::AllEndpointsFailed`] lists
+    /// each error. Failover daemons are always reached over Unix sockets with
+    /// this builder's timeouts and socket type.
+    pub fn failover_socket<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.failover_sockets.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add several failover daemon sockets; see
+    /// [`failover_socket`](Self::failover_socket).
+    pub fn failover_sockets<I>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        self.failover_sockets
+            .extend(paths.into_iter().map(|path| path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Set the connection timeout.
+    ///
+    /// This sets the connect, read and write timeouts to the same value.
+    /// Pass `None` to disable timeouts.
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        let timeout = timeout.into();
+        self.connect_timeout = timeout;
+        self.read_timeout = timeout;
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Set the timeout for establishing the connection.
+    ///
+    /// Pass `None` to wait indefinitely.
+    pub fn connect_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.connect_timeout = timeout.into();
+        self
+    }
+
+    /// Set the timeout for reading the daemon's response.
+    ///
+    /// Pass `None` to wait indefinitely.
+    pub fn read_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.read_timeout = timeout.into();
+        self
+    }
+
+    /// Set the timeout for sending the runner request.
+    ///
+    /// Pass `None` to wait indefinitely.
+    pub fn write_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.write_timeout = timeout.into();
+        self
+    }
+
+    /// Normalize the keys of runner extras to `case`.
+    ///
+    /// The convention is applied to the extras of every runner request
+    /// before it is sent and to the extras of every returned runner, so
+    /// callers see one convention regardless of what the daemon echoes back.
+    pub fn extras_key_case(mut self, case: KeyCase) -> Self {
+        self.extras_key_case = case;
+        self
+    }
+
+    /// Choose which hostname [`Runner::hostname`](crate::Runner::hostname)
+    /// holds when the client runs in a different UTS namespace than the
+    /// daemon, e.g. inside a container.
+    ///
+    /// Whenever the two differ, the client's hostname is also recorded in
+    /// [`Runner::client_hostname`](crate::Runner::client_hostname). Defaults
+    /// to [`HostnamePolicy::Daemon`].
+    pub fn hostname_policy(mut self, policy: HostnamePolicy) -> Self {
+        self.hostname_policy = policy;
+        self
+    }
+
+    /// Reject responses whose runner extras exceed `limits` with
+    /// [`GetMyIdError::MalformedResponse`].
+    ///
+    /// Defaults to [`JsonLimits::new`]; pass [`JsonLimits::unlimited`] to
+    /// accept any extras. Views returned by
+    /// [`get_identity_view`](Client::get_identity_view) keep their extras
+    /// unparsed and are not checked.
+    pub fn json_limits(mut self, limits: JsonLimits) -> Self {
+        self.json_limits = limits;
+        self
+    }
+
+    /// Reject responses carrying fields outside the daemon protocol with
+    /// [`GetMyIdError::UnknownField`] naming the field, e.g. `runner.color`.
+    ///
+    /// By default unknown top-level fields are ignored and unknown runner
+    /// fields end up in [`Runner::extra`](crate::Runner::extra). In strict
+    /// mode runner extras are only accepted if the runner request sent
+    /// carries them, so a protocol mismatch is an error instead. Responses
+    /// in the legacy flat format are rejected too.
+    pub fn strict_parsing(mut self, strict: bool) -> Self {
+        self.strict_parsing = strict;
+        self
+    }
+
+    /// Fail with [`GetMyIdError::ResponseTooLarge`] as soon as a daemon
+    /// response grows beyond `max` bytes, so a misbehaving or spoofed daemon
+    /// cannot make the client allocate unbounded memory.
+    ///
+    /// Defaults to [`DEFAULT_MAX_RESPONSE_BYTES`] (1 MiB).
+    pub fn max_response_bytes(mut self, max: usize) -> Self {
+        self.max_response_bytes = max;
+        self
+    }
+
+    /// Send `runner` with every request.
+    ///
+    /// Runner requests passed to
+    /// [`get_identity_with_runner`](Client::get_identity_with_runner) are
+    /// merged over it with [`RunnerRequest::with_defaults`], so per-call
+    /// fields win; [`get_identity`](Client::get_identity) sends it as is.
+    pub fn default_runner(mut self, runner: RunnerRequest) -> Self {
+        self.default_runner = Some(runner);
+        self
+    }
+
+    /// Set the kind of Unix socket used to reach the daemon.
+    ///
+    /// With [`SocketType::SeqPacket`] the request and response are each sent
+    /// as a single message, so the exchange does not depend on a write-side
+    /// shutdown or on the daemon closing the connection. The daemon must
+    /// listen on a `SOCK_SEQPACKET` socket.
+    pub fn socket_type(mut self, socket_type: SocketType) -> Self {
+        self.socket_type = socket_type;
+        self
+    }
+
+    /// Retry up to `retries` times when the daemon is unreachable, times out
+    /// or reports being degraded.
+    ///
+    /// Rejections such as `E_NO_MATCH` are never retried. Defaults to 0.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retry.retries = retries;
+        self
+    }
+
+    /// Set the delay before the first retry; it doubles after every attempt.
+    ///
+    /// A `retry_after` hint from a degraded daemon is used instead when
+    /// present, up to 30 seconds.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry.backoff = backoff;
+        self
+    }
+
+    /// Reuse a resolved identity for `ttl` instead of asking the daemon again.
+    ///
+    /// Identities are cached per runner request and shared between clones of
+    /// the client. Pass `None` to disable caching (the default).
+    pub fn cache_ttl(mut self, ttl: impl Into<Option<Duration>>) -> Self {
+        self.cache_ttl = ttl.into();
+        self
+    }
+
+    /// Cache at most `max` identities, evicting the least recently used.
+    ///
+    /// Defaults to 1024. Expired identities are also swept out as new ones
+    /// are cached.
+    pub fn cache_max_entries(mut self, max: usize) -> Self {
+        self.cache_limits.max_entries = max;
+        self
+    }
+
+    /// Bound the approximate memory used by cached identities, evicting the
+    /// least recently used.
+    ///
+    /// Sizes are estimated from the serialized identity and its runner
+    /// context; a single identity larger than `max` is not cached. Pass
+    /// `None` to remove the bound (the default).
+    pub fn cache_max_bytes(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.cache_limits.max_bytes = max.into();
+        self
+    }
+
+    /// Answer daemon errors with these codes, such as `E_MAINTENANCE`, with
+    /// the identity cached earlier instead of failing.
+    ///
+    /// Keep a clone of `codes` to change them at runtime; see
+    /// [`SoftErrorCodes`]. Only lookups through the identity cache are
+    /// affected, so this needs `cache_ttl`. No code is soft by default.
+    pub fn soft_error_codes(mut self, codes: SoftErrorCodes) -> Self {
+        self.soft_errors = codes;
+        self
+    }
+
+    /// Return the last failure to reach the daemon again, without
+    /// contacting it, for `ttl` after it happened.
+    ///
+    /// Only failures showing the daemon is down are cached: a missing
+    /// socket, a refused connection or a timeout, once retries and failover
+    /// daemons are exhausted. Hot request paths then fail fast during an
+    /// outage instead of waiting for the connect timeout on every call.
+    /// Pass `None` to disable (the default).
+    pub fn negative_cache_ttl(mut self, ttl: impl Into<Option<Duration>>) -> Self {
+        self.negative_cache_ttl = ttl.into();
+        self
+    }
+
+    /// Set how symlinks in the socket path are treated.
+    ///
+    /// By default they are followed, so `/var/run/whoami.sock` reaches the
+    /// daemon at `/run/whoami.sock`; errors then name both paths. Relative
+    /// socket paths are made absolute when the client is built.
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// Only trust a daemon running as `uid`, e.g. `0` for a daemon run by
+    /// root.
+    ///
+    /// After connecting, the client reads the uid of the process serving
+    /// the socket with `SO_PEERCRED` and fails with
+    /// [`GetMyIdError::UntrustedDaemon`] before sending anything if it
+    /// differs, so a local process listening on a look-alike socket cannot
+    /// hand out fake identities. Applies to failover daemons too, but not to
+    /// custom transports. Pass `None` to disable (the default).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::{Client, GetMyIdError};
+    ///
+    /// let client = Client::builder().require_peer_uid(0).build();
+    /// if let Err(e @ GetMyIdError::UntrustedDaemon { .. }) = client.get_identity() {
+    ///     panic!("refusing identity: {e}");
+    /// }
+    /// ```
+    pub fn require_peer_uid(mut self, uid: impl Into<Option<u32>>) -> Self {
+        self.peer_uid = uid.into();
+        self
+    }
+
+    /// Send a fresh random nonce with every request and require the daemon
+    /// to echo it in its response.
+    ///
+    /// Responses without the nonce, e.g. a recorded answer replayed to the
+    /// client or one served from a cache between the client and the daemon,
+    /// are rejected with [`GetMyIdError::NonceMismatch`]. Requires a daemon
+    /// supporting nonces; disabled by default.
+    pub fn require_nonce(mut self, enabled: bool) -> Self {
+        self.require_nonce = enabled;
+        self
+    }
+
+    /// Give every request a fresh [request ID](RunnerRequest::with_request_id)
+    /// unless its runner context carries one already.
+    ///
+    /// The daemon echoes the ID in the identity's runner object, from where
+    /// it reaches the config server, so one lookup can be followed through
+    /// the logs of all three. Disabled by default.
+    pub fn request_ids(mut self, enabled: bool) -> Self {
+        self.request_ids = enabled;
+        self
+    }
+
+    /// Report every exchange with a daemon to `sink`, e.g. to count
+    /// requests and errors by [outcome](crate::RequestMetrics::outcome) and
+    /// record connect latencies and response sizes in histograms.
+    ///
+    /// Lookups through [`get_identity`](Client::get_identity) and its
+    /// variants are reported, once per daemon asked; views, typed
+    /// identities, long polls and [sessions](Client::connect) are not.
+    pub fn metrics(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.metrics = Some(Metrics::new(sink));
+        self
+    }
 
-    match daemon_response.data {
-        ResponseData::Success {
-            identity,
-            idm_url,
-            config_url,
-            token,
-            runner,
-        } => Ok(Identity {
-            identity,
-            idm_url,
-            config_url,
-            token,
-            runner,
-        }),
-        ResponseData::Error { .. } => Err(GetMyIdError::MissingField { field: "identity" }),
+    /// Send the trace context returned by `source` with every request, as
+    /// the [`TRACEPARENT_FIELD`](crate::TRACEPARENT_FIELD) runner field
+    /// (requires `otel` feature).
+    ///
+    /// `source` is called for each request and typically reads the current
+    /// span of the application's tracer; requests go out unchanged when it
+    /// returns `None`. The trace context is not part of the cache key, so
+    /// cached identities carry the one of the lookup that fetched them.
+    #[cfg(feature = "otel")]
+    pub fn trace_context(
+        mut self,
+        source: impl Fn() -> Option<crate::TraceParent> + Send + Sync + 'static,
+    ) -> Self {
+        self.trace_context = Some(TraceContext::new(source));
+        self
     }
+
+    /// Require the daemon to sign its responses with the Ed25519 key whose
+    /// raw 32-byte public key is `public_key` (requires `signing` feature).
+    ///
+    /// Requests then ask the daemon for a signed answer, and responses that
+    /// are not signed or whose signature does not verify are rejected with
+    /// [`GetMyIdError::InvalidSignature`] before they are parsed, so an
+    /// identity relayed or cached on its way cannot be tampered with.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let public_key = std::fs::read("/etc/whoami/daemon.pub")?;
+    /// let client = getmyid::Client::builder()
+    ///     .daemon_public_key(public_key)
+    ///     .build();
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "signing")]
+    pub fn daemon_public_key(mut self, public_key: impl Into<Vec<u8>>) -> Self {
+        self.daemon_public_key = Some(public_key.into().into());
+        self
+    }
+
+    /// Use a custom transport instead of the Unix socket.
+    ///
+    /// The socket path and timeouts configured on this builder are not
+    /// applied to custom transports.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Replace the socket path with the first live candidate, if any.
+    fn discover(&mut self) {
+        if self.transport.is_some() {
+            return;
+        }
+        if let Some(candidates) = self.socket_candidates.take() {
+            if let Some(path) = probe_candidates(candidates, self.socket_type, self.connect_timeout)
+            {
+                self.socket_path = path;
+            }
+        }
+    }
+
+    /// Build the client.
+    pub fn build(mut self) -> Client {
+        self.discover();
+        self.socket_path = absolute_socket_path(self.socket_path);
+        let unix_transport = self
+            .transport
+            .is_none()
+            .then(|| self.unix_transport(&self.socket_path));
+        let transport = self.transport.take().unwrap_or_else(|| {
+            let transport: Arc<dyn Transport> = Arc::new(unix_transport.clone().unwrap());
+            transport
+        });
+        let failover = std::mem::take(&mut self.failover_sockets)
+            .into_iter()
+            .map(absolute_socket_path)
+            .map(|path| {
+                let transport: Arc<dyn Transport> = Arc::new(self.unix_transport(&path));
+                (path, transport)
+            })
+            .collect();
+
+        Client {
+            socket_path: self.socket_path,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            extras_key_case: self.extras_key_case,
+            hostname_policy: self.hostname_policy,
+            json_limits: self.json_limits,
+            strict_parsing: self.strict_parsing,
+            soft_errors: self.soft_errors,
+            max_response_bytes: self.max_response_bytes,
+            default_runner: self
+                .default_runner
+                .map(|r| r.normalize_keys(self.extras_key_case)),
+            retry: self.retry,
+            cache: self
+                .cache_ttl
+                .map(|ttl| Arc::new(IdentityCache::new(ttl, self.cache_limits))),
+            negative_cache: self
+                .negative_cache_ttl
+                .map(|ttl| Arc::new(NegativeCache::new(ttl))),
+            pressure: Arc::default(),
+            flights: Arc::default(),
+            transport,
+            unix_transport,
+            failover,
+            #[cfg(feature = "signing")]
+            daemon_public_key: self.daemon_public_key,
+            require_nonce: self.require_nonce,
+            request_ids: self.request_ids,
+            metrics: self.metrics,
+            #[cfg(feature = "otel")]
+            trace_context: self.trace_context,
+        }
+    }
+
+    fn unix_transport(&self, path: &Path) -> UnixTransport {
+        UnixTransport::new(path)
+            .with_socket_type(self.socket_type)
+            .with_symlink_policy(self.symlink_policy)
+            .with_connect_timeout(self.connect_timeout)
+            .with_read_timeout(self.read_timeout)
+            .with_write_timeout(self.write_timeout)
+            .with_required_peer_uid(self.peer_uid)
+    }
+}
+
+/// Read the daemon's response into `response` until it closes the
+/// connection, failing once it grows beyond `max` bytes.
+///
+/// The capacity of `response` is reused.
+pub(crate) fn read_response(
+    stream: &mut dyn Read,
+    response: &mut String,
+    max: usize,
+) -> Result<()> {
+    let mut bytes = std::mem::take(response).into_bytes();
+    bytes.clear();
+    Read::take(stream, read_limit(max))
+        .read_to_end(&mut bytes)
+        .map_err(GetMyIdError::ReadError)?;
+    *response = response_text(bytes, max)?;
+    Ok(())
+}
+
+/// How many bytes to read to tell whether a response exceeds `max`.
+pub(crate) fn read_limit(max: usize) -> u64 {
+    (max as u64).saturating_add(1)
+}
+
+/// Decode a response read up to [`read_limit`] bytes, failing if it
+/// exceeds `max`.
+pub(crate) fn response_text(bytes: Vec<u8>, max: usize) -> Result<String> {
+    if bytes.len() > max {
+        return Err(GetMyIdError::ResponseTooLarge { max });
+    }
+    String::from_utf8(bytes)
+        .map_err(|e| GetMyIdError::ReadError(io::Error::new(io::ErrorKind::InvalidData, e)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::DaemonErrorCode;
 
     #[test]
     fn test_parse_success_response() {
         let response = r#"{"status":"ok","identity":"BILLING_PROD","idm_url":"https://auth.example.com/oauth2/billing","config_url":"https://config.example.com/api/billing","token":"tok_billing_xxx","runner":{"identity":"BILLING_PROD","hostname":"worker-01","process":"billing-app","pid":1234,"uid":1001,"gid":1001}}"#;
-        
+
         let identity = parse_response(response).unwrap();
-        
+
         assert_eq!(identity.identity, "BILLING_PROD");
         assert_eq!(identity.idm_url, "https://auth.example.com/oauth2/billing");
-        assert_eq!(identity.config_url, "https://config.example.com/api/billing");
+        assert_eq!(
+            identity.config_url,
+            "https://config.example.com/api/billing"
+        );
         assert_eq!(identity.token, "tok_billing_xxx");
         assert_eq!(identity.runner.identity, "BILLING_PROD");
         assert_eq!(identity.runner.hostname, "worker-01");
@@ -268,9 +1612,9 @@ mod tests {
     #[test]
     fn test_parse_success_response_with_instance_id() {
         let response = r#"{"status":"ok","identity":"TRUSTEE_AGENT","idm_url":"https://auth.example.com/oauth2/trustee","config_url":"https://config.example.com/api/trustee","token":"tok_trustee_xxx","runner":{"instance_id":42,"timestamp":1738512000,"identity":"TRUSTEE_AGENT","hostname":"worker-03","process":"trustee","pid":26567,"uid":1000,"gid":1000}}"#;
-        
+
         let identity = parse_response(response).unwrap();
-        
+
         assert_eq!(identity.identity, "TRUSTEE_AGENT");
         assert_eq!(identity.runner.instance_id, Some(42));
         assert_eq!(identity.runner.timestamp, Some(1738512000));
@@ -278,28 +1622,77 @@ mod tests {
         assert_eq!(identity.runner.pid, 26567);
     }
 
+    #[test]
+    fn test_parse_token_expiry() {
+        let response = r#"{"status":"ok","identity":"A","idm_url":"","config_url":"","token":"t","expires_at":1900000000,"runner":{}}"#;
+        assert_eq!(
+            parse_response(response).unwrap().expires_at,
+            Some(1900000000)
+        );
+
+        let response = r#"{"status":"ok","identity":"A","idm_url":"","config_url":"","token":"t","ttl":300,"runner":{}}"#;
+        let identity = parse_response(response).unwrap();
+        assert!(identity.expires_in().unwrap() > Duration::from_secs(290));
+        assert!(!identity.runner.extra.contains_key("ttl"));
+    }
+
     #[test]
     fn test_parse_error_response() {
         let response = r#"{"status":"error","error_code":"E_NO_MATCH","message":"No identity rule matches process 'unknown' (uid=1000)"}"#;
-        
+
         let result = parse_response(response);
-        
+
         match result {
             Err(GetMyIdError::DaemonError { code, message }) => {
-                assert_eq!(code, "E_NO_MATCH");
+                assert_eq!(code, DaemonErrorCode::NoMatch);
                 assert!(message.contains("No identity rule matches"));
             }
             _ => panic!("Expected DaemonError"),
         }
     }
 
+    #[test]
+    fn test_parse_degraded_response() {
+        let response =
+            r#"{"status":"degraded","message":"rules reload in progress","retry_after_ms":1500}"#;
+
+        match parse_response(response) {
+            Err(GetMyIdError::DaemonDegraded {
+                message,
+                code,
+                retry_after,
+            }) => {
+                assert_eq!(message, "rules reload in progress");
+                assert_eq!(code, None);
+                assert_eq!(retry_after, Some(Duration::from_millis(1500)));
+            }
+            other => panic!("Expected DaemonDegraded, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_degraded_response_with_error_code() {
+        let response = r#"{"status":"degraded","error_code":"E_MAINTENANCE","message":"maintenance","retry_after_ms":500}"#;
+
+        match parse_response(response) {
+            Err(GetMyIdError::DaemonDegraded {
+                code, retry_after, ..
+            }) => {
+                assert_eq!(code, Some("E_MAINTENANCE".into()));
+                assert_eq!(retry_after, Some(Duration::from_millis(500)));
+            }
+            other => panic!("Expected DaemonDegraded, got {other:?}"),
+        }
+        assert_eq!(check_known_fields(response, None).ok(), Some(()));
+    }
+
     #[test]
     fn test_parse_invalid_json() {
         let response = "not json";
-        
+
         let result = parse_response(response);
-        
-        assert!(matches!(result, Err(GetMyIdError::InvalidJson(_))));
+
+        assert!(matches!(result, Err(GetMyIdError::InvalidJson { .. })));
     }
 
     #[test]
@@ -308,35 +1701,925 @@ mod tests {
             .socket_path("/tmp/test.sock")
             .timeout(Duration::from_secs(10))
             .build();
-        
+
         assert_eq!(client.socket_path(), Path::new("/tmp/test.sock"));
         assert_eq!(client.timeout(), Some(Duration::from_secs(10)));
     }
 
     #[test]
     fn test_client_builder_no_timeout() {
+        let client = Client::builder().timeout(None).build();
+
+        assert_eq!(client.timeout(), None);
+        assert_eq!(client.connect_timeout(), None);
+        assert_eq!(client.write_timeout(), None);
+    }
+
+    #[test]
+    fn test_client_builder_separate_timeouts() {
         let client = Client::builder()
-            .timeout(None)
+            .timeout(Duration::from_secs(1))
+            .connect_timeout(Duration::from_secs(30))
+            .read_timeout(Duration::from_millis(500))
             .build();
-        
-        assert_eq!(client.timeout(), None);
+
+        assert_eq!(client.connect_timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(client.read_timeout(), Some(Duration::from_millis(500)));
+        assert_eq!(client.write_timeout(), Some(Duration::from_secs(1)));
+        assert_eq!(client.timeout(), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_client_builder_abstract_socket() {
+        let client = Client::builder().abstract_socket("whoami").build();
+
+        assert_eq!(client.socket_path(), Path::new("@whoami"));
+        assert!(!matches!(
+            client.get_identity(),
+            Err(GetMyIdError::SocketNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_client_builder_socket_candidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let live = dir.path().join("whoami.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&live).unwrap();
+
+        let client = Client::builder()
+            .socket_candidates([dir.path().join("missing.sock"), live.clone()])
+            .build();
+        assert_eq!(client.socket_path(), live);
+
+        let client = Client::builder()
+            .socket_path("/tmp/fallback.sock")
+            .socket_candidates(["/nonexistent/whoami.sock"])
+            .build();
+        assert_eq!(client.socket_path(), Path::new("/tmp/fallback.sock"));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_failover_sockets() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let degraded = FakeDaemon::with_response(FakeResponse::Raw(
+            r#"{"status":"degraded","message":"reloading"}"#.to_string(),
+        ))
+        .unwrap();
+        let healthy = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path("/nonexistent/whoami.sock")
+            .failover_sockets([degraded.socket_path(), healthy.socket_path()])
+            .build();
+
+        assert_eq!(client.get_identity().unwrap().identity, "TEST_IDENTITY");
+        assert_eq!(degraded.requests().len(), 1);
+
+        healthy.respond_with(FakeResponse::Raw(
+            r#"{"status":"degraded","message":"draining"}"#.to_string(),
+        ));
+        match client.get_identity() {
+            Err(GetMyIdError::AllEndpointsFailed { failures }) => {
+                assert_eq!(failures.len(), 3);
+                assert!(matches!(failures[0].1, GetMyIdError::SocketNotFound(_)));
+                assert!(matches!(failures[1].1, GetMyIdError::DaemonDegraded { .. }));
+                assert_eq!(failures[2].0, healthy.socket_path());
+            }
+            other => panic!("Expected AllEndpointsFailed, got {other:?}"),
+        }
+
+        healthy.respond_with(FakeResponse::no_match());
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::DaemonError { .. })
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_max_response_bytes() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        assert_eq!(client.max_response_bytes(), DEFAULT_MAX_RESPONSE_BYTES);
+        assert!(client.get_identity().is_ok());
+
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .max_response_bytes(64)
+            .build();
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::ResponseTooLarge { max: 64 })
+        ));
+
+        let mut response = String::new();
+        read_response(&mut "{}".as_bytes(), &mut response, 2).unwrap();
+        assert_eq!(response, "{}");
+        assert!(matches!(
+            read_response(&mut "{} ".as_bytes(), &mut response, 2),
+            Err(GetMyIdError::ResponseTooLarge { max: 2 })
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_from_fd() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let stream = UnixStream::connect(daemon.socket_path()).unwrap();
+        let client = Client::from_fd(stream.into());
+
+        let identity = client.get_identity().unwrap();
+        assert_eq!(identity.identity, "TEST_IDENTITY");
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::ConnectionFailed { source, .. })
+                if source.kind() == std::io::ErrorKind::NotConnected
+        ));
+        assert_eq!(daemon.requests().len(), 1);
     }
 
     #[test]
     fn test_default_client() {
         let client = Client::new();
-        
+
         assert_eq!(client.socket_path(), Path::new(DEFAULT_SOCKET_PATH));
         assert_eq!(client.timeout(), Some(DEFAULT_TIMEOUT));
     }
 
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_extras_key_case() {
+        use crate::testing::FakeDaemon;
+        use crate::KeyCase;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .extras_key_case(KeyCase::Camel)
+            .build();
+
+        let identity = client
+            .get_identity_with_runner(Some(RunnerRequest::new().with_field("deploy_region", "eu")))
+            .unwrap();
+
+        let sent = daemon.requests()[0].runner().unwrap();
+        assert_eq!(sent.extra.get("deployRegion").unwrap(), "eu");
+        assert_eq!(identity.runner.extra.get("deployRegion").unwrap(), "eu");
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_hostname_policy() {
+        use crate::testing::FakeDaemon;
+        use crate::HostnamePolicy;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let local = procfs::hostname().unwrap();
+        assert_ne!(local, "test-host");
+
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        assert_eq!(client.hostname_policy(), HostnamePolicy::Daemon);
+        let runner = client.get_identity().unwrap().runner.clone();
+        assert_eq!(runner.hostname, "test-host");
+        assert_eq!(runner.client_hostname.as_deref(), Some(local.as_str()));
+
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .hostname_policy(HostnamePolicy::Client)
+            .build();
+        let runner = client.get_identity().unwrap().runner.clone();
+        assert_eq!(runner.hostname, local);
+        assert_eq!(runner.client_hostname, Some(local));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_json_limits() {
+        use crate::testing::FakeDaemon;
+        use crate::JsonLimits;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let runner =
+            RunnerRequest::new().with_field("labels", serde_json::json!({"team": ["billing"]}));
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        assert_eq!(client.json_limits(), JsonLimits::new());
+        assert!(client
+            .get_identity_with_runner(Some(runner.clone()))
+            .is_ok());
+
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .json_limits(JsonLimits::new().with_max_depth(1))
+            .build();
+        match client.get_identity_with_runner(Some(runner.clone())) {
+            Err(GetMyIdError::MalformedResponse { field, limit, max }) => {
+                assert_eq!(
+                    (field.as_str(), limit, max),
+                    ("runner.labels", "max_depth", 1)
+                );
+            }
+            other => panic!("expected MalformedResponse, got {other:?}"),
+        }
+        let typed = client.get_identity_as::<serde_json::Value>(Some(runner.clone()));
+        assert!(matches!(
+            typed,
+            Err(GetMyIdError::MalformedResponse {
+                limit: "max_depth",
+                ..
+            })
+        ));
+        let mut buf = String::new();
+        assert!(matches!(
+            client.get_identity_view(Some(runner), &mut buf),
+            Err(GetMyIdError::MalformedResponse {
+                limit: "max_depth",
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_get_identity_raw() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .cache_ttl(Duration::from_secs(60))
+            .build();
+
+        let raw = client.get_identity_raw().unwrap();
+        assert_eq!(raw.identity.identity, "TEST_IDENTITY");
+        assert_eq!(parse_response(&raw.raw).unwrap().token, raw.identity.token);
+        assert_eq!(raw.json().unwrap()["status"], "ok");
+
+        // Raw lookups bypass the cache but refresh it
+        client.get_identity_raw().unwrap();
+        assert_eq!(client.get_identity().unwrap(), raw.identity);
+        assert_eq!(daemon.requests().len(), 2);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_concurrent_lookups_coalesce() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let delayed = FakeResponse::default().delayed(Duration::from_millis(100));
+        let daemon = FakeDaemon::with_response(delayed).unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+
+        let barrier = std::sync::Barrier::new(8);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    assert_eq!(client.get_identity().unwrap().token, "tok_test");
+                });
+            }
+        });
+        assert_eq!(daemon.requests().len(), 1);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_concurrent_rejections_coalesce() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let delayed = FakeResponse::no_match().delayed(Duration::from_millis(100));
+        let daemon = FakeDaemon::with_response(delayed).unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+
+        let barrier = std::sync::Barrier::new(8);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    assert!(client.get_identity().unwrap_err().is_identity_rejected());
+                });
+            }
+        });
+        assert_eq!(daemon.requests().len(), 1);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_check_compatibility() {
+        use crate::protocol::DaemonResponse;
+        use crate::testing::{FakeDaemon, FakeResponse};
+        use crate::Compatibility;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let compatibility = client.check_compatibility().unwrap();
+        assert_eq!(compatibility, Compatibility::Unknown);
+
+        let identity = DaemonResponse::identity(FakeDaemon::sample_identity());
+        let mut response = serde_json::to_value(identity).unwrap();
+        response["protocol_version"] = 1.into();
+        response["daemon_version"] = "0.9.0".into();
+        daemon.respond_with(FakeResponse::Raw(response.to_string()));
+        let raw = client.get_identity_raw().unwrap();
+        assert_eq!(raw.daemon_version.protocol, Some(1));
+        assert_eq!(raw.daemon_version.release.as_deref(), Some("0.9.0"));
+        assert_eq!(client.check_compatibility().unwrap(), Compatibility::Newer);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_soft_error_codes() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+        use crate::SoftErrorCodes;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let soft = SoftErrorCodes::default();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .cache_ttl(Duration::from_millis(20))
+            .soft_error_codes(soft.clone())
+            .build();
+        let identity = client.get_identity().unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+
+        daemon.respond_with(FakeResponse::error("E_MAINTENANCE", "upgrading"));
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::DaemonError { .. })
+        ));
+
+        // Adjusted at runtime through the shared handle
+        soft.insert("E_MAINTENANCE");
+        assert_eq!(client.get_identity().unwrap(), identity);
+        assert_eq!(client.soft_error_codes().soft_failures(), 1);
+        assert_eq!(soft.last_failure().unwrap().message, "upgrading");
+
+        // Nothing cached for this runner context
+        let runner = RunnerRequest::new().with_instance_id(7);
+        assert!(client.get_identity_with_runner(Some(runner)).is_err());
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_degraded_serves_cached_identity() {
+        use crate::protocol::DaemonResponse;
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .cache_ttl(Duration::from_millis(20))
+            .build();
+        let identity = client.get_identity().unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+
+        let degraded = DaemonResponse::degraded("maintenance window", None);
+        daemon.respond_with(FakeResponse::Raw(serde_json::to_string(&degraded).unwrap()));
+        assert_eq!(client.get_identity().unwrap(), identity);
+        let failure = client.soft_error_codes().last_failure().unwrap();
+        assert_eq!(failure.message, "maintenance window");
+        assert_eq!(failure.code, None);
+
+        daemon.respond_with(FakeResponse::Raw(
+            r#"{"status":"degraded","error_code":"E_MAINTENANCE","message":"upgrading"}"#
+                .to_string(),
+        ));
+        assert_eq!(client.get_identity().unwrap(), identity);
+        let failure = client.soft_error_codes().last_failure().unwrap();
+        assert_eq!(failure.code, Some("E_MAINTENANCE".into()));
+
+        // Nothing cached for this runner context
+        let runner = RunnerRequest::new().with_instance_id(7);
+        assert!(matches!(
+            client.get_identity_with_runner(Some(runner)),
+            Err(GetMyIdError::DaemonDegraded { .. })
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_wait_for_change() {
+        use crate::protocol::DaemonResponse;
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let daemon = FakeDaemon::with_handler(|request| {
+            let json = request.json().unwrap_or_default();
+            let response = match json["wait"]["since"].as_str() {
+                Some("v2") => DaemonResponse::unchanged("v2"),
+                _ => DaemonResponse::identity(FakeDaemon::sample_identity()).with_version("v2"),
+            };
+            FakeResponse::Raw(serde_json::to_string(&response).unwrap())
+        })
+        .unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let timeout = Duration::from_secs(1);
+
+        let change = client.wait_for_change("", timeout).unwrap();
+        match &change {
+            IdentityChange::Changed { identity, version } => {
+                assert_eq!(identity.token, "tok_test");
+                assert_eq!(version, "v2");
+            }
+            other => panic!("unexpected change: {other:?}"),
+        }
+        let unchanged = IdentityChange::Unchanged {
+            version: "v2".to_string(),
+        };
+        assert_eq!(client.wait_for_change("v2", timeout).unwrap(), unchanged);
+        let wait = daemon.requests()[1].json().unwrap()["wait"].clone();
+        assert_eq!(wait["timeout_ms"], 1000);
+
+        // Daemons without long-poll support answer without a version
+        daemon.respond_with(FakeResponse::default());
+        assert!(matches!(
+            client.wait_for_change("v2", timeout),
+            Err(GetMyIdError::MissingField { field: "version" })
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_strict_parsing() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .strict_parsing(true)
+            .build();
+        assert!(client.strict_parsing());
+        let runner = RunnerRequest::new().with_field("region", "eu-west-1");
+        assert!(client.get_identity_with_runner(Some(runner)).is_ok());
+
+        let mut identity = FakeDaemon::sample_identity();
+        identity
+            .runner
+            .extra
+            .insert("color".to_string(), "blue".into());
+        daemon.respond_with(FakeResponse::identity(identity));
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::UnknownField { path }) if path == "runner.color"
+        ));
+
+        let lenient = Client::builder().socket_path(daemon.socket_path()).build();
+        assert!(!lenient.strict_parsing());
+        let identity = lenient.get_identity().unwrap();
+        assert_eq!(identity.runner.extra["color"], "blue");
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_get_identity_with_context() {
+        use crate::testing::FakeDaemon;
+
+        #[derive(Serialize)]
+        struct Context {
+            instance_id: u64,
+            tenant: &'static str,
+        }
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let context = Context {
+            instance_id: 3,
+            tenant: "acme",
+        };
+        let identity = client.get_identity_with_context(&context).unwrap();
+
+        assert_eq!(identity.runner.instance_id, Some(3));
+        assert_eq!(identity.runner.extra["tenant"], "acme");
+        let sent = daemon.requests()[0].runner().unwrap();
+        assert_eq!(sent.extra["tenant"], "acme");
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_get_identity_as() {
+        use crate::testing::FakeDaemon;
+
+        #[derive(serde::Deserialize)]
+        struct Placement {
+            identity: String,
+            region: String,
+        }
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let runner = RunnerRequest::new().with_region("eu");
+        let identity: TypedIdentity<Placement> = client.get_identity_as(Some(runner)).unwrap();
+
+        assert_eq!(identity.token, "tok_test");
+        assert_eq!(identity.runner.identity, "TEST_IDENTITY");
+        assert_eq!(identity.runner.region, "eu");
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_reserved_runner_field() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+
+        let runner = RunnerRequest::new().with_field("hostname", "spoofed");
+        let result = client.get_identity_with_runner(Some(runner));
+        assert!(matches!(
+            result,
+            Err(GetMyIdError::ReservedField { field }) if field == "hostname"
+        ));
+        assert!(daemon.requests().is_empty());
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_require_peer_uid() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let uid = unsafe { libc::geteuid() };
+        let builder = Client::builder().socket_path(daemon.socket_path());
+        let client = builder.clone().require_peer_uid(uid).build();
+        assert_eq!(client.get_identity().unwrap().token, "tok_test");
+
+        let client = builder.require_peer_uid(uid + 1).build();
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::UntrustedDaemon { expected, .. }) if expected == uid + 1
+        ));
+        let mut request = client.try_get_identity().unwrap();
+        let result = loop {
+            if let std::task::Poll::Ready(result) = request.poll() {
+                break result;
+            }
+        };
+        assert!(matches!(result, Err(GetMyIdError::UntrustedDaemon { .. })));
+    }
+
+    #[cfg(all(feature = "signing", feature = "test-util"))]
+    #[test]
+    fn test_daemon_public_key() {
+        use crate::protocol::DaemonResponse;
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let response = DaemonResponse::identity(FakeDaemon::sample_identity());
+        let response = serde_json::to_string(&response).unwrap();
+        let (signed, public_key) = crate::signing::sign(&response, &[7; 32]);
+        let daemon = FakeDaemon::with_response(FakeResponse::Raw(signed)).unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .daemon_public_key(public_key)
+            .build();
+        assert_eq!(client.get_identity().unwrap().token, "tok_test");
+        assert_eq!(daemon.requests()[0].json().unwrap()["sign"], true);
+        let mut buf = String::new();
+        let view = client.get_identity_view(None, &mut buf).unwrap();
+        assert_eq!(view.token, "tok_test");
+
+        daemon.respond_with(FakeResponse::default());
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::InvalidSignature(_))
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_require_nonce() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .require_nonce(true)
+            .build();
+        client.get_identity().unwrap();
+        client.connect().unwrap().get_identity().unwrap();
+        let requests = daemon.requests();
+        let nonces: Vec<_> = requests.iter().map(|r| r.nonce().unwrap()).collect();
+        assert_eq!(nonces[0].len(), 32);
+        assert_ne!(nonces[0], nonces[1]);
+
+        // A recorded answer to an earlier request is rejected
+        let replayed = crate::protocol::DaemonResponse::identity(FakeDaemon::sample_identity())
+            .with_nonce(Some(nonces[0].clone()));
+        let replayed = serde_json::to_string(&replayed).unwrap();
+        daemon.respond_with(FakeResponse::Raw(replayed));
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::NonceMismatch { actual: Some(actual), .. }) if actual == nonces[0]
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_metrics_sink() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+        use crate::RequestMetrics;
+        use std::sync::Mutex;
+
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&recorded);
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path().with_extension("missing"))
+            .failover_socket(daemon.socket_path())
+            .retries(0)
+            .cache_ttl(Duration::from_secs(60))
+            .metrics(move |request: &RequestMetrics<'_>| {
+                let outcome = request.outcome().to_string();
+                let connected = request.connect_latency.is_some();
+                let bytes = request.response_bytes;
+                sink.lock().unwrap().push((outcome, connected, bytes));
+            })
+            .build();
+
+        client.get_identity().unwrap();
+        client.get_identity().unwrap();
+        daemon.respond_with(FakeResponse::no_match());
+        let runner = RunnerRequest::new();
+        client.get_identity_with_runner(Some(runner)).unwrap_err();
+
+        // Each daemon asked is reported, cache hits are not
+        let recorded = recorded.lock().unwrap();
+        let outcomes: Vec<_> = recorded.iter().map(|(o, c, _)| (o.as_str(), *c)).collect();
+        let expected = [
+            ("unreachable", false),
+            ("ok", true),
+            ("unreachable", false),
+            ("E_NO_MATCH", true),
+        ];
+        assert_eq!(outcomes, expected);
+        assert!(recorded[1].2 > 0 && recorded[3].2 > 0);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_request_ids() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .strict_parsing(true)
+            .request_ids(true)
+            .build();
+
+        let first = client.get_identity().unwrap();
+        let second = client.get_identity().unwrap();
+        let requests = daemon.requests();
+        let sent = requests[0].runner().unwrap().request_id.unwrap();
+        assert_eq!(first.runner.request_id(), Some(sent.as_str()));
+        assert_ne!(second.runner.request_id(), first.runner.request_id());
+
+        let runner = RunnerRequest {
+            request_id: Some("req-1".to_string()),
+            ..RunnerRequest::default()
+        };
+        let identity = client.get_identity_with_runner(Some(runner)).unwrap();
+        assert_eq!(identity.runner.request_id(), Some("req-1"));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_request_id_shared_by_retries() {
+        use crate::testing::{FakeDaemon, FakeResponse, Scenario};
+
+        let degraded = FakeResponse::Raw(r#"{"status":"degraded","message":"busy"}"#.to_string());
+        let primary = FakeDaemon::with_response(degraded.clone()).unwrap();
+        let failover = FakeDaemon::start().unwrap();
+        failover.play(
+            Scenario::new()
+                .then(degraded, 1)
+                .then(FakeResponse::default(), 1),
+        );
+        let client = Client::builder()
+            .socket_path(primary.socket_path())
+            .failover_socket(failover.socket_path())
+            .request_ids(true)
+            .retries(1)
+            .retry_backoff(Duration::from_millis(1))
+            .build();
+
+        let identity = client.get_identity().unwrap();
+        let ids: Vec<_> = primary
+            .requests()
+            .into_iter()
+            .chain(failover.requests())
+            .map(|request| request.runner().unwrap().request_id.unwrap())
+            .collect();
+        assert_eq!(ids.len(), 4);
+        assert!(ids.iter().all(|id| *id == ids[0]));
+        assert_eq!(identity.runner.request_id(), Some(ids[0].as_str()));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_ping() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let pong = FakeResponse::Raw(r#"{"status":"pong"}"#.to_string());
+        let daemon = FakeDaemon::with_response(pong).unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        client.ping().unwrap();
+        assert_eq!(daemon.requests()[0].json().unwrap()["ping"], true);
+
+        // Daemons without ping support answer as usual
+        daemon.respond_with(FakeResponse::default());
+        client.ping().unwrap();
+        daemon.respond_with(FakeResponse::no_match());
+        client.ping().unwrap();
+        daemon.respond_with(FakeResponse::error("E_INTERNAL", "database down"));
+        client.ping().unwrap_err();
+        daemon.respond_with(FakeResponse::goaway());
+        assert!(client.ping().unwrap_err().is_transient());
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_daemon_info() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let info = r#"{"status":"info","protocol_version":1,"daemon_version":"0.2.0","features":["wait","ping"]}"#;
+        let daemon = FakeDaemon::with_response(FakeResponse::Raw(info.to_string())).unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let info = client.daemon_info().unwrap();
+        assert_eq!(daemon.requests()[0].json().unwrap()["info"], true);
+        assert!(info.supports("ping"));
+        assert!(info.compatibility().is_tested());
+
+        // Daemons without info support answer with an identity
+        daemon.respond_with(FakeResponse::default());
+        let info = client.daemon_info().unwrap();
+        assert_eq!(info.features, None);
+        daemon.respond_with(FakeResponse::goaway());
+        client.daemon_info().unwrap_err();
+    }
+
+    #[cfg(all(feature = "admin", feature = "test-util"))]
+    #[test]
+    fn test_get_identity_for_pid() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let daemon = FakeDaemon::with_handler(|request| {
+            let mut identity = FakeDaemon::sample_identity();
+            let target = request.json().and_then(|r| r["target_pid"].as_u64());
+            if let Some(pid) = target {
+                identity.runner.pid = u32::try_from(pid).unwrap();
+            }
+            FakeResponse::identity(identity)
+        })
+        .unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let identity = client.get_identity_for_pid(4242).unwrap();
+        assert_eq!(identity.runner.pid, 4242);
+
+        // Daemons without support answer for the caller
+        daemon.respond_with(FakeResponse::default());
+        let error = client.get_identity_for_pid(4242).unwrap_err();
+        let own = std::process::id();
+        assert!(matches!(error, GetMyIdError::PidMismatch { resolved, .. } if resolved == own));
+        daemon.respond_with(FakeResponse::error("E_ACCESS_DENIED", "not a supervisor"));
+        let error = client.get_identity_for_pid(4242).unwrap_err();
+        assert!(error.is_identity_rejected());
+    }
+
+    #[cfg(all(feature = "otel", feature = "test-util"))]
+    #[test]
+    fn test_trace_context() {
+        use crate::testing::FakeDaemon;
+        use crate::{TraceParent, TRACEPARENT_FIELD};
+
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let parent = TraceParent::parse(traceparent).unwrap();
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .strict_parsing(true)
+            .trace_context(move || Some(parent))
+            .build();
+
+        let identity = client.get_identity().unwrap();
+        assert_eq!(identity.runner.traceparent(), Some(parent));
+        let sent = daemon.requests()[0].runner().unwrap();
+        assert_eq!(sent.extra[TRACEPARENT_FIELD], traceparent);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_default_runner() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .default_runner(RunnerRequest::new().with_instance_id(1).with_region("eu"))
+            .build();
+
+        client.get_identity().unwrap();
+        client
+            .get_identity_with_runner(Some(RunnerRequest::new().with_instance_id(2)))
+            .unwrap();
+
+        let requests = daemon.requests();
+        let first = requests[0].runner().unwrap();
+        assert_eq!(first.instance_id, Some(1));
+        let second = requests[1].runner().unwrap();
+        assert_eq!(second.instance_id, Some(2));
+        assert_eq!(second.region.as_deref(), Some("eu"));
+    }
+
+    #[test]
+    fn test_client_builder_retry_and_cache() {
+        let client = Client::builder()
+            .retries(3)
+            .cache_ttl(Duration::from_secs(60))
+            .build();
+
+        assert_eq!(client.retries(), 3);
+        assert_eq!(client.cache_ttl(), Some(Duration::from_secs(60)));
+        assert_eq!(Client::new().cache_ttl(), None);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_retry_and_cache_against_fake_daemon() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+
+        let daemon = FakeDaemon::start().unwrap();
+        daemon.respond_with(FakeResponse::Raw(
+            r#"{"status":"degraded","message":"reloading"}"#.to_string(),
+        ));
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .retries(1)
+            .retry_backoff(Duration::from_millis(10))
+            .cache_ttl(Duration::from_secs(60))
+            .build();
+
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::DaemonDegraded { .. })
+        ));
+        assert_eq!(daemon.requests().len(), 2);
+
+        daemon.respond_with(FakeResponse::default());
+        client.get_identity().unwrap();
+        client.clone().get_identity().unwrap();
+        assert_eq!(daemon.requests().len(), 3);
+
+        // The cache hit is not a lookup; the degraded one failed after retrying
+        let pressure = client.pressure();
+        assert_eq!((pressure.in_flight, pressure.queued), (0, 0));
+        assert_eq!(pressure.recent_lookups, 2);
+        assert_eq!(pressure.error_rate, 0.5);
+    }
+
+    #[test]
+    fn test_negative_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("whoami.sock");
+        let client = Client::builder()
+            .socket_path(&path)
+            .timeout(Duration::from_millis(50))
+            .negative_cache_ttl(Duration::from_millis(100))
+            .build();
+        assert_eq!(
+            client.negative_cache_ttl(),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(Client::new().negative_cache_ttl(), None);
+
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::SocketNotFound(_))
+        ));
+
+        // The daemon comes back, but the outage is still cached
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(matches!(
+            client.clone().get_identity(),
+            Err(GetMyIdError::SocketNotFound(_))
+        ));
+
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::ReadError(_))
+        ));
+    }
+
     #[test]
     fn test_runner_request_builder() {
         let req = RunnerRequest::new()
             .with_instance_id(42)
             .with_timestamp(1738512000)
             .with_field("custom", "value");
-        
+
         assert_eq!(req.instance_id, Some(42));
         assert_eq!(req.timestamp, Some(1738512000));
         assert_eq!(req.extra.get("custom").unwrap(), "value");
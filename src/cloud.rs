@@ -0,0 +1,437 @@
+//! Cloud instance-identity fallback (requires `cloud` feature).
+//!
+//! Fleets often mix hosts running the whoami daemon with cloud VMs that do
+//! not. [`CloudMetadataProvider`] derives an identity from the instance
+//! metadata service instead, and [`DualStackProvider`](crate::DualStackProvider)
+//! uses it only when the daemon socket is absent.
+//!
+//! Identities from this provider are labeled: the runner carries an
+//! [`IDENTITY_SOURCE_FIELD`] entry naming the metadata service, and the
+//! token is the platform's signed attestation (the AWS PKCS#7 identity
+//! document signature, or a Google-signed identity JWT) for the config
+//! server to verify. Nothing here is vouched for by `SO_PEERCRED`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use getmyid::{CloudMetadataProvider, DualStackProvider, IdentityProvider};
+//!
+//! let provider = DualStackProvider::new(
+//!     getmyid::Client::new(),
+//!     CloudMetadataProvider::aws()
+//!         .identity("BILLING_PROD")
+//!         .config_url("https://config.example.com/api/billing"),
+//! );
+//! let identity = provider.get_identity()?;
+//! # Ok::<(), getmyid::GetMyIdError>(())
+//! ```
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::{GetMyIdError, Result};
+use crate::procfs;
+use crate::provider::IdentityProvider;
+use crate::types::{Identity, Runner, RunnerRequest};
+
+/// Runner field naming the metadata service an identity came from.
+pub const IDENTITY_SOURCE_FIELD: &str = "identity_source";
+
+/// Address of the link-local instance metadata service.
+const DEFAULT_METADATA_ADDR: &str = "169.254.169.254:80";
+
+/// Default timeout for each metadata request; the service is link-local, so
+/// a slow answer means it is not there.
+const DEFAULT_METADATA_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Lifetime requested for AWS IMDSv2 session tokens.
+const AWS_TOKEN_TTL_SECS: &str = "60";
+
+/// Cloud platform whose metadata service is queried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloudPlatform {
+    /// Amazon EC2 instance metadata service (IMDSv2).
+    Aws,
+    /// Google Compute Engine metadata server.
+    Gcp,
+}
+
+impl CloudPlatform {
+    /// Label stored under [`IDENTITY_SOURCE_FIELD`].
+    pub fn source(self) -> &'static str {
+        match self {
+            Self::Aws => "aws-imds",
+            Self::Gcp => "gcp-metadata",
+        }
+    }
+}
+
+/// Provider deriving the identity from a cloud instance metadata service.
+#[derive(Debug, Clone)]
+pub struct CloudMetadataProvider {
+    platform: CloudPlatform,
+    addr: String,
+    timeout: Duration,
+    identity: Option<String>,
+    idm_url: String,
+    config_url: String,
+    audience: Option<String>,
+}
+
+/// What a metadata service reports about the instance.
+struct InstanceFacts {
+    default_identity: String,
+    token: String,
+    account: String,
+    instance_id: String,
+    region: String,
+}
+
+/// Fields of the AWS instance identity document used here.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AwsIdentityDocument {
+    instance_id: String,
+    account_id: String,
+    region: String,
+}
+
+impl CloudMetadataProvider {
+    /// Create a provider for `platform` using the link-local metadata service.
+    pub fn new(platform: CloudPlatform) -> Self {
+        Self {
+            platform,
+            addr: DEFAULT_METADATA_ADDR.to_string(),
+            timeout: DEFAULT_METADATA_TIMEOUT,
+            identity: None,
+            idm_url: String::new(),
+            config_url: String::new(),
+            audience: None,
+        }
+    }
+
+    /// Create a provider for Amazon EC2.
+    pub fn aws() -> Self {
+        Self::new(CloudPlatform::Aws)
+    }
+
+    /// Create a provider for Google Compute Engine.
+    pub fn gcp() -> Self {
+        Self::new(CloudPlatform::Gcp)
+    }
+
+    /// Set the metadata service address (`host:port`).
+    pub fn endpoint(mut self, addr: impl Into<String>) -> Self {
+        self.addr = addr.into();
+        self
+    }
+
+    /// Set the timeout for each metadata request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the application-level identity name.
+    ///
+    /// Defaults to `<platform>:<account or project>:<instance id>`.
+    pub fn identity(mut self, identity: impl Into<String>) -> Self {
+        self.identity = Some(identity.into());
+        self
+    }
+
+    /// Set the IDM URL returned with the identity.
+    pub fn idm_url(mut self, url: impl Into<String>) -> Self {
+        self.idm_url = url.into();
+        self
+    }
+
+    /// Set the config URL returned with the identity.
+    pub fn config_url(mut self, url: impl Into<String>) -> Self {
+        self.config_url = url.into();
+        self
+    }
+
+    /// Set the audience of the GCP identity token.
+    ///
+    /// Defaults to the config URL, or `getmyid` when none is set. Ignored on
+    /// AWS.
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    /// Get the platform queried.
+    pub fn platform(&self) -> CloudPlatform {
+        self.platform
+    }
+
+    fn fetch(&self) -> Result<Identity> {
+        let facts = match self.platform {
+            CloudPlatform::Aws => self.fetch_aws()?,
+            CloudPlatform::Gcp => self.fetch_gcp()?,
+        };
+
+        let identity = self.identity.clone().unwrap_or(facts.default_identity);
+        let (uid, gid) = procfs::uid_gid().unwrap_or_default();
+        let mut runner = Runner {
+            identity: identity.clone(),
+            hostname: procfs::hostname().unwrap_or_default(),
+            process: procfs::process_name().unwrap_or_default(),
+            pid: std::process::id(),
+            uid,
+            gid,
+            ..Runner::default()
+        };
+        runner.extra.extend([
+            (
+                IDENTITY_SOURCE_FIELD.to_string(),
+                self.platform.source().into(),
+            ),
+            ("cloud_account".to_string(), facts.account.into()),
+            ("cloud_instance_id".to_string(), facts.instance_id.into()),
+            ("cloud_region".to_string(), facts.region.into()),
+        ]);
+
+        Ok(Identity {
+            identity,
+            idm_url: self.idm_url.clone(),
+            config_url: self.config_url.clone(),
+            token: facts.token.into(),
+            expires_at: None,
+            bootstrap: None,
+            runner,
+        })
+    }
+
+    fn fetch_aws(&self) -> Result<InstanceFacts> {
+        let session = self.request(
+            "PUT",
+            "/latest/api/token",
+            &[("X-aws-ec2-metadata-token-ttl-seconds", AWS_TOKEN_TTL_SECS)],
+        )?;
+        let headers = [("X-aws-ec2-metadata-token", session.trim())];
+
+        let document = self.request(
+            "GET",
+            "/latest/dynamic/instance-identity/document",
+            &headers,
+        )?;
+        let document: AwsIdentityDocument =
+            serde_json::from_str(&document).map_err(GetMyIdError::invalid_json)?;
+        let signature = self.request("GET", "/latest/dynamic/instance-identity/pkcs7", &headers)?;
+
+        Ok(InstanceFacts {
+            default_identity: format!("aws:{}:{}", document.account_id, document.instance_id),
+            token: signature.trim().to_string(),
+            account: document.account_id,
+            instance_id: document.instance_id,
+            region: document.region,
+        })
+    }
+
+    fn fetch_gcp(&self) -> Result<InstanceFacts> {
+        let headers = [("Metadata-Flavor", "Google")];
+        let get = |path: &str| {
+            self.request("GET", &format!("/computeMetadata/v1/{path}"), &headers)
+                .map(|body| body.trim().to_string())
+        };
+
+        let project = get("project/project-id")?;
+        let instance_id = get("instance/id")?;
+        let zone = get("instance/zone")?;
+        let zone = zone.rsplit('/').next().unwrap_or_default().to_string();
+        let audience = self.audience.clone().unwrap_or_else(|| {
+            if self.config_url.is_empty() {
+                "getmyid".to_string()
+            } else {
+                self.config_url.clone()
+            }
+        });
+        let token = get(&format!(
+            "instance/service-accounts/default/identity?audience={}&format=full",
+            encode_query(&audience)
+        ))?;
+
+        Ok(InstanceFacts {
+            default_identity: format!("gcp:{project}:{instance_id}"),
+            token,
+            account: project,
+            instance_id,
+            region: zone,
+        })
+    }
+
+    /// Perform a bodyless HTTP/1.0 request and return the response body.
+    fn request(&self, method: &str, path: &str, headers: &[(&str, &str)]) -> Result<String> {
+        let unavailable = |e: std::io::Error| {
+            GetMyIdError::CloudMetadata(format!("{method} {} failed: {e}", self.addr))
+        };
+
+        let addr = self
+            .addr
+            .to_socket_addrs()
+            .map_err(unavailable)?
+            .next()
+            .ok_or_else(|| GetMyIdError::CloudMetadata(format!("cannot resolve {}", self.addr)))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(unavailable)?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(unavailable)?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(unavailable)?;
+
+        let mut request = format!("{method} {path} HTTP/1.0\r\nHost: {}\r\n", self.addr);
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("Content-Length: 0\r\n\r\n");
+        stream.write_all(request.as_bytes()).map_err(unavailable)?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(unavailable)?;
+
+        let (head, body) = response.split_once("\r\n\r\n").ok_or_else(|| {
+            GetMyIdError::CloudMetadata(format!("malformed HTTP response to {method} {path}"))
+        })?;
+        let status = head.split_whitespace().nth(1).unwrap_or_default();
+        if status != "200" {
+            return Err(GetMyIdError::CloudMetadata(format!(
+                "{method} {path} returned HTTP status {status}"
+            )));
+        }
+        Ok(body.to_string())
+    }
+}
+
+impl IdentityProvider for CloudMetadataProvider {
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        let mut identity = self.fetch()?;
+        if let Some(runner) = runner {
+            identity.runner.merge_request(runner);
+        }
+        Ok(identity)
+    }
+}
+
+/// Percent-encode a query parameter value.
+fn encode_query(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve `count` HTTP requests, answering each with `respond(request line)`.
+    fn serve<F>(count: usize, respond: F) -> String
+    where
+        F: Fn(&str) -> (u16, String) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(count) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let (status, body) = respond(&request);
+                let response = format!("HTTP/1.0 {status} OK\r\n\r\n{body}");
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_aws_identity() {
+        let addr = serve(3, |request| {
+            if request.starts_with("PUT /latest/api/token") {
+                return (200, "session".to_string());
+            }
+            assert!(request.contains("X-aws-ec2-metadata-token: session"));
+            if request.starts_with("GET /latest/dynamic/instance-identity/document") {
+                let document = r#"{"instanceId":"i-0abc","accountId":"123456789012","region":"eu-west-1","architecture":"x86_64"}"#;
+                (200, document.to_string())
+            } else {
+                (
+                    200,
+                    "MIAGCSqGSIb3DQEHAqCAMIACAQExCzAJBgUrDgMCGgUA\n".to_string(),
+                )
+            }
+        });
+
+        let provider = CloudMetadataProvider::aws().endpoint(addr);
+        let identity = provider
+            .get_identity_with_runner(Some(RunnerRequest::new().with_instance_id(4)))
+            .unwrap();
+
+        assert_eq!(identity.identity, "aws:123456789012:i-0abc");
+        assert_eq!(
+            identity.token,
+            "MIAGCSqGSIb3DQEHAqCAMIACAQExCzAJBgUrDgMCGgUA"
+        );
+        assert_eq!(identity.runner.extra[IDENTITY_SOURCE_FIELD], "aws-imds");
+        assert_eq!(identity.runner.extra["cloud_region"], "eu-west-1");
+        assert_eq!(identity.runner.instance_id, Some(4));
+        assert_eq!(identity.runner.pid, std::process::id());
+    }
+
+    #[test]
+    fn test_gcp_identity() {
+        let addr = serve(4, |request| {
+            assert!(request.contains("Metadata-Flavor: Google"));
+            let path = request.split_whitespace().nth(1).unwrap();
+            let body = match path {
+                "/computeMetadata/v1/project/project-id" => "billing-prod",
+                "/computeMetadata/v1/instance/id" => "4520031799277581759",
+                "/computeMetadata/v1/instance/zone" => "projects/1234/zones/us-central1-a",
+                _ => {
+                    assert!(path.contains("audience=https%3A%2F%2Fconfig.example.com"));
+                    "eyJhbGciOiJSUzI1NiJ9.e30.sig"
+                }
+            };
+            (200, body.to_string())
+        });
+
+        let provider = CloudMetadataProvider::gcp()
+            .endpoint(addr)
+            .identity("BILLING_PROD")
+            .config_url("https://config.example.com");
+        let identity = provider.get_identity().unwrap();
+
+        assert_eq!(identity.identity, "BILLING_PROD");
+        assert_eq!(identity.runner.identity, "BILLING_PROD");
+        assert_eq!(identity.token, "eyJhbGciOiJSUzI1NiJ9.e30.sig");
+        assert_eq!(identity.runner.extra[IDENTITY_SOURCE_FIELD], "gcp-metadata");
+        assert_eq!(identity.runner.extra["cloud_region"], "us-central1-a");
+    }
+
+    #[test]
+    fn test_metadata_errors() {
+        let addr = serve(1, |_| (404, String::new()));
+        assert!(matches!(
+            CloudMetadataProvider::aws().endpoint(addr).get_identity(),
+            Err(GetMyIdError::CloudMetadata(msg)) if msg.contains("404")
+        ));
+    }
+}
@@ -0,0 +1,6 @@
+//! Compatibility helpers for applications migrating to this crate.
+//!
+//! See [`legacy`] for the original request-less exchange used by
+//! hand-rolled socket clients.
+
+pub mod legacy;
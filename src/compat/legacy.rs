@@ -0,0 +1,205 @@
+//! The original whoami exchange: connect, send nothing, read to EOF.
+//!
+//! Applications that talked to the daemon with hand-rolled socket code can
+//! switch to [`exchange`] first, keeping the exact bytes they used to parse,
+//! and move to [`Client::get_identity`] once [`detect_format`] confirms the
+//! daemon speaks the current protocol.
+//!
+//! The clients themselves accept [`ResponseFormat::Flat`] responses from
+//! daemons predating the `runner` object, mapping them with [`parse_flat`],
+//! so the client can be upgraded before the daemon.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use getmyid::compat::legacy::{self, ResponseFormat};
+//! use getmyid::Client;
+//!
+//! let client = Client::new();
+//! let raw = legacy::exchange(&client)?;
+//!
+//! match legacy::detect_format(&raw) {
+//!     ResponseFormat::Runner => println!("daemon supports runner context"),
+//!     ResponseFormat::Flat => println!("daemon predates the runner object"),
+//!     other => println!("unexpected response: {other:?}"),
+//! }
+//! # Ok::<(), getmyid::GetMyIdError>(())
+//! ```
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::client::{read_response, Client};
+use crate::error::{GetMyIdError, Result};
+use crate::types::{Identity, Runner};
+
+/// Shape of a raw daemon response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// Current format, with process details nested in a `runner` object.
+    Runner,
+    /// Flat format used before the `runner` object, with `pid`, `uid`, `gid`
+    /// and `process` at the top level.
+    Flat,
+    /// An error response, which looks the same in both formats.
+    Error,
+    /// Not a recognizable whoami response.
+    Unknown,
+}
+
+/// Successful response in the flat format.
+#[derive(Debug, Deserialize)]
+struct FlatResponse {
+    identity: String,
+    #[serde(default, alias = "kanidm_url")]
+    idm_url: String,
+    #[serde(default)]
+    config_url: String,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    hostname: String,
+    #[serde(default)]
+    process: String,
+    #[serde(default)]
+    pid: u32,
+    #[serde(default)]
+    uid: u32,
+    #[serde(default)]
+    gid: u32,
+}
+
+/// Perform the original exchange and return the daemon's response verbatim.
+///
+/// No request is written; the daemon's reply is read until it closes the
+/// connection. The client's socket path, timeouts and maximum response size
+/// are honored.
+///
+/// # Errors
+///
+/// Returns an error if the socket cannot be reached or the response cannot
+/// be read. The response is not parsed.
+pub fn exchange(client: &Client) -> Result<String> {
+    let mut stream = client.dial()?;
+    let mut response = String::new();
+    read_response(&mut stream, &mut response, client.max_response_bytes())?;
+    Ok(response)
+}
+
+/// Perform the original exchange and report which format the daemon uses.
+pub fn probe(client: &Client) -> Result<ResponseFormat> {
+    exchange(client).map(|raw| detect_format(&raw))
+}
+
+/// Classify a raw daemon response without fully parsing it.
+pub fn detect_format(raw: &str) -> ResponseFormat {
+    let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(raw) else {
+        return ResponseFormat::Unknown;
+    };
+
+    match fields.get("status").and_then(Value::as_str) {
+        Some("ok") => {}
+        Some(_) => return ResponseFormat::Error,
+        None => return ResponseFormat::Unknown,
+    }
+
+    if fields.get("runner").is_some_and(Value::is_object) {
+        ResponseFormat::Runner
+    } else if ["pid", "uid", "gid", "process"]
+        .iter()
+        .any(|key| fields.contains_key(*key))
+    {
+        ResponseFormat::Flat
+    } else {
+        ResponseFormat::Unknown
+    }
+}
+
+/// Parse a successful response in the [`ResponseFormat::Flat`] format.
+///
+/// The process details move into [`Identity::runner`], whose `identity`
+/// repeats the top-level one; `kanidm_url` is read as `idm_url`, and fields
+/// added after the flat format default to empty.
+///
+/// # Errors
+///
+/// Returns [`GetMyIdError::InvalidJson`] if `raw` is not a flat success
+/// response.
+pub fn parse_flat(raw: &str) -> Result<Identity> {
+    let flat: FlatResponse =
+        serde_json::from_str(raw).map_err(|e| GetMyIdError::invalid_response(e, raw))?;
+    Ok(Identity {
+        runner: Runner {
+            identity: flat.identity.clone(),
+            hostname: flat.hostname,
+            process: flat.process,
+            pid: flat.pid,
+            uid: flat.uid,
+            gid: flat.gid,
+            ..Runner::default()
+        },
+        identity: flat.identity,
+        idm_url: flat.idm_url,
+        config_url: flat.config_url,
+        token: flat.token.into(),
+        expires_at: flat.expires_at,
+        bootstrap: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_detect_format() {
+        let current = r#"{"status":"ok","identity":"APP","idm_url":"","config_url":"","token":"","runner":{"pid":1}}"#;
+        let flat = r#"{"status":"ok","identity":"APP","kanidm_url":"","pid":1,"uid":0,"gid":0,"process":"app"}"#;
+        let error = r#"{"status":"error","error_code":"E_NO_MATCH","message":"no match"}"#;
+
+        assert_eq!(detect_format(current), ResponseFormat::Runner);
+        assert_eq!(detect_format(flat), ResponseFormat::Flat);
+        assert_eq!(detect_format(error), ResponseFormat::Error);
+        assert_eq!(detect_format("not json"), ResponseFormat::Unknown);
+        assert_eq!(detect_format(r#"{"status":"ok"}"#), ResponseFormat::Unknown);
+    }
+
+    #[test]
+    fn test_parse_flat() {
+        let flat = r#"{"status":"ok","identity":"APP","kanidm_url":"https://idm","pid":7,"uid":1000,"gid":1000,"process":"app"}"#;
+        let identity = parse_flat(flat).unwrap();
+        assert_eq!(identity.identity, "APP");
+        assert_eq!(identity.idm_url, "https://idm");
+        assert_eq!(identity.token, "");
+        assert_eq!(identity.runner.identity, "APP");
+        assert_eq!(identity.runner.pid, 7);
+        assert_eq!(identity.runner.uid, 1000);
+        assert_eq!(identity.runner.process, "app");
+
+        // Clients accept the flat format transparently
+        let parsed = crate::protocol::parse_response(flat).unwrap();
+        assert_eq!(parsed, identity);
+        assert!(parse_flat(r#"{"status":"ok"}"#).is_err());
+    }
+
+    #[test]
+    fn test_exchange_returns_raw_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("whoami.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let raw = "{\"status\":\"ok\", \"identity\":\"APP\",\"pid\":7}\n";
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+        });
+
+        let client = Client::builder().socket_path(&path).build();
+        assert_eq!(exchange(&client).unwrap(), raw);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+//! Daemon versions and the range this crate is tested against.
+
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+
+use crate::protocol::STATUS_INFO;
+
+/// Protocol revisions this crate is tested against.
+pub const TESTED_PROTOCOL_VERSIONS: RangeInclusive<u32> = 1..=1;
+
+/// Daemon releases this crate is tested against, as `(major, minor)`.
+pub const TESTED_DAEMON_VERSIONS: RangeInclusive<(u64, u64)> = (0, 1)..=(0, 2);
+
+/// Versions a daemon reported in its response, from the optional
+/// `protocol_version` and `daemon_version` fields.
+///
+/// Available on [`RawIdentity::daemon_version`](crate::RawIdentity::daemon_version).
+/// Daemons predating these fields report neither.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DaemonVersion {
+    /// Revision of the wire protocol, e.g. `1`.
+    pub protocol: Option<u32>,
+    /// Release of the daemon, e.g. `0.2.1`.
+    pub release: Option<String>,
+}
+
+impl DaemonVersion {
+    /// Read the versions from a raw daemon response.
+    ///
+    /// Fields that are absent or not of the expected type are left `None`.
+    pub fn from_response(response: &str) -> Self {
+        let Ok(serde_json::Value::Object(fields)) = serde_json::from_str(response) else {
+            return Self::default();
+        };
+        Self {
+            protocol: fields
+                .get("protocol_version")
+                .and_then(|v| v.as_u64())
+                .and_then(|v| u32::try_from(v).ok()),
+            release: fields
+                .get("daemon_version")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+        }
+    }
+
+    /// `(major, minor)` of the release, if it starts with numeric
+    /// components; a leading `v` and pre-release suffixes are ignored.
+    pub fn release_series(&self) -> Option<(u64, u64)> {
+        let release = self.release.as_deref()?;
+        let mut parts = release.strip_prefix('v').unwrap_or(release).split('.');
+        let major = leading_number(parts.next()?)?;
+        let minor = parts.next().and_then(leading_number).unwrap_or(0);
+        Some((major, minor))
+    }
+
+    /// Compare the versions to the range this crate is tested against.
+    ///
+    /// The protocol revision is checked first, then the release series.
+    pub fn compatibility(&self) -> Compatibility {
+        let protocol = self
+            .protocol
+            .map(|v| position(&v, &TESTED_PROTOCOL_VERSIONS));
+        let release = self
+            .release_series()
+            .map(|v| position(&v, &TESTED_DAEMON_VERSIONS));
+        let position = match protocol {
+            Some(Ordering::Equal) | None => release.or(protocol),
+            _ => protocol,
+        };
+        match position {
+            None => Compatibility::Unknown,
+            Some(Ordering::Less) => Compatibility::Older,
+            Some(Ordering::Equal) => Compatibility::Tested,
+            Some(Ordering::Greater) => Compatibility::Newer,
+        }
+    }
+}
+
+/// What a daemon reported about itself, returned by `Client::daemon_info`.
+///
+/// Daemons answering info requests report the protocol features they
+/// support; older daemons answer with an identity, from which only the
+/// versions are read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DaemonInfo {
+    /// Versions the daemon reported.
+    pub version: DaemonVersion,
+    /// Protocol features the daemon supports, e.g. `keep_alive`, `wait`,
+    /// `sign` or `nonce`; `None` if it does not answer info requests.
+    pub features: Option<Vec<String>>,
+}
+
+impl DaemonInfo {
+    /// Read the versions and features from a raw daemon response.
+    ///
+    /// Features are only read from [`STATUS_INFO`] responses; entries that
+    /// are not strings are skipped.
+    pub fn from_response(response: &str) -> Self {
+        let features = match serde_json::from_str(response) {
+            Ok(serde_json::Value::Object(fields))
+                if fields.get("status").and_then(|s| s.as_str()) == Some(STATUS_INFO) =>
+            {
+                let features = fields.get("features").and_then(|f| f.as_array());
+                let names = features.into_iter().flatten().filter_map(|f| f.as_str());
+                Some(names.map(str::to_string).collect())
+            }
+            _ => None,
+        };
+        Self {
+            version: DaemonVersion::from_response(response),
+            features,
+        }
+    }
+
+    /// Check if the daemon reported supporting `feature`, always `false`
+    /// for daemons that do not answer info requests.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features
+            .as_ref()
+            .is_some_and(|features| features.iter().any(|f| f == feature))
+    }
+
+    /// Compare the daemon's versions to the range this crate is tested
+    /// against.
+    pub fn compatibility(&self) -> Compatibility {
+        self.version.compatibility()
+    }
+}
+
+/// How a daemon's version relates to the range this crate is tested
+/// against, returned by `Client::check_compatibility`.
+///
+/// Versions outside the range usually work, but are worth a warning in
+/// the application's logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Within [`TESTED_PROTOCOL_VERSIONS`] and [`TESTED_DAEMON_VERSIONS`].
+    Tested,
+    /// Older than the tested range.
+    Older,
+    /// Newer than the tested range.
+    Newer,
+    /// The daemon reported no version.
+    Unknown,
+}
+
+impl Compatibility {
+    /// Check if the daemon is within the tested range.
+    pub fn is_tested(self) -> bool {
+        self == Self::Tested
+    }
+}
+
+/// Where `value` lies relative to `range`: `Equal` inside it.
+fn position<T: PartialOrd>(value: &T, range: &RangeInclusive<T>) -> Ordering {
+    if value < range.start() {
+        Ordering::Less
+    } else if value > range.end() {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    }
+}
+
+fn leading_number(part: &str) -> Option<u64> {
+    let end = part
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(part.len());
+    part[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(protocol: Option<u32>, release: Option<&str>) -> DaemonVersion {
+        DaemonVersion {
+            protocol,
+            release: release.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_from_response() {
+        let response = r#"{"status":"ok","protocol_version":1,"daemon_version":"0.2.1"}"#;
+        assert_eq!(
+            DaemonVersion::from_response(response),
+            version(Some(1), Some("0.2.1"))
+        );
+        let response = r#"{"status":"ok","protocol_version":"1","daemon_version":2}"#;
+        assert_eq!(DaemonVersion::from_response(response), version(None, None));
+        assert_eq!(DaemonVersion::from_response("garbage"), version(None, None));
+    }
+
+    #[test]
+    fn test_release_series() {
+        assert_eq!(version(None, Some("0.2.1")).release_series(), Some((0, 2)));
+        assert_eq!(
+            version(None, Some("v1.3-rc1")).release_series(),
+            Some((1, 3))
+        );
+        assert_eq!(version(None, Some("2")).release_series(), Some((2, 0)));
+        assert_eq!(version(None, Some("dev")).release_series(), None);
+    }
+
+    #[test]
+    fn test_compatibility() {
+        use Compatibility::*;
+
+        assert_eq!(version(None, None).compatibility(), Unknown);
+        assert_eq!(version(Some(1), None).compatibility(), Tested);
+        assert_eq!(version(None, Some("0.1.4")).compatibility(), Tested);
+        assert_eq!(version(Some(1), Some("0.2.0")).compatibility(), Tested);
+        assert_eq!(version(Some(0), Some("0.2.0")).compatibility(), Older);
+        assert_eq!(version(Some(1), Some("0.0.9")).compatibility(), Older);
+        assert_eq!(version(Some(2), None).compatibility(), Newer);
+        assert_eq!(version(Some(1), Some("1.0.0")).compatibility(), Newer);
+        assert_eq!(version(Some(2), Some("0.0.9")).compatibility(), Newer);
+        assert_eq!(version(None, Some("nightly")).compatibility(), Unknown);
+        assert!(Tested.is_tested());
+    }
+
+    #[test]
+    fn test_daemon_info_from_response() {
+        let response = r#"{"status":"info","protocol_version":1,"daemon_version":"0.2.1","features":["wait","keep_alive",3]}"#;
+        let info = DaemonInfo::from_response(response);
+        assert_eq!(info.version, version(Some(1), Some("0.2.1")));
+        assert_eq!(
+            info.features.as_deref(),
+            Some(&["wait".to_string(), "keep_alive".to_string()][..])
+        );
+        assert!(info.supports("wait"));
+        assert!(!info.supports("sign"));
+        assert_eq!(info.compatibility(), Compatibility::Tested);
+
+        let response = r#"{"status":"ok","daemon_version":"0.1.0","features":["wait"]}"#;
+        let info = DaemonInfo::from_response(response);
+        assert_eq!(info.features, None);
+        assert!(!info.supports("wait"));
+        assert_eq!(info.version.release.as_deref(), Some("0.1.0"));
+    }
+}
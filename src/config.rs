@@ -0,0 +1,395 @@
+//! Client settings read from outside the program.
+//!
+//! Deployment tooling can reconfigure clients through environment variables
+//! without recompiling or threading builder options through every consumer:
+//!
+//! | Variable | Setting |
+//! |----------|---------|
+//! | `GETMYID_SOCKET` | Socket path (`@name` for an abstract socket) |
+//! | `GETMYID_TIMEOUT_MS` | Connect, read and write timeouts |
+//! | `GETMYID_CONNECT_TIMEOUT_MS` | Connect timeout |
+//! | `GETMYID_READ_TIMEOUT_MS` | Read timeout |
+//! | `GETMYID_WRITE_TIMEOUT_MS` | Write timeout |
+//! | `GETMYID_SOCKET_TYPE` | `stream` or `seqpacket` (synchronous client only) |
+//! | `GETMYID_RETRIES` | Retries after a transient failure |
+//! | `GETMYID_RETRY_BACKOFF_MS` | Delay before the first retry |
+//! | `GETMYID_CACHE_TTL_MS` | How long resolved identities are reused |
+//! | `GETMYID_CACHE_MAX_ENTRIES` | Maximum number of cached identities |
+//! | `GETMYID_CACHE_MAX_BYTES` | Approximate memory bound of the cache |
+//!
+//! Timeouts are in milliseconds; `0` disables the timeout or the cache, and
+//! removes the memory bound. The per-phase variables take precedence over
+//! `GETMYID_TIMEOUT_MS`.
+//!
+//! With the `config-file` feature the same settings can be read from a TOML
+//! file, `SYSTEM_CONFIG_PATH` and `user_config_path` by default. Keys
+//! mirror the variables without their prefix:
+//!
+//! ```toml
+//! socket = "/var/run/whoami.sock"
+//! socket_type = "stream"
+//! timeout_ms = 5000
+//! read_timeout_ms = 500
+//! retries = 2
+//! retry_backoff_ms = 100
+//! cache_ttl_ms = 60000
+//! cache_max_entries = 4096
+//! ```
+//!
+//! Environment variables take precedence over files, and builder options set
+//! afterwards take precedence over both.
+
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(feature = "config-file")]
+use std::path::Path;
+
+use crate::error::{GetMyIdError, Result};
+use crate::transport::SocketType;
+
+/// Variable holding the socket path.
+pub const ENV_SOCKET: &str = "GETMYID_SOCKET";
+/// Variable holding the timeout for all phases, in milliseconds.
+pub const ENV_TIMEOUT_MS: &str = "GETMYID_TIMEOUT_MS";
+/// Variable holding the connect timeout, in milliseconds.
+pub const ENV_CONNECT_TIMEOUT_MS: &str = "GETMYID_CONNECT_TIMEOUT_MS";
+/// Variable holding the read timeout, in milliseconds.
+pub const ENV_READ_TIMEOUT_MS: &str = "GETMYID_READ_TIMEOUT_MS";
+/// Variable holding the write timeout, in milliseconds.
+pub const ENV_WRITE_TIMEOUT_MS: &str = "GETMYID_WRITE_TIMEOUT_MS";
+/// Variable holding the socket type.
+pub const ENV_SOCKET_TYPE: &str = "GETMYID_SOCKET_TYPE";
+/// Variable holding the number of retries after a transient failure.
+pub const ENV_RETRIES: &str = "GETMYID_RETRIES";
+/// Variable holding the delay before the first retry, in milliseconds.
+pub const ENV_RETRY_BACKOFF_MS: &str = "GETMYID_RETRY_BACKOFF_MS";
+/// Variable holding how long resolved identities are cached, in milliseconds.
+pub const ENV_CACHE_TTL_MS: &str = "GETMYID_CACHE_TTL_MS";
+/// Variable holding the maximum number of cached identities.
+pub const ENV_CACHE_MAX_ENTRIES: &str = "GETMYID_CACHE_MAX_ENTRIES";
+/// Variable holding the approximate memory bound of the cache, in bytes.
+pub const ENV_CACHE_MAX_BYTES: &str = "GETMYID_CACHE_MAX_BYTES";
+
+/// System-wide configuration file read by `from_default_files`.
+#[cfg(feature = "config-file")]
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/getmyid.toml";
+
+/// Per-user configuration file read by `from_default_files`:
+/// `$XDG_CONFIG_HOME/getmyid.toml`, falling back to `~/.config/getmyid.toml`.
+///
+/// Returns `None` if neither variable is set.
+#[cfg(feature = "config-file")]
+pub fn user_config_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|home| !home.is_empty())
+                .map(|home| PathBuf::from(home).join(".config"))
+        })
+        .map(|dir| dir.join("getmyid.toml"))
+}
+
+/// Settings overriding builder defaults; `None` leaves a setting unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Settings {
+    pub socket_path: Option<PathBuf>,
+    pub connect_timeout: Option<Option<Duration>>,
+    pub read_timeout: Option<Option<Duration>>,
+    pub write_timeout: Option<Option<Duration>>,
+    pub socket_type: Option<SocketType>,
+    pub retries: Option<u32>,
+    pub retry_backoff: Option<Duration>,
+    pub cache_ttl: Option<Option<Duration>>,
+    pub cache_max_entries: Option<usize>,
+    pub cache_max_bytes: Option<Option<usize>>,
+}
+
+impl Settings {
+    /// Read settings from the `GETMYID_*` environment variables.
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let timeout = |name: &str| -> Result<Option<Option<Duration>>> {
+            var(name)
+                .map(|value| {
+                    let ms: u64 = value.trim().parse().map_err(|_| invalid(name, &value))?;
+                    Ok((ms > 0).then(|| Duration::from_millis(ms)))
+                })
+                .transpose()
+        };
+
+        let all = timeout(ENV_TIMEOUT_MS)?;
+        let socket_type = var(ENV_SOCKET_TYPE)
+            .map(|value| parse_socket_type(&value).ok_or_else(|| invalid(ENV_SOCKET_TYPE, &value)))
+            .transpose()?;
+        let number = |name: &str| -> Result<Option<u64>> {
+            var(name)
+                .map(|value| value.trim().parse().map_err(|_| invalid(name, &value)))
+                .transpose()
+        };
+        let retries = var(ENV_RETRIES)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(ENV_RETRIES, &value))
+            })
+            .transpose()?;
+        let retry_backoff = var(ENV_RETRY_BACKOFF_MS)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map(Duration::from_millis)
+                    .map_err(|_| invalid(ENV_RETRY_BACKOFF_MS, &value))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            socket_path: var(ENV_SOCKET)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            connect_timeout: timeout(ENV_CONNECT_TIMEOUT_MS)?.or(all),
+            read_timeout: timeout(ENV_READ_TIMEOUT_MS)?.or(all),
+            write_timeout: timeout(ENV_WRITE_TIMEOUT_MS)?.or(all),
+            socket_type,
+            retries,
+            retry_backoff,
+            cache_ttl: timeout(ENV_CACHE_TTL_MS)?,
+            cache_max_entries: number(ENV_CACHE_MAX_ENTRIES)?.map(saturating_usize),
+            cache_max_bytes: number(ENV_CACHE_MAX_BYTES)?.map(max_bytes),
+        })
+    }
+
+    /// Read settings from the TOML file at `path`.
+    #[cfg(feature = "config-file")]
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| invalid_config(path, e))?;
+        Self::from_toml(&contents).map_err(|message| invalid_config(path, message))
+    }
+
+    /// Read settings from the default files that exist, the user file
+    /// taking precedence over the system-wide one.
+    #[cfg(feature = "config-file")]
+    pub fn from_default_files() -> Result<Self> {
+        let mut settings = Self::default();
+        let paths = std::iter::once(PathBuf::from(SYSTEM_CONFIG_PATH)).chain(user_config_path());
+        for path in paths.filter(|path| path.is_file()) {
+            settings = settings.overlay(Self::from_file(&path)?);
+        }
+        Ok(settings)
+    }
+
+    #[cfg(feature = "config-file")]
+    fn from_toml(contents: &str) -> std::result::Result<Self, String> {
+        let file: FileSettings = toml::from_str(contents).map_err(|e| e.message().to_string())?;
+        let timeout = |ms: Option<u64>| ms.map(|ms| (ms > 0).then(|| Duration::from_millis(ms)));
+        let all = timeout(file.timeout_ms);
+        let socket_type = file
+            .socket_type
+            .map(|value| {
+                parse_socket_type(&value).ok_or_else(|| format!("invalid socket_type {value:?}"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            socket_path: file.socket.filter(|path| !path.as_os_str().is_empty()),
+            connect_timeout: timeout(file.connect_timeout_ms).or(all),
+            read_timeout: timeout(file.read_timeout_ms).or(all),
+            write_timeout: timeout(file.write_timeout_ms).or(all),
+            socket_type,
+            retries: file.retries,
+            retry_backoff: file.retry_backoff_ms.map(Duration::from_millis),
+            cache_ttl: timeout(file.cache_ttl_ms),
+            cache_max_entries: file.cache_max_entries.map(saturating_usize),
+            cache_max_bytes: file.cache_max_bytes.map(max_bytes),
+        })
+    }
+
+    /// Layer `top` over these settings: options present in `top` win.
+    #[cfg(feature = "config-file")]
+    pub fn overlay(self, top: Self) -> Self {
+        Self {
+            socket_path: top.socket_path.or(self.socket_path),
+            connect_timeout: top.connect_timeout.or(self.connect_timeout),
+            read_timeout: top.read_timeout.or(self.read_timeout),
+            write_timeout: top.write_timeout.or(self.write_timeout),
+            socket_type: top.socket_type.or(self.socket_type),
+            retries: top.retries.or(self.retries),
+            retry_backoff: top.retry_backoff.or(self.retry_backoff),
+            cache_ttl: top.cache_ttl.or(self.cache_ttl),
+            cache_max_entries: top.cache_max_entries.or(self.cache_max_entries),
+            cache_max_bytes: top.cache_max_bytes.or(self.cache_max_bytes),
+        }
+    }
+}
+
+/// Contents of a configuration file.
+#[cfg(feature = "config-file")]
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileSettings {
+    socket: Option<PathBuf>,
+    socket_type: Option<String>,
+    timeout_ms: Option<u64>,
+    connect_timeout_ms: Option<u64>,
+    read_timeout_ms: Option<u64>,
+    write_timeout_ms: Option<u64>,
+    retries: Option<u32>,
+    retry_backoff_ms: Option<u64>,
+    cache_ttl_ms: Option<u64>,
+    cache_max_entries: Option<u64>,
+    cache_max_bytes: Option<u64>,
+}
+
+fn saturating_usize(n: u64) -> usize {
+    usize::try_from(n).unwrap_or(usize::MAX)
+}
+
+/// Memory bound of the cache; `0` removes the bound.
+fn max_bytes(n: u64) -> Option<usize> {
+    (n > 0).then(|| saturating_usize(n))
+}
+
+fn parse_socket_type(value: &str) -> Option<SocketType> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "stream" => Some(SocketType::Stream),
+        "seqpacket" => Some(SocketType::SeqPacket),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "config-file")]
+fn invalid_config(path: &Path, message: impl ToString) -> GetMyIdError {
+    GetMyIdError::InvalidConfig {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+    }
+}
+
+fn invalid(name: &str, value: &str) -> GetMyIdError {
+    GetMyIdError::InvalidEnvVar {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn settings(vars: &[(&str, &str)]) -> Result<Settings> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Settings::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_settings_from_vars() {
+        assert_eq!(settings(&[]).unwrap(), Settings::default());
+
+        let parsed = settings(&[
+            (ENV_SOCKET, "@whoami"),
+            (ENV_TIMEOUT_MS, "250"),
+            (ENV_CONNECT_TIMEOUT_MS, "0"),
+            (ENV_SOCKET_TYPE, "SeqPacket"),
+        ])
+        .unwrap();
+        assert_eq!(parsed.socket_path, Some(PathBuf::from("@whoami")));
+        assert_eq!(parsed.connect_timeout, Some(None));
+        assert_eq!(parsed.read_timeout, Some(Some(Duration::from_millis(250))));
+        assert_eq!(parsed.write_timeout, Some(Some(Duration::from_millis(250))));
+        assert_eq!(parsed.socket_type, Some(SocketType::SeqPacket));
+    }
+
+    #[test]
+    fn test_settings_invalid_values() {
+        assert!(matches!(
+            settings(&[(ENV_TIMEOUT_MS, "5s")]),
+            Err(GetMyIdError::InvalidEnvVar { name, value }) if name == ENV_TIMEOUT_MS && value == "5s"
+        ));
+        assert!(matches!(
+            settings(&[(ENV_SOCKET_TYPE, "dgram")]),
+            Err(GetMyIdError::InvalidEnvVar { .. })
+        ));
+        assert!(matches!(
+            settings(&[(ENV_RETRIES, "-1")]),
+            Err(GetMyIdError::InvalidEnvVar { name, .. }) if name == ENV_RETRIES
+        ));
+    }
+
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn test_settings_overlay() {
+        let base = settings(&[
+            (ENV_SOCKET, "/etc/whoami.sock"),
+            (ENV_RETRIES, "3"),
+            (ENV_CACHE_TTL_MS, "60000"),
+        ])
+        .unwrap();
+        let top = settings(&[
+            (ENV_RETRIES, "1"),
+            (ENV_CACHE_TTL_MS, "0"),
+            (ENV_CACHE_MAX_BYTES, "0"),
+        ])
+        .unwrap();
+
+        let merged = base.overlay(top);
+        assert_eq!(merged.socket_path, Some(PathBuf::from("/etc/whoami.sock")));
+        assert_eq!(merged.retries, Some(1));
+        assert_eq!(merged.cache_ttl, Some(None));
+        assert_eq!(merged.cache_max_bytes, Some(None));
+    }
+
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn test_settings_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("getmyid.toml");
+        std::fs::write(
+            &path,
+            "socket = \"@whoami\"\ntimeout_ms = 250\nread_timeout_ms = 0\nsocket_type = \"seqpacket\"\nretries = 2\nretry_backoff_ms = 50\ncache_ttl_ms = 1000\ncache_max_entries = 10\ncache_max_bytes = 4096\n",
+        )
+        .unwrap();
+
+        let parsed = Settings::from_file(&path).unwrap();
+        assert_eq!(parsed.socket_path, Some(PathBuf::from("@whoami")));
+        assert_eq!(
+            parsed.connect_timeout,
+            Some(Some(Duration::from_millis(250)))
+        );
+        assert_eq!(parsed.read_timeout, Some(None));
+        assert_eq!(parsed.socket_type, Some(SocketType::SeqPacket));
+        assert_eq!(parsed.retries, Some(2));
+        assert_eq!(parsed.retry_backoff, Some(Duration::from_millis(50)));
+        assert_eq!(parsed.cache_ttl, Some(Some(Duration::from_secs(1))));
+        assert_eq!(parsed.cache_max_entries, Some(10));
+        assert_eq!(parsed.cache_max_bytes, Some(Some(4096)));
+    }
+
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn test_settings_from_invalid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("getmyid.toml");
+        std::fs::write(&path, "sockett = \"/tmp/whoami.sock\"\n").unwrap();
+
+        assert!(matches!(
+            Settings::from_file(&path),
+            Err(GetMyIdError::InvalidConfig { path: p, message }) if p == path && message.contains("sockett")
+        ));
+        assert!(matches!(
+            Settings::from_file(&dir.path().join("missing.toml")),
+            Err(GetMyIdError::InvalidConfig { .. })
+        ));
+    }
+}
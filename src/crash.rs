@@ -0,0 +1,229 @@
+//! Identity snapshots for crash reports.
+
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{fence, AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::support::REDACTED;
+use crate::types::Identity;
+
+/// Capacity of the crash snapshot buffer; longer snapshots are truncated.
+pub const CRASH_SNAPSHOT_SIZE: usize = 512;
+
+/// How often a reader retries when it races with an update.
+const READ_ATTEMPTS: usize = 4;
+
+static CRASH_REPORT: CrashReport = CrashReport::new();
+
+/// Get the process-wide [`CrashReport`].
+///
+/// # Example
+///
+/// ```no_run
+/// let identity = getmyid::get_identity()?;
+/// let report = getmyid::crash_report_hook();
+/// report.record(&identity);
+/// report.install_panic_hook();
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+pub fn crash_report_hook() -> &'static CrashReport {
+    &CRASH_REPORT
+}
+
+/// A redacted identity snapshot kept in a pre-allocated buffer, so crash
+/// handlers can report which identity the process held without allocating,
+/// locking or asking the daemon.
+///
+/// [`record`](CrashReport::record) formats the snapshot ahead of time, with
+/// the token replaced by `[redacted]`. Reading it back with
+/// [`copy_to`](CrashReport::copy_to) or [`write_to`](CrashReport::write_to)
+/// is async-signal-safe and may be done from a signal handler.
+#[derive(Debug)]
+pub struct CrashReport {
+    /// Odd while an update is in progress.
+    seq: AtomicUsize,
+    len: AtomicUsize,
+    buf: [AtomicU8; CRASH_SNAPSHOT_SIZE],
+    writer: Mutex<()>,
+    panic_hook: AtomicBool,
+}
+
+impl CrashReport {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            buf: [const { AtomicU8::new(0) }; CRASH_SNAPSHOT_SIZE],
+            writer: Mutex::new(()),
+            panic_hook: AtomicBool::new(false),
+        }
+    }
+
+    /// Replace the snapshot with a redacted description of `identity`.
+    ///
+    /// Call it again whenever the process resolves a new identity.
+    pub fn record(&self, identity: &Identity) {
+        let snapshot = snapshot(identity);
+        let mut end = snapshot.len().min(CRASH_SNAPSHOT_SIZE);
+        while !snapshot.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let _writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (slot, byte) in self.buf.iter().zip(&snapshot.as_bytes()[..end]) {
+            slot.store(*byte, Ordering::Relaxed);
+        }
+        self.len.store(end, Ordering::Relaxed);
+        self.seq.fetch_add(1, Ordering::Release);
+    }
+
+    /// Copy the snapshot into `out` and return its length, truncated to
+    /// `out`; zero if nothing was recorded or an update was interrupted.
+    ///
+    /// Async-signal-safe.
+    pub fn copy_to(&self, out: &mut [u8]) -> usize {
+        for _ in 0..READ_ATTEMPTS {
+            let before = self.seq.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                // An update in progress, possibly on the thread we interrupted
+                continue;
+            }
+            let len = self.len.load(Ordering::Relaxed).min(out.len());
+            for (byte, slot) in out.iter_mut().zip(&self.buf[..len]) {
+                *byte = slot.load(Ordering::Relaxed);
+            }
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return len;
+            }
+        }
+        0
+    }
+
+    /// Write the snapshot and a newline to the file descriptor `fd`, e.g.
+    /// `2` for stderr. Nothing is written if no snapshot was recorded.
+    ///
+    /// Async-signal-safe: it uses a stack buffer and `write(2)` only.
+    pub fn write_to(&self, fd: RawFd) {
+        let mut line = [0u8; CRASH_SNAPSHOT_SIZE + 1];
+        let len = self.copy_to(&mut line[..CRASH_SNAPSHOT_SIZE]);
+        if len == 0 {
+            return;
+        }
+        line[len] = b'\n';
+        let mut written = 0;
+        while written <= len {
+            let rest = &line[written..=len];
+            // SAFETY: `rest` is a valid buffer of `rest.len()` bytes
+            let n = unsafe { libc::write(fd, rest.as_ptr().cast(), rest.len()) };
+            if n <= 0 {
+                return;
+            }
+            written += n as usize;
+        }
+    }
+
+    /// Get the current snapshot, if one was recorded.
+    pub fn snapshot(&self) -> Option<String> {
+        let mut buf = [0u8; CRASH_SNAPSHOT_SIZE];
+        let len = self.copy_to(&mut buf);
+        (len > 0).then(|| String::from_utf8_lossy(&buf[..len]).into_owned())
+    }
+
+    /// Register a panic hook writing the snapshot to stderr before running
+    /// the previously installed hook.
+    ///
+    /// Installing more than once has no effect.
+    pub fn install_panic_hook(&'static self) {
+        if self.panic_hook.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            self.write_to(libc::STDERR_FILENO);
+            previous(info);
+        }));
+    }
+}
+
+/// Describe `identity` on one line, without its token.
+fn snapshot(identity: &Identity) -> String {
+    let runner = &identity.runner;
+    let token = if identity.token.is_empty() {
+        ""
+    } else {
+        REDACTED
+    };
+    format!(
+        "getmyid identity={} uid={} gid={} pid={} hostname={} idm_url={} config_url={} token={token}",
+        identity.identity,
+        runner.uid,
+        runner.gid,
+        runner.pid,
+        runner.hostname,
+        identity.idm_url,
+        identity.config_url,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Runner;
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+
+    fn identity(name: &str) -> Identity {
+        Identity {
+            identity: name.to_string(),
+            idm_url: "https://idm.example.com".to_string(),
+            config_url: String::new(),
+            token: "tok_secret".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner {
+                pid: 4242,
+                uid: 1000,
+                gid: 1000,
+                hostname: "worker-01".to_string(),
+                ..Runner::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_crash_report_record() {
+        let report = CrashReport::new();
+        assert_eq!(report.snapshot(), None);
+
+        report.record(&identity("BILLING_PROD"));
+        let snapshot = report.snapshot().unwrap();
+        assert!(snapshot.starts_with("getmyid identity=BILLING_PROD uid=1000 gid=1000 pid=4242"));
+        assert!(snapshot.ends_with("token=[redacted]"));
+        assert!(!snapshot.contains("tok_secret"));
+
+        let mut short = [0u8; 15];
+        assert_eq!(report.copy_to(&mut short), 15);
+        assert_eq!(&short, b"getmyid identit");
+
+        report.record(&identity(&"é".repeat(CRASH_SNAPSHOT_SIZE)));
+        let snapshot = report.snapshot().unwrap();
+        assert!(snapshot.len() <= CRASH_SNAPSHOT_SIZE);
+        assert!(!snapshot.contains('\u{fffd}'));
+    }
+
+    #[test]
+    fn test_crash_report_write_to() {
+        let report = CrashReport::new();
+        report.record(&identity("BILLING_PROD"));
+
+        let (mut reader, writer) = std::os::unix::net::UnixStream::pair().unwrap();
+        report.write_to(writer.as_raw_fd());
+        drop(writer);
+        let mut written = String::new();
+        reader.read_to_string(&mut written).unwrap();
+        assert_eq!(written, format!("{}\n", report.snapshot().unwrap()));
+    }
+}
@@ -0,0 +1,159 @@
+//! Field-by-field comparison of two identities.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use crate::types::{Identity, Runner};
+
+/// Which fields differ between two identities, created by
+/// [`Identity::diff`].
+///
+/// Tokens are only compared, never copied, so a diff can be logged as is.
+/// Its `Display` output lists the changed fields, e.g.
+/// `token, runner.pid`, or `unchanged`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdentityDiff {
+    /// The identity name changed.
+    pub identity: bool,
+    /// The IDM URL changed.
+    pub idm_url: bool,
+    /// The config URL changed.
+    pub config_url: bool,
+    /// The token changed.
+    pub token: bool,
+    /// The token expiry changed.
+    pub expires_at: bool,
+    /// The inlined bootstrap configuration changed.
+    pub bootstrap: bool,
+    /// Names of the runner fields that changed, appeared or disappeared,
+    /// custom fields included, in alphabetical order.
+    pub runner: Vec<String>,
+}
+
+impl IdentityDiff {
+    /// Check if no field changed.
+    pub fn is_empty(&self) -> bool {
+        self.changed_fields().is_empty()
+    }
+
+    /// Get the names of the changed fields, runner fields prefixed with
+    /// `runner.`.
+    pub fn changed_fields(&self) -> Vec<String> {
+        let top = [
+            ("identity", self.identity),
+            ("idm_url", self.idm_url),
+            ("config_url", self.config_url),
+            ("token", self.token),
+            ("expires_at", self.expires_at),
+            ("bootstrap", self.bootstrap),
+        ];
+        let top = top
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(field, _)| field.to_string());
+        let runner = self.runner.iter().map(|field| format!("runner.{field}"));
+        top.chain(runner).collect()
+    }
+}
+
+impl fmt::Display for IdentityDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = self.changed_fields();
+        if fields.is_empty() {
+            f.write_str("unchanged")
+        } else {
+            f.write_str(&fields.join(", "))
+        }
+    }
+}
+
+impl Identity {
+    /// Describe which fields differ between this identity and `other`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let before = getmyid::get_identity()?;
+    /// let after = getmyid::get_identity()?;
+    /// let diff = before.diff(&after);
+    /// if !diff.is_empty() {
+    ///     println!("identity changed: {diff}");
+    /// }
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn diff(&self, other: &Identity) -> IdentityDiff {
+        IdentityDiff {
+            identity: self.identity != other.identity,
+            idm_url: self.idm_url != other.idm_url,
+            config_url: self.config_url != other.config_url,
+            token: self.token != other.token,
+            expires_at: self.expires_at != other.expires_at,
+            bootstrap: self.bootstrap != other.bootstrap,
+            runner: runner_diff(&self.runner, &other.runner),
+        }
+    }
+}
+
+/// Names of the fields of the serialized runners that differ.
+fn runner_diff(a: &Runner, b: &Runner) -> Vec<String> {
+    let (a, b) = (fields(a), fields(b));
+    let names: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    names
+        .into_iter()
+        .filter(|name| a.get(*name) != b.get(*name))
+        .cloned()
+        .collect()
+}
+
+fn fields(runner: &Runner) -> Map<String, Value> {
+    match serde_json::to_value(runner) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity() -> Identity {
+        Identity {
+            identity: "APP".to_string(),
+            idm_url: "https://auth.example.com".to_string(),
+            config_url: "https://config.example.com".to_string(),
+            token: "tok".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner {
+                identity: "APP".to_string(),
+                pid: 42,
+                ..Runner::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_identity_diff() {
+        let before = identity();
+        let diff = before.diff(&identity());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "unchanged");
+
+        let mut after = identity();
+        after.token = "tok_rotated".into();
+        after.runner.pid = 43;
+        after.runner.instance_id = Some(1);
+        after.runner.extra.insert("shard".to_string(), 7.into());
+        let diff = before.diff(&after);
+        assert!(diff.token && !diff.identity && !diff.config_url);
+        assert_eq!(diff.runner, ["instance_id", "pid", "shard"]);
+        let expected = "token, runner.instance_id, runner.pid, runner.shard";
+        assert_eq!(diff.to_string(), expected);
+        assert!(!format!("{diff:?}").contains("tok_rotated"));
+
+        // Symmetric for fields that disappear
+        assert_eq!(after.diff(&before), diff);
+    }
+}
@@ -1,21 +1,63 @@
 //! Error types for getmyid client library.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+/// Bytes of a malformed response quoted on each side of the error position.
+const EXCERPT_CONTEXT: usize = 64;
+
 /// Errors that can occur when communicating with the whoami daemon.
+///
+/// New variants may be added in minor releases. Rather than matching every
+/// variant, decide how to react with [`is_transient`](GetMyIdError::is_transient),
+/// [`is_daemon_unavailable`](GetMyIdError::is_daemon_unavailable) and
+/// [`is_identity_rejected`](GetMyIdError::is_identity_rejected).
+///
+/// # Example
+///
+/// ```no_run
+/// match getmyid::get_identity() {
+///     Ok(identity) => println!("running as {}", identity.identity),
+///     Err(e) if e.is_identity_rejected() => panic!("no identity for this process: {e}"),
+///     Err(e) if e.is_daemon_unavailable() => eprintln!("daemon down, using fallback: {e}"),
+///     Err(e) => eprintln!("{e}"),
+/// }
+/// ```
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum GetMyIdError {
     /// Failed to connect to the Unix Domain Socket.
-    #[error("failed to connect to socket at {path}: {source}")]
+    #[error("failed to connect to socket at {}: {source}", display_path(.path, .resolved.as_deref()))]
     ConnectionFailed {
         /// The socket path that failed to connect.
         path: PathBuf,
+        /// Where `path` resolved to through symlinks, if elsewhere.
+        resolved: Option<PathBuf>,
         /// The underlying I/O error.
         #[source]
         source: std::io::Error,
     },
 
+    /// The socket exists but this process may not connect to it (`EACCES`).
+    ///
+    /// Connecting needs write permission on the socket, which daemons
+    /// usually grant to a group: check that the caller is a member of
+    /// `socket_group`. The ownership fields are `None` when the socket could
+    /// not be inspected, e.g. because a parent directory is not searchable.
+    #[error("permission denied on socket at {}: {}, caller uid {caller_uid}", .path.display(), display_ownership(*.socket_owner, *.socket_group, *.socket_mode))]
+    PermissionDenied {
+        /// The configured socket path.
+        path: PathBuf,
+        /// User ID owning the socket.
+        socket_owner: Option<u32>,
+        /// Group ID owning the socket.
+        socket_group: Option<u32>,
+        /// Permission bits of the socket.
+        socket_mode: Option<u32>,
+        /// Effective user ID of the calling process.
+        caller_uid: u32,
+    },
+
     /// Failed to read response from the daemon.
     #[error("failed to read response: {0}")]
     ReadError(#[source] std::io::Error),
@@ -25,18 +67,47 @@ pub enum GetMyIdError {
     WriteError(#[source] std::io::Error),
 
     /// Response is not valid JSON.
-    #[error("invalid JSON response: {0}")]
-    InvalidJson(#[source] serde_json::Error),
+    ///
+    /// For daemon responses, the error carries an excerpt of the raw
+    /// response around where parsing failed, with token values masked, to
+    /// help debug protocol mismatches.
+    #[error("invalid JSON response: {source}{}", display_excerpt(.excerpt.as_deref(), *.offset))]
+    InvalidJson {
+        /// The underlying parse error.
+        #[source]
+        source: serde_json::Error,
+        /// Up to 128 bytes of the raw daemon response around `offset`.
+        excerpt: Option<String>,
+        /// Byte offset in the raw daemon response where parsing failed.
+        offset: Option<usize>,
+    },
 
     /// Daemon returned an error response.
     #[error("daemon error ({code}): {message}")]
     DaemonError {
-        /// Error code from the daemon (e.g., "E_NO_MATCH").
-        code: String,
+        /// Error code from the daemon.
+        code: DaemonErrorCode,
         /// Human-readable error message.
         message: String,
     },
 
+    /// Daemon is degraded (e.g. in a maintenance window) and did not resolve
+    /// an identity.
+    ///
+    /// Unlike [`GetMyIdError::DaemonError`] this does not mean the process was
+    /// rejected. With `cache_ttl` set, the clients answer it with the identity
+    /// cached earlier, as for a [soft code](crate::SoftErrorCodes); it is
+    /// only returned when there is none.
+    #[error("daemon degraded: {message}")]
+    DaemonDegraded {
+        /// Human-readable description of the degradation.
+        message: String,
+        /// Error code from the daemon, e.g. `E_MAINTENANCE`, if provided.
+        code: Option<DaemonErrorCode>,
+        /// How long the daemon suggests waiting before retrying, if provided.
+        retry_after: Option<std::time::Duration>,
+    },
+
     /// Response is missing required fields.
     #[error("invalid response: missing field '{field}'")]
     MissingField {
@@ -44,14 +115,584 @@ pub enum GetMyIdError {
         field: &'static str,
     },
 
+    /// The response carries a field outside the daemon protocol, rejected
+    /// because strict parsing is enabled.
+    #[error("invalid response: unknown field '{path}'")]
+    UnknownField {
+        /// Path of the unknown field, e.g. `runner.color`.
+        path: String,
+    },
+
+    /// The runner request carries a custom field that the daemon injects
+    /// itself, e.g. `pid`, and would overwrite.
+    #[error("runner field '{field}' is reserved for the daemon")]
+    ReservedField {
+        /// The reserved field.
+        field: String,
+    },
+
+    /// The response is larger than the client's maximum response size.
+    #[error("response exceeds the maximum of {max} bytes")]
+    ResponseTooLarge {
+        /// The configured maximum, in bytes.
+        max: usize,
+    },
+
+    /// A value in the response exceeds the client's
+    /// [`JsonLimits`](crate::JsonLimits).
+    #[error("malformed response: {field} exceeds {limit} of {max}")]
+    MalformedResponse {
+        /// The offending field, e.g. `runner.labels`.
+        field: String,
+        /// The violated limit, `max_depth` or `max_string_len`.
+        limit: &'static str,
+        /// The value of the violated limit.
+        max: usize,
+    },
+
     /// Socket path does not exist.
     #[error("socket path does not exist: {0}")]
     SocketNotFound(PathBuf),
 
+    /// Socket path is a symlink whose target does not exist, e.g. a
+    /// `/var/run` link into a `/run` the daemon has not created its socket
+    /// in yet.
+    #[error("socket path {path} is a symlink to {target}, which does not exist")]
+    DanglingSocketLink {
+        /// The configured socket path.
+        path: PathBuf,
+        /// Where the symlink points.
+        target: PathBuf,
+    },
+
+    /// Socket path goes through a symlink while the client's
+    /// [`SymlinkPolicy`](crate::SymlinkPolicy) rejects them.
+    #[error("socket path {path} resolves through a symlink to {resolved}, which is not allowed")]
+    SymlinkRejected {
+        /// The configured socket path.
+        path: PathBuf,
+        /// Where the path resolves to.
+        resolved: PathBuf,
+    },
+
+    /// The process serving the socket does not run as the uid the client
+    /// requires with [`ClientBuilder::require_peer_uid`](crate::ClientBuilder::require_peer_uid),
+    /// so it may be an impostor handing out fake identities.
+    #[error("daemon at {} runs as uid {uid}, expected uid {expected}", .path.display())]
+    UntrustedDaemon {
+        /// The configured socket path.
+        path: PathBuf,
+        /// The uid the peer runs as, from `SO_PEERCRED`.
+        uid: u32,
+        /// The required uid.
+        expected: u32,
+    },
+
     /// Connection timeout.
     #[error("connection timeout after {0:?}")]
     Timeout(std::time::Duration),
+
+    /// TLS transport configuration is invalid (requires `tls` feature).
+    #[error("invalid TLS configuration: {0}")]
+    InvalidTlsConfig(String),
+
+    /// The cloud instance metadata service could not provide an identity
+    /// (requires `cloud` feature).
+    #[error("cloud metadata unavailable: {0}")]
+    CloudMetadata(String),
+
+    /// A template passed to [`Identity::render_template`](crate::Identity::render_template)
+    /// is malformed or references an unknown or unset placeholder.
+    #[error("invalid template: {0}")]
+    InvalidTemplate(String),
+
+    /// A required environment variable is not set.
+    #[error("environment variable {0} is not set")]
+    MissingEnvVar(String),
+
+    /// An optional integration was requested but its Cargo feature is not
+    /// enabled in this build (see [`capabilities`](crate::capabilities)).
+    #[error("feature `{0}` is not enabled in this build of getmyid")]
+    FeatureDisabled(String),
+
+    /// Every daemon endpoint configured for failover failed.
+    ///
+    /// Each endpoint is listed with the transient error it returned, in the
+    /// order they were tried.
+    #[error("all daemon endpoints failed: {}", format_failures(.failures))]
+    AllEndpointsFailed {
+        /// Socket path and error of each endpoint tried.
+        failures: Vec<(PathBuf, GetMyIdError)>,
+    },
+
+    /// A [`ProviderChain`](crate::ProviderChain) has no providers to ask.
+    #[error("no identity provider configured")]
+    NoProvider,
+
+    /// A configuration file cannot be read or parsed.
+    #[error("invalid configuration file {path}: {message}")]
+    InvalidConfig {
+        /// Path of the configuration file.
+        path: PathBuf,
+        /// What is wrong with it.
+        message: String,
+    },
+
+    /// An environment variable holds a value that cannot be used.
+    #[error("invalid value {value:?} for environment variable {name}")]
+    InvalidEnvVar {
+        /// Name of the variable.
+        name: String,
+        /// The rejected value.
+        value: String,
+    },
+
+    /// The identity token is not a JWT, fails verification (`jwt` feature),
+    /// or cannot be sent in an HTTP header (`headers` feature).
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+
+    /// The identity's token could not be exchanged at its IDM (requires
+    /// `idm` feature).
+    #[error("token exchange failed: {0}")]
+    TokenExchangeFailed(String),
+
+    /// The configuration inlined by the daemon cannot be decoded or fails
+    /// verification (requires `bootstrap` feature).
+    #[error("invalid bootstrap configuration: {0}")]
+    InvalidBootstrapConfig(String),
+
+    /// The identity's configuration could not be fetched from its
+    /// `config_url` (requires `http` feature).
+    #[error("config fetch failed: {0}")]
+    ConfigFetchFailed(String),
+
+    /// The response does not echo the nonce of the request it should
+    /// answer, so it may be a replayed or cached answer to another request.
+    #[error("response nonce {} does not match the request nonce {expected}", .actual.as_deref().unwrap_or("(none)"))]
+    NonceMismatch {
+        /// The nonce sent with the request.
+        expected: String,
+        /// The nonce the response carries, if any.
+        actual: Option<String>,
+    },
+
+    /// A daemon response was not signed, or its signature does not verify
+    /// against the pinned public key (requires `signing` feature).
+    #[error("response signature verification failed: {0}")]
+    InvalidSignature(String),
+
+    /// The encrypted channel to the daemon could not be established, e.g.
+    /// because the daemon does not hold the expected static key (requires
+    /// `noise` feature).
+    #[error("secure channel to the daemon failed: {0}")]
+    SecureChannel(String),
+
+    /// The daemon answered a lookup of another process with the identity
+    /// of a different one, usually because it does not support PID-targeted
+    /// lookups and resolved the caller (requires `admin` feature).
+    #[error("daemon resolved PID {resolved} instead of the requested PID {requested}")]
+    PidMismatch {
+        /// The PID the lookup asked for.
+        requested: u32,
+        /// The PID of the identity the daemon returned.
+        resolved: u32,
+    },
+}
+
+impl GetMyIdError {
+    /// Whether retrying the request may succeed: the daemon was unreachable,
+    /// slow or degraded, as opposed to rejecting the process or the client
+    /// being misconfigured.
+    ///
+    /// These are the errors the client's retries and failover act on.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::ConnectionFailed { .. }
+                | Self::SocketNotFound(_)
+                | Self::DanglingSocketLink { .. }
+                | Self::Timeout(_)
+                | Self::ReadError(_)
+                | Self::WriteError(_)
+                | Self::DaemonDegraded { .. }
+        ) || matches!(self, Self::AllEndpointsFailed { failures } if failures.iter().all(|(_, e)| e.is_transient()))
+    }
+
+    /// A JSON error without a response excerpt.
+    pub(crate) fn invalid_json(source: serde_json::Error) -> Self {
+        Self::InvalidJson {
+            source,
+            excerpt: None,
+            offset: None,
+        }
+    }
+
+    /// A JSON error for the daemon response `response`, quoting the part of
+    /// it where parsing failed.
+    pub(crate) fn invalid_response(source: serde_json::Error, response: &str) -> Self {
+        let offset = error_offset(&source, response);
+        Self::InvalidJson {
+            excerpt: Some(excerpt(response, offset)),
+            offset: Some(offset),
+            source,
+        }
+    }
+
+    /// Whether no daemon answered: its socket is missing, refuses
+    /// connections or times out, or the daemon reported being degraded.
+    ///
+    /// Callers with another source of identity, such as a previously
+    /// fetched one, can fall back to it on these errors.
+    pub fn is_daemon_unavailable(&self) -> bool {
+        match self {
+            Self::ConnectionFailed { .. }
+            | Self::SocketNotFound(_)
+            | Self::DanglingSocketLink { .. }
+            | Self::Timeout(_)
+            | Self::DaemonDegraded { .. } => true,
+            Self::AllEndpointsFailed { failures } => {
+                failures.iter().all(|(_, e)| e.is_daemon_unavailable())
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the daemon answered but refused an identity to this process,
+    /// e.g. because no rule matches it.
+    ///
+    /// Retrying will not help until the daemon's rules change, so callers
+    /// should fail fast.
+    pub fn is_identity_rejected(&self) -> bool {
+        matches!(self, Self::DaemonError { code, .. } if !code.is_retryable())
+    }
+
+    /// A copy of this error if it shows the daemon could not be reached at
+    /// all: its socket is missing, refuses connections or times out.
+    pub(crate) fn unreachable_copy(&self) -> Option<Self> {
+        Some(match self {
+            Self::ConnectionFailed {
+                path,
+                resolved,
+                source,
+            } => Self::ConnectionFailed {
+                path: path.clone(),
+                resolved: resolved.clone(),
+                source: std::io::Error::new(source.kind(), source.to_string()),
+            },
+            Self::SocketNotFound(path) => Self::SocketNotFound(path.clone()),
+            Self::DanglingSocketLink { path, target } => Self::DanglingSocketLink {
+                path: path.clone(),
+                target: target.clone(),
+            },
+            Self::Timeout(timeout) => Self::Timeout(*timeout),
+            Self::AllEndpointsFailed { failures } => Self::AllEndpointsFailed {
+                failures: failures
+                    .iter()
+                    .map(|(path, e)| Some((path.clone(), e.unreachable_copy()?)))
+                    .collect::<Option<_>>()?,
+            },
+            _ => return None,
+        })
+    }
+
+    /// A copy of this error if callers asking the daemon at the same time
+    /// would get it too: the daemon could not be reached, rejected the
+    /// request or is degraded.
+    pub(crate) fn shared_copy(&self) -> Option<Self> {
+        Some(match self {
+            Self::DaemonError { code, message } => Self::DaemonError {
+                code: code.clone(),
+                message: message.clone(),
+            },
+            Self::DaemonDegraded {
+                message,
+                code,
+                retry_after,
+            } => Self::DaemonDegraded {
+                message: message.clone(),
+                code: code.clone(),
+                retry_after: *retry_after,
+            },
+            _ => return self.unreachable_copy(),
+        })
+    }
+}
+
+/// Error code of a [`GetMyIdError::DaemonError`].
+///
+/// # Example
+///
+/// ```no_run
+/// use getmyid::{DaemonErrorCode, GetMyIdError};
+///
+/// match getmyid::get_identity() {
+///     Ok(identity) => println!("running as {}", identity.identity),
+///     Err(GetMyIdError::DaemonError { code: DaemonErrorCode::NoMatch, .. }) => {
+///         eprintln!("no identity rule matches this process");
+///     }
+///     Err(e) => eprintln!("{e}"),
+/// }
+/// ```
+///
+/// Codes gain variants as daemons define them, so matches need a wildcard
+/// arm; compare [`as_str`](DaemonErrorCode::as_str) rather than matching
+/// `Unknown` to test for a specific unlisted code.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DaemonErrorCode {
+    /// No identity rule matches the process (`E_NO_MATCH`).
+    NoMatch,
+    /// The process matched a rule but may not obtain the identity
+    /// (`E_ACCESS_DENIED`).
+    AccessDenied,
+    /// The daemon failed while resolving the identity (`E_INTERNAL`).
+    InternalError,
+    /// A code this version of the crate does not know, as sent. Codes
+    /// caught here may get their own variant in later versions.
+    Unknown(String),
+}
+
+impl DaemonErrorCode {
+    /// Get the code as sent by the daemon, e.g. `E_NO_MATCH`.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::NoMatch => "E_NO_MATCH",
+            Self::AccessDenied => "E_ACCESS_DENIED",
+            Self::InternalError => "E_INTERNAL",
+            Self::Unknown(code) => code,
+        }
+    }
+
+    /// Whether asking again later may succeed: the daemon failed, as
+    /// opposed to rejecting the process.
+    ///
+    /// The client's retries and failover do not act on this; it is for
+    /// callers deciding whether to retry on their own schedule.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::InternalError)
+    }
+}
+
+impl From<String> for DaemonErrorCode {
+    fn from(code: String) -> Self {
+        match code.as_str() {
+            "E_NO_MATCH" => Self::NoMatch,
+            "E_ACCESS_DENIED" => Self::AccessDenied,
+            "E_INTERNAL" => Self::InternalError,
+            _ => Self::Unknown(code),
+        }
+    }
+}
+
+impl From<&str> for DaemonErrorCode {
+    fn from(code: &str) -> Self {
+        Self::from(code.to_string())
+    }
+}
+
+impl std::fmt::Display for DaemonErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn display_path(path: &Path, resolved: Option<&Path>) -> String {
+    match resolved {
+        Some(resolved) => format!("{} (resolved to {})", path.display(), resolved.display()),
+        None => path.display().to_string(),
+    }
+}
+
+fn display_ownership(owner: Option<u32>, group: Option<u32>, mode: Option<u32>) -> String {
+    match (owner, group, mode) {
+        (Some(owner), Some(group), Some(mode)) => {
+            format!("owned by uid {owner} gid {group} with mode {mode:o}")
+        }
+        _ => "socket ownership unknown".to_string(),
+    }
+}
+
+fn display_excerpt(excerpt: Option<&str>, offset: Option<usize>) -> String {
+    match (excerpt, offset) {
+        (Some(excerpt), Some(offset)) => format!(" (byte {offset} of response, near {excerpt:?})"),
+        _ => String::new(),
+    }
+}
+
+/// Byte offset in `response` of the position reported by `error`.
+fn error_offset(error: &serde_json::Error, response: &str) -> usize {
+    let line_start: usize = response
+        .split_inclusive('\n')
+        .take(error.line().saturating_sub(1))
+        .map(str::len)
+        .sum();
+    (line_start + error.column().saturating_sub(1)).min(response.len())
+}
+
+/// The part of `response` within [`EXCERPT_CONTEXT`] bytes of `offset`,
+/// with the values of `"token"` fields masked.
+fn excerpt(response: &str, offset: usize) -> String {
+    let masked = mask_tokens(response);
+    let mut start = offset.saturating_sub(EXCERPT_CONTEXT);
+    while !response.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (offset + EXCERPT_CONTEXT).min(response.len());
+    while !response.is_char_boundary(end) {
+        end += 1;
+    }
+    masked[start..end].to_string()
+}
+
+/// `response` with the values of `"token"` fields replaced by `*`s.
+pub(crate) fn mask_tokens(response: &str) -> String {
+    // Masking byte for byte keeps offsets and UTF-8 boundaries intact
+    let mut masked = response.as_bytes().to_vec();
+    let mut from = 0;
+    while let Some(found) = response[from..].find("\"token\"") {
+        let mut i = from + found + "\"token\"".len();
+        while masked
+            .get(i)
+            .is_some_and(|b| b.is_ascii_whitespace() || *b == b':')
+        {
+            i += 1;
+        }
+        if masked.get(i) == Some(&b'"') {
+            i += 1;
+            while i < masked.len() && masked[i] != b'"' {
+                if masked[i] == b'\\' && i + 1 < masked.len() {
+                    masked[i] = b'*';
+                    i += 1;
+                }
+                masked[i] = b'*';
+                i += 1;
+            }
+        }
+        from = i.min(response.len());
+        while !response.is_char_boundary(from) {
+            from += 1;
+        }
+    }
+    String::from_utf8_lossy(&masked).into_owned()
+}
+
+fn format_failures(failures: &[(PathBuf, GetMyIdError)]) -> String {
+    failures
+        .iter()
+        .map(|(path, error)| format!("{}: {error}", path.display()))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 /// Result type alias for getmyid operations.
 pub type Result<T> = std::result::Result<T, GetMyIdError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn test_daemon_error_code() {
+        for code in [
+            "E_NO_MATCH",
+            "E_ACCESS_DENIED",
+            "E_INTERNAL",
+            "E_RATE_LIMITED",
+        ] {
+            assert_eq!(DaemonErrorCode::from(code).as_str(), code);
+        }
+        assert_eq!(
+            DaemonErrorCode::from("E_RATE_LIMITED"),
+            DaemonErrorCode::Unknown("E_RATE_LIMITED".to_string())
+        );
+        assert!(DaemonErrorCode::InternalError.is_retryable());
+        assert!(!DaemonErrorCode::NoMatch.is_retryable());
+
+        let error = GetMyIdError::DaemonError {
+            code: DaemonErrorCode::NoMatch,
+            message: "no rule".to_string(),
+        };
+        assert_eq!(error.to_string(), "daemon error (E_NO_MATCH): no rule");
+    }
+
+    #[test]
+    fn test_invalid_response_excerpt() {
+        let response = "HTTP/1.1 400 Bad Request\r\n\r\n";
+        let source = serde_json::from_str::<serde_json::Value>(response).unwrap_err();
+        let error = GetMyIdError::invalid_response(source, response);
+        assert!(matches!(
+            &error,
+            GetMyIdError::InvalidJson { excerpt: Some(excerpt), offset: Some(0), .. }
+                if excerpt == response
+        ));
+        assert!(error
+            .to_string()
+            .ends_with(r#"(byte 0 of response, near "HTTP/1.1 400 Bad Request\r\n\r\n")"#));
+
+        let response = format!(
+            "{{\"status\":\"ok\",\"token\": \"tok_\\\"secret\",\n\"pad\":\"{}\",\"runner\":{{,}}}}",
+            "x".repeat(100)
+        );
+        let source = serde_json::from_str::<serde_json::Value>(&response).unwrap_err();
+        let GetMyIdError::InvalidJson {
+            excerpt: Some(excerpt),
+            offset: Some(offset),
+            ..
+        } = GetMyIdError::invalid_response(source, &response)
+        else {
+            panic!("expected an excerpt");
+        };
+        assert_eq!(&response[offset..], ",}}");
+        assert_eq!(excerpt, response[offset - EXCERPT_CONTEXT..]);
+
+        let source = serde_json::from_str::<serde_json::Value>(&response[..60]).unwrap_err();
+        let error = GetMyIdError::invalid_response(source, &response[..60]);
+        assert!(!error.to_string().contains("secret"));
+        assert!(error.to_string().contains(r#"\"token\": \"************\""#));
+    }
+
+    #[test]
+    fn test_error_classification() {
+        let rejected = GetMyIdError::DaemonError {
+            code: DaemonErrorCode::AccessDenied,
+            message: "denied".to_string(),
+        };
+        assert!(rejected.is_identity_rejected());
+        assert!(!rejected.is_transient());
+        assert!(!rejected.is_daemon_unavailable());
+
+        let failing = GetMyIdError::DaemonError {
+            code: DaemonErrorCode::InternalError,
+            message: "boom".to_string(),
+        };
+        assert!(!failing.is_identity_rejected());
+
+        let absent = GetMyIdError::AllEndpointsFailed {
+            failures: vec![
+                (
+                    "/run/a.sock".into(),
+                    GetMyIdError::SocketNotFound("/run/a.sock".into()),
+                ),
+                (
+                    "/run/b.sock".into(),
+                    GetMyIdError::Timeout(Duration::from_secs(1)),
+                ),
+            ],
+        };
+        assert!(absent.is_transient());
+        assert!(absent.is_daemon_unavailable());
+        assert!(!absent.is_identity_rejected());
+
+        let dropped = GetMyIdError::ReadError(io::ErrorKind::UnexpectedEof.into());
+        assert!(dropped.is_transient());
+        assert!(!dropped.is_daemon_unavailable());
+
+        let misconfigured = GetMyIdError::MissingEnvVar("GETMYID_SOCKET".to_string());
+        assert!(!misconfigured.is_transient());
+        assert!(!misconfigured.is_daemon_unavailable());
+        assert!(!misconfigured.is_identity_rejected());
+    }
+}
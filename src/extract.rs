@@ -0,0 +1,116 @@
+//! `axum` extractor for the process identity (requires `axum` feature).
+
+use axum_core::extract::{FromRef, FromRequestParts};
+use axum_core::response::{IntoResponse, Response};
+use http::request::Parts;
+use http::StatusCode;
+
+use crate::async_client::AsyncClient;
+use crate::error::GetMyIdError;
+use crate::types::Identity;
+
+/// Extractor resolving the process identity with the [`AsyncClient`] in the
+/// application state.
+///
+/// The state must be an `AsyncClient` or provide one through [`FromRef`].
+/// Clones of a client share its identity cache, so build it with a
+/// [`cache_ttl`](crate::AsyncClientBuilder::cache_ttl) to avoid a daemon
+/// round-trip on every request. Failures reject the request with an
+/// [`IdentityRejection`].
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use getmyid::{AsyncClient, WhoAmI};
+///
+/// async fn handler(WhoAmI(identity): WhoAmI) -> String {
+///     format!("serving as {}", identity.identity)
+/// }
+///
+/// let client = AsyncClient::builder()
+///     .cache_ttl(Duration::from_secs(60))
+///     .build();
+/// // axum::Router::new().route("/", axum::routing::get(handler)).with_state(client)
+/// ```
+#[derive(Debug, Clone)]
+pub struct WhoAmI(pub Identity);
+
+impl<S> FromRequestParts<S> for WhoAmI
+where
+    AsyncClient: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = IdentityRejection;
+
+    async fn from_request_parts(_: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        AsyncClient::from_ref(state)
+            .get_identity()
+            .await
+            .map(WhoAmI)
+            .map_err(IdentityRejection)
+    }
+}
+
+/// Rejection of a [`WhoAmI`] extraction, wrapping the client's error.
+///
+/// Responds with `503 Service Unavailable` when the daemon is unreachable
+/// or degraded, and `500 Internal Server Error` otherwise, with the error
+/// message as body.
+#[derive(Debug)]
+pub struct IdentityRejection(pub GetMyIdError);
+
+impl IntoResponse for IdentityRejection {
+    fn into_response(self) -> Response {
+        let status = if self.0.is_transient() {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        (status, self.0.to_string()).into_response()
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeDaemon, FakeResponse};
+
+    async fn extract(client: &AsyncClient) -> Result<WhoAmI, IdentityRejection> {
+        let (mut parts, ()) = http::Request::new(()).into_parts();
+        WhoAmI::from_request_parts(&mut parts, client).await
+    }
+
+    #[tokio::test]
+    async fn test_who_am_i() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+
+        let WhoAmI(mut identity) = extract(&client).await.unwrap();
+        identity.runner.client_hostname = None;
+        assert_eq!(identity, FakeDaemon::sample_identity());
+
+        daemon.respond_with(FakeResponse::no_match());
+        let rejection = extract(&client).await.unwrap_err();
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_who_am_i_daemon_down() {
+        let client = AsyncClient::builder()
+            .socket_path("/nonexistent/whoami.sock")
+            .build();
+
+        let rejection = extract(&client).await.unwrap_err();
+        assert!(matches!(rejection.0, GetMyIdError::SocketNotFound(_)));
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
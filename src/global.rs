@@ -0,0 +1,87 @@
+//! A process-wide client for code without one passed down to it.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::client::{Client, ClientBuilder};
+use crate::config::Settings;
+
+/// How long the client created by [`global`] caches identities, unless
+/// `GETMYID_CACHE_TTL_MS` sets another TTL.
+pub const GLOBAL_CACHE_TTL: Duration = Duration::from_secs(60);
+
+static GLOBAL: OnceLock<Client> = OnceLock::new();
+
+/// Get the process-wide client, creating it on first use.
+///
+/// Libraries deep in a dependency tree can resolve the identity through it
+/// without a [`Client`] plumbed through every layer, and share one
+/// identity cache: unless the application called [`init_global`] first,
+/// the client is configured by the `GETMYID_*` environment variables and
+/// caches identities for [`GLOBAL_CACHE_TTL`]. If a variable cannot be
+/// parsed, the environment is ignored and the defaults are used; call
+/// [`init_global`] with [`Client::from_env`] to surface such errors.
+///
+/// # Example
+///
+/// ```no_run
+/// let identity = getmyid::global().get_identity()?;
+/// println!("running as {}", identity.identity);
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+pub fn global() -> &'static Client {
+    GLOBAL.get_or_init(|| {
+        ClientBuilder::new()
+            .cache_ttl(GLOBAL_CACHE_TTL)
+            .apply(Settings::from_env().unwrap_or_default())
+            .build()
+    })
+}
+
+/// Set the client [`global`] returns, typically early in `main`.
+///
+/// The client is used as given; enable its identity cache with
+/// [`ClientBuilder::cache_ttl`] to share identities between callers.
+///
+/// Returns `false`, dropping `client`, if the global client was already
+/// set by an earlier call or by the first use of [`global`].
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// let client = getmyid::ClientBuilder::from_env()?
+///     .cache_ttl(Duration::from_secs(300))
+///     .build();
+/// if !getmyid::init_global(client) {
+///     eprintln!("getmyid::global() was used before it was configured");
+/// }
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+pub fn init_global(client: Client) -> bool {
+    GLOBAL.set(client).is_ok()
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::testing::FakeDaemon;
+
+    #[test]
+    fn test_global_client() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .cache_ttl(GLOBAL_CACHE_TTL)
+            .build();
+        assert!(init_global(client));
+
+        assert_eq!(global().get_identity().unwrap().token, "tok_test");
+        assert_eq!(global().get_identity().unwrap().token, "tok_test");
+        assert_eq!(daemon.requests().len(), 1);
+        assert_eq!(global().socket_path(), daemon.socket_path());
+
+        assert!(!init_global(Client::new()));
+    }
+}
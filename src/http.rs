@@ -0,0 +1,565 @@
+//! Typed configuration fetching from `config_url` (requires `http` feature).
+//!
+//! The identity's `config_url` points at a config server expecting the
+//! daemon-issued token as a bearer token. [`Identity::fetch_config`] performs
+//! that GET and deserializes the JSON response.
+//!
+//! The minimal HTTP/1.0 client here, with TLS through rustls, is also used
+//! by the `idm` token exchange.
+//!
+//! # Example
+//!
+//! ```no_run
+//! #[derive(serde::Deserialize)]
+//! struct AppConfig {
+//!     database_url: String,
+//! }
+//!
+//! let identity = getmyid::get_identity()?;
+//! let config: AppConfig = identity.fetch_config()?;
+//! println!("database: {}", config.database_url);
+//! # Ok::<(), getmyid::GetMyIdError>(())
+//! ```
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConnection, StreamOwned};
+use serde::de::DeserializeOwned;
+
+use crate::client::read_limit;
+use crate::error::{GetMyIdError, Result};
+use crate::tls::{host_of, trusting, Pem};
+use crate::types::Identity;
+
+/// CA bundle trusted for `https` URLs unless `SSL_CERT_FILE` or a CA set on
+/// the request names another.
+const DEFAULT_CA_BUNDLE: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+/// Default timeout for each phase of a request.
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default maximum size of an HTTP response, headers included.
+pub const DEFAULT_MAX_HTTP_RESPONSE_BYTES: usize = 1024 * 1024;
+
+/// Settings for fetching an identity's configuration from its `config_url`.
+///
+/// [`Identity::fetch_config`] uses the defaults: `https` config URLs only,
+/// a 10 second timeout, a response limit of
+/// [`DEFAULT_MAX_HTTP_RESPONSE_BYTES`] and, for `https` URLs, the CA bundle named by `SSL_CERT_FILE` or found at
+/// `/etc/ssl/certs/ca-certificates.crt`.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::collections::HashMap;
+/// use std::time::Duration;
+/// use getmyid::ConfigFetch;
+///
+/// let identity = getmyid::get_identity()?;
+/// let config: HashMap<String, String> = ConfigFetch::new()
+///     .ca_cert_file("/etc/getmyid/config-ca.pem")
+///     .timeout(Duration::from_secs(3))
+///     .fetch(&identity)?;
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFetch {
+    http: HttpClient,
+}
+
+impl ConfigFetch {
+    /// Create a fetch with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the PEM CA certificates in the file at `path` for `https`
+    /// config URLs instead of the system bundle.
+    pub fn ca_cert_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.http.ca = Some(Pem::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Trust the PEM CA certificates in `pem` for `https` config URLs
+    /// instead of the system bundle.
+    pub fn ca_cert_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.http.ca = Some(Pem::Bytes(pem.into()));
+        self
+    }
+
+    /// Set the connect, read and write timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.http.timeout = timeout;
+        self
+    }
+
+    /// Set the maximum size of the config server's response, headers
+    /// included. Defaults to [`DEFAULT_MAX_HTTP_RESPONSE_BYTES`].
+    pub fn max_response_bytes(mut self, max: usize) -> Self {
+        self.http.max_response_bytes = max;
+        self
+    }
+
+    /// Allow plain `http` config URLs, sending the token unencrypted, e.g.
+    /// to a server on the same host. Defaults to `false`.
+    pub fn allow_http(mut self, allow: bool) -> Self {
+        self.http.allow_http = allow;
+        self
+    }
+
+    /// GET `identity`'s `config_url` with its token as bearer token and
+    /// deserialize the JSON response.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::ConfigFetchFailed`] if the identity has no
+    /// usable config URL, it is a plain `http` URL without
+    /// [`allow_http`](ConfigFetch::allow_http), the server is unreachable or answers with a status
+    /// other than 200 or a response larger than the limit,
+    /// [`GetMyIdError::InvalidJson`] if the response does not
+    /// deserialize into `T`, and [`GetMyIdError::InvalidTlsConfig`] if the
+    /// CA certificates cannot be loaded.
+    pub fn fetch<T: DeserializeOwned>(&self, identity: &Identity) -> Result<T> {
+        if identity.config_url.is_empty() {
+            return Err(failed("identity has no config URL".to_string()));
+        }
+        let url = HttpUrl::parse(&identity.config_url).map_err(failed)?;
+        let request = format!(
+            "GET {} HTTP/1.0\r\n\
+             Host: {}\r\n\
+             Authorization: {}\r\n\
+             Accept: application/json\r\n\r\n",
+            url.target(),
+            url.authority,
+            identity.auth_header_value()
+        );
+
+        let response = self
+            .http
+            .send(&url, request.as_bytes(), GetMyIdError::ConfigFetchFailed)?;
+        let (status, body) = split_response(&response)
+            .ok_or_else(|| failed("malformed HTTP response from the config server".to_string()))?;
+        if status != "200" {
+            return Err(failed(format!(
+                "config server returned HTTP status {status}"
+            )));
+        }
+        serde_json::from_str(&body).map_err(GetMyIdError::invalid_json)
+    }
+
+    /// Fetch the configuration without blocking the async runtime (requires
+    /// `tokio` feature).
+    ///
+    /// The request runs on Tokio's blocking thread pool.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`fetch`](ConfigFetch::fetch).
+    #[cfg(feature = "tokio")]
+    pub async fn fetch_async<T>(&self, identity: &Identity) -> Result<T>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let fetch = self.clone();
+        let identity = identity.clone();
+        tokio::task::spawn_blocking(move || fetch.fetch(&identity))
+            .await
+            .map_err(|e| failed(format!("fetch task failed: {e}")))?
+    }
+}
+
+impl Identity {
+    /// Fetch and deserialize this identity's configuration from
+    /// `config_url`, using the default [`ConfigFetch`] settings (requires
+    /// `http` feature).
+    ///
+    /// # Errors
+    ///
+    /// See [`ConfigFetch::fetch`].
+    pub fn fetch_config<T: DeserializeOwned>(&self) -> Result<T> {
+        ConfigFetch::new().fetch(self)
+    }
+
+    /// Async version of [`fetch_config`](Identity::fetch_config) (requires
+    /// `http` and `tokio` features).
+    ///
+    /// # Errors
+    ///
+    /// See [`ConfigFetch::fetch`].
+    #[cfg(feature = "tokio")]
+    pub async fn fetch_config_async<T>(&self) -> Result<T>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        ConfigFetch::new().fetch_async(self).await
+    }
+}
+
+fn failed(message: String) -> GetMyIdError {
+    GetMyIdError::ConfigFetchFailed(message)
+}
+
+/// Connection settings shared by the HTTP requests of this crate.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    pub(crate) ca: Option<Pem>,
+    pub(crate) timeout: Duration,
+    pub(crate) max_response_bytes: usize,
+    pub(crate) allow_http: bool,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self {
+            ca: None,
+            timeout: DEFAULT_HTTP_TIMEOUT,
+            max_response_bytes: DEFAULT_MAX_HTTP_RESPONSE_BYTES,
+            allow_http: false,
+        }
+    }
+}
+
+impl HttpClient {
+    /// Send `request` to `url` and read the whole response, up to
+    /// `max_response_bytes`, reporting failures through `fail`.
+    ///
+    /// Requests carry credentials, so plain `http` URLs are refused unless
+    /// `allow_http` is set.
+    pub(crate) fn send(
+        &self,
+        url: &HttpUrl,
+        request: &[u8],
+        fail: fn(String) -> GetMyIdError,
+    ) -> Result<Vec<u8>> {
+        if !url.tls && !self.allow_http {
+            return Err(fail(format!(
+                "refusing to send credentials to {} over plain http",
+                url.authority
+            )));
+        }
+        let unreachable = |e: io::Error| fail(format!("cannot reach {}: {e}", url.authority));
+
+        let addr = url
+            .authority
+            .to_socket_addrs()
+            .map_err(unreachable)?
+            .next()
+            .ok_or_else(|| fail(format!("cannot resolve {}", url.authority)))?;
+        let mut tcp = TcpStream::connect_timeout(&addr, self.timeout).map_err(unreachable)?;
+        tcp.set_read_timeout(Some(self.timeout))
+            .map_err(unreachable)?;
+        tcp.set_write_timeout(Some(self.timeout))
+            .map_err(unreachable)?;
+
+        let max = self.max_response_bytes;
+        let mut response = Vec::new();
+        if !url.tls {
+            tcp.write_all(request).map_err(unreachable)?;
+            tcp.take(read_limit(max))
+                .read_to_end(&mut response)
+                .map_err(unreachable)?;
+            return checked_response(response, max, fail);
+        }
+
+        let ca = self.ca.clone().unwrap_or_else(|| {
+            Pem::File(
+                std::env::var_os("SSL_CERT_FILE")
+                    .unwrap_or_else(|| DEFAULT_CA_BUNDLE.into())
+                    .into(),
+            )
+        });
+        let config = trusting(&ca)?.with_no_client_auth();
+        let host = host_of(&url.authority).to_string();
+        let server_name = ServerName::try_from(host.clone())
+            .map_err(|_| GetMyIdError::InvalidTlsConfig(format!("invalid server name '{host}'")))?;
+        let tls = ClientConnection::new(Arc::new(config), server_name)
+            .map_err(|e| unreachable(io::Error::other(e)))?;
+
+        let mut stream = StreamOwned::new(tls, tcp);
+        stream.write_all(request).map_err(unreachable)?;
+        match stream.take(read_limit(max)).read_to_end(&mut response) {
+            Ok(_) => {}
+            // Servers often close HTTP/1.0 connections without close_notify
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !response.is_empty() => {}
+            Err(e) => return Err(unreachable(e)),
+        }
+        checked_response(response, max, fail)
+    }
+}
+
+/// Fail through `fail` if `response`, read up to [`read_limit`] bytes,
+/// exceeds `max` or its body is shorter than its `Content-Length`.
+fn checked_response(
+    response: Vec<u8>,
+    max: usize,
+    fail: fn(String) -> GetMyIdError,
+) -> Result<Vec<u8>> {
+    if response.len() > max {
+        return Err(fail(format!("response exceeds {max} bytes")));
+    }
+    let Some(end) = response.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(response);
+    };
+    let head = String::from_utf8_lossy(&response[..end]);
+    let length = head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("content-length")
+            .then(|| value.trim().parse::<usize>())
+    });
+    let body = response.len() - end - 4;
+    match length {
+        Some(Ok(length)) if body < length => Err(fail(format!(
+            "truncated response: {body} of {length} body bytes"
+        ))),
+        Some(Err(_)) => Err(fail("invalid Content-Length in response".to_string())),
+        _ => Ok(response),
+    }
+}
+
+/// The parts of an `http` or `https` URL.
+#[derive(Debug, PartialEq)]
+pub(crate) struct HttpUrl {
+    pub(crate) tls: bool,
+    /// `host:port`, with the scheme's default port filled in.
+    pub(crate) authority: String,
+    pub(crate) path: String,
+    /// The query string including its `?`, or empty.
+    pub(crate) query: String,
+}
+
+impl HttpUrl {
+    /// Parse `url`, describing what is wrong with it on failure.
+    pub(crate) fn parse(url: &str) -> std::result::Result<Self, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("unsupported URL '{url}'"));
+        };
+
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+        if authority.is_empty() {
+            return Err(format!("URL '{url}' has no host"));
+        }
+        let has_port = authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| !port.contains(']'));
+        let authority = if has_port {
+            authority.to_string()
+        } else {
+            format!("{authority}:{}", if tls { 443 } else { 80 })
+        };
+        let (path, query) = path.split_at(path.find('?').unwrap_or(path.len()));
+
+        Ok(Self {
+            tls,
+            authority,
+            path: path.to_string(),
+            query: query.to_string(),
+        })
+    }
+
+    /// The request target: the path, or `/`, followed by the query.
+    pub(crate) fn target(&self) -> String {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        format!("{path}{}", self.query)
+    }
+}
+
+/// Split a raw HTTP response into its status code and body.
+pub(crate) fn split_response(response: &[u8]) -> Option<(String, String)> {
+    let response = String::from_utf8_lossy(response);
+    let (head, body) = response.split_once("\r\n\r\n")?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    Some((status.to_string(), body.to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::Runner;
+    use serde::Deserialize;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answer one HTTP request to `http://127.0.0.1:<port>/<base>` with
+    /// `status` and `body`, returning the URL and the request's text
+    /// through the join handle.
+    pub(crate) fn serve(
+        base: &str,
+        status: &str,
+        body: &str,
+    ) -> (String, thread::JoinHandle<String>) {
+        let response = format!("HTTP/1.0 {status}\r\nContent-Type: application/json\r\n\r\n{body}");
+        serve_raw(base, response)
+    }
+
+    /// Answer one HTTP request to `http://127.0.0.1:<port>/<base>` with the
+    /// raw `response`, as [`serve`] does.
+    fn serve_raw(base: &str, response: String) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/{base}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .map_or(0, |len| len.parse().unwrap());
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct AppConfig {
+        database_url: String,
+    }
+
+    /// The fetch settings of the tests, whose servers speak plain http.
+    fn fetch() -> ConfigFetch {
+        ConfigFetch::new().allow_http(true)
+    }
+
+    fn identity(config_url: String) -> Identity {
+        Identity {
+            identity: "BILLING_PROD".to_string(),
+            idm_url: String::new(),
+            config_url,
+            token: "tok_billing".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner::default(),
+        }
+    }
+
+    #[test]
+    fn test_fetch_config() {
+        let (url, server) = serve(
+            "api/billing?env=prod",
+            "200 OK",
+            r#"{"database_url":"postgres://db/billing"}"#,
+        );
+        let config: AppConfig = fetch().fetch(&identity(url)).unwrap();
+        let request = server.join().unwrap();
+
+        assert_eq!(config.database_url, "postgres://db/billing");
+        assert!(request.starts_with("GET /api/billing?env=prod HTTP/1.0\r\n"));
+        assert!(request.contains("Authorization: Bearer tok_billing\r\n"));
+    }
+
+    #[test]
+    fn test_fetch_config_errors() {
+        let (url, server) = serve("api/billing", "403 Forbidden", "");
+        let err = fetch().fetch::<AppConfig>(&identity(url)).unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(&err, GetMyIdError::ConfigFetchFailed(m) if m.contains("403")));
+
+        let (url, server) = serve("api/billing", "200 OK", r#"{"unexpected":true}"#);
+        let err = fetch().fetch::<AppConfig>(&identity(url)).unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(err, GetMyIdError::InvalidJson { .. }));
+
+        assert!(matches!(
+            identity(String::new()).fetch_config::<AppConfig>(),
+            Err(GetMyIdError::ConfigFetchFailed(_))
+        ));
+        let err = identity("http://127.0.0.1:9/api".to_string())
+            .fetch_config::<AppConfig>()
+            .unwrap_err();
+        assert!(matches!(&err, GetMyIdError::ConfigFetchFailed(m) if m.contains("plain http")));
+    }
+
+    #[test]
+    fn test_fetch_config_truncated() {
+        let body = r#"{"database_url":"postgres://db/billing"}"#;
+        let response = format!("HTTP/1.0 200 OK\r\nContent-Length: 100\r\n\r\n{body}");
+        let (url, server) = serve_raw("api/billing", response);
+        let err = fetch().fetch::<AppConfig>(&identity(url)).unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(&err, GetMyIdError::ConfigFetchFailed(m) if m.contains("truncated")));
+
+        let response = format!(
+            "HTTP/1.0 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (url, server) = serve_raw("api/billing", response);
+        let config: AppConfig = fetch().fetch(&identity(url)).unwrap();
+        server.join().unwrap();
+        assert_eq!(config.database_url, "postgres://db/billing");
+    }
+
+    #[test]
+    fn test_fetch_config_response_limit() {
+        let body = format!(r#"{{"database_url":"{}"}}"#, "x".repeat(4096));
+        let (url, server) = serve("api/billing", "200 OK", &body);
+        let err = fetch()
+            .max_response_bytes(1024)
+            .fetch::<AppConfig>(&identity(url))
+            .unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(&err, GetMyIdError::ConfigFetchFailed(m) if m.contains("1024 bytes")));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_fetch_config_async() {
+        let (url, server) = serve(
+            "api/billing",
+            "200 OK",
+            r#"{"database_url":"postgres://db/billing"}"#,
+        );
+        let config: AppConfig = fetch().fetch_async(&identity(url)).await.unwrap();
+        server.join().unwrap();
+
+        assert_eq!(config.database_url, "postgres://db/billing");
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            HttpUrl::parse("https://auth.example.com").unwrap(),
+            HttpUrl {
+                tls: true,
+                authority: "auth.example.com:443".to_string(),
+                path: String::new(),
+                query: String::new(),
+            }
+        );
+        let url = HttpUrl::parse("http://[::1]:8080/base/?x=1#top").unwrap();
+        assert_eq!(url.authority, "[::1]:8080");
+        assert_eq!(url.path, "/base/");
+        assert_eq!(url.target(), "/base/?x=1");
+        assert_eq!(
+            HttpUrl::parse("http://config.internal?x=1")
+                .unwrap()
+                .target(),
+            "/?x=1"
+        );
+        assert!(HttpUrl::parse("ftp://auth.example.com").is_err());
+        assert!(HttpUrl::parse("https:///path").is_err());
+    }
+}
@@ -0,0 +1,411 @@
+//! OAuth2 token exchange against the identity's IDM (requires `idm` feature).
+//!
+//! The daemon hands each process an identity name and token. Services then
+//! usually trade them for an access token at `idm_url`; this module performs
+//! that exchange as an OAuth2 client-credentials grant, authenticating with
+//! the identity name as client ID and the daemon-issued token as client
+//! secret.
+//!
+//! # Example
+//!
+//! ```no_run
+//! let identity = getmyid::get_identity()?;
+//! let access = identity.exchange_token()?;
+//! println!("Authorization: {} {}", access.token_type, access.access_token);
+//! # Ok::<(), getmyid::GetMyIdError>(())
+//! ```
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+
+use crate::error::{GetMyIdError, Result};
+use crate::http::{split_response, HttpClient, HttpUrl};
+use crate::sensitive::SensitiveString;
+use crate::support::redact_token;
+use crate::tls::Pem;
+use crate::types::{expiry_from_ttl, time_until, Identity};
+
+/// Path of the token endpoint below `idm_url`.
+pub const DEFAULT_TOKEN_PATH: &str = "/oauth2/token";
+
+/// An access token issued by the IDM.
+///
+/// The access token is redacted in `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AccessToken {
+    /// The access token.
+    pub access_token: SensitiveString,
+    /// How to present it, usually `Bearer`.
+    pub token_type: String,
+    /// Expiry in seconds since the Unix epoch, from the IDM's `expires_in`.
+    pub expires_at: Option<u64>,
+    /// Granted scopes, if the IDM reported them.
+    pub scope: Option<String>,
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("access_token", &redact_token(&self.access_token))
+            .field("token_type", &self.token_type)
+            .field("expires_at", &self.expires_at)
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+impl AccessToken {
+    /// Whether the access token has expired.
+    ///
+    /// Tokens without an expiry never expire.
+    pub fn is_expired(&self) -> bool {
+        self.expires_in() == Some(Duration::ZERO)
+    }
+
+    /// Get the time left before the access token expires, zero once it has.
+    ///
+    /// Returns `None` if the IDM reported no expiry, and [`Duration::MAX`]
+    /// for expiries too far ahead to represent.
+    pub fn expires_in(&self) -> Option<Duration> {
+        self.expires_at.map(time_until)
+    }
+}
+
+/// Settings for exchanging an identity's token at its `idm_url`.
+///
+/// [`Identity::exchange_token`] uses the defaults: the
+/// [`DEFAULT_TOKEN_PATH`] endpoint, no scope, a 10 second timeout, and for
+/// `https` URLs the CA bundle named by `SSL_CERT_FILE` or found at
+/// `/etc/ssl/certs/ca-certificates.crt`.
+///
+/// # Example
+///
+/// ```no_run
+/// use getmyid::TokenExchange;
+///
+/// let identity = getmyid::get_identity()?;
+/// let access = TokenExchange::new()
+///     .token_path("/oauth2/token/billing")
+///     .scope("config:read")
+///     .ca_cert_file("/etc/getmyid/idm-ca.pem")
+///     .exchange(&identity)?;
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenExchange {
+    token_path: String,
+    scope: Option<String>,
+    http: HttpClient,
+}
+
+impl Default for TokenExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TokenExchange {
+    /// Create an exchange with the default settings.
+    pub fn new() -> Self {
+        Self {
+            token_path: DEFAULT_TOKEN_PATH.to_string(),
+            scope: None,
+            http: HttpClient::default(),
+        }
+    }
+
+    /// Set the path of the token endpoint, appended to the path of
+    /// `idm_url`.
+    pub fn token_path(mut self, path: impl Into<String>) -> Self {
+        self.token_path = path.into();
+        self
+    }
+
+    /// Request the space-separated `scope`.
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Trust the PEM CA certificates in the file at `path` for `https` IDM
+    /// URLs instead of the system bundle.
+    pub fn ca_cert_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.http.ca = Some(Pem::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Trust the PEM CA certificates in `pem` for `https` IDM URLs instead
+    /// of the system bundle.
+    pub fn ca_cert_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.http.ca = Some(Pem::Bytes(pem.into()));
+        self
+    }
+
+    /// Set the connect, read and write timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.http.timeout = timeout;
+        self
+    }
+
+    /// Allow plain `http` IDM URLs, sending the token unencrypted, e.g. to
+    /// an IDM on the same host. Defaults to `false`.
+    pub fn allow_http(mut self, allow: bool) -> Self {
+        self.http.allow_http = allow;
+        self
+    }
+
+    /// Set the maximum size of the IDM's response, headers included.
+    /// Defaults to [`DEFAULT_MAX_HTTP_RESPONSE_BYTES`](crate::DEFAULT_MAX_HTTP_RESPONSE_BYTES).
+    pub fn max_response_bytes(mut self, max: usize) -> Self {
+        self.http.max_response_bytes = max;
+        self
+    }
+
+    /// Exchange `identity`'s token for an access token.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::TokenExchangeFailed`] if the identity has no
+    /// usable IDM URL, it is a plain `http` URL without
+    /// [`allow_http`](TokenExchange::allow_http), the IDM is unreachable, or
+    /// it rejects the request, and [`GetMyIdError::InvalidTlsConfig`] if the
+    /// CA certificates cannot be loaded.
+    pub fn exchange(&self, identity: &Identity) -> Result<AccessToken> {
+        if identity.idm_url.is_empty() {
+            return Err(failed("identity has no IDM URL"));
+        }
+        let url = HttpUrl::parse(&identity.idm_url).map_err(failed)?;
+        let path = format!(
+            "{}/{}",
+            url.path.trim_end_matches('/'),
+            self.token_path.trim_start_matches('/')
+        );
+
+        let mut body = "grant_type=client_credentials".to_string();
+        if let Some(scope) = &self.scope {
+            body.push_str(&format!("&scope={}", form_encode(scope)));
+        }
+        let credentials = format!(
+            "{}:{}",
+            form_encode(&identity.identity),
+            form_encode(&identity.token)
+        );
+        let request = format!(
+            "POST {path} HTTP/1.0\r\n\
+             Host: {}\r\n\
+             Authorization: Basic {}\r\n\
+             Content-Type: application/x-www-form-urlencoded\r\n\
+             Accept: application/json\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            url.authority,
+            STANDARD.encode(credentials),
+            body.len()
+        );
+
+        let response =
+            self.http
+                .send(&url, request.as_bytes(), GetMyIdError::TokenExchangeFailed)?;
+        parse_response(&response)
+    }
+
+    /// Exchange `identity`'s token for an access token without blocking the
+    /// async runtime (requires `tokio` feature).
+    ///
+    /// The exchange runs on Tokio's blocking thread pool.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`exchange`](TokenExchange::exchange).
+    #[cfg(feature = "tokio")]
+    pub async fn exchange_async(&self, identity: &Identity) -> Result<AccessToken> {
+        let exchange = self.clone();
+        let identity = identity.clone();
+        tokio::task::spawn_blocking(move || exchange.exchange(&identity))
+            .await
+            .map_err(|e| failed(format!("exchange task failed: {e}")))?
+    }
+}
+
+impl Identity {
+    /// Exchange the daemon-issued token for an access token at `idm_url`,
+    /// using the default [`TokenExchange`] settings (requires `idm`
+    /// feature).
+    ///
+    /// # Errors
+    ///
+    /// See [`TokenExchange::exchange`].
+    pub fn exchange_token(&self) -> Result<AccessToken> {
+        TokenExchange::new().exchange(self)
+    }
+
+    /// Async version of [`exchange_token`](Identity::exchange_token)
+    /// (requires `idm` and `tokio` features).
+    ///
+    /// # Errors
+    ///
+    /// See [`TokenExchange::exchange`].
+    #[cfg(feature = "tokio")]
+    pub async fn exchange_token_async(&self) -> Result<AccessToken> {
+        TokenExchange::new().exchange_async(self).await
+    }
+}
+
+/// Successful token endpoint response (RFC 6749, section 5.1).
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+/// Token endpoint error response (RFC 6749, section 5.2).
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+fn parse_response(response: &[u8]) -> Result<AccessToken> {
+    let (status, body) =
+        split_response(response).ok_or_else(|| failed("malformed HTTP response from the IDM"))?;
+
+    if status != "200" {
+        return Err(failed(match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(ErrorResponse {
+                error,
+                error_description: Some(description),
+            }) => format!("IDM returned HTTP status {status}: {error} ({description})"),
+            Ok(ErrorResponse { error, .. }) => {
+                format!("IDM returned HTTP status {status}: {error}")
+            }
+            Err(_) => format!("IDM returned HTTP status {status}"),
+        }));
+    }
+
+    let token: TokenResponse = serde_json::from_str(&body).map_err(GetMyIdError::invalid_json)?;
+    Ok(AccessToken {
+        access_token: token.access_token.into(),
+        token_type: token.token_type,
+        expires_at: token.expires_in.map(expiry_from_ttl),
+        scope: token.scope,
+    })
+}
+
+/// Encode `value` for `application/x-www-form-urlencoded` bodies and Basic
+/// credentials (RFC 6749, appendix B).
+fn form_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b' ' => "+".to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn failed(message: impl Into<String>) -> GetMyIdError {
+    GetMyIdError::TokenExchangeFailed(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::serve;
+    use crate::types::Runner;
+
+    fn identity(idm_url: String) -> Identity {
+        Identity {
+            identity: "BILLING_PROD".to_string(),
+            idm_url,
+            config_url: String::new(),
+            token: "tok/secret".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner::default(),
+        }
+    }
+
+    #[test]
+    fn test_exchange_token() {
+        let (url, server) = serve(
+            "idm",
+            "200 OK",
+            r#"{"access_token":"at_123","token_type":"Bearer","expires_in":300,"scope":"config:read"}"#,
+        );
+        let access = TokenExchange::new()
+            .allow_http(true)
+            .scope("config:read")
+            .exchange(&identity(url))
+            .unwrap();
+
+        assert_eq!(access.access_token, "at_123");
+        assert_eq!(access.token_type, "Bearer");
+        assert_eq!(access.scope.as_deref(), Some("config:read"));
+        assert!(access.expires_in().unwrap() > Duration::from_secs(290));
+        assert!(!access.is_expired());
+
+        let request = server.join().unwrap();
+        let credentials = STANDARD.encode("BILLING_PROD:tok%2Fsecret");
+        assert!(request.starts_with("POST /idm/oauth2/token HTTP/1.0\r\n"));
+        assert!(request.contains(&format!("Authorization: Basic {credentials}\r\n")));
+        assert!(request.ends_with("grant_type=client_credentials&scope=config%3Aread"));
+    }
+
+    #[test]
+    fn test_exchange_token_rejected() {
+        let (url, server) = serve(
+            "idm",
+            "401 Unauthorized",
+            r#"{"error":"invalid_client","error_description":"unknown client"}"#,
+        );
+        let err = TokenExchange::new()
+            .allow_http(true)
+            .exchange(&identity(url))
+            .unwrap_err();
+        server.join().unwrap();
+
+        assert!(matches!(err, GetMyIdError::TokenExchangeFailed(_)));
+        assert!(err.to_string().contains("invalid_client (unknown client)"));
+
+        assert!(matches!(
+            identity(String::new()).exchange_token(),
+            Err(GetMyIdError::TokenExchangeFailed(_))
+        ));
+        let err = identity("http://127.0.0.1:9/idm".to_string())
+            .exchange_token()
+            .unwrap_err();
+        assert!(err.to_string().contains("plain http"));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_exchange_token_async() {
+        let (url, server) = serve(
+            "idm",
+            "200 OK",
+            r#"{"access_token":"at_123","token_type":"Bearer"}"#,
+        );
+        let access = TokenExchange::new()
+            .allow_http(true)
+            .exchange_async(&identity(url))
+            .await
+            .unwrap();
+        server.join().unwrap();
+
+        assert_eq!(access.access_token, "at_123");
+        assert_eq!(access.expires_at, None);
+    }
+}
@@ -0,0 +1,101 @@
+//! `tonic` interceptor adding the token to gRPC calls (requires `tonic`
+//! feature).
+
+use std::sync::Arc;
+
+use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::managed::ManagedIdentity;
+
+/// Interceptor setting the `authorization` metadata of outgoing gRPC calls
+/// to `Bearer <token>`.
+///
+/// The token is read from a [`ManagedIdentity`] on every call, so calls
+/// always carry the latest token without a daemon round-trip. Calls that
+/// already set `authorization` are sent unchanged.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use getmyid::{AsyncClient, ManagedIdentity, TokenInterceptor};
+///
+/// # async fn example() -> Result<(), getmyid::GetMyIdError> {
+/// let identity = ManagedIdentity::start(AsyncClient::new(), Duration::from_secs(60)).await?;
+/// let interceptor = TokenInterceptor::from(identity);
+/// // let client = BillingClient::with_interceptor(channel, interceptor);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenInterceptor {
+    identity: Arc<ManagedIdentity>,
+}
+
+impl TokenInterceptor {
+    /// Create an interceptor reading the token from `identity`.
+    pub fn new(identity: Arc<ManagedIdentity>) -> Self {
+        Self { identity }
+    }
+
+    /// Get the identity the token is read from.
+    pub fn identity(&self) -> &Arc<ManagedIdentity> {
+        &self.identity
+    }
+}
+
+impl From<ManagedIdentity> for TokenInterceptor {
+    fn from(identity: ManagedIdentity) -> Self {
+        Self::new(Arc::new(identity))
+    }
+}
+
+impl Interceptor for TokenInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if !request.metadata().contains_key("authorization") {
+            let value = MetadataValue::try_from(self.identity.current().auth_header_value())
+                .map_err(|_| Status::internal("identity token is not a valid metadata value"))?;
+            request.metadata_mut().insert("authorization", value);
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::async_client::AsyncClient;
+    use crate::testing::FakeDaemon;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_token_interceptor() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let managed = ManagedIdentity::start(client, Duration::from_secs(60))
+            .await
+            .unwrap();
+        let mut interceptor = TokenInterceptor::from(managed);
+
+        let request = interceptor.call(Request::new(())).unwrap();
+        assert_eq!(
+            request.metadata().get("authorization").unwrap(),
+            "Bearer tok_test"
+        );
+
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            "authorization",
+            MetadataValue::from_static("Basic dXNlcg=="),
+        );
+        let request = interceptor.call(request).unwrap();
+        assert_eq!(
+            request.metadata().get("authorization").unwrap(),
+            "Basic dXNlcg=="
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Inspection of JWT tokens (requires `jwt` feature).
+//!
+//! Daemons backed by an identity provider often hand out JWTs as the
+//! identity token. [`Identity::token_claims`] decodes the payload without
+//! checking the signature, which is enough to read the subject or expiry for
+//! logging and refresh scheduling; [`Identity::verify_token_claims`] checks
+//! the signature and validity window first, for callers that need to trust
+//! the claims.
+//!
+//! # Example
+//!
+//! ```no_run
+//! let identity = getmyid::get_identity()?;
+//! let claims = identity.token_claims()?;
+//! println!("subject {:?}, audience {:?}", claims.sub, claims.aud);
+//! # Ok::<(), getmyid::GetMyIdError>(())
+//! ```
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::alphabet::URL_SAFE;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use ring::{hmac, signature};
+use serde::de::{DeserializeOwned, Deserializer};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{GetMyIdError, Result};
+use crate::types::Identity;
+
+/// Base64url, accepting segments with or without padding.
+const BASE64URL: GeneralPurpose = GeneralPurpose::new(
+    &URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Registered claims of a JWT, plus any others in `extra`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct TokenClaims {
+    /// Subject (`sub`).
+    #[serde(default)]
+    pub sub: Option<String>,
+    /// Issuer (`iss`).
+    #[serde(default)]
+    pub iss: Option<String>,
+    /// Audiences (`aud`), whether the token lists one or several.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub aud: Vec<String>,
+    /// Expiry in seconds since the Unix epoch (`exp`).
+    #[serde(default)]
+    pub exp: Option<u64>,
+    /// Start of validity in seconds since the Unix epoch (`nbf`).
+    #[serde(default)]
+    pub nbf: Option<u64>,
+    /// Issue time in seconds since the Unix epoch (`iat`).
+    #[serde(default)]
+    pub iat: Option<u64>,
+    /// All other claims.
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+/// Key checking a JWT signature with
+/// [`Identity::verify_token_claims`].
+///
+/// The token's `alg` header must match the key type, so a token cannot pick
+/// a weaker algorithm than the caller expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum JwtKey {
+    /// `HS256`: HMAC-SHA256 with a shared secret.
+    Hs256(Vec<u8>),
+    /// `RS256`: RSA PKCS#1 v1.5 with SHA-256, given the DER-encoded
+    /// `RSAPublicKey` (2048 to 8192 bits).
+    Rs256(Vec<u8>),
+    /// `ES256`: ECDSA P-256 with SHA-256, given the uncompressed public
+    /// point.
+    Es256(Vec<u8>),
+}
+
+impl JwtKey {
+    fn alg(&self) -> &'static str {
+        match self {
+            JwtKey::Hs256(_) => "HS256",
+            JwtKey::Rs256(_) => "RS256",
+            JwtKey::Es256(_) => "ES256",
+        }
+    }
+
+    fn verify(&self, message: &[u8], sig: &[u8]) -> bool {
+        match self {
+            JwtKey::Hs256(secret) => {
+                hmac::verify(&hmac::Key::new(hmac::HMAC_SHA256, secret), message, sig).is_ok()
+            }
+            JwtKey::Rs256(der) => {
+                signature::UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, der)
+                    .verify(message, sig)
+                    .is_ok()
+            }
+            JwtKey::Es256(point) => {
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                    .verify(message, sig)
+                    .is_ok()
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+}
+
+/// The three segments of a compact JWT.
+struct Parts<'a> {
+    /// The `header.payload` bytes covered by the signature.
+    signing_input: &'a str,
+    header: &'a str,
+    payload: &'a str,
+    signature: &'a str,
+}
+
+impl<'a> Parts<'a> {
+    fn split(token: &'a str) -> Result<Self> {
+        let mut parts = token.split('.');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature), None) => Ok(Self {
+                signing_input: &token[..header.len() + 1 + payload.len()],
+                header,
+                payload,
+                signature,
+            }),
+            _ => Err(invalid(
+                "token is not a JWT (expected three '.'-separated parts)",
+            )),
+        }
+    }
+}
+
+impl Identity {
+    /// Decode the claims of the token without checking its signature.
+    ///
+    /// Use this to read the subject, audience or expiry for display and
+    /// refresh scheduling; anything security-relevant should go through
+    /// [`verify_token_claims`](Identity::verify_token_claims).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidToken`] if the token is not a JWT.
+    pub fn token_claims(&self) -> Result<TokenClaims> {
+        let parts = Parts::split(&self.token)?;
+        decode_segment(parts.payload, "payload")
+    }
+
+    /// Check the token's signature with `key` and its `exp` / `nbf`
+    /// validity window, then decode its claims.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidToken`] if the token is not a JWT, its
+    /// algorithm does not match `key`, the signature is wrong, or it is
+    /// expired or not yet valid.
+    pub fn verify_token_claims(&self, key: &JwtKey) -> Result<TokenClaims> {
+        let parts = Parts::split(&self.token)?;
+        let header: Header = decode_segment(parts.header, "header")?;
+        if header.alg != key.alg() {
+            return Err(invalid(format!(
+                "token is signed with {}, expected {}",
+                header.alg,
+                key.alg()
+            )));
+        }
+        let sig = BASE64URL
+            .decode(parts.signature)
+            .map_err(|e| invalid(format!("invalid signature encoding: {e}")))?;
+        if !key.verify(parts.signing_input.as_bytes(), &sig) {
+            return Err(invalid("signature verification failed"));
+        }
+
+        let claims: TokenClaims = decode_segment(parts.payload, "payload")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        if claims.exp.is_some_and(|exp| now >= exp) {
+            return Err(invalid("token has expired"));
+        }
+        if claims.nbf.is_some_and(|nbf| now < nbf) {
+            return Err(invalid("token is not valid yet"));
+        }
+        Ok(claims)
+    }
+}
+
+fn decode_segment<T: DeserializeOwned>(segment: &str, name: &str) -> Result<T> {
+    let json = BASE64URL
+        .decode(segment)
+        .map_err(|e| invalid(format!("invalid {name} encoding: {e}")))?;
+    serde_json::from_slice(&json).map_err(|e| invalid(format!("invalid {name}: {e}")))
+}
+
+fn invalid(message: impl Into<String>) -> GetMyIdError {
+    GetMyIdError::InvalidToken(message.into())
+}
+
+/// Accept `"aud": "x"` as well as `"aud": ["x", "y"]`.
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(aud)) => vec![aud],
+        Some(OneOrMany::Many(aud)) => aud,
+        None => Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Runner;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair};
+
+    fn identity(token: String) -> Identity {
+        Identity {
+            identity: "BILLING_PROD".to_string(),
+            idm_url: String::new(),
+            config_url: String::new(),
+            token: token.into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner::default(),
+        }
+    }
+
+    /// Build `header.payload` for `alg` and `claims`.
+    fn signing_input(alg: &str, claims: &Value) -> String {
+        let header = BASE64URL.encode(serde_json::json!({ "alg": alg, "typ": "JWT" }).to_string());
+        let payload = BASE64URL.encode(claims.to_string());
+        format!("{header}.{payload}")
+    }
+
+    fn hs256_token(secret: &[u8], claims: &Value) -> String {
+        let input = signing_input("HS256", claims);
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, secret), input.as_bytes());
+        format!("{input}.{}", BASE64URL.encode(tag.as_ref()))
+    }
+
+    #[test]
+    fn test_token_claims_without_verification() {
+        let claims = serde_json::json!({
+            "sub": "billing", "aud": "config", "exp": 4_000_000_000u64, "scope": "read"
+        });
+        let claims = identity(hs256_token(b"secret", &claims))
+            .token_claims()
+            .unwrap();
+
+        assert_eq!(claims.sub.as_deref(), Some("billing"));
+        assert_eq!(claims.aud, ["config"]);
+        assert_eq!(claims.exp, Some(4_000_000_000));
+        assert_eq!(claims.extra["scope"], "read");
+
+        assert!(matches!(
+            identity("tok_opaque".to_string()).token_claims(),
+            Err(GetMyIdError::InvalidToken(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_hs256() {
+        let claims = serde_json::json!({ "sub": "billing", "aud": ["a", "b"] });
+        let signed = identity(hs256_token(b"secret", &claims));
+
+        let verified = signed
+            .verify_token_claims(&JwtKey::Hs256(b"secret".to_vec()))
+            .unwrap();
+        assert_eq!(verified.aud, ["a", "b"]);
+
+        for key in [JwtKey::Hs256(b"other".to_vec()), JwtKey::Es256(Vec::new())] {
+            assert!(matches!(
+                signed.verify_token_claims(&key),
+                Err(GetMyIdError::InvalidToken(_))
+            ));
+        }
+
+        let expired = serde_json::json!({ "sub": "billing", "exp": 1_000_000_000u64 });
+        let err = identity(hs256_token(b"secret", &expired))
+            .verify_token_claims(&JwtKey::Hs256(b"secret".to_vec()))
+            .unwrap_err();
+        assert!(err.to_string().contains("expired"));
+    }
+
+    #[test]
+    fn test_verify_es256() {
+        let rng = SystemRandom::new();
+        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref(), &rng).unwrap();
+
+        let input = signing_input("ES256", &serde_json::json!({ "sub": "billing" }));
+        let sig = pair.sign(&rng, input.as_bytes()).unwrap();
+        let identity = identity(format!("{input}.{}", BASE64URL.encode(sig.as_ref())));
+        let key = JwtKey::Es256(pair.public_key().as_ref().to_vec());
+
+        assert_eq!(
+            identity.verify_token_claims(&key).unwrap().sub.as_deref(),
+            Some("billing")
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! `tower` middleware resolving the identity for inner services (requires
+//! `tower` feature).
+
+use std::error::Error;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::sync::Mutex;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::async_client::AsyncClient;
+use crate::async_transport::BoxFuture;
+use crate::error::Result;
+use crate::types::Identity;
+
+/// Error type of [`GetMyIdService`]: the inner service's error or a
+/// [`GetMyIdError`](crate::GetMyIdError) resolving the identity.
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Layer wrapping services in a [`GetMyIdService`].
+///
+/// The identity is resolved with the layer's client on the first request,
+/// then cached until its token expires. Every service created by the layer,
+/// and every clone of them, shares the cache.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use getmyid::{AsyncClient, GetMyIdLayer, Identity};
+/// use tower_layer::Layer;
+///
+/// // In the inner service:
+/// fn caller<B>(request: &http::Request<B>) -> Option<&str> {
+///     let identity = request.extensions().get::<Arc<Identity>>()?;
+///     Some(&identity.identity)
+/// }
+///
+/// # fn example<S>(service: S) {
+/// let service = GetMyIdLayer::new(AsyncClient::new()).layer(service);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct GetMyIdLayer {
+    resolver: Arc<Resolver>,
+}
+
+impl GetMyIdLayer {
+    /// Create a layer resolving the identity with `client`.
+    pub fn new(client: AsyncClient) -> Self {
+        Self {
+            resolver: Arc::new(Resolver {
+                client,
+                identity: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+impl<S> Layer<S> for GetMyIdLayer {
+    type Service = GetMyIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GetMyIdService {
+            inner,
+            resolver: Arc::clone(&self.resolver),
+        }
+    }
+}
+
+/// Service inserting the caller's identity into the request extensions as
+/// an `Arc<Identity>` before calling the inner service.
+///
+/// Requests fail without reaching the inner service if the identity cannot
+/// be resolved. Created by [`GetMyIdLayer`].
+#[derive(Debug, Clone)]
+pub struct GetMyIdService<S> {
+    inner: S,
+    resolver: Arc<Resolver>,
+}
+
+impl<S, B> Service<http::Request<B>> for GetMyIdService<S>
+where
+    S: Service<http::Request<B>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, std::result::Result<S::Response, BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), BoxError>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        // Call the instance that was polled ready, leaving a fresh clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let resolver = Arc::clone(&self.resolver);
+        Box::pin(async move {
+            let identity = resolver.identity().await?;
+            request.extensions_mut().insert(identity);
+            inner.call(request).await.map_err(Into::into)
+        })
+    }
+}
+
+/// Lazily resolved identity shared by a layer and its services.
+#[derive(Debug)]
+struct Resolver {
+    client: AsyncClient,
+    identity: Mutex<Option<Arc<Identity>>>,
+}
+
+impl Resolver {
+    /// Get the cached identity, resolving it if absent or expired.
+    ///
+    /// The lock is held while resolving so concurrent requests wait for a
+    /// single daemon lookup.
+    async fn identity(&self) -> Result<Arc<Identity>> {
+        let mut cached = self.identity.lock().await;
+        if let Some(identity) = cached.as_ref().filter(|identity| !identity.is_expired()) {
+            return Ok(Arc::clone(identity));
+        }
+        let identity = Arc::new(self.client.get_identity().await?);
+        *cached = Some(Arc::clone(&identity));
+        Ok(identity)
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::error::GetMyIdError;
+    use crate::testing::{FakeDaemon, FakeResponse};
+    use std::future::poll_fn;
+
+    /// Inner service answering with the name of the identity it was given.
+    #[derive(Clone)]
+    struct Whoami;
+
+    impl Service<http::Request<()>> for Whoami {
+        type Response = String;
+        type Error = BoxError;
+        type Future = BoxFuture<'static, std::result::Result<String, BoxError>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::result::Result<(), BoxError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            let identity = request.extensions().get::<Arc<Identity>>().cloned();
+            Box::pin(async move { Ok(identity.map(|i| i.identity.clone()).unwrap_or_default()) })
+        }
+    }
+
+    async fn send<S>(service: &mut S) -> std::result::Result<String, BoxError>
+    where
+        S: Service<http::Request<()>, Response = String, Error = BoxError>,
+    {
+        poll_fn(|cx| service.poll_ready(cx)).await?;
+        service.call(http::Request::new(())).await
+    }
+
+    #[tokio::test]
+    async fn test_layer_caches_identity() {
+        let daemon = FakeDaemon::start().unwrap();
+        let layer = GetMyIdLayer::new(
+            AsyncClient::builder()
+                .socket_path(daemon.socket_path())
+                .build(),
+        );
+        let mut first = layer.layer(Whoami);
+        let mut second = layer.layer(Whoami);
+
+        let name = FakeDaemon::sample_identity().identity.clone();
+
+        assert!(daemon.requests().is_empty());
+        assert_eq!(send(&mut first).await.unwrap(), name);
+        assert_eq!(send(&mut second).await.unwrap(), name);
+        assert_eq!(daemon.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_layer_refreshes_expired_identity() {
+        let mut expired = FakeDaemon::sample_identity();
+        expired.expires_at = Some(1_000_000_000);
+        let daemon = FakeDaemon::with_response(FakeResponse::identity(expired)).unwrap();
+        let mut service = GetMyIdLayer::new(
+            AsyncClient::builder()
+                .socket_path(daemon.socket_path())
+                .build(),
+        )
+        .layer(Whoami);
+
+        send(&mut service).await.unwrap();
+        send(&mut service).await.unwrap();
+        assert_eq!(daemon.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_layer_unresolved_identity() {
+        let mut service = GetMyIdLayer::new(
+            AsyncClient::builder()
+                .socket_path("/nonexistent/whoami.sock")
+                .build(),
+        )
+        .layer(Whoami);
+
+        let err = send(&mut service).await.unwrap_err();
+        assert!(err.downcast_ref::<GetMyIdError>().is_some());
+    }
+}
@@ -12,6 +12,7 @@
 //! - **Asynchronous client**: Enable the `tokio` feature for async support
 //! - **Runner context**: Send client context that gets merged with server identity
 //! - **Builder pattern**: Flexible client configuration
+//! - **Providers**: Swap the daemon for static or environment identities via [`IdentityProvider`]
 //! - **Type-safe**: Strongly typed identity and error types
 //!
 //! ## Quick Start
@@ -61,8 +62,11 @@
 //! ### Asynchronous Usage (requires `tokio` feature)
 //!
 //! ```no_run
+//! # #[cfg(not(feature = "tokio"))] fn main() {}
+//! # #[cfg(feature = "tokio")]
 //! use getmyid::AsyncClient;
 //!
+//! # #[cfg(feature = "tokio")]
 //! #[tokio::main]
 //! async fn main() -> Result<(), getmyid::GetMyIdError> {
 //!     let client = AsyncClient::new();
@@ -86,6 +90,22 @@
 //!     .build();
 //! ```
 //!
+//! ## Feature Layout
+//!
+//! The core (types, the [`protocol`] module and the synchronous [`Client`])
+//! is always built. The `async` feature adds `AsyncClient`, `integrations`
+//! the `reqwest`, `tower`, `axum`, `tonic` and `actix` glue on top of it, and
+//! `testing` the `testing::FakeDaemon` test double. Each layer only depends
+//! on the ones before it.
+//!
+//! ## Stability
+//!
+//! Everything available without the `unstable` feature follows semantic
+//! versioning. APIs behind `unstable` are experimental: they may change or
+//! disappear in any release, so pin an exact version when enabling it.
+//! Currently this covers `AsyncClientBuilder::hedge_after`
+//! (with the `tokio` feature).
+//!
 //! ## How It Works
 //!
 //! 1. Your application connects to the whoami daemon's Unix Domain Socket
@@ -104,20 +124,182 @@
 #![warn(missing_docs)]
 #![warn(rustdoc::missing_crate_level_docs)]
 
+mod activation;
+mod binding;
+mod capabilities;
 mod client;
+pub mod compat;
+mod compatibility;
+pub mod config;
+mod crash;
+mod diff;
 mod error;
+mod global;
+mod limits;
+mod logging;
+mod metrics;
+mod nonblocking;
+mod pressure;
+mod procfs;
+pub mod protocol;
+mod provider;
+mod retry;
+mod sensitive;
+mod session;
+mod singleflight;
+mod soft;
+mod support;
+mod transport;
+mod typed;
 mod types;
+mod view;
 
 #[cfg(feature = "tokio")]
 mod async_client;
 
+#[cfg(feature = "tokio")]
+mod async_transport;
+
+#[cfg(feature = "tokio")]
+mod managed;
+
+#[cfg(feature = "tokio")]
+mod pool;
+
+#[cfg(feature = "tokio")]
+pub mod scaffold;
+
+#[cfg(feature = "tokio")]
+mod watch;
+
+#[cfg(feature = "test-util")]
+pub mod testing;
+
+#[cfg(feature = "tls")]
+mod tls;
+
+#[cfg(feature = "cloud")]
+mod cloud;
+
+#[cfg(feature = "jwt")]
+mod jwt;
+
+#[cfg(feature = "http")]
+mod http;
+
+#[cfg(feature = "idm")]
+mod idm;
+
+#[cfg(feature = "bootstrap")]
+mod bootstrap;
+
+#[cfg(feature = "reqwest")]
+mod middleware;
+
+#[cfg(feature = "tower")]
+mod layer;
+
+#[cfg(feature = "axum")]
+mod extract;
+
+#[cfg(feature = "tonic")]
+mod interceptor;
+
+#[cfg(feature = "actix")]
+mod actix;
+
+#[cfg(feature = "secrecy")]
+mod secret;
+
+#[cfg(feature = "signing")]
+mod signing;
+
+#[cfg(feature = "noise")]
+mod noise;
+
+#[cfg(feature = "otel")]
+mod otel;
+
 // Re-export main types
-pub use client::{Client, ClientBuilder, DEFAULT_SOCKET_PATH, DEFAULT_TIMEOUT};
-pub use error::{GetMyIdError, Result};
-pub use types::{Identity, Runner, RunnerRequest};
+pub use activation::{
+    default_socket_candidates, discover_socket_path, inherited_socket_path, INHERITED_SOCKET_NAME,
+};
+pub use binding::{BindingVerifier, DEFAULT_BINDING_CAPACITY};
+pub use capabilities::{capabilities, Capabilities};
+pub use client::{
+    Client, ClientBuilder, DEFAULT_MAX_RESPONSE_BYTES, DEFAULT_SOCKET_PATH, DEFAULT_TIMEOUT,
+};
+pub use compatibility::{
+    Compatibility, DaemonInfo, DaemonVersion, TESTED_DAEMON_VERSIONS, TESTED_PROTOCOL_VERSIONS,
+};
+pub use crash::{crash_report_hook, CrashReport, CRASH_SNAPSHOT_SIZE};
+pub use diff::IdentityDiff;
+pub use error::{DaemonErrorCode, GetMyIdError, Result};
+pub use global::{global, init_global, GLOBAL_CACHE_TTL};
+pub use limits::{JsonLimits, DEFAULT_MAX_DEPTH, DEFAULT_MAX_STRING_LEN};
+pub use metrics::{MetricsSink, RequestMetrics};
+pub use nonblocking::{IdentityRequest, Interest};
+pub use pressure::{Pressure, PRESSURE_WINDOW};
+pub use provider::{
+    DaemonProvider, DualStackProvider, EnvProvider, FallbackPolicy, IdentityProvider,
+    ProviderChain, StaticProvider, DEFAULT_ENV_PREFIX,
+};
+pub use sensitive::SensitiveString;
+pub use session::Session;
+pub use soft::{SoftErrorCodes, SoftFailure};
+pub use support::{ClientSettings, SocketReport, SupportBundle};
+pub use transport::{
+    ConnectedTransport, Connection, SocketType, SymlinkPolicy, Transport, UnixTransport,
+    ABSTRACT_SOCKET_PREFIX,
+};
+pub use typed::TypedIdentity;
+pub use types::{
+    BootstrapPayload, HostnamePolicy, Identity, IdentityChange, KeyCase, RawIdentity, Runner,
+    RunnerRequest,
+};
+pub use view::{IdentityView, RunnerView};
 
+#[cfg(feature = "actix")]
+pub use actix::{IdentityMiddleware, IdentityMiddlewareService};
 #[cfg(feature = "tokio")]
 pub use async_client::{AsyncClient, AsyncClientBuilder};
+#[cfg(feature = "bootstrap")]
+pub use bootstrap::MAX_BOOTSTRAP_SIZE;
+#[cfg(feature = "cloud")]
+pub use cloud::{CloudMetadataProvider, CloudPlatform, IDENTITY_SOURCE_FIELD};
+#[cfg(feature = "axum")]
+pub use extract::{IdentityRejection, WhoAmI};
+#[cfg(feature = "http")]
+pub use http::{ConfigFetch, DEFAULT_MAX_HTTP_RESPONSE_BYTES};
+#[cfg(feature = "idm")]
+pub use idm::{AccessToken, TokenExchange, DEFAULT_TOKEN_PATH};
+#[cfg(feature = "tonic")]
+pub use interceptor::TokenInterceptor;
+#[cfg(feature = "jwt")]
+pub use jwt::{JwtKey, TokenClaims};
+#[cfg(feature = "tower")]
+pub use layer::{BoxError, GetMyIdLayer, GetMyIdService};
+#[cfg(feature = "tokio")]
+pub use managed::ManagedIdentity;
+#[cfg(feature = "reqwest")]
+pub use middleware::TokenMiddleware;
+#[cfg(feature = "noise")]
+pub use noise::{NoiseTransport, NOISE_PARAMS};
+#[cfg(feature = "otel")]
+pub use otel::{TraceParent, TRACEPARENT_FIELD};
+#[cfg(feature = "tokio")]
+pub use pool::{AsyncClientPool, Priority, DEFAULT_CHECKOUT_TIMEOUT};
+#[cfg(feature = "secrecy")]
+pub use secret::SecretIdentity;
+#[cfg(feature = "tls")]
+pub use tls::{TcpTlsTransport, TcpTlsTransportBuilder};
+#[cfg(feature = "tokio")]
+pub use watch::IdentityWatch;
+
+#[cfg(feature = "tokio")]
+pub use async_transport::{
+    AsyncConnectedTransport, AsyncConnection, AsyncTransport, AsyncUnixTransport, BoxFuture,
+};
 
 /// Convenience function to get identity using default settings.
 ///
@@ -0,0 +1,230 @@
+//! Limits on the JSON values daemons may send as runner extras.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::{GetMyIdError, Result};
+use crate::types::Runner;
+
+/// Default [`JsonLimits::max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/// Default [`JsonLimits::max_string_len`], in bytes.
+pub const DEFAULT_MAX_STRING_LEN: usize = 64 * 1024;
+
+/// Longest extra key quoted in a [`GetMyIdError::MalformedResponse`].
+const MAX_FIELD_LEN: usize = 64;
+
+/// Bounds on the runner extras of daemon responses, enforced when the
+/// clients parse a response.
+///
+/// Responses whose extras nest arrays and objects deeper than
+/// [`max_depth`](JsonLimits::max_depth), or hold a key or string value
+/// longer than [`max_string_len`](JsonLimits::max_string_len) bytes, are
+/// rejected with [`GetMyIdError::MalformedResponse`] instead of being handed
+/// to the application.
+///
+/// # Example
+///
+/// ```
+/// use getmyid::{Client, JsonLimits};
+///
+/// let client = Client::builder()
+///     .json_limits(JsonLimits::new().with_max_depth(4).with_max_string_len(1024))
+///     .build();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonLimits {
+    max_depth: usize,
+    max_string_len: usize,
+}
+
+impl Default for JsonLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsonLimits {
+    /// Create limits of [`DEFAULT_MAX_DEPTH`] and [`DEFAULT_MAX_STRING_LEN`].
+    pub const fn new() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_string_len: DEFAULT_MAX_STRING_LEN,
+        }
+    }
+
+    /// Create limits accepting any extras `serde_json` can parse.
+    pub const fn unlimited() -> Self {
+        Self {
+            max_depth: usize::MAX,
+            max_string_len: usize::MAX,
+        }
+    }
+
+    /// Set how deeply arrays and objects may nest inside an extra; `0`
+    /// allows scalar extras only.
+    pub const fn with_max_depth(mut self, max: usize) -> Self {
+        self.max_depth = max;
+        self
+    }
+
+    /// Set the maximum length in bytes of extra keys, including those of
+    /// nested objects, and string values.
+    pub const fn with_max_string_len(mut self, max: usize) -> Self {
+        self.max_string_len = max;
+        self
+    }
+
+    /// Get the maximum nesting depth of an extra.
+    pub const fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Get the maximum length in bytes of keys and string values.
+    pub const fn max_string_len(&self) -> usize {
+        self.max_string_len
+    }
+
+    /// Check the extras of `runner` against these limits.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::MalformedResponse`] naming the first extra
+    /// found to exceed a limit.
+    pub fn check(&self, runner: &Runner) -> Result<()> {
+        for (key, value) in &runner.extra {
+            let violated = if key.len() > self.max_string_len {
+                Some(("max_string_len", self.max_string_len))
+            } else {
+                self.violation(value, 0)
+            };
+            if let Some((limit, max)) = violated {
+                return Err(GetMyIdError::MalformedResponse {
+                    field: format!("runner.{}", truncate(key)),
+                    limit,
+                    max,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the runner extras of the raw daemon `response` against these
+    /// limits, for responses parsed without going through [`Runner`].
+    ///
+    /// Responses without a runner object that parses are left to the
+    /// caller's parser to reject.
+    pub(crate) fn check_response(&self, response: &str) -> Result<()> {
+        #[derive(Deserialize)]
+        struct Envelope {
+            runner: Option<Runner>,
+        }
+
+        if *self == Self::unlimited() {
+            return Ok(());
+        }
+        match serde_json::from_str::<Envelope>(response) {
+            Ok(Envelope {
+                runner: Some(runner),
+            }) => self.check(&runner),
+            _ => Ok(()),
+        }
+    }
+
+    /// The limit `value`, nested `depth` levels into an extra, exceeds.
+    fn violation(&self, value: &Value, depth: usize) -> Option<(&'static str, usize)> {
+        match value {
+            Value::String(s) => {
+                (s.len() > self.max_string_len).then_some(("max_string_len", self.max_string_len))
+            }
+            Value::Array(items) => self.nested_violation(items.iter(), depth),
+            Value::Object(map) if map.keys().any(|key| key.len() > self.max_string_len) => {
+                Some(("max_string_len", self.max_string_len))
+            }
+            Value::Object(map) => self.nested_violation(map.values(), depth),
+            _ => None,
+        }
+    }
+
+    /// The limit exceeded by a container `depth` levels into an extra, or
+    /// by one of its `children`.
+    fn nested_violation<'a>(
+        &self,
+        mut children: impl Iterator<Item = &'a Value>,
+        depth: usize,
+    ) -> Option<(&'static str, usize)> {
+        if depth >= self.max_depth {
+            return Some(("max_depth", self.max_depth));
+        }
+        children.find_map(|child| self.violation(child, depth + 1))
+    }
+}
+
+/// Shorten `key` for an error message.
+fn truncate(key: &str) -> &str {
+    let mut end = key.len().min(MAX_FIELD_LEN);
+    while !key.is_char_boundary(end) {
+        end -= 1;
+    }
+    &key[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn runner(key: &str, value: Value) -> Runner {
+        let mut runner = Runner::default();
+        runner.extra.insert(key.to_string(), value);
+        runner
+    }
+
+    fn violated(limits: JsonLimits, runner: &Runner) -> Option<(String, &'static str, usize)> {
+        match limits.check(runner) {
+            Ok(()) => None,
+            Err(GetMyIdError::MalformedResponse { field, limit, max }) => Some((field, limit, max)),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_json_limits_depth() {
+        let limits = JsonLimits::new().with_max_depth(2);
+        let nested = runner("labels", json!({"team": {"name": "billing"}}));
+        assert_eq!(violated(limits, &nested), None);
+
+        let deeper = runner("labels", json!({"team": {"owners": ["alice"]}}));
+        assert_eq!(
+            violated(limits, &deeper),
+            Some(("runner.labels".to_string(), "max_depth", 2))
+        );
+
+        let scalar = runner("region", json!("eu"));
+        assert_eq!(violated(JsonLimits::new().with_max_depth(0), &scalar), None);
+    }
+
+    #[test]
+    fn test_json_limits_string_len() {
+        let limits = JsonLimits::new().with_max_string_len(8);
+        assert_eq!(violated(limits, &runner("region", json!("eu-west"))), None);
+        assert_eq!(
+            violated(limits, &runner("region", json!("eu-west-1a"))),
+            Some(("runner.region".to_string(), "max_string_len", 8))
+        );
+        assert_eq!(
+            violated(limits, &runner("tags", json!([{"a_long_key": 1}]))),
+            Some(("runner.tags".to_string(), "max_string_len", 8))
+        );
+
+        let long_key = "k".repeat(100);
+        let (field, limit, _) = violated(limits, &runner(&long_key, json!(1))).unwrap();
+        assert_eq!(field.len(), "runner.".len() + MAX_FIELD_LEN);
+        assert_eq!(limit, "max_string_len");
+
+        let huge = runner("blob", json!("x".repeat(DEFAULT_MAX_STRING_LEN + 1)));
+        assert!(violated(JsonLimits::new(), &huge).is_some());
+        assert_eq!(violated(JsonLimits::unlimited(), &huge), None);
+    }
+}
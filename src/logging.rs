@@ -0,0 +1,86 @@
+//! Records for the `log` facade (requires `log` feature).
+//!
+//! Without the feature the macros compile to nothing, so call sites need
+//! no `cfg` attributes. Records use the `getmyid` target and never include
+//! tokens: identities are logged by name, errors by their message.
+
+/// Emit a `log` record at `$level` if the `log` feature is enabled.
+macro_rules! record {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::$level!(target: "getmyid", $($arg)+);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+/// Log connection attempts and resolved identities.
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        $crate::logging::record!(debug, $($arg)+)
+    };
+}
+
+/// Log retries, failovers and failed lookups.
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        $crate::logging::record!(warn, $($arg)+)
+    };
+}
+
+pub(crate) use {log_debug, log_warn, record};
+
+#[cfg(test)]
+mod tests {
+    #[cfg(all(feature = "log", feature = "test-util"))]
+    #[test]
+    fn test_lookups_are_logged() {
+        use std::sync::Mutex;
+
+        use log::{Level, LevelFilter, Log, Metadata, Record};
+
+        use crate::testing::{FakeDaemon, FakeResponse};
+        use crate::Client;
+
+        static RECORDS: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+        struct Capture;
+
+        impl Log for Capture {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn log(&self, record: &Record<'_>) {
+                if record.target() == "getmyid" {
+                    let message = record.args().to_string();
+                    RECORDS.lock().unwrap().push((record.level(), message));
+                }
+            }
+
+            fn flush(&self) {}
+        }
+
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(LevelFilter::Debug);
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        client.get_identity().unwrap();
+        daemon.respond_with(FakeResponse::no_match());
+        client.get_identity().unwrap_err();
+
+        let records = RECORDS.lock().unwrap();
+        let socket = daemon.socket_path().display().to_string();
+        let asked = format!("asking the whoami daemon at {socket}");
+        assert!(records.contains(&(Level::Debug, asked)));
+        let resolved = "resolved identity TEST_IDENTITY".to_string();
+        assert!(records.contains(&(Level::Debug, resolved)));
+        let rejected = |(level, message): &(Level, String)| {
+            *level == Level::Warn && message.contains("E_NO_MATCH")
+        };
+        assert!(records.iter().any(rejected));
+        let leaks_token = |(_, message): &(Level, String)| message.contains("tok_test");
+        assert!(!records.iter().any(leaks_token));
+    }
+}
@@ -0,0 +1,248 @@
+//! Identities kept fresh by a background task (requires `tokio` feature).
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::async_client::AsyncClient;
+use crate::error::Result;
+use crate::types::{Identity, RunnerRequest};
+
+type RotationCallback = dyn Fn(&Identity) + Send + Sync;
+
+/// Callbacks registered with [`ManagedIdentity::on_token_rotated`], shared
+/// with the refresh task.
+#[derive(Default)]
+struct RotationCallbacks(Mutex<Vec<Box<RotationCallback>>>);
+
+impl RotationCallbacks {
+    fn push(&self, callback: Box<RotationCallback>) {
+        self.lock().push(callback);
+    }
+
+    fn notify(&self, identity: &Identity) {
+        for callback in self.lock().iter() {
+            callback(identity);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Box<RotationCallback>>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl std::fmt::Debug for RotationCallbacks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RotationCallbacks")
+            .field(&self.lock().len())
+            .finish()
+    }
+}
+
+/// Handle to an identity refreshed in the background.
+///
+/// [`start`](ManagedIdentity::start) resolves the identity once, then spawns
+/// a task polling the daemon every `refresh` interval through an
+/// [`IdentityWatch`](crate::IdentityWatch). Readers such as HTTP clients
+/// call [`current`](ManagedIdentity::current) on every request and always
+/// see the latest token without a daemon round-trip.
+///
+/// Failed refreshes keep the last identity, and polling backs off until the
+/// daemon answers again. The task stops when the handle is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use getmyid::{AsyncClient, ManagedIdentity};
+///
+/// # async fn example() -> Result<(), getmyid::GetMyIdError> {
+/// let identity = ManagedIdentity::start(AsyncClient::new(), Duration::from_secs(60)).await?;
+///
+/// // On every outgoing request:
+/// let token = identity.current().token.clone();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ManagedIdentity {
+    identity: watch::Receiver<Arc<Identity>>,
+    rotated: Arc<RotationCallbacks>,
+    refresh: JoinHandle<()>,
+}
+
+impl ManagedIdentity {
+    /// Resolve the identity with `client` and keep refreshing it every
+    /// `refresh` interval on the current Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns the client's error if the first lookup fails; no task is
+    /// spawned in that case.
+    pub async fn start(client: AsyncClient, refresh: Duration) -> Result<Self> {
+        Self::start_with_runner(client, refresh, None).await
+    }
+
+    /// Like [`start`](ManagedIdentity::start), sending `runner` as context
+    /// with the first lookup and every refresh.
+    ///
+    /// # Errors
+    ///
+    /// Returns the client's error if the first lookup fails; no task is
+    /// spawned in that case.
+    pub async fn start_with_runner(
+        client: AsyncClient,
+        refresh: Duration,
+        runner: Option<RunnerRequest>,
+    ) -> Result<Self> {
+        let mut changes = client.watch_identity_with_runner(refresh, runner);
+        let (tx, rx) = watch::channel(Arc::new(changes.changed().await?));
+        let rotated = Arc::new(RotationCallbacks::default());
+        let callbacks = Arc::clone(&rotated);
+        let refresh = tokio::spawn(async move {
+            loop {
+                if let Ok(identity) = changes.changed().await {
+                    let identity = Arc::new(identity);
+                    let previous = tx.send_replace(Arc::clone(&identity));
+                    if previous.token != identity.token {
+                        callbacks.notify(&identity);
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            identity: rx,
+            rotated,
+            refresh,
+        })
+    }
+
+    /// Get the latest identity.
+    pub fn current(&self) -> Arc<Identity> {
+        Arc::clone(&self.identity.borrow())
+    }
+
+    /// Get a receiver notified whenever the identity, its uid, its URLs or
+    /// its token change.
+    ///
+    /// Receivers keep the last identity after the handle is dropped but are
+    /// no longer updated.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Identity>> {
+        self.identity.clone()
+    }
+
+    /// Call `callback` with the refreshed identity whenever it carries a
+    /// different token than the one before, e.g. to rebuild HTTP clients or
+    /// flush credentials derived from the old token.
+    ///
+    /// Callbacks run on the refresh task, after [`current`](Self::current)
+    /// returns the new identity, and should not block; hand longer work to
+    /// a task of its own.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use getmyid::{AsyncClient, ManagedIdentity};
+    ///
+    /// # async fn example() -> Result<(), getmyid::GetMyIdError> {
+    /// let identity = ManagedIdentity::start(AsyncClient::new(), Duration::from_secs(60)).await?;
+    /// identity.on_token_rotated(|identity| {
+    ///     println!("token of {} rotated", identity.identity);
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_token_rotated<F>(&self, callback: F)
+    where
+        F: Fn(&Identity) + Send + Sync + 'static,
+    {
+        self.rotated.push(Box::new(callback));
+    }
+}
+
+impl Drop for ManagedIdentity {
+    fn drop(&mut self) {
+        self.refresh.abort();
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeDaemon, FakeResponse};
+
+    #[tokio::test]
+    async fn test_managed_identity_refreshes_token() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let managed = ManagedIdentity::start(client, Duration::from_millis(10))
+            .await
+            .unwrap();
+        let mut changes = managed.subscribe();
+
+        assert_eq!(managed.current().token, "tok_test");
+
+        let mut rotated = FakeDaemon::sample_identity();
+        rotated.token = "tok_rotated".into();
+        daemon.respond_with(FakeResponse::identity(rotated));
+        tokio::time::timeout(Duration::from_secs(5), changes.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(managed.current().token, "tok_rotated");
+
+        daemon.respond_with(FakeResponse::no_match());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(managed.current().token, "tok_rotated");
+    }
+
+    #[tokio::test]
+    async fn test_managed_identity_token_rotated_callback() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let managed = ManagedIdentity::start(client, Duration::from_millis(10))
+            .await
+            .unwrap();
+        let (tx, mut rotations) = tokio::sync::mpsc::unbounded_channel();
+        managed.on_token_rotated(move |identity| {
+            tx.send(identity.token.clone()).unwrap();
+        });
+        let mut changes = managed.subscribe();
+
+        // A new URL with the same token is a change but not a rotation
+        let mut moved = FakeDaemon::sample_identity();
+        moved.config_url = "https://config2.example.com".to_string();
+        daemon.respond_with(FakeResponse::identity(moved.clone()));
+        tokio::time::timeout(Duration::from_secs(5), changes.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(rotations.try_recv().is_err());
+
+        let mut rotated = moved;
+        rotated.token = "tok_rotated".into();
+        daemon.respond_with(FakeResponse::identity(rotated));
+        let token = tokio::time::timeout(Duration::from_secs(5), rotations.recv())
+            .await
+            .unwrap();
+        assert_eq!(token.as_deref(), Some("tok_rotated"));
+    }
+
+    #[tokio::test]
+    async fn test_managed_identity_initial_failure() {
+        let client = AsyncClient::builder()
+            .socket_path("/nonexistent/whoami.sock")
+            .build();
+
+        assert!(ManagedIdentity::start(client, Duration::from_secs(1))
+            .await
+            .is_err());
+    }
+}
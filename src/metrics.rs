@@ -0,0 +1,200 @@
+//! Hooks reporting identity lookups to an application's metrics system.
+//!
+//! The crate does not depend on a metrics library. A [`MetricsSink`] set
+//! with [`ClientBuilder::metrics`](crate::ClientBuilder::metrics) or
+//! `AsyncClientBuilder::metrics` is handed a [`RequestMetrics`] after every
+//! exchange with a daemon, and feeds the counters and histograms of
+//! whichever library the application uses.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::error::GetMyIdError;
+
+/// Receives a [`RequestMetrics`] after every exchange with a daemon.
+///
+/// Closures taking a `&RequestMetrics` implement the trait. Sinks are
+/// called on the thread or task performing the lookup, so they should only
+/// update counters.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+/// use getmyid::{Client, RequestMetrics};
+///
+/// let no_match = Arc::new(AtomicU64::new(0));
+/// let counter = Arc::clone(&no_match);
+/// let client = Client::builder()
+///     .metrics(move |request: &RequestMetrics<'_>| {
+///         if request.outcome() == "E_NO_MATCH" {
+///             counter.fetch_add(1, Ordering::Relaxed);
+///         }
+///     })
+///     .build();
+/// ```
+pub trait MetricsSink: Send + Sync {
+    /// Record one exchange with a daemon.
+    fn record_request(&self, request: &RequestMetrics<'_>);
+}
+
+impl<F> MetricsSink for F
+where
+    F: Fn(&RequestMetrics<'_>) + Send + Sync,
+{
+    fn record_request(&self, request: &RequestMetrics<'_>) {
+        self(request)
+    }
+}
+
+/// Measurements of one exchange with a daemon, passed to a [`MetricsSink`].
+///
+/// Every attempt counts: a lookup retried twice, or answered by a failover
+/// daemon, is reported once per daemon asked. Identities served from the
+/// cache are not reported.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct RequestMetrics<'a> {
+    /// Time taken to connect to the daemon, `None` if connecting failed.
+    pub connect_latency: Option<Duration>,
+    /// Total time of the exchange, connecting included, until the response
+    /// was parsed or the exchange failed.
+    pub duration: Duration,
+    /// Size of the raw response in bytes, `0` if none was read.
+    pub response_bytes: usize,
+    /// Why the exchange failed, `None` on success.
+    pub error: Option<&'a GetMyIdError>,
+}
+
+impl RequestMetrics<'_> {
+    /// Check if the daemon resolved an identity.
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Get a label for the outcome suitable for a metrics dimension: `ok`,
+    /// the daemon error code such as `E_NO_MATCH`, or one of `degraded`,
+    /// `unreachable`, `timeout`, `io`, `invalid_response` and `other`.
+    pub fn outcome(&self) -> &str {
+        match self.error {
+            None => "ok",
+            Some(GetMyIdError::DaemonError { code, .. }) => code.as_str(),
+            Some(GetMyIdError::DaemonDegraded { .. }) => "degraded",
+            Some(GetMyIdError::Timeout(_)) => "timeout",
+            Some(
+                GetMyIdError::ConnectionFailed { .. }
+                | GetMyIdError::SocketNotFound(_)
+                | GetMyIdError::DanglingSocketLink { .. }
+                | GetMyIdError::PermissionDenied { .. },
+            ) => "unreachable",
+            Some(GetMyIdError::ReadError(_) | GetMyIdError::WriteError(_)) => "io",
+            Some(
+                GetMyIdError::InvalidJson { .. }
+                | GetMyIdError::MissingField { .. }
+                | GetMyIdError::UnknownField { .. }
+                | GetMyIdError::ReservedField { .. }
+                | GetMyIdError::ResponseTooLarge { .. }
+                | GetMyIdError::MalformedResponse { .. },
+            ) => "invalid_response",
+            Some(_) => "other",
+        }
+    }
+}
+
+/// The sink configured on a client, shared between its clones.
+#[derive(Clone)]
+pub(crate) struct Metrics(Arc<dyn MetricsSink>);
+
+impl Metrics {
+    pub fn new(sink: impl MetricsSink + 'static) -> Self {
+        Self(Arc::new(sink))
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MetricsSink")
+    }
+}
+
+/// Times one exchange with a daemon for [`RequestMetrics`].
+pub(crate) struct RequestTimer {
+    started: Instant,
+    connect_latency: Option<Duration>,
+}
+
+impl RequestTimer {
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            connect_latency: None,
+        }
+    }
+
+    /// Mark the connection to the daemon as established.
+    pub fn connected(&mut self) {
+        self.connect_latency = Some(self.started.elapsed());
+    }
+
+    /// Report the exchange, which read `response_bytes` and ended with
+    /// `error`, to `metrics`.
+    pub fn finish(
+        self,
+        metrics: Option<&Metrics>,
+        response_bytes: usize,
+        error: Option<&GetMyIdError>,
+    ) {
+        if let Some(Metrics(sink)) = metrics {
+            sink.record_request(&RequestMetrics {
+                connect_latency: self.connect_latency,
+                duration: self.started.elapsed(),
+                response_bytes,
+                error,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DaemonErrorCode;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_request_timer_reports_outcome() {
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&outcomes);
+        let metrics = Metrics::new(move |request: &RequestMetrics<'_>| {
+            let outcome = request.outcome().to_string();
+            let connected = request.connect_latency.is_some();
+            recorded
+                .lock()
+                .unwrap()
+                .push((outcome, connected, request.response_bytes));
+        });
+
+        let mut timer = RequestTimer::start();
+        timer.connected();
+        timer.finish(Some(&metrics), 120, None);
+        let no_match = GetMyIdError::DaemonError {
+            code: DaemonErrorCode::NoMatch,
+            message: "no match".to_string(),
+        };
+        let mut timer = RequestTimer::start();
+        timer.connected();
+        timer.finish(Some(&metrics), 60, Some(&no_match));
+        let timeout = GetMyIdError::Timeout(Duration::from_secs(1));
+        RequestTimer::start().finish(Some(&metrics), 0, Some(&timeout));
+        RequestTimer::start().finish(None, 0, None);
+
+        let expected = [
+            ("ok".to_string(), true, 120),
+            ("E_NO_MATCH".to_string(), true, 60),
+            ("timeout".to_string(), false, 0),
+        ];
+        assert_eq!(*outcomes.lock().unwrap(), expected);
+    }
+}
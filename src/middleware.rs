@@ -0,0 +1,151 @@
+//! `reqwest-middleware` integration (requires `reqwest` feature).
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use http::Extensions;
+use reqwest::header::AUTHORIZATION;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+
+use crate::managed::ManagedIdentity;
+
+/// Middleware attaching `Authorization: Bearer <token>` to outgoing
+/// requests.
+///
+/// The token is read from a [`ManagedIdentity`] on every request, so
+/// requests always carry the latest token without a daemon round-trip and
+/// application code never handles it. Requests that already set an
+/// `Authorization` header are sent unchanged.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use getmyid::{AsyncClient, ManagedIdentity, TokenMiddleware};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let identity = ManagedIdentity::start(AsyncClient::new(), Duration::from_secs(60)).await?;
+/// let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+///     .with(TokenMiddleware::from(identity))
+///     .build();
+///
+/// let response = client.get("http://billing.internal/api/invoices").send().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenMiddleware {
+    identity: Arc<ManagedIdentity>,
+}
+
+impl TokenMiddleware {
+    /// Create a middleware reading the token from `identity`.
+    pub fn new(identity: Arc<ManagedIdentity>) -> Self {
+        Self { identity }
+    }
+
+    /// Get the identity the token is read from.
+    pub fn identity(&self) -> &Arc<ManagedIdentity> {
+        &self.identity
+    }
+}
+
+impl From<ManagedIdentity> for TokenMiddleware {
+    fn from(identity: ManagedIdentity) -> Self {
+        Self::new(Arc::new(identity))
+    }
+}
+
+#[async_trait]
+impl Middleware for TokenMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if !req.headers().contains_key(AUTHORIZATION) {
+            let value = self
+                .identity
+                .current()
+                .header_value()
+                .map_err(reqwest_middleware::Error::middleware)?;
+            req.headers_mut().insert(AUTHORIZATION, value);
+        }
+        next.run(req, extensions).await
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::async_client::AsyncClient;
+    use crate::testing::{FakeDaemon, FakeResponse};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answer one HTTP request with `200 OK` and return its head.
+    async fn serve_once(listener: TcpListener) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8(head).unwrap().to_ascii_lowercase()
+    }
+
+    async fn send(middleware: &TokenMiddleware, authorization: Option<&str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = tokio::spawn(serve_once(listener));
+
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware.clone())
+            .build();
+        let mut request = client.get(url);
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        assert_eq!(request.send().await.unwrap().status(), 200);
+        server.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_token_middleware() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let managed = ManagedIdentity::start(client, Duration::from_millis(10))
+            .await
+            .unwrap();
+        let mut changes = managed.subscribe();
+        let middleware = TokenMiddleware::from(managed);
+
+        assert!(send(&middleware, None)
+            .await
+            .contains("authorization: bearer tok_test\r\n"));
+        assert!(send(&middleware, Some("Basic dXNlcg=="))
+            .await
+            .contains("authorization: basic dxnlcg==\r\n"));
+
+        let mut rotated = FakeDaemon::sample_identity();
+        rotated.token = "tok_rotated".into();
+        daemon.respond_with(FakeResponse::identity(rotated));
+        tokio::time::timeout(Duration::from_secs(5), changes.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(send(&middleware, None)
+            .await
+            .contains("authorization: bearer tok_rotated\r\n"));
+    }
+}
@@ -0,0 +1,357 @@
+//! Noise-encrypted channel to the daemon (requires `noise` feature).
+//!
+//! The whoami socket is normally only reachable by local processes, but
+//! where it is relayed, e.g. through a bind mount shared with a sidecar or
+//! a socket proxy, tokens cross more hands than the daemon's.
+//! [`NoiseTransport`] wraps another transport and runs a
+//! [`NOISE_PARAMS`] handshake before the request is sent: both sides prove
+//! they hold their static keys, and the request and response travel
+//! encrypted and authenticated.
+//!
+//! Each Noise message is framed with a 2-byte big-endian length prefix.
+//! The handshake prologue is `getmyid-noise-v1`, so daemons speaking
+//! another framing fail the handshake instead of misreading the stream.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use getmyid::{Client, NoiseTransport, UnixTransport};
+//!
+//! # fn example(private_key: Vec<u8>, daemon_public_key: Vec<u8>) -> getmyid::Result<()> {
+//! let transport = NoiseTransport::new(
+//!     UnixTransport::new("/var/run/whoami.sock"),
+//!     private_key,
+//!     daemon_public_key,
+//! );
+//! let client = Client::builder().transport(transport).build();
+//! let identity = client.get_identity()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use snow::{Builder, HandshakeState, TransportState};
+
+use crate::error::{GetMyIdError, Result};
+use crate::transport::{Connection, Transport};
+
+/// The Noise protocol spoken by [`NoiseTransport`]: the `KK` pattern, in
+/// which both sides know each other's static public key in advance.
+pub const NOISE_PARAMS: &str = "Noise_KK_25519_ChaChaPoly_BLAKE2s";
+
+const PROLOGUE: &[u8] = b"getmyid-noise-v1";
+
+/// Length of the authentication tag appended to every encrypted message.
+const TAG_LEN: usize = 16;
+
+/// Largest plaintext carried by a single transport message.
+const MAX_PLAINTEXT: usize = u16::MAX as usize - TAG_LEN;
+
+/// A [`Transport`] encrypting the exchange with the daemon (requires `noise`
+/// feature).
+///
+/// The inner transport must be stream-oriented; `SOCK_SEQPACKET` sockets
+/// are rejected. Only the synchronous [`Client`](crate::Client) and
+/// [`Session`](crate::Session) are covered; the async client has no Noise
+/// transport yet.
+pub struct NoiseTransport {
+    inner: Box<dyn Transport>,
+    private_key: Vec<u8>,
+    daemon_public_key: Vec<u8>,
+}
+
+impl NoiseTransport {
+    /// Wrap `inner`, authenticating as `private_key` and expecting the
+    /// daemon to hold the private half of `daemon_public_key`. Both are raw
+    /// 32-byte X25519 keys.
+    pub fn new(
+        inner: impl Transport + 'static,
+        private_key: impl Into<Vec<u8>>,
+        daemon_public_key: impl Into<Vec<u8>>,
+    ) -> Self {
+        Self {
+            inner: Box::new(inner),
+            private_key: private_key.into(),
+            daemon_public_key: daemon_public_key.into(),
+        }
+    }
+
+    /// Generate a fresh X25519 key pair, returned as `(private, public)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::SecureChannel`] if no randomness is available.
+    pub fn generate_keypair() -> Result<(Vec<u8>, Vec<u8>)> {
+        let keypair = Builder::new(params())
+            .generate_keypair()
+            .map_err(channel_error)?;
+        Ok((keypair.private, keypair.public))
+    }
+
+    /// Get the daemon public key the handshake expects.
+    pub fn daemon_public_key(&self) -> &[u8] {
+        &self.daemon_public_key
+    }
+
+    fn initiator(&self) -> Result<HandshakeState> {
+        Builder::new(params())
+            .local_private_key(&self.private_key)
+            .remote_public_key(&self.daemon_public_key)
+            .prologue(PROLOGUE)
+            .build_initiator()
+            .map_err(channel_error)
+    }
+}
+
+impl fmt::Debug for NoiseTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseTransport")
+            .field("inner", &self.inner)
+            .field("private_key", &"[REDACTED]")
+            .field("daemon_public_key", &hex(&self.daemon_public_key))
+            .finish()
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for NoiseTransport {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.private_key);
+    }
+}
+
+impl Transport for NoiseTransport {
+    fn dial(&self) -> Result<Box<dyn Connection>> {
+        let mut stream = self.inner.dial()?;
+        if stream.is_message_oriented() {
+            return Err(GetMyIdError::SecureChannel(
+                "the inner transport must be stream-oriented".to_string(),
+            ));
+        }
+
+        let mut handshake = self.initiator()?;
+        let mut message = vec![0; u16::MAX as usize];
+        let len = handshake
+            .write_message(&[], &mut message)
+            .map_err(channel_error)?;
+        write_frame(&mut stream, &message[..len]).map_err(GetMyIdError::WriteError)?;
+        let reply = read_frame(&mut stream)
+            .map_err(GetMyIdError::ReadError)?
+            .ok_or_else(|| {
+                GetMyIdError::SecureChannel(
+                    "daemon closed the connection during the handshake".to_string(),
+                )
+            })?;
+        handshake
+            .read_message(&reply, &mut message)
+            .map_err(channel_error)?;
+        let state = handshake.into_transport_mode().map_err(channel_error)?;
+
+        Ok(Box::new(NoiseConnection {
+            stream,
+            state,
+            plaintext: Vec::new(),
+            position: 0,
+        }))
+    }
+}
+
+/// An established Noise session over an inner connection.
+struct NoiseConnection {
+    stream: Box<dyn Connection>,
+    state: TransportState,
+    /// The decrypted message being read, and how much of it was consumed.
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl Read for NoiseConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.plaintext.len() {
+            let Some(frame) = read_frame(&mut self.stream)? else {
+                return Ok(0);
+            };
+            self.plaintext.resize(frame.len(), 0);
+            let len = self
+                .state
+                .read_message(&frame, &mut self.plaintext)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.plaintext.truncate(len);
+            self.position = 0;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Write for NoiseConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk = &buf[..buf.len().min(MAX_PLAINTEXT)];
+        let mut message = vec![0; chunk.len() + TAG_LEN];
+        let len = self
+            .state
+            .write_message(chunk, &mut message)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_frame(&mut self.stream, &message[..len])?;
+        Ok(chunk.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Connection for NoiseConnection {
+    fn finish_request(&mut self) -> io::Result<()> {
+        self.stream.finish_request()
+    }
+}
+
+fn params() -> snow::params::NoiseParams {
+    NOISE_PARAMS.parse().expect("valid Noise parameters")
+}
+
+fn channel_error(error: snow::Error) -> GetMyIdError {
+    GetMyIdError::SecureChannel(error.to_string())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Write `message` with its length prefix.
+pub(crate) fn write_frame(stream: &mut impl Write, message: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Noise message too long"))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(message)
+}
+
+/// Read the next length-prefixed message, or `None` if the stream ended
+/// before one started.
+pub(crate) fn read_frame(stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 2];
+    loop {
+        match stream.read(&mut len[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    stream.read_exact(&mut len[1..])?;
+    let mut message = vec![0; u16::from_be_bytes(len).into()];
+    stream.read_exact(&mut message)?;
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::{SocketAddr, UnixListener};
+    use std::thread::JoinHandle;
+
+    use crate::transport::abstract_path;
+    use crate::{Client, RunnerRequest, UnixTransport};
+
+    const RESPONSE: &str = r#"{"status":"ok","identity":"APP","idm_url":"","config_url":"","token":"tok","runner":{"identity":"APP","pid":1}}"#;
+
+    /// Answer one request as a Noise daemon with `private_key` expecting a
+    /// client holding `client_public_key`, returning the decrypted request.
+    fn serve_once(
+        listener: UnixListener,
+        private_key: Vec<u8>,
+        client_public_key: Vec<u8>,
+    ) -> JoinHandle<Option<String>> {
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut handshake = Builder::new(params())
+                .local_private_key(&private_key)
+                .remote_public_key(&client_public_key)
+                .prologue(PROLOGUE)
+                .build_responder()
+                .unwrap();
+            let mut buf = vec![0; u16::MAX as usize];
+            let first = read_frame(&mut stream).unwrap()?;
+            handshake.read_message(&first, &mut buf).ok()?;
+            let len = handshake.write_message(&[], &mut buf).unwrap();
+            write_frame(&mut stream, &buf[..len]).unwrap();
+            let mut state = handshake.into_transport_mode().unwrap();
+
+            let mut request = Vec::new();
+            while let Some(frame) = read_frame(&mut stream).unwrap() {
+                let len = state.read_message(&frame, &mut buf).unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+            let len = state.write_message(RESPONSE.as_bytes(), &mut buf).unwrap();
+            write_frame(&mut stream, &buf[..len]).unwrap();
+            Some(String::from_utf8(request).unwrap())
+        })
+    }
+
+    fn listen(name: &str) -> UnixListener {
+        let name = format!("getmyid-test-noise-{name}-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        UnixListener::bind_addr(&addr).unwrap()
+    }
+
+    fn client(listener: &UnixListener, private_key: &[u8], daemon_key: &[u8]) -> Client {
+        let name = listener.local_addr().unwrap();
+        let name = OsStr::from_bytes(name.as_abstract_name().unwrap());
+        let inner = UnixTransport::new(abstract_path(name));
+        let transport = NoiseTransport::new(inner, private_key, daemon_key);
+        Client::builder().transport(transport).retries(0).build()
+    }
+
+    #[test]
+    fn test_noise_exchange() {
+        let (client_private, client_public) = NoiseTransport::generate_keypair().unwrap();
+        let (daemon_private, daemon_public) = NoiseTransport::generate_keypair().unwrap();
+        let listener = listen("ok");
+        let client = client(&listener, &client_private, &daemon_public);
+        let server = serve_once(listener, daemon_private, client_public);
+
+        let runner = RunnerRequest {
+            instance_id: Some(3),
+            ..RunnerRequest::default()
+        };
+        let identity = client.get_identity_with_runner(Some(runner)).unwrap();
+        assert_eq!(identity.token, "tok");
+        let request = server.join().unwrap().unwrap();
+        assert!(request.contains(r#""instance_id":3"#), "{request}");
+    }
+
+    #[test]
+    fn test_noise_wrong_daemon_key() {
+        let (client_private, client_public) = NoiseTransport::generate_keypair().unwrap();
+        let (impostor_private, _) = NoiseTransport::generate_keypair().unwrap();
+        let (_, daemon_public) = NoiseTransport::generate_keypair().unwrap();
+        let listener = listen("impostor");
+        let client = client(&listener, &client_private, &daemon_public);
+        let server = serve_once(listener, impostor_private, client_public);
+
+        match client.get_identity() {
+            Err(e @ GetMyIdError::SecureChannel(_)) => assert!(!e.is_transient()),
+            other => panic!("expected SecureChannel, got {other:?}"),
+        }
+        assert!(server.join().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_noise_debug_redacts_private_key() {
+        let transport = NoiseTransport::new(UnixTransport::new("/tmp/x"), [0xab; 32], [1; 32]);
+        let debug = format!("{transport:?}");
+        assert!(!debug.contains("171") && !debug.contains("abab"), "{debug}");
+        assert!(debug.contains(&"01".repeat(32)));
+    }
+}
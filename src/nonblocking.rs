@@ -0,0 +1,274 @@
+//! Non-blocking identity lookups for event loops.
+
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::PathBuf;
+use std::task::Poll;
+
+use socket2::{SockAddr, Socket};
+
+use crate::client::{response_text, Client};
+use crate::error::{GetMyIdError, Result};
+use crate::protocol::{check_nonce, Request};
+use crate::transport::{check_peer_uid, connect_error, peer_uid, UnixTransport};
+use crate::types::{Identity, RunnerRequest};
+
+/// Size of the chunks the response is read in.
+const READ_CHUNK: usize = 4096;
+
+/// Readiness an [`IdentityRequest`] waits for before it can make progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    /// Wait until the socket is readable.
+    Readable,
+    /// Wait until the socket is writable.
+    Writable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Connecting,
+    Writing,
+    Reading,
+    Done,
+}
+
+/// An identity lookup in progress on a non-blocking socket, started with
+/// [`Client::try_get_identity`].
+///
+/// Register the socket ([`AsRawFd`] / [`AsFd`]) with an event loop such as
+/// `mio` or `calloop` for the current [`interest`](IdentityRequest::interest)
+/// and call [`poll`](IdentityRequest::poll) whenever it is ready. `poll`
+/// never blocks: it returns `Poll::Pending` until the daemon has answered.
+///
+/// The client's timeouts do not apply; the event loop decides how long to
+/// wait. The request must not be polled again once it returned
+/// `Poll::Ready`.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::task::Poll;
+/// use getmyid::Client;
+///
+/// let client = Client::new();
+/// let mut request = client.try_get_identity()?;
+/// let identity = loop {
+///     match request.poll() {
+///         Poll::Ready(result) => break result?,
+///         // Wait for `request.interest()` on `request.as_raw_fd()`
+///         Poll::Pending => {}
+///     }
+/// };
+/// println!("Identity: {}", identity.identity);
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+#[derive(Debug)]
+pub struct IdentityRequest {
+    client: Client,
+    socket: Socket,
+    addr: SockAddr,
+    path: PathBuf,
+    resolved: Option<PathBuf>,
+    peer_uid: Option<u32>,
+    nonce: Option<String>,
+    state: State,
+    runner: Option<RunnerRequest>,
+    request: Vec<u8>,
+    written: usize,
+    response: Vec<u8>,
+}
+
+impl IdentityRequest {
+    /// Start sending `runner` to the daemon behind `transport` on behalf of
+    /// `client`.
+    pub(crate) fn start(
+        client: Client,
+        transport: &UnixTransport,
+        runner: Option<RunnerRequest>,
+    ) -> Result<Self> {
+        let request = client.new_request(runner.clone());
+        let nonce = request.nonce.clone();
+        let request = match request {
+            request if request == Request::default() => Vec::new(),
+            request => request.to_json()?.into_bytes(),
+        };
+        let (socket, addr, resolved) = transport.nonblocking_socket()?;
+        Ok(Self {
+            client,
+            socket,
+            addr,
+            path: transport.path().to_path_buf(),
+            resolved,
+            peer_uid: transport.required_peer_uid(),
+            nonce,
+            state: State::Connecting,
+            runner,
+            request,
+            written: 0,
+            response: Vec::new(),
+        })
+    }
+
+    /// Make as much progress as possible without blocking.
+    ///
+    /// Returns the identity, or the error that ended the lookup, once the
+    /// daemon has answered; `Poll::Pending` when the socket must first
+    /// become ready for [`interest`](IdentityRequest::interest).
+    pub fn poll(&mut self) -> Poll<Result<Identity>> {
+        let result = match self.advance() {
+            Ok(None) => return Poll::Pending,
+            Ok(Some(response)) => self
+                .client
+                .identity_from_response(&response, self.runner.as_ref()),
+            Err(e) => Err(e),
+        };
+        self.state = State::Done;
+        Poll::Ready(result)
+    }
+
+    /// Get the readiness the request waits for.
+    pub fn interest(&self) -> Interest {
+        match self.state {
+            State::Connecting | State::Writing => Interest::Writable,
+            State::Reading | State::Done => Interest::Readable,
+        }
+    }
+
+    /// Whether the request returned its result.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Run the exchange until it would block, returning the response once
+    /// the daemon closes the connection.
+    fn advance(&mut self) -> Result<Option<String>> {
+        let max = self.client.max_response_bytes();
+        loop {
+            match self.state {
+                State::Connecting => match self.socket.connect(&self.addr) {
+                    Ok(()) => {
+                        if let Some(expected) = self.peer_uid {
+                            check_peer_uid(&self.path, peer_uid(&self.socket), expected)?;
+                        }
+                        self.state = if self.request.is_empty() {
+                            State::Reading
+                        } else {
+                            State::Writing
+                        };
+                    }
+                    Err(e) if is_retry(&e) => return Ok(None),
+                    Err(e) => return Err(connect_error(&self.path, self.resolved.clone(), e)),
+                },
+                State::Writing if self.written == self.request.len() => {
+                    // Shutdown write side to signal we're done sending
+                    self.socket.shutdown(Shutdown::Write).ok();
+                    self.state = State::Reading;
+                }
+                State::Writing => match (&self.socket).write(&self.request[self.written..]) {
+                    Ok(n) => self.written += n,
+                    Err(e) if is_retry(&e) => return Ok(None),
+                    Err(e) => return Err(GetMyIdError::WriteError(e)),
+                },
+                State::Reading => {
+                    let mut chunk = [0u8; READ_CHUNK];
+                    match (&self.socket).read(&mut chunk) {
+                        Ok(0) => {
+                            let response = std::mem::take(&mut self.response);
+                            let mut response = response_text(response, max)?;
+                            self.client.open_response(&mut response)?;
+                            check_nonce(self.nonce.as_deref(), &response)?;
+                            return Ok(Some(response));
+                        }
+                        Ok(n) if self.response.len() + n > max => {
+                            return Err(GetMyIdError::ResponseTooLarge { max });
+                        }
+                        Ok(n) => self.response.extend_from_slice(&chunk[..n]),
+                        Err(e) if is_retry(&e) => return Ok(None),
+                        Err(e) => return Err(GetMyIdError::ReadError(e)),
+                    }
+                }
+                State::Done => {
+                    return Err(GetMyIdError::ReadError(io::Error::other(
+                        "identity request polled after completion",
+                    )));
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for IdentityRequest {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl AsFd for IdentityRequest {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+}
+
+/// Whether `error` means the operation should be tried again: it would
+/// block, or was interrupted.
+///
+/// `WouldBlock` is returned until the socket is ready; a `connect` refused
+/// because the daemon's backlog is full reports it too.
+fn is_retry(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_try_get_identity() {
+        use crate::testing::FakeDaemon;
+        use crate::RunnerRequest;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+
+        let wait = |mut request: IdentityRequest| loop {
+            match request.poll() {
+                Poll::Ready(result) => {
+                    assert!(request.is_done());
+                    assert!(matches!(request.poll(), Poll::Ready(Err(_))));
+                    break result;
+                }
+                Poll::Pending => std::thread::yield_now(),
+            }
+        };
+
+        let request = client.try_get_identity().unwrap();
+        assert_eq!(request.interest(), Interest::Writable);
+        let identity = wait(request).unwrap();
+        assert_eq!(identity.identity, "TEST_IDENTITY");
+        assert_eq!(identity.token, "tok_test");
+
+        let runner = RunnerRequest::new().with_instance_id(7);
+        wait(client.try_get_identity_with_runner(Some(runner)).unwrap()).unwrap();
+        let requests = daemon.requests();
+        assert_eq!(requests[0].raw(), "");
+        assert_eq!(requests[1].json().unwrap()["runner"]["instance_id"], 7);
+    }
+
+    #[test]
+    fn test_try_get_identity_missing_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = Client::builder()
+            .socket_path(dir.path().join("missing.sock"))
+            .build();
+        assert!(matches!(
+            client.try_get_identity(),
+            Err(GetMyIdError::SocketNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! W3C trace-context propagation through the runner context (requires
+//! `otel` feature).
+//!
+//! With a trace-context source set through
+//! [`ClientBuilder::trace_context`](crate::ClientBuilder::trace_context),
+//! every request carries the caller's current `traceparent` as the
+//! [`TRACEPARENT_FIELD`] runner field. Daemons that understand it log it,
+//! and echo it back in the runner object like any other runner field, where
+//! [`Runner::traceparent`] reads it, so identity lookups and the config
+//! server calls that follow them correlate in traces.
+//!
+//! The crate does not depend on an OpenTelemetry SDK: the source is a
+//! closure reading the current span context from whichever tracer the
+//! application uses.
+//!
+//! # Example
+//!
+//! ```
+//! use getmyid::{Client, TraceParent};
+//!
+//! fn current_span() -> Option<TraceParent> {
+//!     // e.g. from opentelemetry::Context::current().span().span_context()
+//!     TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+//! }
+//!
+//! let client = Client::builder().trace_context(current_span).build();
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::types::{Runner, RunnerRequest};
+
+/// Runner field carrying the W3C `traceparent` header value.
+pub const TRACEPARENT_FIELD: &str = "traceparent";
+
+/// A W3C trace context `traceparent` value (requires `otel` feature).
+///
+/// Formatted as `00-<trace id>-<parent id>-<flags>` in lowercase hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceParent {
+    /// The 16-byte trace ID, never zero.
+    pub trace_id: u128,
+    /// The 8-byte ID of the calling span, never zero.
+    pub parent_id: u64,
+    /// Trace flags; bit 0 marks the trace as sampled.
+    pub flags: u8,
+}
+
+impl TraceParent {
+    /// Parse a `traceparent` value, `None` if it is malformed.
+    ///
+    /// Versions after `00` are accepted as long as they start with the
+    /// fields of version `00`, as the specification requires.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('-');
+        let version = parts.next().filter(|v| is_hex(v, 2) && *v != "ff")?;
+        let trace_id = parts.next().filter(|id| is_hex(id, 32))?;
+        let parent_id = parts.next().filter(|id| is_hex(id, 16))?;
+        let flags = parts.next().filter(|flags| is_hex(flags, 2))?;
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        let parent = Self {
+            trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+            parent_id: u64::from_str_radix(parent_id, 16).ok()?,
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        };
+        (parent.trace_id != 0 && parent.parent_id != 0).then_some(parent)
+    }
+
+    /// Check if the caller sampled the trace.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 1 == 1
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+impl RunnerRequest {
+    /// Carry `parent` as the [`TRACEPARENT_FIELD`] runner field.
+    pub fn with_traceparent(self, parent: &TraceParent) -> Self {
+        self.with_field(TRACEPARENT_FIELD, parent.to_string())
+    }
+}
+
+impl Runner {
+    /// Get the trace context the daemon echoed, if it is present and valid.
+    pub fn traceparent(&self) -> Option<TraceParent> {
+        let value = self.extra.get(TRACEPARENT_FIELD)?.as_str()?;
+        TraceParent::parse(value)
+    }
+}
+
+/// The trace-context source configured on a client, shared between its
+/// clones.
+#[derive(Clone)]
+pub(crate) struct TraceContext(Arc<dyn Fn() -> Option<TraceParent> + Send + Sync>);
+
+impl TraceContext {
+    pub fn new(source: impl Fn() -> Option<TraceParent> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(source))
+    }
+
+    /// Add the current trace context, if any, to `runner`.
+    pub fn inject(&self, runner: Option<RunnerRequest>) -> Option<RunnerRequest> {
+        match (self.0)() {
+            Some(parent) => Some(runner.unwrap_or_default().with_traceparent(&parent)),
+            None => runner,
+        }
+    }
+}
+
+impl fmt::Debug for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceContext")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_traceparent() {
+        let parent = TraceParent::parse(TRACEPARENT).unwrap();
+        assert_eq!(parent.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(parent.parent_id, 0x00f067aa0ba902b7);
+        assert!(parent.is_sampled());
+        assert_eq!(parent.to_string(), TRACEPARENT);
+
+        let future = format!("01{}-extra", &TRACEPARENT[2..]);
+        assert_eq!(TraceParent::parse(&future), Some(parent));
+        for invalid in [
+            "",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert_eq!(TraceParent::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_inject_trace_context() {
+        let parent = TraceParent::parse(TRACEPARENT).unwrap();
+        let source = TraceContext::new(move || Some(parent));
+        let runner = source.inject(Some(RunnerRequest::new().with_instance_id(1)));
+        let runner = runner.unwrap();
+        assert_eq!(runner.instance_id, Some(1));
+        assert_eq!(runner.extra[TRACEPARENT_FIELD], TRACEPARENT);
+        assert!(source.inject(None).is_some());
+        assert_eq!(TraceContext::new(|| None).inject(None), None);
+
+        let echoed = Runner {
+            extra: runner.extra,
+            ..Runner::default()
+        };
+        assert_eq!(echoed.traceparent(), Some(parent));
+    }
+}
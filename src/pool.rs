@@ -0,0 +1,376 @@
+//! A pool of warm connections to the daemon for async services.
+
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::{Mutex as AsyncMutex, Semaphore};
+
+use crate::async_client::{with_timeout, AsyncClient};
+use crate::async_transport::AsyncConnection;
+use crate::client::{read_limit, response_text};
+use crate::error::{GetMyIdError, Result};
+use crate::protocol::{check_nonce, is_goaway};
+use crate::types::{Identity, RunnerRequest};
+
+/// Default time an [`AsyncClientPool`] lookup waits for a free connection.
+pub const DEFAULT_CHECKOUT_TIMEOUT: Duration = Duration::from_secs(1);
+
+type PooledConnection = BufReader<Box<dyn AsyncConnection>>;
+
+/// How urgently an [`AsyncClientPool`] lookup needs a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// A lookup the caller is blocked on, e.g. refreshing a token the
+    /// server just rejected with a 401. The default.
+    #[default]
+    Critical,
+    /// A routine lookup, e.g. a periodic refresh ahead of expiry, which
+    /// yields to critical lookups while all connections are in use.
+    Background,
+}
+
+/// A pool of connections to the daemon kept open between lookups, for
+/// services refreshing identities and tokens thousands of times per
+/// second.
+///
+/// Each lookup checks out a connection, waiting up to the checkout timeout
+/// while all of them are in use, and sends a `keep_alive` request as a
+/// line of JSON, like a [`Session`](crate::Session). Connections the daemon
+/// keeps open return to the pool warm; against daemons without framing
+/// support every lookup connects anew, still bounded by the pool size.
+/// Connections the daemon closed or answered with
+/// [`STATUS_GOAWAY`](crate::protocol::STATUS_GOAWAY) are replaced by a new
+/// one, transparently to the lookup.
+///
+/// Lookups tagged [`Priority::Background`] queue for a connection one at a
+/// time, so while the daemon is slow, critical lookups wait behind at most
+/// one of them instead of every routine refresh queued before.
+///
+/// Clones share the connections. The client's timeouts, limits and parsing
+/// options apply; its cache, retries and failover daemons do not.
+///
+/// # Example
+///
+/// ```no_run
+/// use getmyid::{AsyncClient, AsyncClientPool};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), getmyid::GetMyIdError> {
+/// let pool = AsyncClientPool::new(AsyncClient::new(), 8);
+/// let identity = pool.get_identity().await?;
+/// println!("Token: {}", identity.token);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncClientPool {
+    client: AsyncClient,
+    size: usize,
+    checkout_timeout: Option<Duration>,
+    permits: Arc<Semaphore>,
+    background: Arc<AsyncMutex<()>>,
+    idle: Arc<Mutex<Vec<PooledConnection>>>,
+}
+
+impl AsyncClientPool {
+    /// Create a pool of at most `size` connections made with `client`.
+    ///
+    /// A `size` of zero is treated as one. Connections are opened on first
+    /// use.
+    pub fn new(client: AsyncClient, size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            client,
+            size,
+            checkout_timeout: Some(DEFAULT_CHECKOUT_TIMEOUT),
+            permits: Arc::new(Semaphore::new(size)),
+            background: Arc::default(),
+            idle: Arc::default(),
+        }
+    }
+
+    /// Set how long a lookup waits for a free connection before failing
+    /// with [`GetMyIdError::Timeout`]. Pass `None` to wait indefinitely.
+    ///
+    /// Defaults to [`DEFAULT_CHECKOUT_TIMEOUT`].
+    pub fn with_checkout_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.checkout_timeout = timeout.into();
+        self
+    }
+
+    /// Get the client connections are made with.
+    pub fn client(&self) -> &AsyncClient {
+        &self.client
+    }
+
+    /// Get the maximum number of connections.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Get how long a lookup waits for a free connection.
+    pub fn checkout_timeout(&self) -> Option<Duration> {
+        self.checkout_timeout
+    }
+
+    /// Get the number of open connections waiting for a lookup.
+    pub fn idle_connections(&self) -> usize {
+        self.lock_idle().len()
+    }
+
+    /// Get the identity of the current process.
+    pub async fn get_identity(&self) -> Result<Identity> {
+        self.get_identity_with_runner(None).await
+    }
+
+    /// Get the identity, sending `runner` as context.
+    ///
+    /// The client's default runner is merged in and extras keys are
+    /// normalized as for [`AsyncClient::get_identity_with_runner`].
+    pub async fn get_identity_with_runner(
+        &self,
+        runner: Option<RunnerRequest>,
+    ) -> Result<Identity> {
+        self.get_identity_with_priority(runner, Priority::Critical)
+            .await
+    }
+
+    /// Get the identity, sending `runner` as context, with `priority`
+    /// deciding which waiting lookup gets the next free connection.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::{AsyncClient, AsyncClientPool, Priority};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), getmyid::GetMyIdError> {
+    /// let pool = AsyncClientPool::new(AsyncClient::new(), 8);
+    /// let refreshed = pool.get_identity_with_priority(None, Priority::Background).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_identity_with_priority(
+        &self,
+        runner: Option<RunnerRequest>,
+        priority: Priority,
+    ) -> Result<Identity> {
+        let runner = self.client.prepare_runner(runner);
+        let mut request = self.client.new_request(runner.clone());
+        request.keep_alive = true;
+        let mut line = request.to_json()?.into_bytes();
+        line.push(b'\n');
+
+        let _permit = with_timeout(self.checkout_timeout, async {
+            let _queued = match priority {
+                Priority::Critical => None,
+                Priority::Background => Some(self.background.lock().await),
+            };
+            // The semaphore is never closed
+            Ok(self.permits.acquire().await.ok())
+        })
+        .await?;
+        let response = self.round_trip(&line).await?;
+        check_nonce(request.nonce.as_deref(), &response)?;
+        self.client
+            .identity_from_response(&response, runner.as_ref())
+    }
+
+    /// Send `request` over an idle connection, or a new one if there is
+    /// none or the daemon closed it or is going away.
+    async fn round_trip(&self, request: &[u8]) -> Result<String> {
+        let idle = self.lock_idle().pop();
+        if let Some(stream) = idle {
+            match self.send(stream, request).await? {
+                Some(response) if !is_goaway(&response) => return Ok(response),
+                _ => {}
+            }
+        }
+        let stream = BufReader::new(self.client.dial().await?);
+        Ok(self.send(stream, request).await?.unwrap_or_default())
+    }
+
+    /// Exchange `request` over `stream`, returning the stream to the pool if
+    /// the daemon framed its response and is not going away. Returns `None`
+    /// if the connection was closed before the request could be answered.
+    async fn send(&self, mut stream: PooledConnection, request: &[u8]) -> Result<Option<String>> {
+        let written = with_timeout(self.client.write_timeout(), async {
+            let connection = stream.get_mut();
+            connection
+                .write_all(request)
+                .await
+                .map_err(GetMyIdError::WriteError)?;
+            connection.flush().await.map_err(GetMyIdError::WriteError)
+        })
+        .await;
+        match written {
+            Ok(()) => {}
+            Err(GetMyIdError::WriteError(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        }
+
+        let max = self.client.max_response_bytes();
+        let mut response = Vec::new();
+        with_timeout(self.client.read_timeout(), async {
+            match (&mut stream)
+                .take(read_limit(max))
+                .read_until(b'\n', &mut response)
+                .await
+            {
+                Ok(_) => Ok(()),
+                // The daemon closed the connection with the request unread
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset && response.is_empty() => {
+                    Ok(())
+                }
+                Err(e) => Err(GetMyIdError::ReadError(e)),
+            }
+        })
+        .await?;
+        if response.is_empty() {
+            return Ok(None);
+        }
+        let framed = response.last() == Some(&b'\n');
+        if framed {
+            response.pop();
+        }
+        let mut response = response_text(response, max)?;
+        self.client.open_response(&mut response)?;
+        if framed && !is_goaway(&response) {
+            self.lock_idle().push(stream);
+        }
+        Ok(Some(response))
+    }
+
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, Vec<PooledConnection>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for AsyncClientPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncClientPool")
+            .field("client", &self.client)
+            .field("size", &self.size)
+            .field("checkout_timeout", &self.checkout_timeout)
+            .field("idle_connections", &self.idle_connections())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeDaemon, FakeResponse, Scenario};
+
+    #[tokio::test]
+    async fn test_pool_reuses_connections() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let pool = AsyncClientPool::new(client, 2);
+
+        let lookups = (0..6).map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move { pool.get_identity().await })
+        });
+        for lookup in lookups.collect::<Vec<_>>() {
+            assert_eq!(lookup.await.unwrap().unwrap().token, "tok_test");
+        }
+        assert!((1..=2).contains(&pool.idle_connections()));
+
+        let requests = daemon.requests();
+        assert_eq!(requests.len(), 6);
+        assert!(requests
+            .iter()
+            .all(|request| request.json().unwrap()["keep_alive"] == true));
+    }
+
+    #[tokio::test]
+    async fn test_pool_replaces_connections_going_away() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let pool = AsyncClientPool::new(client, 1);
+        pool.get_identity().await.unwrap();
+        assert_eq!(pool.idle_connections(), 1);
+
+        daemon.play(
+            Scenario::new()
+                .then(FakeResponse::goaway(), 1)
+                .then(FakeResponse::default(), 1),
+        );
+        assert_eq!(pool.get_identity().await.unwrap().token, "tok_test");
+        assert_eq!(pool.idle_connections(), 1);
+        assert_eq!(daemon.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_pool_checkout_timeout() {
+        let delayed = FakeResponse::default().delayed(Duration::from_millis(300));
+        let daemon = FakeDaemon::with_response(delayed).unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let pool = AsyncClientPool::new(client, 1).with_checkout_timeout(Duration::from_millis(50));
+        assert_eq!(pool.size(), 1);
+
+        let busy = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.get_identity().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(matches!(
+            pool.get_identity().await,
+            Err(GetMyIdError::Timeout(timeout)) if timeout == Duration::from_millis(50)
+        ));
+        assert!(busy.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pool_priority() {
+        let delayed = FakeResponse::default().delayed(Duration::from_millis(50));
+        let daemon = FakeDaemon::with_response(delayed).unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let pool = AsyncClientPool::new(client, 1).with_checkout_timeout(None);
+
+        let mut lookups = Vec::new();
+        for (call, priority) in [
+            ("busy", Priority::Critical),
+            ("first", Priority::Background),
+            ("second", Priority::Background),
+            ("critical", Priority::Critical),
+        ] {
+            let pool = pool.clone();
+            let runner = RunnerRequest::new().with_field("call", call);
+            lookups.push(tokio::spawn(async move {
+                pool.get_identity_with_priority(Some(runner), priority)
+                    .await
+            }));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        for lookup in lookups {
+            assert!(lookup.await.unwrap().is_ok());
+        }
+
+        let calls: Vec<_> = daemon
+            .requests()
+            .iter()
+            .map(|request| request.json().unwrap()["runner"]["call"].clone())
+            .collect();
+        assert_eq!(calls, ["busy", "first", "critical", "second"]);
+    }
+}
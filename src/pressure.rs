@@ -0,0 +1,142 @@
+//! Load and health signals of a client's identity lookups.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How far back [`Pressure::error_rate`] looks.
+pub const PRESSURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Most lookup outcomes remembered for the error rate.
+const MAX_OUTCOMES: usize = 1024;
+
+/// Snapshot of how hard a client's identity lookups are struggling,
+/// returned by `Client::pressure` and `AsyncClient::pressure`.
+///
+/// Only lookups that reach the daemon are counted: identities served from
+/// the cache and failures replayed by the negative cache are not. The
+/// counters are shared between clones of a client.
+///
+/// # Example
+///
+/// ```
+/// let client = getmyid::Client::new();
+/// let pressure = client.pressure();
+/// if pressure.queued > 0 || pressure.error_rate > 0.5 {
+///     eprintln!("identity lookups are struggling; deferring optional work");
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct Pressure {
+    /// Lookups currently in progress, including those waiting to retry.
+    pub in_flight: usize,
+    /// Lookups waiting out a retry backoff before asking the daemon again.
+    pub queued: usize,
+    /// Lookups completed within the last [`PRESSURE_WINDOW`].
+    pub recent_lookups: usize,
+    /// Fraction of [`recent_lookups`](Pressure::recent_lookups) that failed
+    /// after retries and failover, from `0.0` to `1.0`; `0.0` without
+    /// recent lookups.
+    pub error_rate: f64,
+}
+
+/// Counters behind [`Pressure`], shared between clones of a client.
+#[derive(Debug, Default)]
+pub(crate) struct PressureTracker {
+    in_flight: AtomicUsize,
+    queued: AtomicUsize,
+    /// Completion time and success of recent lookups, oldest first.
+    outcomes: Mutex<VecDeque<(Instant, bool)>>,
+}
+
+/// Counts a lookup as in flight or queued until dropped.
+pub(crate) struct Counted<'a>(&'a AtomicUsize);
+
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl PressureTracker {
+    /// Count a lookup as in flight until the guard is dropped.
+    pub fn start(&self) -> Counted<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Counted(&self.in_flight)
+    }
+
+    /// Count a lookup as queued for a retry until the guard is dropped.
+    pub fn queue(&self) -> Counted<'_> {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        Counted(&self.queued)
+    }
+
+    /// Record the final outcome of a lookup.
+    pub fn record(&self, success: bool) {
+        let mut outcomes = self.lock();
+        if outcomes.len() == MAX_OUTCOMES {
+            outcomes.pop_front();
+        }
+        outcomes.push_back((Instant::now(), success));
+    }
+
+    pub fn snapshot(&self) -> Pressure {
+        let mut outcomes = self.lock();
+        while outcomes
+            .front()
+            .is_some_and(|(at, _)| at.elapsed() > PRESSURE_WINDOW)
+        {
+            outcomes.pop_front();
+        }
+        let failures = outcomes.iter().filter(|(_, success)| !success).count();
+        Pressure {
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            recent_lookups: outcomes.len(),
+            error_rate: if outcomes.is_empty() {
+                0.0
+            } else {
+                failures as f64 / outcomes.len() as f64
+            },
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<(Instant, bool)>> {
+        self.outcomes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pressure_tracker() {
+        let tracker = PressureTracker::default();
+        assert_eq!(tracker.snapshot().error_rate, 0.0);
+
+        let in_flight = tracker.start();
+        let queued = tracker.queue();
+        let pressure = tracker.snapshot();
+        assert_eq!((pressure.in_flight, pressure.queued), (1, 1));
+        drop(queued);
+        drop(in_flight);
+
+        tracker.record(true);
+        tracker.record(false);
+        tracker.record(false);
+        tracker.record(true);
+        let pressure = tracker.snapshot();
+        assert_eq!((pressure.in_flight, pressure.queued), (0, 0));
+        assert_eq!(pressure.recent_lookups, 4);
+        assert_eq!(pressure.error_rate, 0.5);
+
+        for _ in 0..MAX_OUTCOMES {
+            tracker.record(true);
+        }
+        assert_eq!(tracker.snapshot().recent_lookups, MAX_OUTCOMES);
+        assert_eq!(tracker.snapshot().error_rate, 0.0);
+    }
+}
@@ -0,0 +1,179 @@
+//! Helpers for reading information about the current process from `/proc`.
+
+use std::fs;
+
+/// Name of the current process as reported by `/proc/self/comm`.
+pub(crate) fn process_name() -> Option<String> {
+    fs::read_to_string("/proc/self/comm")
+        .ok()
+        .map(|s| s.trim_end().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Kernel hostname of the current UTS namespace.
+pub(crate) fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|s| s.trim_end().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Real user and group IDs of the current process.
+pub(crate) fn uid_gid() -> Option<(u32, u32)> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let uid = status_id(&status, "Uid:")?;
+    let gid = status_id(&status, "Gid:")?;
+    Some((uid, gid))
+}
+
+/// Start time of the current process, in clock ticks since boot.
+pub(crate) fn start_time() -> Option<u64> {
+    stat_start_time(&fs::read_to_string("/proc/self/stat").ok()?)
+}
+
+/// ID of the container the current process runs in, from
+/// `/proc/self/cgroup`, the `/run/.containerenv` Podman mounts or, if the
+/// cgroup namespace hides it, the mount sources in `/proc/self/mountinfo`.
+pub(crate) fn container_id() -> Option<String> {
+    fs::read_to_string("/proc/self/cgroup")
+        .ok()
+        .and_then(|cgroup| cgroup_container_id(&cgroup))
+        .or_else(|| containerenv_id(&fs::read_to_string("/run/.containerenv").ok()?))
+        .or_else(|| mountinfo_container_id(&fs::read_to_string("/proc/self/mountinfo").ok()?))
+}
+
+/// Cgroup of the current process, as listed in `/proc/self/cgroup`.
+pub(crate) fn cgroup_path() -> Option<String> {
+    parse_cgroup_path(&fs::read_to_string("/proc/self/cgroup").ok()?)
+}
+
+/// The unified (v2) cgroup path, or else the first v1 path, skipping the
+/// root a cgroup namespace shows.
+fn parse_cgroup_path(cgroup: &str) -> Option<String> {
+    let entries: Vec<(&str, &str)> = cgroup
+        .lines()
+        .filter_map(|line| {
+            let (_, rest) = line.split_once(':')?;
+            rest.split_once(':')
+        })
+        .filter(|(_, path)| !path.is_empty() && *path != "/")
+        .collect();
+    entries
+        .iter()
+        .find(|(controllers, _)| controllers.is_empty())
+        .or(entries.first())
+        .map(|(_, path)| path.to_string())
+}
+
+/// Read the `id="<id>"` line of Podman's `/run/.containerenv`.
+fn containerenv_id(containerenv: &str) -> Option<String> {
+    containerenv
+        .lines()
+        .find_map(|line| line.strip_prefix("id="))
+        .map(|id| id.trim_matches('"'))
+        .filter(|id| is_container_id(id))
+        .map(str::to_string)
+}
+
+/// Find the 64-digit hex container ID in cgroup paths such as
+/// `/kubepods/burstable/pod<uid>/<id>` or `/system.slice/docker-<id>.scope`.
+fn cgroup_container_id(cgroup: &str) -> Option<String> {
+    cgroup
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .find(|word| is_container_id(word))
+        .map(str::to_string)
+}
+
+/// Find the container ID in mount sources such as
+/// `/var/lib/docker/containers/<id>/hostname`; image layer IDs elsewhere in
+/// the mounts are skipped.
+fn mountinfo_container_id(mountinfo: &str) -> Option<String> {
+    mountinfo
+        .split("/containers/")
+        .skip(1)
+        .filter_map(|rest| rest.split('/').next())
+        .find(|word| is_container_id(word))
+        .map(str::to_string)
+}
+
+fn is_container_id(word: &str) -> bool {
+    word.len() == 64 && word.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Parse the `starttime` field (22nd) of `/proc/<pid>/stat`.
+///
+/// The command name in field 2 may contain spaces and parentheses, so
+/// fields are counted from the last `)`.
+fn stat_start_time(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Parse the first (real) ID from a `Uid:`/`Gid:` line of `/proc/self/status`.
+fn status_id(status: &str, key: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_id() {
+        let status = "Name:\tapp\nUid:\t1001\t1001\t1001\t1001\nGid:\t100\t100\t100\t100\n";
+
+        assert_eq!(status_id(status, "Uid:"), Some(1001));
+        assert_eq!(status_id(status, "Gid:"), Some(100));
+        assert_eq!(status_id(status, "Groups:"), None);
+    }
+
+    #[test]
+    fn test_stat_start_time() {
+        let stat = "4242 (my (odd) app) S 1 4242 4242 0 -1 4194560 120 0 0 0 3 1 0 0 20 0 1 0 98765 1000 200";
+        assert_eq!(stat_start_time(stat), Some(98765));
+        assert_eq!(stat_start_time("4242 (app) S 1"), None);
+        assert!(start_time().is_some());
+    }
+
+    #[test]
+    fn test_container_id() {
+        let id = "3f4e1b0c9d8a7f6e5d4c3b2a19081726354453627180918a7b6c5d4e3f2a1b0c";
+        let cgroup = format!("12:pids:/kubepods/besteffort/pod1a2b/{id}\n0::/\n");
+        assert_eq!(cgroup_container_id(&cgroup).as_deref(), Some(id));
+        let cgroup = format!("0::/system.slice/docker-{id}.scope\n");
+        assert_eq!(cgroup_container_id(&cgroup).as_deref(), Some(id));
+        assert_eq!(cgroup_container_id("0::/user.slice\n"), None);
+
+        let layer = id.replace('3', "0");
+        let mountinfo = format!(
+            "1 0 0:1 / / rw - overlay overlay rw,upperdir=/var/lib/docker/overlay2/{layer}/diff\n\
+             2 1 8:1 /var/lib/docker/containers/{id}/hostname /etc/hostname rw - ext4 /dev/sda1 rw\n"
+        );
+        assert_eq!(mountinfo_container_id(&mountinfo).as_deref(), Some(id));
+        let mountinfo = format!("1 0 0:1 / / - {layer}");
+        assert_eq!(mountinfo_container_id(&mountinfo), None);
+
+        let containerenv = format!("engine=\"podman-4.9.3\"\nname=\"web\"\nid=\"{id}\"\n");
+        assert_eq!(containerenv_id(&containerenv).as_deref(), Some(id));
+        assert_eq!(containerenv_id("engine=\"podman-4.9.3\"\n"), None);
+    }
+
+    #[test]
+    fn test_parse_cgroup_path() {
+        let v1 = "9:name=systemd:/\n4:memory:/docker/abc\n1:cpu:/docker/abc\n0::/\n";
+        assert_eq!(parse_cgroup_path(v1).as_deref(), Some("/docker/abc"));
+        let v2 = "1:cpu:/legacy\n0::/system.slice/app.service\n";
+        assert_eq!(
+            parse_cgroup_path(v2).as_deref(),
+            Some("/system.slice/app.service")
+        );
+        assert_eq!(parse_cgroup_path("0::/\n"), None);
+        assert_eq!(parse_cgroup_path(""), None);
+    }
+}
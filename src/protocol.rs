@@ -0,0 +1,858 @@
+//! Wire types of the whoami daemon protocol.
+//!
+//! A client connects to the daemon socket, optionally sends a [`Request`]
+//! carrying runner context, shuts down its write side and reads a single
+//! [`DaemonResponse`] until the daemon closes the connection. These types
+//! and [`parse_response`] are what the clients use, exposed for proxies,
+//! alternative clients and test tooling.
+//!
+//! # Example
+//!
+//! ```
+//! use getmyid::protocol::{DaemonResponse, Request};
+//! use getmyid::RunnerRequest;
+//!
+//! let request = Request::new(Some(RunnerRequest::new().with_instance_id(42)));
+//! assert_eq!(request.to_json()?, r#"{"runner":{"instance_id":42}}"#);
+//!
+//! let response = DaemonResponse::error("E_NO_MATCH", "No identity rule matches process");
+//! let json = serde_json::to_string(&response).unwrap();
+//! assert!(getmyid::protocol::parse_response(&json).is_err());
+//! # Ok::<(), getmyid::GetMyIdError>(())
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compat::legacy::{self, ResponseFormat};
+use crate::error::{DaemonErrorCode, GetMyIdError, Result};
+use crate::types::{
+    expiry_from_ttl, BootstrapPayload, Identity, IdentityChange, Runner, RunnerRequest,
+};
+
+/// `status` of a successful response.
+pub const STATUS_OK: &str = "ok";
+
+/// `status` of an error response.
+pub const STATUS_ERROR: &str = "error";
+
+/// `status` of a degraded response.
+pub const STATUS_DEGRADED: &str = "degraded";
+
+/// `status` of a long-poll response whose identity did not change.
+pub const STATUS_UNCHANGED: &str = "unchanged";
+
+/// `status` of a daemon about to hand its socket over to a new process,
+/// e.g. during a live upgrade.
+///
+/// The request was not served; it should be sent again on a new
+/// connection, which the new process accepts.
+pub const STATUS_GOAWAY: &str = "goaway";
+
+/// `status` of the answer to a [`Request::ping`] from daemons supporting
+/// pings.
+pub const STATUS_PONG: &str = "pong";
+
+/// `status` of the answer to a [`Request::info`] from daemons supporting
+/// info requests, carrying the daemon's versions and features.
+pub const STATUS_INFO: &str = "info";
+
+/// Fields of the response's `runner` object the daemon injects, which
+/// runner requests cannot carry as custom fields.
+pub const RESERVED_RUNNER_FIELDS: &[&str] =
+    &["identity", "hostname", "process", "pid", "uid", "gid"];
+
+/// Request sent to the daemon.
+///
+/// Clients on stream sockets may send nothing at all; the daemon then
+/// answers as for a request without runner context.
+///
+/// Build requests with [`Request::new`] and the `with_*` methods, as
+/// fields are added when the protocol grows.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Request {
+    /// Runner context merged into the response's `runner` object.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runner: Option<RunnerRequest>,
+    /// Long-poll for a change instead of answering immediately.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait: Option<Wait>,
+    /// Keep the connection open for further requests.
+    ///
+    /// The request is sent as a single line, and the daemon answers with a
+    /// single line and waits for the next request instead of closing the
+    /// connection.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keep_alive: bool,
+    /// Ask the daemon to sign its answer, sent as a [`SignedResponse`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sign: bool,
+    /// Random value the daemon echoes in [`DaemonResponse::nonce`], so a
+    /// replayed or cached answer to another request is not accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// Only check that the daemon is alive. Daemons supporting pings answer
+    /// [`STATUS_PONG`] without resolving an identity; others answer as
+    /// usual.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ping: bool,
+    /// Ask the daemon for its versions and supported features. Daemons
+    /// supporting info requests answer [`STATUS_INFO`] without resolving an
+    /// identity; others answer as usual.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub info: bool,
+    /// Resolve the identity of the process with this PID rather than the
+    /// caller's. Daemons only honour it for privileged callers, rejecting
+    /// others with `E_ACCESS_DENIED`; daemons without support ignore it
+    /// and answer for the caller.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_pid: Option<u32>,
+}
+
+/// Long-poll parameters of a [`Request`].
+///
+/// The daemon holds the connection until the identity's version differs
+/// from `since`, answering with the new identity and its `version`, or
+/// until `timeout_ms` passed, answering [`STATUS_UNCHANGED`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wait {
+    /// Version the caller already has.
+    pub since: String,
+    /// How long the daemon may hold the connection, in milliseconds.
+    pub timeout_ms: u64,
+}
+
+impl Request {
+    /// Create a request carrying `runner`.
+    pub fn new(runner: Option<RunnerRequest>) -> Self {
+        Self {
+            runner,
+            wait: None,
+            keep_alive: false,
+            sign: false,
+            nonce: None,
+            ping: false,
+            info: false,
+            target_pid: None,
+        }
+    }
+
+    /// Attach a fresh random nonce for the daemon to echo.
+    pub fn with_nonce(mut self) -> Self {
+        self.nonce = Some(new_nonce());
+        self
+    }
+
+    /// Keep the connection open for further requests.
+    pub fn with_keep_alive(mut self) -> Self {
+        self.keep_alive = true;
+        self
+    }
+
+    /// Ask the daemon to sign its answer.
+    pub fn with_sign(mut self) -> Self {
+        self.sign = true;
+        self
+    }
+
+    /// Only check that the daemon is alive.
+    pub fn with_ping(mut self) -> Self {
+        self.ping = true;
+        self
+    }
+
+    /// Ask the daemon for its versions and supported features.
+    pub fn with_info(mut self) -> Self {
+        self.info = true;
+        self
+    }
+
+    /// Resolve the identity of the process `pid` rather than the caller's.
+    pub fn with_target_pid(mut self, pid: u32) -> Self {
+        self.target_pid = Some(pid);
+        self
+    }
+
+    /// Ask the daemon to wait up to `timeout` for the identity to change
+    /// from version `since`.
+    pub fn with_wait(mut self, since: impl Into<String>, timeout: Duration) -> Self {
+        self.wait = Some(Wait {
+            since: since.into(),
+            timeout_ms: u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX),
+        });
+        self
+    }
+
+    /// Serialize the request as sent on the wire.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::ReservedField`] if the runner carries a
+    /// custom field the daemon would overwrite, and
+    /// [`GetMyIdError::InvalidJson`] if a runner extra cannot be serialized.
+    pub fn to_json(&self) -> Result<String> {
+        if let Some(runner) = &self.runner {
+            runner.check()?;
+        }
+        serde_json::to_string(self).map_err(GetMyIdError::invalid_json)
+    }
+}
+
+/// Answer of a daemon asked to sign it with [`Request::sign`].
+///
+/// The response the daemon would otherwise have sent is carried verbatim
+/// as `payload`, so its exact bytes are covered by the signature; relays
+/// and caches can store the signed answer as is and have it verified later
+/// (with the `signing` feature, see `SignedResponse::verify`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedResponse {
+    /// The response, e.g. a serialized [`DaemonResponse`].
+    pub payload: String,
+    /// Standard base64 of the Ed25519 signature of the payload's bytes.
+    pub signature: String,
+}
+
+/// Response from the daemon.
+///
+/// Build responses with the constructors such as
+/// [`DaemonResponse::identity`] and [`DaemonResponse::error`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct DaemonResponse {
+    /// [`STATUS_OK`], [`STATUS_ERROR`], [`STATUS_DEGRADED`],
+    /// [`STATUS_UNCHANGED`] or [`STATUS_GOAWAY`].
+    pub status: String,
+    /// The [`Request::nonce`] the response answers, echoed by daemons
+    /// supporting nonces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// Fields of the response, depending on the status.
+    #[serde(flatten)]
+    pub data: ResponseData,
+}
+
+/// Fields of a [`DaemonResponse`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+#[non_exhaustive]
+pub enum ResponseData {
+    /// The identity of the calling process.
+    #[non_exhaustive]
+    Success {
+        /// Application-level identity name.
+        identity: String,
+        /// Identity Management (OAuth2/OIDC) URL.
+        idm_url: String,
+        /// Configuration/API server URL.
+        config_url: String,
+        /// Authentication token.
+        token: String,
+        /// Token expiry in seconds since the Unix epoch.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+        /// Token lifetime in seconds, used when `expires_at` is absent.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl: Option<u64>,
+        /// Configuration payload inlined by the daemon.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bootstrap: Option<BootstrapPayload>,
+        /// Version of the identity, sent in answer to a long-poll.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+        /// Runner information.
+        runner: Box<Runner>,
+    },
+    /// The daemon rejected the request.
+    #[non_exhaustive]
+    Error {
+        /// Error code, e.g. `E_NO_MATCH`.
+        error_code: String,
+        /// Human-readable message.
+        message: String,
+    },
+    /// The daemon is degraded (e.g. in maintenance) or going away, and
+    /// resolved no identity.
+    #[non_exhaustive]
+    Degraded {
+        /// Human-readable message.
+        message: String,
+        /// How long to wait before retrying, in milliseconds.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry_after_ms: Option<u64>,
+    },
+    /// A long-poll timed out without the identity changing.
+    #[non_exhaustive]
+    Unchanged {
+        /// Current version of the identity.
+        version: String,
+    },
+}
+
+/// Top-level fields of a successful daemon response.
+const SUCCESS_FIELDS: &[&str] = &[
+    "status",
+    "identity",
+    "idm_url",
+    "config_url",
+    "token",
+    "expires_at",
+    "ttl",
+    "bootstrap",
+    "version",
+    "runner",
+];
+
+/// Top-level fields of a daemon error response.
+const ERROR_FIELDS: &[&str] = &["status", "error_code", "message"];
+
+/// Top-level fields of a degraded daemon response.
+const DEGRADED_FIELDS: &[&str] = &["status", "message", "error_code", "retry_after_ms"];
+
+/// The fields of a degraded or goaway daemon response, whose error code is
+/// optional.
+#[derive(Deserialize)]
+struct DegradedFields {
+    message: String,
+    #[serde(default)]
+    error_code: Option<String>,
+    #[serde(default)]
+    retry_after_ms: Option<u64>,
+}
+
+/// Top-level fields of an unchanged long-poll response.
+const UNCHANGED_FIELDS: &[&str] = &["status", "version"];
+
+/// Top-level fields any daemon response may carry: its versions and the
+/// echoed nonce.
+const COMMON_FIELDS: &[&str] = &["protocol_version", "daemon_version", "nonce"];
+
+/// Typed fields of the `runner` object.
+const RUNNER_FIELDS: &[&str] = &[
+    "identity",
+    "hostname",
+    "process",
+    "pid",
+    "uid",
+    "gid",
+    "instance_id",
+    "timestamp",
+];
+
+/// Runner fields the client adds to requests on its own, which the daemon
+/// echoes like the caller's runner context.
+#[cfg(feature = "otel")]
+const CLIENT_RUNNER_FIELDS: &[&str] = &["request_id", crate::otel::TRACEPARENT_FIELD];
+#[cfg(not(feature = "otel"))]
+const CLIENT_RUNNER_FIELDS: &[&str] = &["request_id"];
+
+/// Fields of the `bootstrap` object.
+const BOOTSTRAP_FIELDS: &[&str] = &["encoding", "data", "sha256"];
+
+/// Check that a daemon response carries no fields outside the protocol.
+///
+/// Runner extras are only accepted if `runner`, the runner request sent,
+/// carries them. Responses that are not JSON objects are left for the
+/// parser to reject.
+pub(crate) fn check_known_fields(response: &str, runner: Option<&RunnerRequest>) -> Result<()> {
+    let Ok(serde_json::Value::Object(response)) = serde_json::from_str(response) else {
+        return Ok(());
+    };
+    let fields = match response.get("status").and_then(|status| status.as_str()) {
+        Some(STATUS_OK) => SUCCESS_FIELDS,
+        Some(STATUS_DEGRADED | STATUS_GOAWAY) => DEGRADED_FIELDS,
+        Some(STATUS_UNCHANGED) => UNCHANGED_FIELDS,
+        _ => ERROR_FIELDS,
+    };
+    let unknown = |path: String| GetMyIdError::UnknownField { path };
+
+    for (key, value) in &response {
+        if !fields.contains(&key.as_str()) && !COMMON_FIELDS.contains(&key.as_str()) {
+            return Err(unknown(key.clone()));
+        }
+        let (known, extras): (&[&str], _) = match key.as_str() {
+            "runner" => (RUNNER_FIELDS, runner),
+            "bootstrap" => (BOOTSTRAP_FIELDS, None),
+            _ => continue,
+        };
+        let Some(object) = value.as_object() else {
+            continue;
+        };
+        let added_by_client =
+            |field: &str| key == "runner" && CLIENT_RUNNER_FIELDS.contains(&field);
+        if let Some(field) = object.keys().find(|field| {
+            !known.contains(&field.as_str())
+                && !extras.is_some_and(|r| r.carries(field))
+                && !added_by_client(field)
+        }) {
+            return Err(unknown(format!("{key}.{field}")));
+        }
+    }
+    Ok(())
+}
+
+/// Parse a response from the daemon into an [`Identity`].
+///
+/// Responses in the flat format of daemons predating the `runner` object
+/// are mapped with [`legacy::parse_flat`]. A `ttl` is converted to
+/// [`Identity::expires_at`]. Runner extras are
+/// returned as sent: the clients' [`JsonLimits`](crate::JsonLimits),
+/// extras key case and hostname policy are not applied.
+///
+/// # Errors
+///
+/// Returns [`GetMyIdError::InvalidJson`] if the response does not parse,
+/// [`GetMyIdError::DaemonError`] or [`GetMyIdError::DaemonDegraded`] for
+/// error, degraded and goaway responses, and [`GetMyIdError::MissingField`] if the
+/// fields do not match the status.
+///
+/// # Example
+///
+/// ```
+/// let response = r#"{"status":"ok","identity":"BILLING_PROD","idm_url":"","config_url":"","token":"tok","runner":{"pid":1234}}"#;
+/// let identity = getmyid::protocol::parse_response(response)?;
+/// assert_eq!(identity.runner.pid, 1234);
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+pub fn parse_response(response: &str) -> Result<Identity> {
+    match parse_answer(response)? {
+        Answer::Identity(identity, _) => Ok(*identity),
+        Answer::Unchanged(_) => Err(GetMyIdError::MissingField { field: "identity" }),
+    }
+}
+
+/// Parse the response to a long-poll [`Request`] carrying [`Wait`].
+///
+/// # Errors
+///
+/// As for [`parse_response`]; [`GetMyIdError::MissingField`] for `version`
+/// if the daemon answered with an identity but no version, which daemons
+/// without long-poll support do.
+pub fn parse_change(response: &str) -> Result<IdentityChange> {
+    match parse_answer(response)? {
+        Answer::Identity(identity, Some(version)) => {
+            Ok(IdentityChange::Changed { identity, version })
+        }
+        Answer::Identity(_, None) => Err(GetMyIdError::MissingField { field: "version" }),
+        Answer::Unchanged(version) => Ok(IdentityChange::Unchanged { version }),
+    }
+}
+
+/// Check that `response` echoes `expected`, the nonce of the request it
+/// answers, if any.
+pub(crate) fn check_nonce(expected: Option<&str>, response: &str) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Echo {
+        #[serde(default)]
+        nonce: Option<String>,
+    }
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let actual = serde_json::from_str::<Echo>(response)
+        .ok()
+        .and_then(|echo| echo.nonce);
+    if actual.as_deref() != Some(expected) {
+        return Err(GetMyIdError::NonceMismatch {
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Check that `identity`, the answer to a lookup of the process
+/// `requested`, belongs to that process.
+#[cfg(feature = "admin")]
+pub(crate) fn check_target_pid(requested: u32, identity: &Identity) -> Result<()> {
+    if identity.runner.pid != requested {
+        return Err(GetMyIdError::PidMismatch {
+            requested,
+            resolved: identity.runner.pid,
+        });
+    }
+    Ok(())
+}
+
+/// Generate 128 random bits, hex-encoded.
+fn new_nonce() -> String {
+    random_bytes().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Generate 128 random bits from the kernel's random source.
+///
+/// Should `getrandom` be unavailable, the bits come from the hasher keys
+/// std seeds from it, which differ for each `RandomState`.
+pub(crate) fn random_bytes() -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        let rest = &mut bytes[filled..];
+        // SAFETY: the pointer and length describe the unfilled part of `bytes`
+        let n = unsafe { libc::getrandom(rest.as_mut_ptr().cast(), rest.len(), 0) };
+        if let Ok(n) = usize::try_from(n) {
+            filled += n;
+        } else if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            let state = RandomState::new();
+            for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+                chunk.copy_from_slice(&state.hash_one(i).to_le_bytes());
+            }
+            break;
+        }
+    }
+    bytes
+}
+
+/// Check if `response` is a [`STATUS_GOAWAY`] response, without parsing
+/// the rest of it.
+pub(crate) fn is_goaway(response: &str) -> bool {
+    #[derive(Deserialize)]
+    struct Status<'a> {
+        status: &'a str,
+    }
+    serde_json::from_str::<Status>(response).is_ok_and(|r| r.status == STATUS_GOAWAY)
+}
+
+/// Check that `response`, the answer to a ping or info request, comes from
+/// a working daemon: one with the `expected` status, an identity, or the
+/// rejection of the caller. Degraded daemons and internal errors fail.
+pub(crate) fn check_alive(response: &str, expected: &str) -> Result<()> {
+    #[derive(Deserialize)]
+    struct Status<'a> {
+        status: &'a str,
+    }
+    if serde_json::from_str::<Status>(response).is_ok_and(|r| r.status == expected) {
+        return Ok(());
+    }
+    match parse_response(response) {
+        Ok(_) => Ok(()),
+        Err(GetMyIdError::DaemonError { code, .. }) if !code.is_retryable() => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A successful daemon answer.
+enum Answer {
+    /// An identity, with its version if the daemon sent one.
+    Identity(Box<Identity>, Option<String>),
+    /// A long-poll timed out at this version.
+    Unchanged(String),
+}
+
+fn parse_answer(response: &str) -> Result<Answer> {
+    let daemon_response: DaemonResponse = match serde_json::from_str(response) {
+        Ok(daemon_response) => daemon_response,
+        Err(_) if legacy::detect_format(response) == ResponseFormat::Flat => {
+            return legacy::parse_flat(response)
+                .map(|identity| Answer::Identity(Box::new(identity), None));
+        }
+        Err(e) => return Err(GetMyIdError::invalid_response(e, response)),
+    };
+
+    if daemon_response.is_degraded() || daemon_response.is_goaway() {
+        // Read directly: with an `error_code`, `data` parses as an error
+        // and would lose `retry_after_ms`
+        return match serde_json::from_str::<DegradedFields>(response) {
+            Ok(fields) => Err(GetMyIdError::DaemonDegraded {
+                message: fields.message,
+                code: fields.error_code.map(DaemonErrorCode::from),
+                retry_after: fields.retry_after_ms.map(Duration::from_millis),
+            }),
+            Err(_) => Err(GetMyIdError::MissingField { field: "message" }),
+        };
+    }
+
+    if daemon_response.status == STATUS_UNCHANGED {
+        return match daemon_response.data {
+            ResponseData::Unchanged { version } => Ok(Answer::Unchanged(version)),
+            _ => Err(GetMyIdError::MissingField { field: "version" }),
+        };
+    }
+
+    if !daemon_response.is_ok() {
+        match daemon_response.data {
+            ResponseData::Error {
+                error_code,
+                message,
+            } => {
+                return Err(GetMyIdError::DaemonError {
+                    code: error_code.into(),
+                    message,
+                });
+            }
+            _ => {
+                return Err(GetMyIdError::MissingField {
+                    field: "error_code",
+                });
+            }
+        }
+    }
+
+    match daemon_response.data {
+        ResponseData::Success {
+            identity,
+            idm_url,
+            config_url,
+            token,
+            expires_at,
+            ttl,
+            bootstrap,
+            version,
+            runner,
+        } => Ok(Answer::Identity(
+            Box::new(Identity {
+                identity,
+                idm_url,
+                config_url,
+                token: token.into(),
+                expires_at: expires_at.or(ttl.map(expiry_from_ttl)),
+                bootstrap,
+                runner: *runner,
+            }),
+            version,
+        )),
+        ResponseData::Error { .. }
+        | ResponseData::Degraded { .. }
+        | ResponseData::Unchanged { .. } => Err(GetMyIdError::MissingField { field: "identity" }),
+    }
+}
+
+impl DaemonResponse {
+    /// Create a successful response carrying `identity`.
+    ///
+    /// The expiry is sent as `expires_at`.
+    pub fn identity(identity: Identity) -> Self {
+        Self {
+            status: STATUS_OK.to_string(),
+            nonce: None,
+            data: ResponseData::Success {
+                identity: identity.identity,
+                idm_url: identity.idm_url,
+                config_url: identity.config_url,
+                token: identity.token.into_string(),
+                expires_at: identity.expires_at,
+                ttl: None,
+                bootstrap: identity.bootstrap,
+                version: None,
+                runner: Box::new(identity.runner),
+            },
+        }
+    }
+
+    /// Set the version of a successful response, as sent in answer to a
+    /// long-poll. Other responses are returned unchanged.
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        if let ResponseData::Success { version: v, .. } = &mut self.data {
+            *v = Some(version.into());
+        }
+        self
+    }
+
+    /// Echo the nonce of the request the response answers.
+    pub fn with_nonce(mut self, nonce: Option<String>) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Create an error response with `error_code` and `message`.
+    pub fn error(error_code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            status: STATUS_ERROR.to_string(),
+            nonce: None,
+            data: ResponseData::Error {
+                error_code: error_code.into(),
+                message: message.into(),
+            },
+        }
+    }
+
+    /// Create a response from a degraded daemon resolving no identity,
+    /// suggesting to retry after `retry_after`.
+    pub fn degraded(message: impl Into<String>, retry_after: Option<Duration>) -> Self {
+        Self {
+            status: STATUS_DEGRADED.to_string(),
+            nonce: None,
+            data: ResponseData::Degraded {
+                message: message.into(),
+                retry_after_ms: retry_after
+                    .map(|delay| u64::try_from(delay.as_millis()).unwrap_or(u64::MAX)),
+            },
+        }
+    }
+
+    /// Create a response telling the client to reconnect, as a daemon
+    /// handing its socket over to a new process sends on persistent
+    /// connections.
+    pub fn goaway(message: impl Into<String>) -> Self {
+        Self {
+            status: STATUS_GOAWAY.to_string(),
+            nonce: None,
+            data: ResponseData::Degraded {
+                message: message.into(),
+                retry_after_ms: None,
+            },
+        }
+    }
+
+    /// Create a long-poll response reporting that the identity is still at
+    /// `version`.
+    pub fn unchanged(version: impl Into<String>) -> Self {
+        Self {
+            status: STATUS_UNCHANGED.to_string(),
+            nonce: None,
+            data: ResponseData::Unchanged {
+                version: version.into(),
+            },
+        }
+    }
+
+    /// Check if the response indicates success.
+    pub fn is_ok(&self) -> bool {
+        self.status == STATUS_OK
+    }
+
+    /// Check if the daemon reports it is degraded (e.g. in maintenance).
+    pub fn is_degraded(&self) -> bool {
+        self.status == STATUS_DEGRADED
+    }
+
+    /// Check if the daemon is handing its socket over to a new process.
+    pub fn is_goaway(&self) -> bool {
+        self.status == STATUS_GOAWAY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unknown_field(response: &str, runner: Option<&RunnerRequest>) -> Option<String> {
+        match check_known_fields(response, runner) {
+            Ok(()) => None,
+            Err(GetMyIdError::UnknownField { path }) => Some(path),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_check_known_fields() {
+        let success = r#"{"status":"ok","identity":"A","idm_url":"","config_url":"","token":"t","ttl":60,"runner":{"identity":"A","pid":1,"region":"eu"}}"#;
+        let runner = RunnerRequest::new().with_field("region", "eu");
+        assert_eq!(unknown_field(success, Some(&runner)), None);
+        assert_eq!(
+            unknown_field(success, None).as_deref(),
+            Some("runner.region")
+        );
+        let runner = RunnerRequest::new().with_region("eu");
+        assert_eq!(unknown_field(success, Some(&runner)), None);
+
+        let debug = r#"{"status":"ok","debug":true}"#;
+        assert_eq!(unknown_field(debug, None).as_deref(), Some("debug"));
+        let bootstrap =
+            r#"{"status":"ok","bootstrap":{"encoding":"","data":"","sha256":"","v":2}}"#;
+        assert_eq!(
+            unknown_field(bootstrap, None).as_deref(),
+            Some("bootstrap.v")
+        );
+
+        let error = r#"{"status":"error","error_code":"E","message":"m","protocol_version":1}"#;
+        assert_eq!(unknown_field(error, None), None);
+        let degraded = r#"{"status":"degraded","message":"m","token":"t"}"#;
+        assert_eq!(unknown_field(degraded, None).as_deref(), Some("token"));
+        assert_eq!(unknown_field("not json", None), None);
+    }
+
+    #[test]
+    fn test_protocol_round_trip() {
+        assert_eq!(Request::default().to_json().unwrap(), "{}");
+        let runner = RunnerRequest::new()
+            .with_region("eu")
+            .with_field("zone", "a");
+        let request = Request::new(Some(runner));
+        let json = request.to_json().unwrap();
+        assert_eq!(serde_json::from_str::<Request>(&json).unwrap(), request);
+        let request = Request::new(Some(RunnerRequest::new().with_field("pid", 1)));
+        assert!(matches!(
+            request.to_json(),
+            Err(GetMyIdError::ReservedField { field }) if field == "pid"
+        ));
+
+        let identity = Identity {
+            identity: "BILLING_PROD".to_string(),
+            idm_url: "https://auth.example.com".to_string(),
+            config_url: String::new(),
+            token: "tok".into(),
+            expires_at: Some(1_700_000_000),
+            bootstrap: None,
+            runner: Runner {
+                pid: 1234,
+                ..Runner::default()
+            },
+        };
+        let json = serde_json::to_string(&DaemonResponse::identity(identity.clone())).unwrap();
+        assert_eq!(parse_response(&json).unwrap(), identity);
+        assert_eq!(unknown_field(&json, None), None);
+
+        let json = serde_json::to_string(&DaemonResponse::error("E_NO_MATCH", "nope")).unwrap();
+        assert!(matches!(
+            parse_response(&json),
+            Err(GetMyIdError::DaemonError { message, .. }) if message == "nope"
+        ));
+    }
+
+    #[test]
+    fn test_goaway() {
+        let json = serde_json::to_string(&DaemonResponse::goaway("upgrading")).unwrap();
+        assert_eq!(json, r#"{"status":"goaway","message":"upgrading"}"#);
+        assert!(is_goaway(&json));
+        assert!(!is_goaway(r#"{"status":"degraded","message":"upgrading"}"#));
+        assert!(!is_goaway("not json"));
+        assert_eq!(unknown_field(&json, None), None);
+
+        let error = parse_response(&json).unwrap_err();
+        assert!(error.is_transient());
+        assert!(matches!(
+            error,
+            GetMyIdError::DaemonDegraded { message, code: None, retry_after: None }
+                if message == "upgrading"
+        ));
+    }
+
+    #[test]
+    fn test_degraded() {
+        let response = DaemonResponse::degraded("maintenance", Some(Duration::from_millis(250)));
+        assert!(response.is_degraded());
+        let json = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            json,
+            r#"{"status":"degraded","message":"maintenance","retry_after_ms":250}"#
+        );
+        assert!(matches!(
+            parse_response(&json),
+            Err(GetMyIdError::DaemonDegraded { retry_after: Some(delay), .. })
+                if delay == Duration::from_millis(250)
+        ));
+    }
+
+    #[test]
+    fn test_check_alive() {
+        let request = serde_json::to_string(&Request::new(None).with_ping()).unwrap();
+        assert_eq!(request, r#"{"ping":true}"#);
+
+        assert!(check_alive(r#"{"status":"pong"}"#, STATUS_PONG).is_ok());
+        assert!(check_alive(r#"{"status":"info","features":[]}"#, STATUS_INFO).is_ok());
+        assert!(check_alive(
+            r#"{"status":"error","error_code":"E_NO_MATCH","message":"no"}"#,
+            STATUS_PONG
+        )
+        .is_ok());
+        let internal = r#"{"status":"error","error_code":"E_INTERNAL","message":"boom"}"#;
+        assert!(check_alive(internal, STATUS_PONG).is_err());
+        let goaway = serde_json::to_string(&DaemonResponse::goaway("upgrading")).unwrap();
+        assert!(check_alive(&goaway, STATUS_PONG).is_err());
+        assert!(check_alive("not json", STATUS_PONG).is_err());
+    }
+}
@@ -0,0 +1,571 @@
+//! Pluggable identity sources.
+//!
+//! Libraries that need the process identity can accept any
+//! [`IdentityProvider`] instead of a concrete [`Client`], letting
+//! applications swap the whoami daemon for a fixed or environment-provided
+//! identity in development and tests without changing code paths.
+
+use std::env;
+use std::fmt;
+use std::io;
+
+use crate::client::Client;
+use crate::error::{GetMyIdError, Result};
+use crate::procfs;
+use crate::types::{Identity, Runner, RunnerRequest};
+
+/// A source of process identity.
+///
+/// # Example
+///
+/// ```no_run
+/// use getmyid::{DaemonProvider, IdentityProvider};
+///
+/// fn connect_to_backend(provider: &dyn IdentityProvider) -> getmyid::Result<()> {
+///     let identity = provider.get_identity()?;
+///     println!("Connecting as {}", identity.identity);
+///     Ok(())
+/// }
+///
+/// connect_to_backend(&DaemonProvider::default())?;
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+pub trait IdentityProvider {
+    /// Get the identity, merging the optional runner context into the
+    /// returned `runner` object.
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity>;
+
+    /// Get the identity without any runner context.
+    fn get_identity(&self) -> Result<Identity> {
+        self.get_identity_with_runner(None)
+    }
+}
+
+impl<P: IdentityProvider + ?Sized> IdentityProvider for &P {
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        (**self).get_identity_with_runner(runner)
+    }
+}
+
+impl<P: IdentityProvider + ?Sized> IdentityProvider for Box<P> {
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        (**self).get_identity_with_runner(runner)
+    }
+}
+
+/// Provider that queries the whoami daemon through a [`Client`].
+#[derive(Debug, Clone, Default)]
+pub struct DaemonProvider {
+    client: Client,
+}
+
+impl DaemonProvider {
+    /// Create a provider backed by the given client.
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Get the underlying client.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl From<Client> for DaemonProvider {
+    fn from(client: Client) -> Self {
+        Self::new(client)
+    }
+}
+
+impl IdentityProvider for DaemonProvider {
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        self.client.get_identity_with_runner(runner)
+    }
+}
+
+/// Provider that asks the whoami daemon first and falls back to another
+/// provider only when the daemon socket is absent.
+///
+/// Hosts without the daemon (for example cloud VMs in a mixed fleet) use the
+/// fallback, typically a `CloudMetadataProvider` (requires `cloud` feature).
+/// Any other failure, including the daemon rejecting the process, is
+/// returned as is: a daemon that is present stays authoritative.
+#[derive(Debug, Clone)]
+pub struct DualStackProvider<F> {
+    daemon: DaemonProvider,
+    fallback: F,
+}
+
+impl<F: IdentityProvider> DualStackProvider<F> {
+    /// Create a provider querying `client` and falling back to `fallback`.
+    pub fn new(client: Client, fallback: F) -> Self {
+        Self {
+            daemon: DaemonProvider::new(client),
+            fallback,
+        }
+    }
+
+    /// Get the daemon provider.
+    pub fn daemon(&self) -> &DaemonProvider {
+        &self.daemon
+    }
+
+    /// Get the fallback provider.
+    pub fn fallback(&self) -> &F {
+        &self.fallback
+    }
+}
+
+impl<F: IdentityProvider> IdentityProvider for DualStackProvider<F> {
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        match self.daemon.get_identity_with_runner(runner.clone()) {
+            Err(e) if FallbackPolicy::DaemonAbsent.allows(&e) => {
+                self.fallback.get_identity_with_runner(runner)
+            }
+            result => result,
+        }
+    }
+}
+
+/// When a provider in a [`ProviderChain`] hands over to the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FallbackPolicy {
+    /// On any error.
+    #[default]
+    OnError,
+    /// When the daemon was unreachable, timed out or reported being
+    /// degraded; rejections and misconfiguration are returned as is.
+    OnTransient,
+    /// Only when no daemon is listening at the socket, as
+    /// [`DualStackProvider`] does.
+    DaemonAbsent,
+    /// Never: the provider's error ends the chain.
+    Never,
+}
+
+impl FallbackPolicy {
+    /// Whether `error` from a provider with this policy should be handed
+    /// over to the next provider.
+    pub fn allows(&self, error: &GetMyIdError) -> bool {
+        match self {
+            Self::OnError => true,
+            Self::OnTransient => error.is_transient(),
+            Self::DaemonAbsent => is_daemon_absent(error),
+            Self::Never => false,
+        }
+    }
+}
+
+/// Ordered list of providers, each tried in turn until one returns an
+/// identity.
+///
+/// Each provider has a [`FallbackPolicy`] deciding which of its errors are
+/// passed over in favor of the next provider. The error of the last
+/// provider tried is returned when none succeeds.
+///
+/// # Example
+///
+/// ```no_run
+/// use getmyid::{DaemonProvider, EnvProvider, FallbackPolicy, IdentityProvider, ProviderChain};
+///
+/// // An explicit WHOAMI_IDENTITY wins; otherwise ask the daemon
+/// let provider = ProviderChain::new()
+///     .with(EnvProvider::new())
+///     .with_policy(DaemonProvider::default(), FallbackPolicy::Never);
+///
+/// let identity = provider.get_identity()?;
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+#[derive(Default)]
+pub struct ProviderChain {
+    links: Vec<(Box<dyn IdentityProvider + Send + Sync>, FallbackPolicy)>,
+}
+
+impl ProviderChain {
+    /// Create an empty chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `provider`, falling through to the next provider on any error.
+    pub fn with(self, provider: impl IdentityProvider + Send + Sync + 'static) -> Self {
+        self.with_policy(provider, FallbackPolicy::default())
+    }
+
+    /// Append `provider` with the given fallback policy.
+    pub fn with_policy(
+        mut self,
+        provider: impl IdentityProvider + Send + Sync + 'static,
+        policy: FallbackPolicy,
+    ) -> Self {
+        self.push(provider, policy);
+        self
+    }
+
+    /// Append `provider` with the given fallback policy.
+    pub fn push(
+        &mut self,
+        provider: impl IdentityProvider + Send + Sync + 'static,
+        policy: FallbackPolicy,
+    ) {
+        self.links.push((Box::new(provider), policy));
+    }
+
+    /// Get the number of providers in the chain.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Whether the chain has no providers.
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+}
+
+impl fmt::Debug for ProviderChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProviderChain")
+            .field(
+                "policies",
+                &self
+                    .links
+                    .iter()
+                    .map(|(_, policy)| policy)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl IdentityProvider for ProviderChain {
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::NoProvider`] if the chain is empty.
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        let mut last_error = GetMyIdError::NoProvider;
+        for (provider, policy) in &self.links {
+            match provider.get_identity_with_runner(runner.clone()) {
+                Ok(identity) => return Ok(identity),
+                Err(e) if policy.allows(&e) => last_error = e,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Whether `error` means no daemon is listening at the socket, as opposed to
+/// a daemon that failed or refused to answer.
+pub(crate) fn is_daemon_absent(error: &GetMyIdError) -> bool {
+    match error {
+        GetMyIdError::SocketNotFound(_) | GetMyIdError::DanglingSocketLink { .. } => true,
+        GetMyIdError::ConnectionFailed { source, .. } => matches!(
+            source.kind(),
+            io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+        ),
+        GetMyIdError::AllEndpointsFailed { failures } => {
+            failures.iter().all(|(_, e)| is_daemon_absent(e))
+        }
+        _ => false,
+    }
+}
+
+/// Provider that always returns the same identity.
+///
+/// Runner context is merged into the returned `runner` object the same way
+/// the daemon does, which makes this provider suitable for tests and local
+/// development.
+///
+/// # Example
+///
+/// ```
+/// use getmyid::{Identity, IdentityProvider, RunnerRequest, StaticProvider};
+///
+/// let provider = StaticProvider::new(
+///     Identity::new("BILLING_DEV", "dev-token")
+///         .with_idm_url("http://localhost:8443")
+///         .with_config_url("http://localhost:8080"),
+/// );
+///
+/// let identity = provider.get_identity_with_runner(Some(RunnerRequest::new().with_instance_id(7)))?;
+/// assert_eq!(identity.identity, "BILLING_DEV");
+/// assert_eq!(identity.runner.instance_id, Some(7));
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticProvider {
+    identity: Identity,
+}
+
+impl StaticProvider {
+    /// Create a provider returning `identity`.
+    pub fn new(identity: Identity) -> Self {
+        Self { identity }
+    }
+
+    /// Get the configured identity.
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+}
+
+impl IdentityProvider for StaticProvider {
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        let mut identity = self.identity.clone();
+        if let Some(runner) = runner {
+            identity.runner.merge_request(runner);
+        }
+        Ok(identity)
+    }
+}
+
+/// Default prefix for the variables read by [`EnvProvider`].
+pub const DEFAULT_ENV_PREFIX: &str = "WHOAMI_";
+
+/// Provider that reads the identity from environment variables.
+///
+/// With the default `WHOAMI_` prefix the following variables are read:
+///
+/// | Variable | Field | Required |
+/// |----------|-------|----------|
+/// | `WHOAMI_IDENTITY` | `identity` | yes |
+/// | `WHOAMI_IDM_URL` | `idm_url` | no |
+/// | `WHOAMI_CONFIG_URL` | `config_url` | no |
+/// | `WHOAMI_TOKEN` | `token` | no |
+/// | `WHOAMI_HOSTNAME` | `runner.hostname` | no |
+/// | `WHOAMI_EXPIRES_AT` | `expires_at` (Unix seconds) | no |
+///
+/// The remaining runner fields (process name, PID, UID, GID) describe the
+/// current process. Unlike the daemon, nothing here is verified by the
+/// kernel, so this provider is meant for development and for processes that
+/// were started with an already-resolved identity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvProvider {
+    prefix: String,
+}
+
+impl Default for EnvProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnvProvider {
+    /// Create a provider reading `WHOAMI_*` variables.
+    pub fn new() -> Self {
+        Self::with_prefix(DEFAULT_ENV_PREFIX)
+    }
+
+    /// Create a provider reading variables with a custom prefix.
+    pub fn with_prefix(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Get the configured variable prefix.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        env::var(format!("{}{}", self.prefix, name)).ok()
+    }
+}
+
+impl IdentityProvider for EnvProvider {
+    fn get_identity_with_runner(&self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        let identity = self
+            .var("IDENTITY")
+            .ok_or_else(|| GetMyIdError::MissingEnvVar(format!("{}IDENTITY", self.prefix)))?;
+        let (uid, gid) = procfs::uid_gid().unwrap_or_default();
+        let expires_at = match self.var("EXPIRES_AT") {
+            Some(value) => Some(value.parse().map_err(|_| GetMyIdError::InvalidEnvVar {
+                name: format!("{}EXPIRES_AT", self.prefix),
+                value,
+            })?),
+            None => None,
+        };
+
+        let mut result = Identity {
+            identity: identity.clone(),
+            idm_url: self.var("IDM_URL").unwrap_or_default(),
+            config_url: self.var("CONFIG_URL").unwrap_or_default(),
+            token: self.var("TOKEN").unwrap_or_default().into(),
+            expires_at,
+            bootstrap: None,
+            runner: Runner {
+                identity,
+                hostname: self
+                    .var("HOSTNAME")
+                    .or_else(procfs::hostname)
+                    .unwrap_or_default(),
+                process: procfs::process_name().unwrap_or_default(),
+                pid: std::process::id(),
+                uid,
+                gid,
+                ..Runner::default()
+            },
+        };
+        if let Some(runner) = runner {
+            result.runner.merge_request(runner);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DaemonErrorCode;
+
+    fn sample_identity() -> Identity {
+        Identity {
+            identity: "BILLING_DEV".to_string(),
+            idm_url: "http://localhost:8443".to_string(),
+            config_url: "http://localhost:8080".to_string(),
+            token: "dev-token".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner {
+                identity: "BILLING_DEV".to_string(),
+                pid: 1,
+                ..Runner::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_static_provider_merges_runner() {
+        let provider = StaticProvider::new(sample_identity());
+        let req = RunnerRequest::new()
+            .with_instance_id(3)
+            .with_field("region", "eu-west-1");
+
+        let identity = provider.get_identity_with_runner(Some(req)).unwrap();
+
+        assert_eq!(identity.runner.instance_id, Some(3));
+        assert_eq!(identity.runner.extra.get("region").unwrap(), "eu-west-1");
+        assert_eq!(identity.runner.pid, 1);
+        assert_eq!(provider.get_identity().unwrap(), sample_identity());
+    }
+
+    #[test]
+    fn test_env_provider() {
+        let provider = EnvProvider::with_prefix("GETMYID_TEST_PROVIDER_");
+        assert!(matches!(
+            provider.get_identity(),
+            Err(GetMyIdError::MissingEnvVar(var)) if var == "GETMYID_TEST_PROVIDER_IDENTITY"
+        ));
+
+        env::set_var("GETMYID_TEST_PROVIDER_IDENTITY", "LOCAL_DEV");
+        env::set_var("GETMYID_TEST_PROVIDER_TOKEN", "tok_dev");
+        env::set_var("GETMYID_TEST_PROVIDER_EXPIRES_AT", "1900000000");
+        let identity = provider.get_identity().unwrap();
+
+        assert_eq!(identity.identity, "LOCAL_DEV");
+        assert_eq!(identity.token, "tok_dev");
+        assert_eq!(identity.expires_at, Some(1900000000));
+        assert_eq!(identity.runner.identity, "LOCAL_DEV");
+        assert_eq!(identity.runner.pid, std::process::id());
+    }
+
+    #[test]
+    fn test_dual_stack_falls_back_when_socket_absent() {
+        let provider = DualStackProvider::new(
+            Client::builder()
+                .socket_path("/nonexistent/whoami.sock")
+                .build(),
+            StaticProvider::new(sample_identity()),
+        );
+
+        let identity = provider
+            .get_identity_with_runner(Some(RunnerRequest::new().with_instance_id(5)))
+            .unwrap();
+        assert_eq!(identity.identity, "BILLING_DEV");
+        assert_eq!(identity.runner.instance_id, Some(5));
+
+        assert!(!is_daemon_absent(&GetMyIdError::DaemonError {
+            code: DaemonErrorCode::NoMatch,
+            message: String::new(),
+        }));
+    }
+
+    #[test]
+    fn test_provider_chain_policies() {
+        let absent = || {
+            DaemonProvider::new(
+                Client::builder()
+                    .socket_path("/nonexistent/whoami.sock")
+                    .build(),
+            )
+        };
+
+        let chain = ProviderChain::new()
+            .with_policy(absent(), FallbackPolicy::DaemonAbsent)
+            .with(StaticProvider::new(sample_identity()));
+        assert_eq!(chain.len(), 2);
+        assert_eq!(
+            chain
+                .get_identity_with_runner(Some(RunnerRequest::new().with_instance_id(5)))
+                .unwrap()
+                .runner
+                .instance_id,
+            Some(5)
+        );
+
+        let chain = ProviderChain::new()
+            .with_policy(absent(), FallbackPolicy::Never)
+            .with(StaticProvider::new(sample_identity()));
+        assert!(matches!(
+            chain.get_identity(),
+            Err(GetMyIdError::SocketNotFound(_))
+        ));
+
+        let chain = ProviderChain::new()
+            .with(EnvProvider::with_prefix("GETMYID_TEST_CHAIN_"))
+            .with(absent());
+        assert!(matches!(
+            chain.get_identity(),
+            Err(GetMyIdError::SocketNotFound(_))
+        ));
+
+        assert!(matches!(
+            ProviderChain::new().get_identity(),
+            Err(GetMyIdError::NoProvider)
+        ));
+    }
+
+    #[test]
+    fn test_fallback_policy_allows() {
+        let rejected = GetMyIdError::DaemonError {
+            code: DaemonErrorCode::NoMatch,
+            message: String::new(),
+        };
+        let timeout = GetMyIdError::Timeout(std::time::Duration::from_secs(1));
+
+        assert!(FallbackPolicy::OnError.allows(&rejected));
+        assert!(!FallbackPolicy::OnTransient.allows(&rejected));
+        assert!(FallbackPolicy::OnTransient.allows(&timeout));
+        assert!(!FallbackPolicy::DaemonAbsent.allows(&timeout));
+        assert!(!FallbackPolicy::Never.allows(&timeout));
+    }
+
+    #[test]
+    fn test_provider_trait_objects() {
+        let providers: Vec<Box<dyn IdentityProvider>> = vec![
+            Box::new(StaticProvider::new(sample_identity())),
+            Box::new(DaemonProvider::new(
+                Client::builder()
+                    .socket_path("/nonexistent/whoami.sock")
+                    .build(),
+            )),
+        ];
+
+        assert!(providers[0].get_identity().is_ok());
+        assert!(matches!(
+            providers[1].get_identity(),
+            Err(GetMyIdError::SocketNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,476 @@
+//! Retrying transient failures and caching resolved identities.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::GetMyIdError;
+use crate::types::{Identity, RunnerRequest};
+
+/// Default delay before the first retry.
+pub(crate) const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest `retry_after` hint from a degraded daemon that is honoured.
+pub(crate) const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// How often and how patiently a request is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: DEFAULT_RETRY_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after the failed attempt number `attempt`
+    /// (starting at 0), or `None` if `error` should be returned.
+    ///
+    /// The backoff doubles after every attempt; a degraded daemon's
+    /// `retry_after` hint is used instead when present, capped at
+    /// [`MAX_RETRY_AFTER`] so a misbehaving daemon cannot stall the caller.
+    pub fn delay(&self, attempt: u32, error: &GetMyIdError) -> Option<Duration> {
+        if attempt >= self.retries || !error.is_transient() {
+            return None;
+        }
+        if let GetMyIdError::DaemonDegraded {
+            retry_after: Some(retry_after),
+            ..
+        } = error
+        {
+            return Some((*retry_after).min(MAX_RETRY_AFTER));
+        }
+        Some(self.backoff.saturating_mul(1 << attempt.min(16)))
+    }
+}
+
+/// Default maximum number of cached identities.
+pub(crate) const DEFAULT_CACHE_MAX_ENTRIES: usize = 1024;
+
+/// Bounds on the memory used by an [`IdentityCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CacheLimits {
+    pub max_entries: usize,
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: DEFAULT_CACHE_MAX_ENTRIES,
+            max_bytes: None,
+        }
+    }
+}
+
+/// The last failure to reach the daemon, returned again without contacting
+/// it until the TTL has passed.
+#[derive(Debug)]
+pub(crate) struct NegativeCache {
+    ttl: Duration,
+    failure: Mutex<Option<(Instant, GetMyIdError)>>,
+}
+
+impl NegativeCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            failure: Mutex::new(None),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Get a copy of the failure recorded less than the TTL ago, if any.
+    pub fn get(&self) -> Option<GetMyIdError> {
+        let failure = self.failure.lock().unwrap_or_else(|e| e.into_inner());
+        match &*failure {
+            Some((at, error)) if at.elapsed() < self.ttl => error.unreachable_copy(),
+            _ => None,
+        }
+    }
+
+    /// Remember `error` if it shows the daemon could not be reached.
+    pub fn record(&self, error: &GetMyIdError) {
+        if let Some(error) = error.unreachable_copy() {
+            *self.failure.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), error));
+        }
+    }
+}
+
+/// Identities resolved recently, keyed by the runner context sent.
+///
+/// Once a limit is reached the least recently used identities are evicted.
+/// Identities with an expired token are dropped when looked up, and all
+/// expired identities at most once per TTL when a new identity is inserted;
+/// until then identities that only outlived the TTL remain available to
+/// [`get_stale`](IdentityCache::get_stale).
+#[derive(Debug)]
+pub(crate) struct IdentityCache {
+    ttl: Duration,
+    limits: CacheLimits,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    /// Keys by last use, least recent first.
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+    last_sweep: Instant,
+}
+
+#[derive(Debug)]
+struct Entry {
+    inserted: Instant,
+    used: u64,
+    size: usize,
+    identity: Identity,
+}
+
+impl Entry {
+    /// Whether the entry outlived the cache TTL or its token expired.
+    fn expired(&self, ttl: Duration) -> bool {
+        self.inserted.elapsed() >= ttl || self.identity.is_expired()
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.map.remove(key) {
+            self.lru.remove(&entry.used);
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl IdentityCache {
+    pub fn new(ttl: Duration, limits: CacheLimits) -> Self {
+        Self {
+            ttl,
+            limits,
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                lru: BTreeMap::new(),
+                tick: 0,
+                bytes: 0,
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Cache key for a runner context; extras are serialized with sorted keys.
+    pub fn key(runner: Option<&RunnerRequest>) -> String {
+        runner
+            .and_then(|runner| serde_json::to_value(runner).ok())
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Get an identity cached less than the TTL ago whose token has not
+    /// expired, marking it as recently used.
+    pub fn get(&self, key: &str) -> Option<Identity> {
+        let mut entries = self.lock();
+        let entry = entries.map.get(key)?;
+        if entry.identity.is_expired() {
+            entries.remove(key);
+            return None;
+        }
+        if entry.expired(self.ttl) {
+            return None;
+        }
+
+        let tick = entries.next_tick();
+        let entry = entries.map.get_mut(key)?;
+        let previous = std::mem::replace(&mut entry.used, tick);
+        let identity = entry.identity.clone();
+        entries.lru.remove(&previous);
+        entries.lru.insert(tick, key.to_string());
+        Some(identity)
+    }
+
+    /// Get an identity that may have outlived the TTL but was not dropped
+    /// yet and whose token has not expired.
+    pub fn get_stale(&self, key: &str) -> Option<Identity> {
+        let entries = self.lock();
+        let entry = entries.map.get(key)?;
+        (!entry.identity.is_expired()).then(|| entry.identity.clone())
+    }
+
+    /// Cache `identity`, evicting the least recently used identities to stay
+    /// within the limits. Identities larger than `max_bytes` are not cached.
+    pub fn insert(&self, key: String, identity: Identity) {
+        let size = key.len() + serialized_len(&identity);
+        if self.limits.max_entries == 0 || self.limits.max_bytes.is_some_and(|max| size > max) {
+            return;
+        }
+
+        let mut entries = self.lock();
+        entries.remove(&key);
+        if entries.last_sweep.elapsed() >= self.ttl {
+            self.sweep(&mut entries);
+        }
+        while entries.map.len() >= self.limits.max_entries
+            || self
+                .limits
+                .max_bytes
+                .is_some_and(|max| entries.bytes + size > max)
+        {
+            let Some((_, oldest)) = entries.lru.pop_first() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+
+        let used = entries.next_tick();
+        entries.lru.insert(used, key.clone());
+        entries.bytes += size;
+        entries.map.insert(
+            key,
+            Entry {
+                inserted: Instant::now(),
+                used,
+                size,
+                identity,
+            },
+        );
+    }
+
+    /// Drop every expired identity.
+    fn sweep(&self, entries: &mut Entries) {
+        let expired: Vec<String> = entries
+            .map
+            .iter()
+            .filter(|(_, entry)| entry.expired(self.ttl))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            entries.remove(&key);
+        }
+        entries.last_sweep = Instant::now();
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.lock().map.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Length of `identity` serialized as JSON, counted without buffering a
+/// copy of its token.
+fn serialized_len(identity: &Identity) -> usize {
+    struct Counter(usize);
+
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    match serde_json::to_writer(&mut counter, identity) {
+        Ok(()) => counter.0,
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DaemonErrorCode;
+    use crate::types::Runner;
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_millis(10),
+        };
+        let timeout = GetMyIdError::Timeout(Duration::from_secs(1));
+        let rejected = GetMyIdError::DaemonError {
+            code: DaemonErrorCode::NoMatch,
+            message: String::new(),
+        };
+        let degraded = GetMyIdError::DaemonDegraded {
+            message: String::new(),
+            code: None,
+            retry_after: Some(Duration::from_millis(250)),
+        };
+
+        assert_eq!(policy.delay(0, &timeout), Some(Duration::from_millis(10)));
+        assert_eq!(policy.delay(1, &timeout), Some(Duration::from_millis(20)));
+        assert_eq!(policy.delay(2, &timeout), None);
+        assert_eq!(policy.delay(0, &rejected), None);
+        assert_eq!(policy.delay(0, &degraded), Some(Duration::from_millis(250)));
+        let stalling = GetMyIdError::DaemonDegraded {
+            message: String::new(),
+            code: None,
+            retry_after: Some(Duration::from_millis(u64::MAX)),
+        };
+        assert_eq!(policy.delay(0, &stalling), Some(MAX_RETRY_AFTER));
+    }
+
+    #[test]
+    fn test_identity_cache() {
+        let identity = Identity {
+            identity: "BILLING_PROD".to_string(),
+            idm_url: String::new(),
+            config_url: String::new(),
+            token: Default::default(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner::default(),
+        };
+        let cache = IdentityCache::new(Duration::from_millis(50), CacheLimits::default());
+        let request = RunnerRequest::new().with_field("a", 1).with_field("b", 2);
+        let key = IdentityCache::key(Some(&request));
+
+        cache.insert(key.clone(), identity.clone());
+        assert_eq!(cache.get(&key), Some(identity.clone()));
+        assert_eq!(cache.get(&IdentityCache::key(None)), None);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(&key), None);
+        assert_eq!(cache.get_stale(&key), Some(identity));
+    }
+
+    fn identity(name: &str) -> Identity {
+        Identity {
+            identity: name.to_string(),
+            idm_url: String::new(),
+            config_url: String::new(),
+            token: Default::default(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner::default(),
+        }
+    }
+
+    #[test]
+    fn test_identity_cache_skips_expired_tokens() {
+        let cache = IdentityCache::new(Duration::from_secs(60), CacheLimits::default());
+        let mut expired = identity("A");
+        expired.expires_at = Some(1_000_000_000);
+
+        cache.insert("a".to_string(), expired);
+        assert_eq!(cache.get_stale("a"), None);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_identity_cache_far_future_expiry() {
+        let cache = IdentityCache::new(Duration::from_secs(60), CacheLimits::default());
+        let response = r#"{"status":"ok","identity":"A","idm_url":"","config_url":"","token":"t","ttl":18446744073709551615,"runner":{}}"#;
+        let identity = crate::protocol::parse_response(response).unwrap();
+        assert_eq!(identity.expires_at, Some(u64::MAX));
+
+        cache.insert("a".to_string(), identity.clone());
+        assert_eq!(cache.get("a"), Some(identity));
+    }
+
+    #[test]
+    fn test_identity_cache_lru_eviction() {
+        let limits = CacheLimits {
+            max_entries: 2,
+            max_bytes: None,
+        };
+        let cache = IdentityCache::new(Duration::from_secs(60), limits);
+
+        cache.insert("a".to_string(), identity("A"));
+        cache.insert("b".to_string(), identity("B"));
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), identity("C"));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a").is_some());
+        assert_eq!(cache.get("b"), None);
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn test_identity_cache_max_bytes() {
+        let size = 1 + serde_json::to_vec(&identity("A")).unwrap().len();
+        let limits = CacheLimits {
+            max_entries: usize::MAX,
+            max_bytes: Some(size * 2),
+        };
+        let cache = IdentityCache::new(Duration::from_secs(60), limits);
+
+        for key in ["a", "b", "c"] {
+            cache.insert(key.to_string(), identity("A"));
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("a"), None);
+
+        cache.insert("big".to_string(), identity(&"X".repeat(size * 2)));
+        assert_eq!(cache.get("big"), None);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_identity_cache_sweeps_expired() {
+        let cache = IdentityCache::new(Duration::from_millis(20), CacheLimits::default());
+        cache.insert("a".to_string(), identity("A"));
+        cache.insert("b".to_string(), identity("B"));
+
+        std::thread::sleep(Duration::from_millis(30));
+        cache.insert("c".to_string(), identity("C"));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_negative_cache() {
+        let cache = NegativeCache::new(Duration::from_millis(20));
+        cache.record(&GetMyIdError::DaemonError {
+            code: DaemonErrorCode::NoMatch,
+            message: String::new(),
+        });
+        assert!(cache.get().is_none());
+
+        cache.record(&GetMyIdError::ConnectionFailed {
+            path: "/run/whoami.sock".into(),
+            resolved: None,
+            source: std::io::ErrorKind::ConnectionRefused.into(),
+        });
+        match cache.get() {
+            Some(GetMyIdError::ConnectionFailed { path, source, .. }) => {
+                assert_eq!(path, std::path::Path::new("/run/whoami.sock"));
+                assert_eq!(source.kind(), std::io::ErrorKind::ConnectionRefused);
+            }
+            other => panic!("unexpected cached failure: {other:?}"),
+        }
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(cache.get().is_none());
+    }
+}
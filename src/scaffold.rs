@@ -0,0 +1,354 @@
+//! The recommended startup and shutdown flow of an identity-gated service,
+//! packaged as an [`IdentityRuntime`] (requires `tokio` feature).
+//!
+//! Starting the runtime waits for the daemon to come up, resolves the
+//! identity with the service's runner context and keeps refreshing it in
+//! the background. The service then fetches its configuration (with the
+//! `http` feature) and runs its worker until a shutdown signal, which the
+//! worker observes through [`Shutdown`] to finish its work in progress.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use getmyid::scaffold::IdentityRuntime;
+//! use getmyid::RunnerRequest;
+//!
+//! # async fn example() -> Result<(), getmyid::GetMyIdError> {
+//! let runtime = IdentityRuntime::builder()
+//!     .runner(RunnerRequest::new().with_region("eu-west-1"))
+//!     .start()
+//!     .await?;
+//! let identity = &runtime;
+//! runtime
+//!     .run(wait_for_sigterm(), |shutdown| async move {
+//!         while !shutdown.is_requested() {
+//!             handle_next_job(&identity.identity().token).await;
+//!         }
+//!     })
+//!     .await;
+//! # Ok(())
+//! # }
+//! # async fn wait_for_sigterm() {}
+//! # async fn handle_next_job(_: &str) {}
+//! ```
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+use crate::async_client::AsyncClient;
+use crate::error::Result;
+use crate::managed::ManagedIdentity;
+use crate::types::{Identity, RunnerRequest};
+
+/// Default time [`IdentityRuntimeBuilder::start`] waits for the daemon.
+pub const DEFAULT_DAEMON_WAIT: Duration = Duration::from_secs(30);
+
+/// Default interval at which an [`IdentityRuntime`] refreshes the identity.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// First and longest pause between attempts to reach the daemon at startup.
+const WAIT_BACKOFF: (Duration, Duration) = (Duration::from_millis(100), Duration::from_secs(2));
+
+/// Builder for an [`IdentityRuntime`].
+#[derive(Debug, Clone)]
+pub struct IdentityRuntimeBuilder {
+    client: Option<AsyncClient>,
+    runner: Option<RunnerRequest>,
+    refresh_interval: Duration,
+    daemon_wait: Duration,
+    #[cfg(feature = "http")]
+    config_fetch: crate::http::ConfigFetch,
+}
+
+impl Default for IdentityRuntimeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdentityRuntimeBuilder {
+    /// Create a builder with the default settings.
+    pub fn new() -> Self {
+        Self {
+            client: None,
+            runner: None,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            daemon_wait: DEFAULT_DAEMON_WAIT,
+            #[cfg(feature = "http")]
+            config_fetch: crate::http::ConfigFetch::new(),
+        }
+    }
+
+    /// Set the client to ask the daemon with.
+    ///
+    /// Defaults to [`AsyncClient::from_env`], configured by the `GETMYID_*`
+    /// environment variables.
+    pub fn client(mut self, client: AsyncClient) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Set the runner context sent with every lookup.
+    pub fn runner(mut self, runner: RunnerRequest) -> Self {
+        self.runner = Some(runner);
+        self
+    }
+
+    /// Set how often the identity is refreshed in the background.
+    ///
+    /// Defaults to [`DEFAULT_REFRESH_INTERVAL`].
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Set how long [`start`](IdentityRuntimeBuilder::start) keeps trying
+    /// while the daemon is unreachable or degraded, e.g. because it starts
+    /// alongside the service.
+    ///
+    /// Defaults to [`DEFAULT_DAEMON_WAIT`].
+    pub fn wait_for_daemon(mut self, timeout: Duration) -> Self {
+        self.daemon_wait = timeout;
+        self
+    }
+
+    /// Set the settings [`IdentityRuntime::fetch_config`] fetches with
+    /// (requires `http` feature).
+    #[cfg(feature = "http")]
+    pub fn config_fetch(mut self, fetch: crate::http::ConfigFetch) -> Self {
+        self.config_fetch = fetch;
+        self
+    }
+
+    /// Wait for the daemon, resolve the identity and start refreshing it on
+    /// the current Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last error if the daemon could not be reached within
+    /// the wait, and other errors, e.g. no rule matching the process,
+    /// immediately.
+    pub async fn start(self) -> Result<IdentityRuntime> {
+        let client = match self.client {
+            Some(client) => client,
+            None => AsyncClient::from_env()?,
+        };
+        wait_for_daemon(&client, self.runner.as_ref(), self.daemon_wait).await?;
+        let identity =
+            ManagedIdentity::start_with_runner(client, self.refresh_interval, self.runner).await?;
+        let (shutdown, _) = watch::channel(false);
+        Ok(IdentityRuntime {
+            identity: Arc::new(identity),
+            shutdown: Arc::new(shutdown),
+            #[cfg(feature = "http")]
+            config_fetch: self.config_fetch,
+        })
+    }
+}
+
+/// Retry the first lookup while it fails transiently, for up to `wait`.
+async fn wait_for_daemon(
+    client: &AsyncClient,
+    runner: Option<&RunnerRequest>,
+    wait: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + wait;
+    let mut backoff = WAIT_BACKOFF.0;
+    loop {
+        match client.get_identity_with_runner(runner.cloned()).await {
+            Ok(_) => return Ok(()),
+            Err(e) if !e.is_transient() || Instant::now() + backoff > deadline => return Err(e),
+            Err(_) => {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(WAIT_BACKOFF.1);
+            }
+        }
+    }
+}
+
+/// A service's identity, kept fresh in the background, and its shutdown
+/// signal.
+///
+/// Created with [`IdentityRuntime::builder`]. Refreshing stops when the
+/// runtime and the handles from
+/// [`managed_identity`](IdentityRuntime::managed_identity) are dropped.
+#[derive(Debug)]
+pub struct IdentityRuntime {
+    identity: Arc<ManagedIdentity>,
+    shutdown: Arc<watch::Sender<bool>>,
+    #[cfg(feature = "http")]
+    config_fetch: crate::http::ConfigFetch,
+}
+
+impl IdentityRuntime {
+    /// Create a builder for a runtime.
+    pub fn builder() -> IdentityRuntimeBuilder {
+        IdentityRuntimeBuilder::new()
+    }
+
+    /// Get the latest identity.
+    pub fn identity(&self) -> Arc<Identity> {
+        self.identity.current()
+    }
+
+    /// Get the background-refreshed identity, e.g. to plug into the
+    /// framework integrations.
+    pub fn managed_identity(&self) -> Arc<ManagedIdentity> {
+        Arc::clone(&self.identity)
+    }
+
+    /// Fetch and deserialize the configuration from the latest identity's
+    /// `config_url` (requires `http` feature).
+    ///
+    /// # Errors
+    ///
+    /// See [`ConfigFetch::fetch`](crate::ConfigFetch::fetch).
+    #[cfg(feature = "http")]
+    pub async fn fetch_config<T>(&self) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned + Send + 'static,
+    {
+        self.config_fetch.fetch_async(&self.identity()).await
+    }
+
+    /// Get the shutdown signal, e.g. for tasks spawned outside
+    /// [`run`](IdentityRuntime::run).
+    pub fn shutdown(&self) -> Shutdown {
+        Shutdown(self.shutdown.subscribe())
+    }
+
+    /// Ask every [`Shutdown`] holder to finish its work.
+    pub fn request_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Run `worker` until it returns, requesting shutdown once `signal`
+    /// completes, e.g. on SIGTERM.
+    ///
+    /// The worker is not cancelled: it is expected to watch the
+    /// [`Shutdown`] it is given and return after finishing its work in
+    /// progress.
+    pub async fn run<S, W, F>(&self, signal: S, worker: W) -> F::Output
+    where
+        S: Future<Output = ()> + Send + 'static,
+        W: FnOnce(Shutdown) -> F,
+        F: Future,
+    {
+        let shutdown = Arc::clone(&self.shutdown);
+        let signal = tokio::spawn(async move {
+            signal.await;
+            shutdown.send_replace(true);
+        });
+        let output = worker(self.shutdown()).await;
+        signal.abort();
+        output
+    }
+}
+
+/// Signal asking a worker to finish its work and return, given to the
+/// worker by [`IdentityRuntime::run`].
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Check if shutdown was requested.
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait until shutdown is requested.
+    pub async fn requested(&self) {
+        let mut requested = self.0.clone();
+        // The runtime holds the sender for as long as workers run
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeDaemon, FakeResponse, Scenario};
+    use crate::GetMyIdError;
+
+    #[tokio::test]
+    async fn test_runtime_waits_for_daemon() {
+        let daemon = FakeDaemon::start().unwrap();
+        let starting = r#"{"status":"degraded","message":"starting"}"#;
+        daemon.play(
+            Scenario::new()
+                .then(FakeResponse::Raw(starting.to_string()), 2)
+                .then(FakeResponse::default(), 1),
+        );
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let runtime = IdentityRuntime::builder()
+            .client(client)
+            .runner(RunnerRequest::new().with_instance_id(3))
+            .start()
+            .await
+            .unwrap();
+
+        assert_eq!(runtime.identity().runner.instance_id, Some(3));
+        let requests = daemon.requests();
+        assert!(requests.len() >= 3);
+        assert!(requests
+            .iter()
+            .all(|request| request.runner().unwrap().instance_id == Some(3)));
+    }
+
+    #[tokio::test]
+    async fn test_runtime_start_failures() {
+        let daemon = FakeDaemon::with_response(FakeResponse::no_match()).unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let started = Instant::now();
+        let result = IdentityRuntime::builder().client(client).start().await;
+        assert!(matches!(result, Err(GetMyIdError::DaemonError { .. })));
+        assert!(started.elapsed() < DEFAULT_DAEMON_WAIT);
+
+        let client = AsyncClient::builder()
+            .socket_path("/nonexistent/whoami.sock")
+            .build();
+        let result = IdentityRuntime::builder()
+            .client(client)
+            .wait_for_daemon(Duration::from_millis(300))
+            .start()
+            .await;
+        assert!(result.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_runtime_run_until_shutdown() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let runtime = IdentityRuntime::builder()
+            .client(client)
+            .start()
+            .await
+            .unwrap();
+
+        let identity = &runtime;
+        let signal = tokio::time::sleep(Duration::from_millis(50));
+        let jobs = runtime
+            .run(signal, |shutdown| async move {
+                let mut jobs = 0;
+                while !shutdown.is_requested() {
+                    assert_eq!(identity.identity().token, "tok_test");
+                    jobs += 1;
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                shutdown.requested().await;
+                jobs
+            })
+            .await;
+        assert!(jobs >= 1);
+        assert!(runtime.shutdown().is_requested());
+    }
+}
@@ -0,0 +1,83 @@
+//! Identities with the token held by the `secrecy` crate (requires `secrecy`
+//! feature).
+
+use secrecy::SecretString;
+
+use crate::types::{BootstrapPayload, Identity, Runner};
+
+/// An [`Identity`] whose token is a [`SecretString`].
+///
+/// The token is moved into the secret without being copied, so it is
+/// zeroized when the `SecretIdentity` is dropped, never shows up in `Debug`
+/// output, and can only be read through
+/// [`ExposeSecret`](secrecy::ExposeSecret).
+///
+/// # Example
+///
+/// ```no_run
+/// use getmyid::SecretIdentity;
+/// use secrecy::ExposeSecret;
+///
+/// let identity = SecretIdentity::from(getmyid::get_identity()?);
+/// println!("{:?}", identity); // token: Secret([REDACTED ...])
+/// let header = format!("Bearer {}", identity.token.expose_secret());
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SecretIdentity {
+    /// Application-level identity name.
+    pub identity: String,
+    /// Identity Management (OAuth2/OIDC) URL.
+    pub idm_url: String,
+    /// Configuration/API server URL.
+    pub config_url: String,
+    /// Authentication token.
+    pub token: SecretString,
+    /// Token expiry in seconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+    /// Configuration payload inlined by the daemon, if any.
+    pub bootstrap: Option<BootstrapPayload>,
+    /// Runner information.
+    pub runner: Runner,
+}
+
+impl From<Identity> for SecretIdentity {
+    fn from(identity: Identity) -> Self {
+        Self {
+            identity: identity.identity,
+            idm_url: identity.idm_url,
+            config_url: identity.config_url,
+            token: SecretString::new(identity.token.into_string()),
+            expires_at: identity.expires_at,
+            bootstrap: identity.bootstrap,
+            runner: identity.runner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn test_secret_identity() {
+        let identity = Identity {
+            identity: "BILLING_PROD".to_string(),
+            idm_url: "https://idm.example.com".to_string(),
+            config_url: String::new(),
+            token: "tok_secret".into(),
+            expires_at: Some(1_700_000_000),
+            bootstrap: None,
+            runner: Runner::default(),
+        };
+
+        let secret = SecretIdentity::from(identity.clone());
+        assert_eq!(secret.identity, identity.identity);
+        assert_eq!(secret.expires_at, identity.expires_at);
+        assert_eq!(secret.token.expose_secret(), "tok_secret");
+        assert!(!format!("{secret:?}").contains("tok_secret"));
+        assert_eq!(identity.token_secret().expose_secret(), "tok_secret");
+    }
+}
@@ -0,0 +1,128 @@
+//! Strings holding credentials, wiped from memory on drop with the
+//! `zeroize` feature.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+use crate::support::redact_token;
+
+/// A string holding a credential: a token, or a daemon response carrying
+/// one.
+///
+/// Dereferences to `str` and displays as its contents, like the `String`
+/// it wraps; `Debug` output is redacted. With the `zeroize` feature the
+/// contents are wiped from memory when the value is dropped.
+///
+/// `SensitiveString` implements `Drop` whichever features are enabled, so
+/// the structs holding one, such as [`Identity`](crate::Identity), move the
+/// same way in every build. Take the contents with
+/// [`into_string`](Self::into_string).
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SensitiveString(String);
+
+impl SensitiveString {
+    /// Wrap `value`, taking ownership without copying it.
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Get the contents.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Take the contents, leaving nothing to wipe.
+    pub fn into_string(mut self) -> String {
+        std::mem::take(&mut self.0)
+    }
+}
+
+impl Drop for SensitiveString {
+    fn drop(&mut self) {
+        #[cfg(feature = "zeroize")]
+        zeroize::Zeroize::zeroize(&mut self.0);
+    }
+}
+
+impl Deref for SensitiveString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for SensitiveString {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for SensitiveString {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SensitiveString {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for SensitiveString {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl PartialEq<str> for SensitiveString {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for SensitiveString {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for SensitiveString {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+impl fmt::Display for SensitiveString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for SensitiveString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(redact_token(&self.0), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensitive_string() {
+        let token = SensitiveString::from("tok_secret");
+        assert_eq!(token, "tok_secret");
+        assert_eq!(token.len(), 10);
+        assert_eq!(token.to_string(), "tok_secret");
+        assert_eq!(format!("{token:?}"), "\"[redacted]\"");
+        assert_eq!(format!("{:?}", SensitiveString::default()), "\"\"");
+        assert_eq!(serde_json::to_string(&token).unwrap(), "\"tok_secret\"");
+        assert_eq!(token.into_string(), "tok_secret");
+    }
+}
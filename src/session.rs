@@ -0,0 +1,251 @@
+//! Persistent connections carrying several exchanges with the daemon.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::client::{read_limit, response_text, Client};
+use crate::error::{GetMyIdError, Result};
+use crate::protocol::{check_nonce, is_goaway};
+use crate::transport::Connection;
+use crate::types::{Identity, RunnerRequest};
+
+/// A connection to the daemon kept open across identity lookups, opened
+/// with [`Client::connect`].
+///
+/// Requests and responses are framed as single lines of JSON, so each
+/// lookup after the first saves connecting to the socket. Daemons without
+/// framing support answer the first request and close the connection; the
+/// session then connects anew for every lookup, like the client.
+///
+/// If the daemon closed an idle connection, or answered with
+/// [`STATUS_GOAWAY`](crate::protocol::STATUS_GOAWAY) while handing its
+/// socket over to a new process, the lookup reconnects once, resolving the
+/// socket path anew. The cache, retries and failover daemons do not apply; the client's
+/// timeouts, limits and parsing options do.
+///
+/// # Example
+///
+/// ```no_run
+/// use getmyid::Client;
+///
+/// let client = Client::new();
+/// let mut session = client.connect()?;
+/// for _ in 0..3 {
+///     println!("Token: {}", session.get_identity()?.token);
+/// }
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+pub struct Session {
+    client: Client,
+    stream: Option<BufReader<Box<dyn Connection>>>,
+}
+
+impl Session {
+    /// Open a session for `client`.
+    pub(crate) fn open(client: Client) -> Result<Self> {
+        let stream = BufReader::new(client.dial()?);
+        Ok(Self {
+            client,
+            stream: Some(stream),
+        })
+    }
+
+    /// Get the identity of the current process.
+    pub fn get_identity(&mut self) -> Result<Identity> {
+        self.get_identity_with_runner(None)
+    }
+
+    /// Get the identity, sending `runner` as context.
+    ///
+    /// The client's default runner is merged in and extras keys are
+    /// normalized as for [`Client::get_identity_with_runner`].
+    pub fn get_identity_with_runner(&mut self, runner: Option<RunnerRequest>) -> Result<Identity> {
+        let runner = self.client.prepare_runner(runner);
+        let mut request = self.client.new_request(runner.clone());
+        request.keep_alive = true;
+        let mut line = request.to_json()?.into_bytes();
+        line.push(b'\n');
+
+        let response = self.round_trip(&line)?;
+        check_nonce(request.nonce.as_deref(), &response)?;
+        self.client
+            .identity_from_response(&response, runner.as_ref())
+    }
+
+    /// Whether the connection is still open, i.e. the daemon keeps framed
+    /// connections alive.
+    pub fn is_open(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Send `request` and read the response, reconnecting once if the
+    /// daemon closed the idle connection or is going away.
+    fn round_trip(&mut self, request: &[u8]) -> Result<String> {
+        if let Some(stream) = self.stream.take() {
+            match self.send(stream, request)? {
+                Some(response) if !is_goaway(&response) => return Ok(response),
+                _ => {}
+            }
+        }
+        let stream = BufReader::new(self.client.dial()?);
+        Ok(self.send(stream, request)?.unwrap_or_default())
+    }
+
+    /// Exchange `request` over `stream`, keeping the stream if the daemon
+    /// framed its response and is not going away. Returns `None` if the
+    /// connection was closed before the request could be answered.
+    fn send(
+        &mut self,
+        mut stream: BufReader<Box<dyn Connection>>,
+        request: &[u8],
+    ) -> Result<Option<String>> {
+        let connection = stream.get_mut();
+        if let Err(e) = connection
+            .write_all(request)
+            .and_then(|()| connection.flush())
+        {
+            return match e.kind() {
+                io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset => Ok(None),
+                _ => Err(GetMyIdError::WriteError(e)),
+            };
+        }
+
+        let max = self.client.max_response_bytes();
+        let mut response = Vec::new();
+        match Read::take(&mut stream, read_limit(max)).read_until(b'\n', &mut response) {
+            Ok(_) => {}
+            // The daemon closed the connection with the request unread
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset && response.is_empty() => {}
+            Err(e) => return Err(GetMyIdError::ReadError(e)),
+        }
+        if response.is_empty() {
+            return Ok(None);
+        }
+        let framed = response.last() == Some(&b'\n');
+        if framed {
+            response.pop();
+        }
+        let mut response = response_text(response, max)?;
+        self.client.open_response(&mut response)?;
+        if framed && !is_goaway(&response) {
+            self.stream = Some(stream);
+        }
+        Ok(Some(response))
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("client", &self.client)
+            .field("open", &self.is_open())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    use crate::protocol::DaemonResponse;
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_session_keeps_connection_open() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let mut session = client.connect().unwrap();
+
+        assert_eq!(session.get_identity().unwrap().token, "tok_test");
+        let runner = RunnerRequest::new().with_instance_id(7);
+        let identity = session.get_identity_with_runner(Some(runner)).unwrap();
+        assert_eq!(identity.runner.instance_id, Some(7));
+        assert!(session.is_open());
+
+        let requests = daemon.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].json().unwrap()["keep_alive"], true);
+        assert_eq!(requests[1].runner().unwrap().instance_id, Some(7));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_session_reconnects_on_goaway() {
+        use crate::testing::{FakeDaemon, FakeResponse};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // The second request is answered with goaway
+        let served = AtomicUsize::new(0);
+        let daemon = FakeDaemon::with_handler(move |_| {
+            let goaway = served.fetch_add(1, Ordering::SeqCst) == 1;
+            if goaway {
+                FakeResponse::goaway()
+            } else {
+                FakeResponse::default()
+            }
+        })
+        .unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let mut session = client.connect().unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(session.get_identity().unwrap().token, "tok_test");
+        }
+        assert!(session.is_open());
+        assert_eq!(daemon.requests().len(), 4);
+
+        // Clients without a session see a transient error
+        daemon.respond_with(FakeResponse::goaway());
+        assert!(client.get_identity().unwrap_err().is_transient());
+    }
+
+    /// Answer `connections` connections with one response each, framed or
+    /// not, closing them afterwards.
+    fn serve_once(listener: UnixListener, connections: usize, framed: bool) {
+        let mut response = serde_json::to_string(&DaemonResponse::identity(identity())).unwrap();
+        if framed {
+            response.push('\n');
+        }
+        for stream in listener.incoming().take(connections) {
+            let mut stream = stream.unwrap();
+            let mut request = Vec::new();
+            BufReader::new(&mut stream)
+                .read_until(b'\n', &mut request)
+                .unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    }
+
+    fn identity() -> Identity {
+        Identity {
+            identity: "APP".to_string(),
+            idm_url: String::new(),
+            config_url: String::new(),
+            token: "tok".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_session_reconnects() {
+        for framed in [true, false] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("whoami.sock");
+            let listener = UnixListener::bind(&path).unwrap();
+            let server = std::thread::spawn(move || serve_once(listener, 3, framed));
+
+            let client = Client::builder().socket_path(&path).build();
+            let mut session = client.connect().unwrap();
+            for _ in 0..3 {
+                assert_eq!(session.get_identity().unwrap().identity, "APP");
+            }
+            assert_eq!(session.is_open(), framed);
+            server.join().unwrap();
+        }
+    }
+}
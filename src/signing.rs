@@ -0,0 +1,103 @@
+//! Verification of signed daemon responses (requires `signing` feature).
+//!
+//! A client configured with
+//! [`ClientBuilder::daemon_public_key`](crate::ClientBuilder::daemon_public_key)
+//! sets [`Request::sign`](crate::protocol::Request::sign), and the daemon
+//! answers with a [`SignedResponse`] whose payload is signed with its
+//! Ed25519 key. The client only parses payloads whose signature verifies
+//! against the pinned public key, so a response tampered with on its way,
+//! e.g. through a relay or a cache, is rejected.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+use crate::error::{GetMyIdError, Result};
+use crate::protocol::SignedResponse;
+
+impl SignedResponse {
+    /// Check the signature against the daemon's raw 32-byte Ed25519
+    /// `public_key` and return the payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidSignature`] if the signature is not
+    /// valid base64 or does not verify.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::protocol::{parse_response, SignedResponse};
+    ///
+    /// # fn example(relayed: &str, public_key: &[u8]) -> getmyid::Result<()> {
+    /// let signed: SignedResponse = serde_json::from_str(relayed).unwrap();
+    /// let identity = parse_response(signed.verify(public_key)?)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn verify(&self, public_key: &[u8]) -> Result<&str> {
+        let signature = STANDARD
+            .decode(&self.signature)
+            .map_err(|_| invalid("signature is not valid base64"))?;
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(self.payload.as_bytes(), &signature)
+            .map_err(|_| invalid("signature does not match the daemon's public key"))?;
+        Ok(&self.payload)
+    }
+}
+
+/// Verify the signed daemon answer `response` against `public_key`,
+/// returning the payload to parse.
+pub(crate) fn open(response: &str, public_key: &[u8]) -> Result<String> {
+    let mut signed: SignedResponse =
+        serde_json::from_str(response).map_err(|_| invalid("response is not signed"))?;
+    signed.verify(public_key)?;
+    Ok(std::mem::take(&mut signed.payload))
+}
+
+fn invalid(message: &str) -> GetMyIdError {
+    GetMyIdError::InvalidSignature(message.to_string())
+}
+
+/// Sign `payload` with the Ed25519 key derived from `seed`, as a signing
+/// daemon would.
+#[cfg(test)]
+pub(crate) fn sign(payload: &str, seed: &[u8; 32]) -> (String, Vec<u8>) {
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    let key_pair = Ed25519KeyPair::from_seed_unchecked(seed).unwrap();
+    let signed = SignedResponse {
+        payload: payload.to_string(),
+        signature: STANDARD.encode(key_pair.sign(payload.as_bytes())),
+    };
+    let json = serde_json::to_string(&signed).unwrap();
+    (json, key_pair.public_key().as_ref().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::parse_response;
+
+    const RESPONSE: &str = r#"{"status":"ok","identity":"APP","idm_url":"","config_url":"","token":"tok","runner":{"identity":"APP","pid":1}}"#;
+
+    #[test]
+    fn test_open_signed_response() {
+        let (signed, public_key) = sign(RESPONSE, &[7; 32]);
+        let payload = open(&signed, &public_key).unwrap();
+        assert_eq!(parse_response(&payload).unwrap().token, "tok");
+
+        let (_, other_key) = sign(RESPONSE, &[8; 32]);
+        let tampered = signed.replace(r#"\"tok\""#, r#"\"forged\""#);
+        for (response, key) in [
+            (signed.as_str(), other_key.as_slice()),
+            (tampered.as_str(), public_key.as_slice()),
+            (RESPONSE, public_key.as_slice()),
+        ] {
+            assert!(matches!(
+                open(response, key),
+                Err(GetMyIdError::InvalidSignature(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Coalescing of concurrent identity lookups with the same runner context.
+//!
+//! The first caller for a key (the leader) asks the daemon; callers
+//! arriving while it does (followers) wait for and share its identity
+//! instead of opening sockets of their own. The daemon's rejections, such
+//! as `E_NO_MATCH`, and failures to reach it are shared too; followers
+//! repeat other failures, such as malformed responses, by looking up
+//! themselves.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::error::{GetMyIdError, Result};
+use crate::types::Identity;
+
+/// Outcome of a leader's lookup as shared with followers: `Err(None)` if
+/// the failure could not be copied or the leader gave up.
+type Outcome = std::result::Result<Identity, Option<GetMyIdError>>;
+
+fn share(result: &Result<Identity>) -> Outcome {
+    match result {
+        Ok(identity) => Ok(identity.clone()),
+        Err(e) => Err(e.shared_copy()),
+    }
+}
+
+/// A follower's copy of `outcome`, or `None` to look up itself.
+fn replay(outcome: &Outcome) -> Option<Result<Identity>> {
+    match outcome {
+        Ok(identity) => Some(Ok(identity.clone())),
+        Err(e) => e.as_ref()?.shared_copy().map(Err),
+    }
+}
+
+/// Lookups in progress on blocking threads, shared between clones of a
+/// `Client`.
+#[derive(Debug, Default)]
+pub(crate) struct SingleFlight {
+    flights: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+#[derive(Debug, Default)]
+struct Flight {
+    outcome: Mutex<Option<Outcome>>,
+    done: Condvar,
+}
+
+impl Flight {
+    fn lock(&self) -> MutexGuard<'_, Option<Outcome>> {
+        self.outcome.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Completes the leader's flight, even if its lookup panicked.
+struct Leader<'a> {
+    flights: &'a SingleFlight,
+    key: String,
+    flight: Arc<Flight>,
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.flights.lock().remove(&self.key);
+        self.flight.lock().get_or_insert(Err(None));
+        self.flight.done.notify_all();
+    }
+}
+
+impl SingleFlight {
+    /// Run `lookup` for `key`, unless another thread is already doing so,
+    /// in which case wait for its outcome.
+    pub fn run(&self, key: String, lookup: impl FnOnce() -> Result<Identity>) -> Result<Identity> {
+        let mut flights = self.lock();
+        if let Some(flight) = flights.get(&key).cloned() {
+            drop(flights);
+            let outcome = flight
+                .done
+                .wait_while(flight.lock(), |outcome| outcome.is_none())
+                .unwrap_or_else(|e| e.into_inner());
+            return match outcome.as_ref().and_then(replay) {
+                Some(result) => result,
+                None => lookup(),
+            };
+        }
+
+        let flight = Arc::new(Flight::default());
+        flights.insert(key.clone(), flight.clone());
+        drop(flights);
+        let leader = Leader {
+            flights: self,
+            key,
+            flight,
+        };
+        let result = lookup();
+        *leader.flight.lock() = Some(share(&result));
+        result
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Arc<Flight>>> {
+        self.flights.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Lookups in progress on async tasks, shared between clones of an
+/// `AsyncClient`.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Default)]
+pub(crate) struct AsyncSingleFlight {
+    flights: Mutex<HashMap<String, AsyncFlight>>,
+}
+
+#[cfg(feature = "tokio")]
+type AsyncFlight = tokio::sync::watch::Receiver<Option<Outcome>>;
+
+/// Removes the leader's flight once its lookup completes or is cancelled;
+/// followers of a cancelled leader see the channel close.
+#[cfg(feature = "tokio")]
+struct AsyncLeader<'a> {
+    flights: &'a AsyncSingleFlight,
+    key: String,
+    flight: AsyncFlight,
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for AsyncLeader<'_> {
+    fn drop(&mut self) {
+        let mut flights = self.flights.lock();
+        // A later leader may have taken over the key after this one
+        if flights
+            .get(&self.key)
+            .is_some_and(|flight| flight.same_channel(&self.flight))
+        {
+            flights.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncSingleFlight {
+    /// Run `lookup` for `key`, unless another task is already doing so, in
+    /// which case wait for its outcome.
+    pub async fn run<F>(&self, key: String, lookup: impl FnOnce() -> F) -> Result<Identity>
+    where
+        F: std::future::Future<Output = Result<Identity>>,
+    {
+        let (sender, flight) = tokio::sync::watch::channel(None);
+        // Look up and register the flight under one guard, so only one task
+        // becomes the leader
+        let follow = {
+            let mut flights = self.lock();
+            let follow = flights.get(&key).cloned();
+            if follow.is_none() {
+                flights.insert(key.clone(), flight.clone());
+            }
+            follow
+        };
+        if let Some(mut flight) = follow {
+            let replayed = match flight.wait_for(Option::is_some).await {
+                Ok(outcome) => outcome.as_ref().and_then(replay),
+                Err(_) => None,
+            };
+            return match replayed {
+                Some(result) => result,
+                None => lookup().await,
+            };
+        }
+
+        let _leader = AsyncLeader {
+            flights: self,
+            key,
+            flight,
+        };
+        let result = lookup().await;
+        sender.send_replace(Some(share(&result)));
+        result
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, AsyncFlight>> {
+        self.flights.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    use crate::types::Runner;
+
+    fn identity() -> Identity {
+        Identity {
+            identity: "APP".to_string(),
+            idm_url: String::new(),
+            config_url: String::new(),
+            token: "tok".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner::default(),
+        }
+    }
+
+    #[test]
+    fn test_single_flight_coalesces() {
+        let flights = SingleFlight::default();
+        let lookups = AtomicUsize::new(0);
+        let barrier = Barrier::new(8);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    let result = flights.run(String::new(), || {
+                        lookups.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(100));
+                        Ok(identity())
+                    });
+                    assert_eq!(result.unwrap().token, "tok");
+                });
+            }
+        });
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert!(flights.lock().is_empty());
+    }
+
+    #[test]
+    fn test_single_flight_errors() {
+        // Timeouts and rejections are shared; other errors make the follower
+        // look up again
+        let no_match = GetMyIdError::DaemonError {
+            code: crate::error::DaemonErrorCode::NoMatch,
+            message: "no match".to_string(),
+        };
+        for (error, shared) in [
+            (GetMyIdError::Timeout(Duration::from_secs(1)), true),
+            (no_match, true),
+            (GetMyIdError::MissingField { field: "identity" }, false),
+        ] {
+            let flights = SingleFlight::default();
+            let error = Mutex::new(Some(error));
+            let lookups = AtomicUsize::new(0);
+            let barrier = Barrier::new(2);
+            let successes = std::thread::scope(|scope| {
+                let threads: Vec<_> = (0..2)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            barrier.wait();
+                            flights.run(String::new(), || {
+                                lookups.fetch_add(1, Ordering::SeqCst);
+                                std::thread::sleep(Duration::from_millis(100));
+                                error.lock().unwrap().take().map_or(Ok(identity()), Err)
+                            })
+                        })
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .filter_map(|thread| thread.join().unwrap().ok())
+                    .count()
+            });
+            let expected = if shared { (1, 0) } else { (2, 1) };
+            assert_eq!((lookups.load(Ordering::SeqCst), successes), expected);
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_single_flight() {
+        let flights = Arc::new(AsyncSingleFlight::default());
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let (flights, lookups) = (flights.clone(), lookups.clone());
+                tokio::spawn(async move {
+                    flights
+                        .run(String::new(), || async {
+                            lookups.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            Ok(identity())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap().token, "tok");
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        // Followers of a cancelled leader look up themselves
+        let leader = tokio::spawn({
+            let flights = flights.clone();
+            async move {
+                let lookup = || std::future::pending::<Result<Identity>>();
+                flights.run(String::new(), lookup).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let follower = tokio::spawn(async move {
+            let lookup = || async { Ok(identity()) };
+            flights.run(String::new(), lookup).await
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        leader.abort();
+        assert!(follower.await.unwrap().is_ok());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_async_single_flight_one_leader() {
+        let flights = Arc::new(AsyncSingleFlight::default());
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(tokio::sync::Barrier::new(16));
+        let tasks: Vec<_> = (0..16)
+            .map(|_| {
+                let (flights, lookups) = (flights.clone(), lookups.clone());
+                let barrier = barrier.clone();
+                tokio::spawn(async move {
+                    barrier.wait().await;
+                    flights
+                        .run(String::new(), || async {
+                            lookups.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            Ok(identity())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert!(flights.lock().is_empty());
+
+        // A finished leader leaves the flight of a later one in place
+        let (_sender, later) = tokio::sync::watch::channel(None);
+        flights.lock().insert(String::new(), later);
+        let (_sender, earlier) = tokio::sync::watch::channel(None);
+        drop(AsyncLeader {
+            flights: &flights,
+            key: String::new(),
+            flight: earlier,
+        });
+        assert!(flights.lock().contains_key(""));
+    }
+}
@@ -0,0 +1,190 @@
+//! Daemon error codes answered from the cache instead of failing.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use crate::error::{DaemonErrorCode, GetMyIdError, Result};
+use crate::retry::IdentityCache;
+use crate::types::Identity;
+
+/// Daemon error codes treated as soft failures, shared between every
+/// client holding the handle and adjustable at runtime.
+///
+/// When the daemon rejects a lookup with a soft code, or reports being
+/// degraded (e.g. in a maintenance window) whatever its code, the client
+/// serves the identity it cached earlier for the same runner context,
+/// provided the entry has not been dropped yet and its token has not
+/// expired. The
+/// failure is recorded as a warning, available from
+/// [`last_failure`](SoftErrorCodes::last_failure) and counted by
+/// [`soft_failures`](SoftErrorCodes::soft_failures). Without a usable
+/// cached identity the error is returned as usual.
+///
+/// Stale identities only exist with `cache_ttl` set: they outlived the TTL
+/// but were not swept yet, which happens at most one TTL later.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use getmyid::{Client, SoftErrorCodes};
+///
+/// let soft = SoftErrorCodes::new(["E_MAINTENANCE"]);
+/// let client = Client::builder()
+///     .cache_ttl(Duration::from_secs(60))
+///     .soft_error_codes(soft.clone())
+///     .build();
+///
+/// // During an incident, without redeploying:
+/// soft.insert("E_INTERNAL");
+/// assert!(client.soft_error_codes().contains(&"E_INTERNAL".into()));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SoftErrorCodes {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    codes: RwLock<HashSet<DaemonErrorCode>>,
+    failures: AtomicU64,
+    last: Mutex<Option<SoftFailure>>,
+}
+
+/// A daemon error answered with a cached identity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SoftFailure {
+    /// Error code returned by the daemon; `None` for a degraded daemon that
+    /// sent none.
+    pub code: Option<DaemonErrorCode>,
+    /// Error message returned by the daemon.
+    pub message: String,
+    /// When the cached identity was served.
+    pub at: SystemTime,
+}
+
+impl SoftErrorCodes {
+    /// Create a set holding `codes`.
+    pub fn new<C: Into<DaemonErrorCode>>(codes: impl IntoIterator<Item = C>) -> Self {
+        let soft = Self::default();
+        soft.replace(codes);
+        soft
+    }
+
+    /// Treat `code` as soft. Returns whether it was not soft already.
+    pub fn insert(&self, code: impl Into<DaemonErrorCode>) -> bool {
+        self.write().insert(code.into())
+    }
+
+    /// Treat `code` as a hard error again. Returns whether it was soft.
+    pub fn remove(&self, code: &DaemonErrorCode) -> bool {
+        self.write().remove(code)
+    }
+
+    /// Replace every soft code with `codes`.
+    pub fn replace<C: Into<DaemonErrorCode>>(&self, codes: impl IntoIterator<Item = C>) {
+        *self.write() = codes.into_iter().map(Into::into).collect();
+    }
+
+    /// Whether `code` is soft.
+    pub fn contains(&self, code: &DaemonErrorCode) -> bool {
+        self.read().contains(code)
+    }
+
+    /// Get the soft codes, in no particular order.
+    pub fn codes(&self) -> Vec<DaemonErrorCode> {
+        self.read().iter().cloned().collect()
+    }
+
+    /// Get how many lookups were answered with a cached identity after a
+    /// soft failure.
+    pub fn soft_failures(&self) -> u64 {
+        self.inner.failures.load(Ordering::Relaxed)
+    }
+
+    /// Get the most recent soft failure, if any.
+    pub fn last_failure(&self) -> Option<SoftFailure> {
+        self.lock_last().clone()
+    }
+
+    /// Record that a cached identity was served after the daemon failed
+    /// with `code`.
+    pub(crate) fn record(&self, code: Option<&DaemonErrorCode>, message: &str) {
+        self.inner.failures.fetch_add(1, Ordering::Relaxed);
+        *self.lock_last() = Some(SoftFailure {
+            code: code.cloned(),
+            message: message.to_string(),
+            at: SystemTime::now(),
+        });
+    }
+
+    /// Answer `error` with the identity cached under the key if the daemon
+    /// failed with a soft code or is degraded, recording the failure.
+    pub(crate) fn recover(
+        &self,
+        cached: Option<(&IdentityCache, &str)>,
+        error: GetMyIdError,
+    ) -> Result<Identity> {
+        let (code, message) = match &error {
+            GetMyIdError::DaemonError { code, message } if self.contains(code) => {
+                (Some(code), message)
+            }
+            GetMyIdError::DaemonDegraded { message, code, .. } => (code.as_ref(), message),
+            _ => return Err(error),
+        };
+        match cached.and_then(|(cache, key)| cache.get_stale(key)) {
+            Some(identity) => {
+                self.record(code, message);
+                Ok(identity)
+            }
+            None => Err(error),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashSet<DaemonErrorCode>> {
+        self.inner.codes.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashSet<DaemonErrorCode>> {
+        self.inner.codes.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_last(&self) -> std::sync::MutexGuard<'_, Option<SoftFailure>> {
+        self.inner.last.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soft_error_codes() {
+        let soft = SoftErrorCodes::new(["E_MAINTENANCE"]);
+        let shared = soft.clone();
+        assert!(shared.contains(&"E_MAINTENANCE".into()));
+        assert!(!shared.contains(&DaemonErrorCode::InternalError));
+
+        assert!(soft.insert("E_INTERNAL"));
+        assert!(!soft.insert(DaemonErrorCode::InternalError));
+        assert!(shared.contains(&DaemonErrorCode::InternalError));
+        assert!(soft.remove(&"E_MAINTENANCE".into()));
+        assert_eq!(shared.codes(), vec![DaemonErrorCode::InternalError]);
+
+        soft.replace(Vec::<DaemonErrorCode>::new());
+        assert!(shared.codes().is_empty());
+
+        assert_eq!(shared.last_failure(), None);
+        soft.record(
+            Some(&DaemonErrorCode::InternalError),
+            "down for maintenance",
+        );
+        assert_eq!(shared.soft_failures(), 1);
+        let failure = shared.last_failure().unwrap();
+        assert_eq!(failure.code, Some(DaemonErrorCode::InternalError));
+        assert_eq!(failure.message, "down for maintenance");
+    }
+}
@@ -0,0 +1,189 @@
+//! Support bundles to attach to bug reports.
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::capabilities::capabilities;
+use crate::client::Client;
+use crate::transport::is_abstract;
+use crate::types::Identity;
+
+/// Replacement for the token in a bundled identity and `Debug` output.
+pub(crate) const REDACTED: &str = "[redacted]";
+
+/// `token` as shown in `Debug` output: [`REDACTED`], or empty if no token
+/// was issued.
+pub(crate) fn redact_token(token: &str) -> &str {
+    if token.is_empty() {
+        token
+    } else {
+        REDACTED
+    }
+}
+
+/// A snapshot of the client setup, the socket and the resolved identity,
+/// serialized to JSON for bug reports.
+///
+/// The token is redacted; URLs and runner fields are kept, so review the
+/// output before posting it publicly.
+///
+/// # Example
+///
+/// ```no_run
+/// use getmyid::{Client, SupportBundle};
+///
+/// let bundle = SupportBundle::collect(&Client::new());
+/// std::fs::write("getmyid-support.json", bundle.to_json())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct SupportBundle {
+    /// Version of this crate.
+    pub version: &'static str,
+    /// Cargo features enabled in this build.
+    pub features: Vec<&'static str>,
+    /// How the client is configured.
+    pub client: ClientSettings,
+    /// What the primary socket path looks like on disk.
+    pub socket: SocketReport,
+    /// The identity resolved by the daemon, with its token redacted.
+    pub identity: Option<Identity>,
+    /// Why no identity could be resolved.
+    pub error: Option<String>,
+}
+
+/// Client configuration captured in a [`SupportBundle`].
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct ClientSettings {
+    /// Primary socket path.
+    pub socket_path: PathBuf,
+    /// Failover socket paths, in the order they are tried.
+    pub failover_sockets: Vec<PathBuf>,
+    /// Connect timeout in milliseconds.
+    pub connect_timeout_ms: Option<u128>,
+    /// Read timeout in milliseconds.
+    pub read_timeout_ms: Option<u128>,
+    /// Write timeout in milliseconds.
+    pub write_timeout_ms: Option<u128>,
+    /// Retries after a transient failure.
+    pub retries: u32,
+    /// Identity cache lifetime in milliseconds, if caching is enabled.
+    pub cache_ttl_ms: Option<u128>,
+}
+
+/// State of the primary socket path captured in a [`SupportBundle`].
+#[derive(Debug, Clone, Default, Serialize)]
+#[non_exhaustive]
+pub struct SocketReport {
+    /// Whether the path is in the Linux abstract namespace, where the file
+    /// checks do not apply.
+    pub abstract_namespace: bool,
+    /// Whether the path exists.
+    pub exists: bool,
+    /// Whether the path is a socket.
+    pub is_socket: bool,
+    /// Owner user ID.
+    pub uid: Option<u32>,
+    /// Owner group ID.
+    pub gid: Option<u32>,
+    /// Permission bits, in octal.
+    pub mode: Option<String>,
+}
+
+impl SupportBundle {
+    /// Collect a bundle for `client`, asking the daemon for an identity once.
+    pub fn collect(client: &Client) -> Self {
+        let (identity, error) = match client.get_identity() {
+            Ok(identity) => (Some(identity.redacted()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            features: capabilities().features(),
+            client: ClientSettings::of(client),
+            socket: SocketReport::of(client.socket_path()),
+            identity,
+            error,
+        }
+    }
+
+    /// Serialize the bundle as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("support bundle serializes to JSON")
+    }
+}
+
+impl ClientSettings {
+    fn of(client: &Client) -> Self {
+        Self {
+            socket_path: client.socket_path().to_path_buf(),
+            failover_sockets: client.failover_sockets().map(Path::to_path_buf).collect(),
+            connect_timeout_ms: client.connect_timeout().map(|d| d.as_millis()),
+            read_timeout_ms: client.read_timeout().map(|d| d.as_millis()),
+            write_timeout_ms: client.write_timeout().map(|d| d.as_millis()),
+            retries: client.retries(),
+            cache_ttl_ms: client.cache_ttl().map(|d| d.as_millis()),
+        }
+    }
+}
+
+impl SocketReport {
+    fn of(path: &Path) -> Self {
+        if is_abstract(path) {
+            return Self {
+                abstract_namespace: true,
+                ..Self::default()
+            };
+        }
+        match fs::metadata(path) {
+            Ok(metadata) => Self {
+                abstract_namespace: false,
+                exists: true,
+                is_socket: metadata.file_type().is_socket(),
+                uid: Some(metadata.uid()),
+                gid: Some(metadata.gid()),
+                mode: Some(format!("{:o}", metadata.permissions().mode() & 0o7777)),
+            },
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_support_bundle_without_daemon() {
+        let client = Client::builder()
+            .socket_path("/nonexistent/whoami.sock")
+            .retries(2)
+            .build();
+        let bundle = SupportBundle::collect(&client);
+
+        assert!(bundle.identity.is_none());
+        assert!(bundle.error.unwrap().contains("/nonexistent/whoami.sock"));
+        assert!(!bundle.socket.exists);
+        assert_eq!(bundle.client.retries, 2);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_support_bundle_redacts_token() {
+        use crate::testing::FakeDaemon;
+
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+        let json = SupportBundle::collect(&client).to_json();
+        let bundle: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(bundle["identity"]["identity"], "TEST_IDENTITY");
+        assert_eq!(bundle["identity"]["token"], REDACTED);
+        assert_eq!(bundle["socket"]["is_socket"], true);
+    }
+}
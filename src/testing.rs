@@ -0,0 +1,720 @@
+//! In-process fake whoami daemon for integration tests (requires `test-util` feature).
+//!
+//! [`FakeDaemon`] binds a Unix socket in a temporary directory and answers
+//! every connection with a canned or programmable [`FakeResponse`], so crates
+//! depending on getmyid can exercise [`Client`](crate::Client) and
+//! `AsyncClient` without a real daemon.
+//!
+//! # Example
+//!
+//! ```
+//! use getmyid::testing::{FakeDaemon, FakeResponse};
+//! use getmyid::{Client, GetMyIdError, RunnerRequest};
+//!
+//! let daemon = FakeDaemon::start()?;
+//! let client = Client::builder().socket_path(daemon.socket_path()).build();
+//!
+//! let identity = client.get_identity_with_runner(Some(RunnerRequest::new().with_instance_id(1)))?;
+//! assert_eq!(identity.identity, "TEST_IDENTITY");
+//! assert_eq!(daemon.requests()[0].runner().unwrap().instance_id, Some(1));
+//!
+//! daemon.respond_with(FakeResponse::no_match());
+//! assert!(matches!(client.get_identity(), Err(GetMyIdError::DaemonError { .. })));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Production incidents can be replayed with a [`Scenario`] of responses
+//! (ok, then errors, then ok again) and a [`FaultProfile`] adding latency
+//! and dropped connections on top:
+//!
+//! ```
+//! use std::time::Duration;
+//! use getmyid::testing::{FakeDaemon, FakeResponse, FaultProfile, Latency, Scenario};
+//! use getmyid::Client;
+//!
+//! let daemon = FakeDaemon::start()?;
+//! daemon.play(
+//!     Scenario::new()
+//!         .then(FakeResponse::default(), 1)
+//!         .then(FakeResponse::error("E_INTERNAL", "reloading"), 2)
+//!         .then(FakeResponse::default(), 1),
+//! );
+//! daemon.set_fault_profile(
+//!     FaultProfile::new()
+//!         .latency(Latency::Uniform(Duration::from_millis(1), Duration::from_millis(5)))
+//!         .seed(7),
+//! );
+//!
+//! let client = Client::builder().socket_path(daemon.socket_path()).build();
+//! assert!(client.get_identity().is_ok());
+//! assert!(client.get_identity().is_err());
+//! assert!(client.get_identity().is_err());
+//! assert!(client.get_identity().is_ok());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::protocol::{is_goaway, DaemonResponse, Request};
+use crate::types::{Identity, Runner, RunnerRequest};
+
+/// How long the fake daemon waits for a request before answering a client
+/// that sent nothing.
+const REQUEST_WAIT: Duration = Duration::from_millis(50);
+
+/// A response the fake daemon sends for one connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FakeResponse {
+    /// A successful response. Runner context sent by the client is merged
+    /// into the identity's `runner` and its nonce echoed, as the real
+    /// daemon does.
+    Identity(Box<Identity>),
+    /// An error response with the given code and message.
+    Error {
+        /// Error code, e.g. `E_NO_MATCH`.
+        code: String,
+        /// Human-readable message.
+        message: String,
+    },
+    /// Bytes sent verbatim, e.g. malformed JSON.
+    Raw(String),
+    /// Another response sent after a delay.
+    Delayed(Duration, Box<FakeResponse>),
+    /// Close the connection without replying.
+    Close,
+}
+
+impl Default for FakeResponse {
+    fn default() -> Self {
+        Self::identity(FakeDaemon::sample_identity())
+    }
+}
+
+impl FakeResponse {
+    /// A successful response carrying `identity`.
+    pub fn identity(identity: Identity) -> Self {
+        Self::Identity(Box::new(identity))
+    }
+
+    /// An error response with the given code and message.
+    pub fn error(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::Error {
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+
+    /// The daemon's `E_NO_MATCH` response.
+    pub fn no_match() -> Self {
+        Self::error("E_NO_MATCH", "No identity rule matches process")
+    }
+
+    /// The response of a daemon handing its socket over to a new process.
+    ///
+    /// On `keep_alive` connections the fake daemon closes the connection
+    /// after sending it.
+    pub fn goaway() -> Self {
+        let response = DaemonResponse::goaway("Daemon is restarting");
+        Self::Raw(serde_json::to_string(&response).unwrap_or_default())
+    }
+
+    /// A response that is not valid JSON.
+    pub fn malformed() -> Self {
+        Self::Raw("{\"status\":\"ok\",".to_string())
+    }
+
+    /// Send this response after `delay`.
+    pub fn delayed(self, delay: Duration) -> Self {
+        Self::Delayed(delay, Box::new(self))
+    }
+
+    /// Render the response for a request, sleeping for any delay.
+    fn render(&self, request: &ReceivedRequest) -> Option<String> {
+        match self {
+            Self::Identity(identity) => {
+                let mut identity = Identity::clone(identity);
+                if let Some(runner) = request.runner() {
+                    identity.runner.merge_request(runner);
+                }
+                let response = DaemonResponse::identity(identity).with_nonce(request.nonce());
+                serde_json::to_string(&response).ok()
+            }
+            Self::Error { code, message } => {
+                let response = DaemonResponse::error(code, message).with_nonce(request.nonce());
+                serde_json::to_string(&response).ok()
+            }
+            Self::Raw(raw) => Some(raw.clone()),
+            Self::Delayed(delay, response) => {
+                thread::sleep(*delay);
+                response.render(request)
+            }
+            Self::Close => None,
+        }
+    }
+}
+
+/// Response times drawn by a [`FaultProfile`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[non_exhaustive]
+pub enum Latency {
+    /// Answer immediately.
+    #[default]
+    None,
+    /// Always wait this long.
+    Fixed(Duration),
+    /// Wait a uniformly distributed time between the two bounds.
+    Uniform(Duration, Duration),
+    /// Wait `base` usually, and `spike` with the given probability, like a
+    /// daemon whose accept queue occasionally stalls.
+    Spikes {
+        /// Usual response time.
+        base: Duration,
+        /// Response time during a spike.
+        spike: Duration,
+        /// Probability of a spike, between 0 and 1.
+        probability: f64,
+    },
+}
+
+/// Latency and dropped connections applied to every response of a
+/// [`FakeDaemon`], on top of its handler or [`Scenario`].
+///
+/// Random draws come from a seeded generator, so a failing test replays the
+/// same sequence of faults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultProfile {
+    latency: Latency,
+    drop_rate: f64,
+    seed: u64,
+}
+
+impl Default for FaultProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FaultProfile {
+    /// A profile without latency or drops.
+    pub fn new() -> Self {
+        Self {
+            latency: Latency::None,
+            drop_rate: 0.0,
+            seed: 0,
+        }
+    }
+
+    /// Delay responses according to `latency`.
+    pub fn latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Close this fraction of connections (between 0 and 1) without
+    /// replying.
+    pub fn drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+
+    /// Seed the random draws (0 by default).
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// A [`FaultProfile`] and the state of its random generator.
+#[derive(Debug)]
+struct Faults {
+    profile: FaultProfile,
+    rng: u64,
+}
+
+impl Faults {
+    fn new(profile: FaultProfile) -> Self {
+        Self {
+            rng: profile.seed,
+            profile,
+        }
+    }
+
+    fn apply(&mut self, response: FakeResponse) -> FakeResponse {
+        if self.chance(self.profile.drop_rate) {
+            return FakeResponse::Close;
+        }
+        let delay = match self.profile.latency {
+            Latency::None => return response,
+            Latency::Fixed(delay) => delay,
+            Latency::Uniform(min, max) => min + max.saturating_sub(min).mul_f64(self.unit()),
+            Latency::Spikes {
+                base,
+                spike,
+                probability,
+            } => {
+                if self.chance(probability) {
+                    spike
+                } else {
+                    base
+                }
+            }
+        };
+        response.delayed(delay)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.unit() < probability
+    }
+
+    /// Next draw in `[0, 1)`, from SplitMix64.
+    fn unit(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A scripted sequence of responses, played with [`FakeDaemon::play`].
+///
+/// Each step answers a number of connections; once the script is exhausted
+/// the last step keeps answering.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scenario {
+    steps: Vec<(FakeResponse, usize)>,
+}
+
+impl Scenario {
+    /// An empty scenario; playing it answers with the default response.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the next `times` connections with `response`.
+    pub fn then(mut self, response: FakeResponse, times: usize) -> Self {
+        self.steps.push((response, times));
+        self
+    }
+
+    /// Response for the `n`th connection (from 0) since the scenario started.
+    fn response(&self, mut n: usize) -> FakeResponse {
+        for (response, times) in &self.steps {
+            if n < *times {
+                return response.clone();
+            }
+            n -= times;
+        }
+        self.steps
+            .last()
+            .map(|(response, _)| response.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// A request received by the fake daemon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedRequest {
+    raw: String,
+}
+
+impl ReceivedRequest {
+    /// The raw bytes the client sent (empty for request-less exchanges),
+    /// without the newline ending requests on `keep_alive` connections.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// The request parsed as JSON, if any was sent.
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.raw).ok()
+    }
+
+    /// The runner context the client sent, if any.
+    pub fn runner(&self) -> Option<RunnerRequest> {
+        serde_json::from_str::<Request>(&self.raw).ok()?.runner
+    }
+
+    /// The nonce the client sent, which the fake daemon echoes in its
+    /// identity and error responses.
+    pub fn nonce(&self) -> Option<String> {
+        serde_json::from_str::<Request>(&self.raw).ok()?.nonce
+    }
+}
+
+type Handler = dyn Fn(&ReceivedRequest) -> FakeResponse + Send + Sync;
+
+struct State {
+    handler: Arc<Handler>,
+    faults: Option<Faults>,
+    requests: Vec<ReceivedRequest>,
+}
+
+/// A fake whoami daemon listening on a temporary Unix socket.
+///
+/// The daemon stops and its socket is removed when the value is dropped.
+pub struct FakeDaemon {
+    path: PathBuf,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+    _dir: tempfile::TempDir,
+}
+
+impl fmt::Debug for FakeDaemon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FakeDaemon")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FakeDaemon {
+    /// Start a daemon answering with [`FakeDaemon::sample_identity`].
+    pub fn start() -> io::Result<Self> {
+        Self::with_response(FakeResponse::default())
+    }
+
+    /// Start a daemon answering every connection with `response`.
+    pub fn with_response(response: FakeResponse) -> io::Result<Self> {
+        Self::with_handler(move |_| response.clone())
+    }
+
+    /// Start a daemon computing each response from the received request.
+    pub fn with_handler<F>(handler: F) -> io::Result<Self>
+    where
+        F: Fn(&ReceivedRequest) -> FakeResponse + Send + Sync + 'static,
+    {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("whoami.sock");
+        let listener = UnixListener::bind(&path)?;
+
+        let state = Arc::new(Mutex::new(State {
+            handler: Arc::new(handler),
+            faults: None,
+            requests: Vec::new(),
+        }));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let accept_thread = {
+            let state = Arc::clone(&state);
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || accept_loop(listener, state, shutdown))
+        };
+
+        Ok(Self {
+            path,
+            state,
+            shutdown,
+            accept_thread: Some(accept_thread),
+            _dir: dir,
+        })
+    }
+
+    /// Identity served by default: `TEST_IDENTITY` running as the current process.
+    pub fn sample_identity() -> Identity {
+        Identity {
+            identity: "TEST_IDENTITY".to_string(),
+            idm_url: "https://idm.test/oauth2/test".to_string(),
+            config_url: "https://config.test/api/test".to_string(),
+            token: "tok_test".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner {
+                identity: "TEST_IDENTITY".to_string(),
+                hostname: "test-host".to_string(),
+                process: "test-process".to_string(),
+                pid: std::process::id(),
+                uid: 1000,
+                gid: 1000,
+                ..Runner::default()
+            },
+        }
+    }
+
+    /// Path of the daemon's socket.
+    pub fn socket_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Answer all subsequent connections with `response`.
+    pub fn respond_with(&self, response: FakeResponse) {
+        self.set_handler(move |_| response.clone());
+    }
+
+    /// Compute all subsequent responses with `handler`.
+    pub fn set_handler<F>(&self, handler: F)
+    where
+        F: Fn(&ReceivedRequest) -> FakeResponse + Send + Sync + 'static,
+    {
+        self.lock().handler = Arc::new(handler);
+    }
+
+    /// Answer subsequent connections following `scenario`, starting from its
+    /// first step.
+    pub fn play(&self, scenario: Scenario) {
+        let served = AtomicUsize::new(0);
+        self.set_handler(move |_| scenario.response(served.fetch_add(1, Ordering::SeqCst)));
+    }
+
+    /// Apply `profile` to all subsequent responses, restarting its random
+    /// draws from the seed.
+    pub fn set_fault_profile(&self, profile: FaultProfile) {
+        self.lock().faults = Some(Faults::new(profile));
+    }
+
+    /// Stop injecting latency and dropped connections.
+    pub fn clear_fault_profile(&self) {
+        self.lock().faults = None;
+    }
+
+    /// Requests received so far, in arrival order.
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Forget the requests received so far.
+    pub fn clear_requests(&self) {
+        self.lock().requests.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for FakeDaemon {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it observes the shutdown flag
+        let _ = UnixStream::connect(&self.path);
+        if let Some(handle) = self.accept_thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn accept_loop(listener: UnixListener, state: Arc<Mutex<State>>, shutdown: Arc<AtomicBool>) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = stream else { continue };
+        let state = Arc::clone(&state);
+        thread::spawn(move || {
+            let _ = serve(stream, &state);
+        });
+    }
+}
+
+/// Answer the requests on one connection: a single one, or each line of a
+/// `keep_alive` connection until the client closes it.
+fn serve(mut stream: UnixStream, state: &Mutex<State>) -> io::Result<()> {
+    let mut raw = read_request(&mut stream)?;
+    loop {
+        let request = ReceivedRequest {
+            raw: raw.trim_end_matches('\n').to_string(),
+        };
+        let keep_alive = request
+            .json()
+            .is_some_and(|json| json["keep_alive"] == true);
+
+        let handler = {
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.requests.push(request.clone());
+            Arc::clone(&state.handler)
+        };
+
+        let mut response = handler(&request);
+        if let Some(faults) = &mut state.lock().unwrap_or_else(|e| e.into_inner()).faults {
+            response = faults.apply(response);
+        }
+        let Some(mut response) = response.render(&request) else {
+            return Ok(());
+        };
+        if !keep_alive {
+            return stream.write_all(response.as_bytes());
+        }
+        let goaway = is_goaway(&response);
+        response.push('\n');
+        stream.write_all(response.as_bytes())?;
+        if goaway {
+            return Ok(());
+        }
+
+        raw = read_line(&mut stream)?;
+        if raw.is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Read the next request line of a `keep_alive` connection, or nothing if
+/// the client closed it.
+fn read_line(stream: &mut UnixStream) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !line.ends_with(b"\n") {
+        match stream.read(&mut buf)? {
+            0 => break,
+            n => line.extend_from_slice(&buf[..n]),
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Read whatever the client sends before it shuts down its write side or
+/// stays silent for [`REQUEST_WAIT`].
+fn read_request(stream: &mut UnixStream) -> io::Result<String> {
+    stream.set_read_timeout(Some(REQUEST_WAIT))?;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    stream.set_read_timeout(None)?;
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Client, DaemonErrorCode, GetMyIdError};
+
+    #[test]
+    fn test_fake_daemon_success_and_requests() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+
+        let identity = client
+            .get_identity_with_runner(Some(RunnerRequest::new().with_field("zone", "eu")))
+            .unwrap();
+
+        assert_eq!(identity.identity, "TEST_IDENTITY");
+        assert_eq!(identity.runner.extra.get("zone").unwrap(), "eu");
+
+        let requests = daemon.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].runner().unwrap().extra.get("zone").unwrap(),
+            "eu"
+        );
+    }
+
+    #[test]
+    fn test_fake_daemon_canned_failures() {
+        let daemon = FakeDaemon::with_response(FakeResponse::no_match()).unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .timeout(Duration::from_millis(200))
+            .build();
+
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::DaemonError {
+                code: DaemonErrorCode::NoMatch,
+                ..
+            })
+        ));
+
+        daemon.respond_with(FakeResponse::malformed());
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::InvalidJson { .. })
+        ));
+
+        daemon.respond_with(FakeResponse::default().delayed(Duration::from_secs(2)));
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::ReadError(_))
+        ));
+        assert_eq!(daemon.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_fake_daemon_scenario() {
+        let daemon = FakeDaemon::start().unwrap();
+        daemon.play(
+            Scenario::new()
+                .then(FakeResponse::default(), 1)
+                .then(FakeResponse::no_match(), 2)
+                .then(FakeResponse::default(), 1),
+        );
+        let client = Client::builder().socket_path(daemon.socket_path()).build();
+
+        let outcomes: Vec<bool> = (0..5).map(|_| client.get_identity().is_ok()).collect();
+        assert_eq!(outcomes, [true, false, false, true, true]);
+    }
+
+    #[test]
+    fn test_fake_daemon_fault_profile() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = Client::builder()
+            .socket_path(daemon.socket_path())
+            .timeout(Duration::from_millis(200))
+            .build();
+
+        daemon.set_fault_profile(FaultProfile::new().drop_rate(1.0));
+        assert!(matches!(
+            client.get_identity(),
+            Err(GetMyIdError::InvalidJson { .. })
+        ));
+
+        daemon
+            .set_fault_profile(FaultProfile::new().latency(Latency::Fixed(Duration::from_secs(2))));
+        assert!(client.get_identity().is_err());
+
+        daemon.clear_fault_profile();
+        assert!(client.get_identity().is_ok());
+    }
+
+    #[test]
+    fn test_fault_latency_draws() {
+        let (min, max) = (Duration::from_millis(10), Duration::from_millis(20));
+        let profile = FaultProfile::new()
+            .latency(Latency::Uniform(min, max))
+            .seed(42);
+        let delays = |profile: &FaultProfile| -> Vec<Duration> {
+            let mut faults = Faults::new(profile.clone());
+            (0..100)
+                .map(|_| match faults.apply(FakeResponse::Close) {
+                    FakeResponse::Delayed(delay, _) => delay,
+                    other => panic!("not delayed: {other:?}"),
+                })
+                .collect()
+        };
+
+        let first = delays(&profile);
+        assert!(first.iter().all(|d| (min..=max).contains(d)));
+        assert!(first.iter().any(|d| *d != first[0]));
+        assert_eq!(first, delays(&profile));
+
+        let mut faults = Faults::new(FaultProfile::new().drop_rate(0.5).seed(1));
+        let dropped = (0..1000)
+            .filter(|_| faults.apply(FakeResponse::default()) == FakeResponse::Close)
+            .count();
+        assert!((400..600).contains(&dropped));
+    }
+}
@@ -0,0 +1,459 @@
+//! TCP + mutual TLS transport for remote whoami daemons (requires `tls` feature).
+//!
+//! A whoami daemon normally identifies local processes through `SO_PEERCRED`.
+//! Deployments that run a central identity service (e.g. on a bastion host)
+//! instead authenticate callers with client certificates; [`TcpTlsTransport`]
+//! lets the regular [`Client`](crate::Client) talk to such a service.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use getmyid::{Client, TcpTlsTransport};
+//!
+//! let transport = TcpTlsTransport::builder("identity.internal:8443")
+//!     .ca_cert_file("/etc/getmyid/ca.pem")
+//!     .client_cert_files("/etc/getmyid/client.pem", "/etc/getmyid/client.key")
+//!     .server_name("identity.internal")
+//!     .timeout(Duration::from_secs(3))
+//!     .build()?;
+//!
+//! let client = Client::builder().transport(transport).build();
+//! let identity = client.get_identity()?;
+//! # Ok::<(), getmyid::GetMyIdError>(())
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::client::WantsClientCert;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, ConfigBuilder, RootCertStore, StreamOwned};
+
+use crate::error::{GetMyIdError, Result};
+use crate::transport::{Connection, Transport};
+
+/// PEM input given either as a file path or as in-memory bytes.
+#[derive(Debug, Clone)]
+pub(crate) enum Pem {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl Pem {
+    fn load(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::File(path) => std::fs::read(path).map_err(|e| {
+                GetMyIdError::InvalidTlsConfig(format!("cannot read {}: {e}", path.display()))
+            }),
+            Self::Bytes(bytes) => Ok(bytes.clone()),
+        }
+    }
+
+    fn certs(&self) -> Result<Vec<CertificateDer<'static>>> {
+        let pem = self.load()?;
+        let certs = CertificateDer::pem_slice_iter(&pem)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| GetMyIdError::InvalidTlsConfig(format!("invalid certificate PEM: {e}")))?;
+        if certs.is_empty() {
+            return Err(GetMyIdError::InvalidTlsConfig(
+                "no certificates found in PEM input".to_string(),
+            ));
+        }
+        Ok(certs)
+    }
+
+    fn private_key(&self) -> Result<PrivateKeyDer<'static>> {
+        PrivateKeyDer::from_pem_slice(&self.load()?)
+            .map_err(|e| GetMyIdError::InvalidTlsConfig(format!("invalid private key PEM: {e}")))
+    }
+}
+
+/// Builder for [`TcpTlsTransport`].
+#[derive(Debug, Clone)]
+pub struct TcpTlsTransportBuilder {
+    addr: String,
+    ca: Option<Pem>,
+    client_cert: Option<(Pem, Pem)>,
+    server_name: Option<String>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl TcpTlsTransportBuilder {
+    fn new(addr: String) -> Self {
+        Self {
+            addr,
+            ca: None,
+            client_cert: None,
+            server_name: None,
+            connect_timeout: Some(crate::DEFAULT_TIMEOUT),
+            read_timeout: Some(crate::DEFAULT_TIMEOUT),
+            write_timeout: Some(crate::DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Trust the CA certificate(s) in the PEM file at `path`.
+    pub fn ca_cert_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.ca = Some(Pem::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Trust the CA certificate(s) in the given PEM bytes.
+    pub fn ca_cert_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca = Some(Pem::Bytes(pem.into()));
+        self
+    }
+
+    /// Authenticate with the client certificate chain and private key in
+    /// the given PEM files.
+    pub fn client_cert_files(mut self, cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Self {
+        self.client_cert = Some((
+            Pem::File(cert.as_ref().to_path_buf()),
+            Pem::File(key.as_ref().to_path_buf()),
+        ));
+        self
+    }
+
+    /// Authenticate with the client certificate chain and private key in
+    /// the given PEM bytes.
+    pub fn client_cert_pem(mut self, cert: impl Into<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        self.client_cert = Some((Pem::Bytes(cert.into()), Pem::Bytes(key.into())));
+        self
+    }
+
+    /// Set the name verified against the daemon's certificate.
+    ///
+    /// Defaults to the host part of the address.
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Set the connect, read and write timeouts to the same value.
+    ///
+    /// Pass `None` to disable timeouts.
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        let timeout = timeout.into();
+        self.connect_timeout = timeout;
+        self.read_timeout = timeout;
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Set the timeout for establishing the TCP connection.
+    pub fn connect_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.connect_timeout = timeout.into();
+        self
+    }
+
+    /// Set the timeout for reading from the daemon.
+    pub fn read_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.read_timeout = timeout.into();
+        self
+    }
+
+    /// Set the timeout for writing to the daemon.
+    pub fn write_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.write_timeout = timeout.into();
+        self
+    }
+
+    /// Load the certificates and build the transport.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidTlsConfig`] if no CA is configured, a
+    /// PEM input cannot be read or parsed, or the server name is invalid.
+    pub fn build(self) -> Result<TcpTlsTransport> {
+        let ca = self.ca.ok_or_else(|| {
+            GetMyIdError::InvalidTlsConfig("no CA certificate configured".to_string())
+        })?;
+        let builder = trusting(&ca)?;
+        let config = match self.client_cert {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(cert.certs()?, key.private_key()?)
+                .map_err(|e| {
+                    GetMyIdError::InvalidTlsConfig(format!("invalid client certificate: {e}"))
+                })?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let host = self
+            .server_name
+            .unwrap_or_else(|| host_of(&self.addr).to_string());
+        let server_name = ServerName::try_from(host.clone())
+            .map_err(|_| GetMyIdError::InvalidTlsConfig(format!("invalid server name '{host}'")))?;
+
+        Ok(TcpTlsTransport {
+            addr: self.addr,
+            server_name,
+            config: Arc::new(config),
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+        })
+    }
+}
+
+/// Start a client configuration trusting the certificates in `ca`.
+pub(crate) fn trusting(ca: &Pem) -> Result<ConfigBuilder<ClientConfig, WantsClientCert>> {
+    let mut roots = RootCertStore::empty();
+    for cert in ca.certs()? {
+        roots
+            .add(cert)
+            .map_err(|e| GetMyIdError::InvalidTlsConfig(format!("invalid CA certificate: {e}")))?;
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    Ok(ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| GetMyIdError::InvalidTlsConfig(e.to_string()))?
+        .with_root_certificates(roots))
+}
+
+/// Extract the host from `host:port` or `[ipv6]:port`.
+pub(crate) fn host_of(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Transport reaching a whoami daemon over TCP with mutual TLS.
+#[derive(Clone)]
+pub struct TcpTlsTransport {
+    addr: String,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl fmt::Debug for TcpTlsTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpTlsTransport")
+            .field("addr", &self.addr)
+            .field("server_name", &self.server_name)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("read_timeout", &self.read_timeout)
+            .field("write_timeout", &self.write_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TcpTlsTransport {
+    /// Create a builder for a daemon listening at `addr` (`host:port`).
+    pub fn builder(addr: impl Into<String>) -> TcpTlsTransportBuilder {
+        TcpTlsTransportBuilder::new(addr.into())
+    }
+
+    /// Get the daemon address.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    fn connection_failed(&self, source: io::Error) -> GetMyIdError {
+        GetMyIdError::ConnectionFailed {
+            path: PathBuf::from(&self.addr),
+            resolved: None,
+            source,
+        }
+    }
+
+    fn connect_tcp(&self) -> Result<TcpStream> {
+        let addrs = self
+            .addr
+            .to_socket_addrs()
+            .map_err(|e| self.connection_failed(e))?;
+
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "address resolved to nothing");
+        for addr in addrs {
+            let attempt = match self.connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match attempt {
+                Ok(stream) => return Ok(stream),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    return Err(GetMyIdError::Timeout(
+                        self.connect_timeout.unwrap_or_default(),
+                    ))
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(self.connection_failed(last_error))
+    }
+}
+
+impl Transport for TcpTlsTransport {
+    fn dial(&self) -> Result<Box<dyn Connection>> {
+        let mut tcp = self.connect_tcp()?;
+        tcp.set_read_timeout(self.read_timeout)
+            .map_err(GetMyIdError::ReadError)?;
+        tcp.set_write_timeout(self.write_timeout)
+            .map_err(GetMyIdError::WriteError)?;
+        tcp.set_nodelay(true).ok();
+
+        let mut tls = ClientConnection::new(Arc::clone(&self.config), self.server_name.clone())
+            .map_err(|e| self.connection_failed(io::Error::other(e)))?;
+        // Complete the handshake up front so certificate problems surface as
+        // connection failures rather than read/write errors.
+        while tls.is_handshaking() {
+            tls.complete_io(&mut tcp)
+                .map_err(|e| self.connection_failed(e))?;
+        }
+
+        Ok(Box::new(TlsConnection(StreamOwned::new(tls, tcp))))
+    }
+}
+
+/// An established TLS session over TCP.
+struct TlsConnection(StreamOwned<ClientConnection, TcpStream>);
+
+impl Read for TlsConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for TlsConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Connection for TlsConnection {
+    fn finish_request(&mut self) -> io::Result<()> {
+        self.0.conn.send_close_notify();
+        self.0.flush()?;
+        self.0.sock.shutdown(Shutdown::Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{ServerConfig, ServerConnection};
+    use std::net::TcpListener;
+
+    struct Pki {
+        ca_pem: String,
+        server: CertifiedKey,
+        client: CertifiedKey,
+    }
+
+    fn pki() -> Pki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let leaf = |name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![name.to_string()])
+                .unwrap()
+                .signed_by(&key, &ca, &ca_key)
+                .unwrap();
+            CertifiedKey {
+                cert,
+                key_pair: key,
+            }
+        };
+
+        Pki {
+            ca_pem: ca.pem(),
+            server: leaf("identity.test"),
+            client: leaf("client.test"),
+        }
+    }
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("identity.internal:8443"), "identity.internal");
+        assert_eq!(host_of("[::1]:8443"), "::1");
+        assert_eq!(host_of("identity.internal"), "identity.internal");
+    }
+
+    #[test]
+    fn test_build_requires_ca() {
+        assert!(matches!(
+            TcpTlsTransport::builder("localhost:1").build(),
+            Err(GetMyIdError::InvalidTlsConfig(_))
+        ));
+        assert!(matches!(
+            TcpTlsTransport::builder("localhost:1")
+                .ca_cert_pem("not a certificate")
+                .build(),
+            Err(GetMyIdError::InvalidTlsConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_mutual_tls_roundtrip() {
+        let pki = pki();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+        let mut client_roots = RootCertStore::empty();
+        client_roots
+            .add(CertificateDer::from_pem_slice(pki.ca_pem.as_bytes()).unwrap())
+            .unwrap();
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(client_roots), provider.clone())
+                .build()
+                .unwrap();
+        let server_config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![pki.server.cert.der().clone()],
+                PrivateKeyDer::from_pem_slice(pki.server.key_pair.serialize_pem().as_bytes())
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(Arc::new(server_config)).unwrap();
+            let mut tls = StreamOwned::new(conn, tcp);
+            let mut request = Vec::new();
+            tls.read_to_end(&mut request).unwrap();
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.contains("\"instance_id\":5"));
+            let response = r#"{"status":"ok","identity":"REMOTE","idm_url":"","config_url":"","token":"t","runner":{"instance_id":5}}"#;
+            tls.write_all(response.as_bytes()).unwrap();
+            tls.conn.send_close_notify();
+            tls.flush().unwrap();
+        });
+
+        let transport = TcpTlsTransport::builder(addr.to_string())
+            .ca_cert_pem(pki.ca_pem.clone())
+            .client_cert_pem(pki.client.cert.pem(), pki.client.key_pair.serialize_pem())
+            .server_name("identity.test")
+            .build()
+            .unwrap();
+        let client = Client::builder().transport(transport).build();
+        let identity = client
+            .get_identity_with_runner(Some(crate::RunnerRequest::new().with_instance_id(5)))
+            .unwrap();
+
+        assert_eq!(identity.identity, "REMOTE");
+        server.join().unwrap();
+    }
+}
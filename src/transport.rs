@@ -0,0 +1,748 @@
+//! Transports carrying the request/response exchange with the daemon.
+//!
+//! The clients only need a byte stream to the daemon; a [`Transport`] dials
+//! one per request. [`UnixTransport`] is the default and connects to the
+//! whoami Unix socket. Custom transports make it possible to test against
+//! in-memory pipes or reach the daemon over other channels without forking
+//! the client logic.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, SockAddr, Socket, Type};
+
+use crate::activation::fd_path;
+use crate::error::{GetMyIdError, Result};
+
+/// Prefix marking a socket path as a Linux abstract namespace name.
+///
+/// A socket path of `@whoami` refers to the abstract socket `whoami`, which
+/// has no filesystem entry.
+pub const ABSTRACT_SOCKET_PREFIX: char = '@';
+
+/// Check whether `path` names an abstract namespace socket.
+pub(crate) fn is_abstract(path: &Path) -> bool {
+    path.as_os_str()
+        .as_bytes()
+        .starts_with(&[ABSTRACT_SOCKET_PREFIX as u8])
+}
+
+/// Build the `@name` socket path for an abstract namespace socket.
+pub(crate) fn abstract_path(name: impl AsRef<OsStr>) -> PathBuf {
+    let mut path = vec![ABSTRACT_SOCKET_PREFIX as u8];
+    path.extend_from_slice(name.as_ref().as_bytes());
+    PathBuf::from(OsStr::from_bytes(&path))
+}
+
+/// How symlinks in a socket path are treated when connecting.
+///
+/// Paths are resolved on every connection, so a symlink that is retargeted
+/// while the client runs is followed to its new target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Connect to wherever the path resolves, such as `/run/whoami.sock`
+    /// for `/var/run/whoami.sock`.
+    #[default]
+    Follow,
+    /// Fail with [`GetMyIdError::SymlinkRejected`] if the path or any of its
+    /// parent directories is a symlink.
+    Reject,
+}
+
+/// Make a relative socket path absolute against the current directory, so
+/// it keeps naming the same socket if the process changes directory.
+pub(crate) fn absolute_socket_path(path: PathBuf) -> PathBuf {
+    if is_abstract(&path) || path.is_absolute() {
+        return path;
+    }
+    std::path::absolute(&path).unwrap_or(path)
+}
+
+/// Check that the socket at `path` exists under `policy`, returning where it
+/// resolves to if that is elsewhere.
+pub(crate) fn resolve_socket_path(path: &Path, policy: SymlinkPolicy) -> Result<Option<PathBuf>> {
+    if is_abstract(path) {
+        return Ok(None);
+    }
+    if !path.exists() {
+        return Err(match fs::read_link(path) {
+            Ok(target) => GetMyIdError::DanglingSocketLink {
+                path: path.to_path_buf(),
+                target: path
+                    .parent()
+                    .map_or(target.clone(), |dir| dir.join(&target)),
+            },
+            Err(_) => GetMyIdError::SocketNotFound(path.to_path_buf()),
+        });
+    }
+
+    // Descriptor links such as /proc/self/fd/N cannot always be
+    // canonicalized but still connect
+    let resolved = fs::canonicalize(path)
+        .ok()
+        .filter(|resolved| resolved != path);
+    if policy == SymlinkPolicy::Reject {
+        let through_symlink = path
+            .ancestors()
+            .any(|dir| fs::symlink_metadata(dir).is_ok_and(|meta| meta.file_type().is_symlink()));
+        if through_symlink {
+            return Err(GetMyIdError::SymlinkRejected {
+                path: path.to_path_buf(),
+                resolved: resolved.unwrap_or_else(|| path.to_path_buf()),
+            });
+        }
+    }
+    Ok(resolved)
+}
+
+/// Turn a failed connect to the socket at `path`, resolved to `resolved`,
+/// into an error, inspecting the socket when permission was denied.
+pub(crate) fn connect_error(
+    path: &Path,
+    resolved: Option<PathBuf>,
+    source: io::Error,
+) -> GetMyIdError {
+    if source.kind() != io::ErrorKind::PermissionDenied {
+        return GetMyIdError::ConnectionFailed {
+            path: path.to_path_buf(),
+            resolved,
+            source,
+        };
+    }
+    let metadata = fs::metadata(resolved.as_deref().unwrap_or(path)).ok();
+    GetMyIdError::PermissionDenied {
+        path: path.to_path_buf(),
+        socket_owner: metadata.as_ref().map(MetadataExt::uid),
+        socket_group: metadata.as_ref().map(MetadataExt::gid),
+        socket_mode: metadata.as_ref().map(|m| m.mode() & 0o7777),
+        // geteuid never fails
+        caller_uid: unsafe { libc::geteuid() },
+    }
+}
+
+/// Get the uid of the process on the other end of the connected Unix
+/// socket `fd`, as recorded by the kernel when it connected.
+pub(crate) fn peer_uid(fd: &impl AsRawFd) -> io::Result<u32> {
+    let mut cred = MaybeUninit::<libc::ucred>::zeroed();
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: `cred` and `len` describe a buffer of the size passed
+    let ret = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            cred.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the buffer was zeroed and filled in by the kernel
+    Ok(unsafe { cred.assume_init() }.uid)
+}
+
+/// Fail with [`GetMyIdError::UntrustedDaemon`] unless the peer of `path`
+/// runs as `expected`.
+pub(crate) fn check_peer_uid(path: &Path, uid: io::Result<u32>, expected: u32) -> Result<()> {
+    let uid = uid.map_err(|e| connect_error(path, None, e))?;
+    if uid != expected {
+        return Err(GetMyIdError::UntrustedDaemon {
+            path: path.to_path_buf(),
+            uid,
+            expected,
+        });
+    }
+    Ok(())
+}
+
+/// Translate `@name` into the leading-NUL form the socket APIs expect.
+pub(crate) fn connect_path(path: &Path) -> Cow<'_, Path> {
+    if is_abstract(path) {
+        let mut raw = path.as_os_str().as_bytes().to_vec();
+        raw[0] = 0;
+        Cow::Owned(PathBuf::from(OsStr::from_bytes(&raw)))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// A bidirectional byte stream to the daemon.
+pub trait Connection: Read + Write + Send {
+    /// Signal that the request has been completely written.
+    ///
+    /// Stream transports half-close their write side so the daemon sees
+    /// end-of-file. The default does nothing.
+    fn finish_request(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether the connection preserves message boundaries.
+    ///
+    /// Message-oriented connections always carry a request message, even
+    /// when no runner context is sent, so the daemon knows when to reply.
+    fn is_message_oriented(&self) -> bool {
+        false
+    }
+}
+
+impl Connection for UnixStream {
+    fn finish_request(&mut self) -> io::Result<()> {
+        self.shutdown(std::net::Shutdown::Write)
+    }
+}
+
+/// Opens connections to the daemon for the synchronous [`Client`](crate::Client).
+///
+/// Implementations are responsible for any timeouts of their own.
+///
+/// # Example
+///
+/// ```
+/// use std::io::{self, Cursor, Read, Write};
+/// use getmyid::{Client, Connection, Transport};
+///
+/// /// A transport replaying a canned response.
+/// #[derive(Debug)]
+/// struct Canned(&'static str);
+///
+/// struct CannedConnection(Cursor<&'static str>);
+///
+/// impl Read for CannedConnection {
+///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+///         self.0.read(buf)
+///     }
+/// }
+///
+/// impl Write for CannedConnection {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         Ok(buf.len())
+///     }
+///     fn flush(&mut self) -> io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// impl Connection for CannedConnection {}
+///
+/// impl Transport for Canned {
+///     fn dial(&self) -> getmyid::Result<Box<dyn Connection>> {
+///         Ok(Box::new(CannedConnection(Cursor::new(self.0))))
+///     }
+/// }
+///
+/// let client = Client::builder()
+///     .transport(Canned(r#"{"status":"error","error_code":"E_NO_MATCH","message":"no match"}"#))
+///     .build();
+/// assert!(client.get_identity().is_err());
+/// ```
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Open a new connection for a single request/response exchange.
+    fn dial(&self) -> Result<Box<dyn Connection>>;
+}
+
+/// Kind of Unix socket used to reach the daemon.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SocketType {
+    /// `SOCK_STREAM`: the request ends with a write-side shutdown and the
+    /// response is read until the daemon closes the connection.
+    #[default]
+    Stream,
+    /// `SOCK_SEQPACKET`: the request and the response are each exactly one
+    /// message, so no shutdown or end-of-file is needed to frame them.
+    SeqPacket,
+}
+
+/// Transport connecting to the daemon's Unix Domain Socket.
+///
+/// This is the transport used by [`Client`](crate::Client) unless another
+/// one is configured. Paths starting with [`ABSTRACT_SOCKET_PREFIX`] refer
+/// to Linux abstract namespace sockets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixTransport {
+    path: PathBuf,
+    socket_type: SocketType,
+    symlink_policy: SymlinkPolicy,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    peer_uid: Option<u32>,
+}
+
+impl UnixTransport {
+    /// Create a transport for the socket at `path`, without timeouts.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            socket_type: SocketType::Stream,
+            symlink_policy: SymlinkPolicy::Follow,
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            peer_uid: None,
+        }
+    }
+
+    /// Set the kind of socket to connect with.
+    pub fn with_socket_type(mut self, socket_type: SocketType) -> Self {
+        self.socket_type = socket_type;
+        self
+    }
+
+    /// Set how symlinks in the socket path are treated.
+    pub fn with_symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// Set the timeout for establishing the connection.
+    pub fn with_connect_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.connect_timeout = timeout.into();
+        self
+    }
+
+    /// Set the timeout for reading the daemon's response.
+    pub fn with_read_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.read_timeout = timeout.into();
+        self
+    }
+
+    /// Set the timeout for sending the request.
+    pub fn with_write_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.write_timeout = timeout.into();
+        self
+    }
+
+    /// Only talk to a daemon running as `uid`, checked with `SO_PEERCRED`
+    /// after connecting; see
+    /// [`ClientBuilder::require_peer_uid`](crate::ClientBuilder::require_peer_uid).
+    pub fn with_required_peer_uid(mut self, uid: impl Into<Option<u32>>) -> Self {
+        self.peer_uid = uid.into();
+        self
+    }
+
+    /// Get the socket path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the uid the daemon is required to run as, if any.
+    pub fn required_peer_uid(&self) -> Option<u32> {
+        self.peer_uid
+    }
+
+    /// Get the kind of socket used.
+    pub fn socket_type(&self) -> SocketType {
+        self.socket_type
+    }
+
+    /// Get how symlinks in the socket path are treated.
+    pub fn symlink_policy(&self) -> SymlinkPolicy {
+        self.symlink_policy
+    }
+
+    /// Connect to the socket and apply the configured I/O timeouts.
+    pub(crate) fn connect(&self) -> Result<Socket> {
+        // Check socket exists (abstract sockets have no filesystem entry)
+        let resolved = resolve_socket_path(&self.path, self.symlink_policy)?;
+        let path = resolved.as_deref().unwrap_or(&self.path);
+
+        // Connect to the socket
+        let stream = connect_unix(&connect_path(path), self.socket_type, self.connect_timeout)
+            .map_err(|e| match (e.kind(), self.connect_timeout) {
+                (io::ErrorKind::TimedOut, Some(timeout)) => GetMyIdError::Timeout(timeout),
+                _ => connect_error(&self.path, resolved, e),
+            })?;
+        if let Some(expected) = self.peer_uid {
+            check_peer_uid(&self.path, peer_uid(&stream), expected)?;
+        }
+
+        // Set I/O timeouts (`None` leaves the operation blocking)
+        stream
+            .set_read_timeout(self.read_timeout)
+            .map_err(GetMyIdError::ReadError)?;
+        stream
+            .set_write_timeout(self.write_timeout)
+            .map_err(GetMyIdError::WriteError)?;
+
+        Ok(stream)
+    }
+
+    /// Create an unconnected, non-blocking stream socket for the daemon,
+    /// with its address and where the socket path resolved to.
+    /// Only stream sockets are supported.
+    pub(crate) fn nonblocking_socket(&self) -> Result<(Socket, SockAddr, Option<PathBuf>)> {
+        if self.socket_type != SocketType::Stream {
+            return Err(GetMyIdError::ConnectionFailed {
+                path: self.path.clone(),
+                resolved: None,
+                source: io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "non-blocking requests need a stream socket",
+                ),
+            });
+        }
+        let resolved = resolve_socket_path(&self.path, self.symlink_policy)?;
+        let path = resolved.as_deref().unwrap_or(&self.path);
+        let setup = || {
+            let addr = SockAddr::unix(connect_path(path))?;
+            let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+            socket.set_nonblocking(true)?;
+            Ok((socket, addr))
+        };
+        let (socket, addr) = setup().map_err(|e| connect_error(&self.path, resolved.clone(), e))?;
+        Ok((socket, addr, resolved))
+    }
+}
+
+impl Transport for UnixTransport {
+    fn dial(&self) -> Result<Box<dyn Connection>> {
+        let socket = self.connect()?;
+        Ok(match self.socket_type {
+            SocketType::Stream => Box::new(UnixStream::from(socket)),
+            SocketType::SeqPacket => Box::new(SeqPacketConnection::new(socket)),
+        })
+    }
+}
+
+/// Transport using a connection to the daemon established by someone else,
+/// such as a supervisor that connects before dropping privileges and hands
+/// the connected socket down to the child.
+///
+/// The daemon answers one request per connection, so the connection serves
+/// a single exchange; later requests fail with
+/// [`GetMyIdError::ConnectionFailed`]. Combine it with a
+/// [`cache_ttl`](crate::ClientBuilder::cache_ttl) to keep answering from the
+/// first identity.
+#[derive(Debug)]
+pub struct ConnectedTransport {
+    pub(crate) path: PathBuf,
+    stream: Mutex<Option<UnixStream>>,
+}
+
+impl ConnectedTransport {
+    /// Create a transport for the connected stream socket `stream`.
+    pub fn new(stream: UnixStream) -> Self {
+        Self {
+            path: fd_path(stream.as_raw_fd()),
+            stream: Mutex::new(Some(stream)),
+        }
+    }
+
+    /// Whether the connection has not been used yet.
+    pub fn is_unused(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    /// Take the connection, failing if it was already used.
+    pub(crate) fn take(&self) -> Result<UnixStream> {
+        self.lock()
+            .take()
+            .ok_or_else(|| GetMyIdError::ConnectionFailed {
+                path: self.path.clone(),
+                resolved: None,
+                source: io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "the pre-established connection was already used",
+                ),
+            })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<UnixStream>> {
+        self.stream.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl From<OwnedFd> for ConnectedTransport {
+    fn from(fd: OwnedFd) -> Self {
+        Self::new(UnixStream::from(fd))
+    }
+}
+
+impl Transport for ConnectedTransport {
+    fn dial(&self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(self.take()?))
+    }
+}
+
+/// A `SOCK_SEQPACKET` connection exchanging one request and one response
+/// message.
+///
+/// Every write is sent as a single message. The first read receives the
+/// whole response message; later reads report end-of-file.
+struct SeqPacketConnection {
+    socket: Socket,
+    response: Option<Cursor<Vec<u8>>>,
+}
+
+impl SeqPacketConnection {
+    fn new(socket: Socket) -> Self {
+        Self {
+            socket,
+            response: None,
+        }
+    }
+
+    fn recv_message(&self) -> io::Result<Vec<u8>> {
+        // Peek with MSG_TRUNC to learn the full message length, so a small
+        // caller buffer cannot truncate the message.
+        let mut probe = [MaybeUninit::<u8>::uninit(); 1];
+        let len = self
+            .socket
+            .recv_with_flags(&mut probe, libc::MSG_PEEK | libc::MSG_TRUNC)?;
+
+        let mut message = vec![0u8; len];
+        let received = (&self.socket).read(&mut message)?;
+        message.truncate(received);
+        Ok(message)
+    }
+}
+
+impl Read for SeqPacketConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.response.is_none() {
+            self.response = Some(Cursor::new(self.recv_message()?));
+        }
+        self.response
+            .as_mut()
+            .map_or(Ok(0), |response| response.read(buf))
+    }
+}
+
+impl Write for SeqPacketConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for SeqPacketConnection {
+    fn is_message_oriented(&self) -> bool {
+        true
+    }
+}
+
+/// Connect to a Unix socket, bounding the connect by `timeout`.
+///
+/// A daemon whose accept backlog is full makes a non-blocking connect fail
+/// with `WouldBlock`; that case is retried until the deadline expires.
+fn connect_unix(
+    path: &Path,
+    socket_type: SocketType,
+    timeout: Option<Duration>,
+) -> io::Result<Socket> {
+    let addr = SockAddr::unix(path)?;
+    let ty = match socket_type {
+        SocketType::Stream => Type::STREAM,
+        SocketType::SeqPacket => Type::SEQPACKET,
+    };
+    let Some(timeout) = timeout else {
+        let socket = Socket::new(Domain::UNIX, ty, None)?;
+        socket.connect(&addr)?;
+        return Ok(socket);
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        let socket = Socket::new(Domain::UNIX, ty, None)?;
+        match socket.connect_timeout(&addr, remaining) {
+            Ok(()) => return Ok(socket),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(remaining.min(Duration::from_millis(10)));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{SocketAddr, UnixListener};
+
+    #[test]
+    fn test_abstract_paths() {
+        let path = abstract_path("whoami");
+
+        assert_eq!(path, Path::new("@whoami"));
+        assert!(is_abstract(&path));
+        assert!(!is_abstract(Path::new("/run/whoami.sock")));
+        assert_eq!(connect_path(&path).as_os_str().as_bytes(), b"\0whoami");
+    }
+
+    #[test]
+    fn test_connect_abstract_socket() {
+        let name = format!("getmyid-test-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let listener = UnixListener::bind_addr(&addr).unwrap();
+
+        for timeout in [None, Some(Duration::from_secs(1))] {
+            let transport = UnixTransport::new(abstract_path(&name)).with_connect_timeout(timeout);
+            transport.connect().unwrap();
+            listener.accept().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_required_peer_uid() {
+        let name = format!("getmyid-test-peer-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let _listener = UnixListener::bind_addr(&addr).unwrap();
+        let uid = unsafe { libc::geteuid() };
+
+        let transport = UnixTransport::new(abstract_path(&name));
+        transport.with_required_peer_uid(uid).connect().unwrap();
+        let impostor = UnixTransport::new(abstract_path(&name)).with_required_peer_uid(uid + 1);
+        match impostor.connect() {
+            Err(GetMyIdError::UntrustedDaemon {
+                uid: peer,
+                expected,
+                ..
+            }) => {
+                assert_eq!((peer, expected), (uid, uid + 1));
+            }
+            other => panic!("expected UntrustedDaemon, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_seqpacket_exchange() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("whoami.sock");
+        let listener = Socket::new(Domain::UNIX, Type::SEQPACKET, None).unwrap();
+        listener.bind(&SockAddr::unix(&path).unwrap()).unwrap();
+        listener.listen(1).unwrap();
+
+        let response = "x".repeat(10_000);
+        let expected = response.clone();
+        let server = std::thread::spawn(move || {
+            let (conn, _) = listener.accept().unwrap();
+            let mut request = [0u8; 64];
+            let len = (&conn).read(&mut request).unwrap();
+            assert_eq!(&request[..len], b"{}");
+            conn.send(response.as_bytes()).unwrap();
+            // Keep the connection open: framing must not depend on EOF
+            std::thread::sleep(Duration::from_millis(200));
+        });
+
+        let transport = UnixTransport::new(&path)
+            .with_socket_type(SocketType::SeqPacket)
+            .with_read_timeout(Duration::from_millis(100));
+        let mut conn = transport.dial().unwrap();
+        assert!(conn.is_message_oriented());
+        conn.write_all(b"{}").unwrap();
+
+        let mut received = String::new();
+        conn.read_to_string(&mut received).unwrap();
+        assert_eq!(received, expected);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_symlinked_socket_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(dir.path()).unwrap();
+        let real = dir.join("run");
+        fs::create_dir(&real).unwrap();
+        std::os::unix::fs::symlink(&real, dir.join("var-run")).unwrap();
+        let path = dir.join("var-run/whoami.sock");
+
+        assert!(matches!(
+            UnixTransport::new(&path).connect(),
+            Err(GetMyIdError::SocketNotFound(p)) if p == path
+        ));
+
+        std::os::unix::fs::symlink(real.join("gone.sock"), real.join("whoami.sock")).unwrap();
+        let err = UnixTransport::new(&path).connect().unwrap_err();
+        assert!(
+            matches!(&err, GetMyIdError::DanglingSocketLink { path: p, .. } if *p == path),
+            "{err}"
+        );
+        fs::remove_file(real.join("whoami.sock")).unwrap();
+
+        let _listener = UnixListener::bind(real.join("whoami.sock")).unwrap();
+        UnixTransport::new(&path).connect().unwrap();
+        assert_eq!(
+            resolve_socket_path(&path, SymlinkPolicy::Follow).unwrap(),
+            Some(real.join("whoami.sock"))
+        );
+        assert!(matches!(
+            UnixTransport::new(&path)
+                .with_symlink_policy(SymlinkPolicy::Reject)
+                .connect(),
+            Err(GetMyIdError::SymlinkRejected { resolved, .. }) if resolved == real.join("whoami.sock")
+        ));
+        UnixTransport::new(real.join("whoami.sock"))
+            .with_symlink_policy(SymlinkPolicy::Reject)
+            .connect()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_connect_error_permission_denied() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("whoami.sock");
+        let _listener = UnixListener::bind(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o660)).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        match connect_error(&path, None, denied) {
+            GetMyIdError::PermissionDenied {
+                socket_owner,
+                socket_group,
+                socket_mode,
+                caller_uid,
+                ..
+            } => {
+                assert_eq!(socket_owner, Some(metadata.uid()));
+                assert_eq!(socket_group, Some(metadata.gid()));
+                assert_eq!(socket_mode, Some(0o660));
+                assert_eq!(caller_uid, unsafe { libc::geteuid() });
+            }
+            other => panic!("unexpected error: {other}"),
+        }
+
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert!(matches!(
+            connect_error(&path, None, refused),
+            GetMyIdError::ConnectionFailed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_absolute_socket_path() {
+        let cwd = std::env::current_dir().unwrap();
+
+        assert_eq!(
+            absolute_socket_path("whoami.sock".into()),
+            cwd.join("whoami.sock")
+        );
+        assert_eq!(
+            absolute_socket_path("/run/whoami.sock".into()),
+            Path::new("/run/whoami.sock")
+        );
+        assert_eq!(absolute_socket_path("@whoami".into()), Path::new("@whoami"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Identities whose runner object deserializes into a caller-defined type.
+
+use std::fmt;
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{GetMyIdError, Result};
+use crate::protocol::{parse_response, STATUS_OK};
+use crate::sensitive::SensitiveString;
+use crate::support::redact_token;
+use crate::types::{expiry_from_ttl, BootstrapPayload};
+
+/// An [`Identity`](crate::Identity) whose `runner` object is parsed
+/// straight into `R`.
+///
+/// Like [`Identity`](crate::Identity), it redacts the token in `Debug`
+/// output.
+///
+/// Applications with a fixed context schema can describe the runner,
+/// server-injected fields and custom fields alike, as one struct, and skip
+/// the detour through [`Runner::extra`](crate::Runner::extra). Get one
+/// with [`Client::get_identity_as`](crate::Client::get_identity_as) or
+/// [`TypedIdentity::parse`].
+///
+/// Only responses with a `runner` object are understood: daemons using the
+/// flat format predating it are rejected with
+/// [`GetMyIdError::MissingField`].
+///
+/// # Example
+///
+/// ```
+/// use getmyid::TypedIdentity;
+///
+/// #[derive(serde::Deserialize)]
+/// struct Placement {
+///     pid: u32,
+///     region: String,
+/// }
+///
+/// let response = r#"{"status":"ok","identity":"BILLING_PROD","idm_url":"","config_url":"","token":"tok","runner":{"pid":42,"region":"eu"}}"#;
+/// let identity: TypedIdentity<Placement> = TypedIdentity::parse(response)?;
+/// assert_eq!(identity.runner.region, "eu");
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypedIdentity<R> {
+    /// Application-level identity name.
+    pub identity: String,
+    /// Identity Management (OAuth2/OIDC) URL.
+    pub idm_url: String,
+    /// Configuration/API server URL.
+    pub config_url: String,
+    /// Authentication token.
+    pub token: SensitiveString,
+    /// Token expiry in seconds since the Unix epoch, from `expires_at` or
+    /// `ttl`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Configuration payload inlined by the daemon, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bootstrap: Option<BootstrapPayload>,
+    /// The runner object.
+    pub runner: R,
+}
+
+impl<R: fmt::Debug> fmt::Debug for TypedIdentity<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedIdentity")
+            .field("identity", &self.identity)
+            .field("idm_url", &self.idm_url)
+            .field("config_url", &self.config_url)
+            .field("token", &redact_token(&self.token))
+            .field("expires_at", &self.expires_at)
+            .field("bootstrap", &self.bootstrap)
+            .field("runner", &self.runner)
+            .finish()
+    }
+}
+
+impl<R: DeserializeOwned> TypedIdentity<R> {
+    /// Parse a daemon response, deserializing its runner object into `R`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as the clients for error and degraded
+    /// responses, [`GetMyIdError::MissingField`] for responses without a
+    /// runner object and [`GetMyIdError::InvalidJson`] if the response is
+    /// malformed or the runner does not match `R`.
+    pub fn parse(response: &str) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Envelope {
+            status: String,
+            #[serde(default)]
+            ttl: Option<u64>,
+            #[serde(default)]
+            runner: Option<IgnoredAny>,
+        }
+
+        let envelope: Envelope = serde_json::from_str(response)
+            .map_err(|e| GetMyIdError::invalid_response(e, response))?;
+        if envelope.status != STATUS_OK {
+            return parse_response(response)
+                .and(Err(GetMyIdError::MissingField { field: "identity" }));
+        }
+        if envelope.runner.is_none() {
+            return Err(GetMyIdError::MissingField { field: "runner" });
+        }
+        let mut identity: Self = serde_json::from_str(response)
+            .map_err(|e| GetMyIdError::invalid_response(e, response))?;
+        identity.expires_at = identity.expires_at.or(envelope.ttl.map(expiry_from_ttl));
+        Ok(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Runner;
+
+    const RESPONSE: &str = r#"{"status":"ok","identity":"BILLING_PROD","idm_url":"https://auth.example.com","config_url":"","token":"tok","ttl":60,"runner":{"identity":"BILLING_PROD","pid":42,"region":"eu","shard":7}}"#;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Placement {
+        pid: u32,
+        region: String,
+        shard: u32,
+    }
+
+    #[test]
+    fn test_typed_identity_parse() {
+        let identity: TypedIdentity<Placement> = TypedIdentity::parse(RESPONSE).unwrap();
+        assert_eq!(identity.identity, "BILLING_PROD");
+        assert!(identity.expires_at.is_some());
+        let placement = Placement {
+            pid: 42,
+            region: "eu".to_string(),
+            shard: 7,
+        };
+        assert_eq!(identity.runner, placement);
+        assert!(!format!("{identity:?}").contains(r#""tok""#));
+
+        let owned = parse_response(RESPONSE).unwrap();
+        let identity: TypedIdentity<Runner> = TypedIdentity::parse(RESPONSE).unwrap();
+        assert_eq!(identity.runner, owned.runner);
+    }
+
+    #[test]
+    fn test_typed_identity_errors() {
+        let error = r#"{"status":"error","error_code":"E_NO_MATCH","message":"m"}"#;
+        assert!(matches!(
+            TypedIdentity::<Placement>::parse(error),
+            Err(GetMyIdError::DaemonError { .. })
+        ));
+        let flat = r#"{"status":"ok","identity":"A","idm_url":"","config_url":"","token":"t"}"#;
+        assert!(matches!(
+            TypedIdentity::<Placement>::parse(flat),
+            Err(GetMyIdError::MissingField { field: "runner" })
+        ));
+        let mismatched = RESPONSE.replace(r#""shard":7"#, r#""shard":"7""#);
+        assert!(matches!(
+            TypedIdentity::<Placement>::parse(&mismatched),
+            Err(GetMyIdError::InvalidJson { .. })
+        ));
+    }
+}
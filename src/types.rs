@@ -1,7 +1,29 @@
 //! Identity types returned by the whoami daemon.
 
+use serde::de::value::MapDeserializer;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::compatibility::DaemonVersion;
+use crate::error::{mask_tokens, GetMyIdError, Result};
+use crate::procfs;
+use crate::protocol::RESERVED_RUNNER_FIELDS;
+use crate::sensitive::SensitiveString;
+use crate::support::{redact_token, REDACTED};
+
+/// Downward API environment variables read by
+/// [`RunnerRequest::from_environment`], by the field they fill.
+const KUBERNETES_VARS: &[(&str, &str)] = &[
+    ("pod_name", "POD_NAME"),
+    ("pod_namespace", "POD_NAMESPACE"),
+    ("node_name", "NODE_NAME"),
+];
+
+/// Namespace of the pod, mounted with its service account token.
+const SERVICE_ACCOUNT_NAMESPACE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
 
 /// Runner information containing both client-provided context and
 /// server-injected identity fields.
@@ -19,6 +41,13 @@ pub struct Runner {
     #[serde(default)]
     pub hostname: String,
 
+    /// Hostname of the client's own UTS namespace, set by the client when it
+    /// differs from the one reported by whoami, e.g. inside a container.
+    ///
+    /// [`HostnamePolicy`] decides which of the two `hostname` holds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_hostname: Option<String>,
+
     /// Process name (injected by whoami).
     #[serde(default)]
     pub process: String,
@@ -48,6 +77,202 @@ pub struct Runner {
     pub extra: HashMap<String, serde_json::Value>,
 }
 
+/// Naming convention applied to the keys of runner extras.
+///
+/// The typed runner fields (`instance_id`, `timestamp`, ...) are part of the
+/// daemon protocol and are never renamed; only the free-form `extra` keys
+/// are normalized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyCase {
+    /// Keep keys exactly as written.
+    #[default]
+    Preserve,
+    /// `snake_case`, e.g. `instanceGroup` becomes `instance_group`.
+    Snake,
+    /// `camelCase`, e.g. `instance_group` becomes `instanceGroup`.
+    ///
+    /// The case of the remaining letters is preserved, so `tls_CA_path`
+    /// becomes `tlsCAPath`.
+    Camel,
+}
+
+/// Which hostname [`Runner::hostname`] holds when the client runs in a
+/// different UTS namespace than the daemon, e.g. inside a container.
+///
+/// Either way, [`Runner::client_hostname`] holds the client's hostname
+/// whenever the two differ.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HostnamePolicy {
+    /// The hostname reported by the daemon.
+    #[default]
+    Daemon,
+    /// The hostname of the client's own UTS namespace; the daemon-reported
+    /// hostname is dropped.
+    Client,
+}
+
+impl KeyCase {
+    /// Convert `key` to this convention.
+    pub fn apply(self, key: &str) -> String {
+        match self {
+            Self::Preserve => key.to_string(),
+            Self::Snake => to_snake_case(key),
+            Self::Camel => to_camel_case(key),
+        }
+    }
+
+    /// Rename the keys of `extra` to this convention.
+    ///
+    /// When two keys map to the same name, the one already written in this
+    /// convention wins.
+    fn normalize(self, extra: &mut HashMap<String, serde_json::Value>) {
+        if self == Self::Preserve {
+            return;
+        }
+        let renamed: Vec<_> = extra
+            .keys()
+            .filter(|key| self.apply(key) != **key)
+            .cloned()
+            .collect();
+        for key in renamed {
+            if let Some(value) = extra.remove(&key) {
+                extra.entry(self.apply(&key)).or_insert(value);
+            }
+        }
+    }
+}
+
+/// Generate a random (version 4) UUID in its hyphenated form.
+fn new_uuid() -> String {
+    let mut bytes = crate::protocol::random_bytes();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn to_snake_case(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    let mut out = String::with_capacity(key.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '-' {
+            out.push('_');
+        } else if c.is_uppercase() {
+            let prev = i.checked_sub(1).map(|p| chars[p]);
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            let boundary = match prev {
+                Some(p) if p.is_lowercase() || p.is_ascii_digit() => true,
+                Some(p) if p.is_uppercase() => next_lower,
+                _ => false,
+            };
+            if boundary {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn to_camel_case(key: &str) -> String {
+    let body = key.trim_start_matches(['_', '-']);
+    let mut out = key[..key.len() - body.len()].to_string();
+    for (i, word) in body.split(['_', '-']).filter(|w| !w.is_empty()).enumerate() {
+        let mut chars = word.chars();
+        let Some(first) = chars.next() else { continue };
+        if i == 0 {
+            if word.chars().all(|c| !c.is_lowercase()) {
+                out.push_str(&word.to_lowercase());
+            } else {
+                out.extend(first.to_lowercase());
+                out.push_str(chars.as_str());
+            }
+        } else {
+            out.extend(first.to_uppercase());
+            out.push_str(chars.as_str());
+        }
+    }
+    out
+}
+
+impl Runner {
+    /// Rename the keys of [`Runner::extra`] to `case`.
+    pub fn normalize_keys(&mut self, case: KeyCase) {
+        case.normalize(&mut self.extra);
+    }
+
+    /// Get the request ID the daemon echoed from the runner request, if any.
+    pub fn request_id(&self) -> Option<&str> {
+        self.extra.get("request_id")?.as_str()
+    }
+
+    /// Deserialize the custom fields in [`Runner::extra`] into `T`, e.g. a
+    /// struct describing the context the application sends.
+    ///
+    /// Fields of `T` missing from the extras need `#[serde(default)]`;
+    /// extras `T` does not name are ignored unless it denies unknown
+    /// fields.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidJson`] if the extras do not match
+    /// `T`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// #[derive(serde::Deserialize)]
+    /// struct Placement {
+    ///     region: String,
+    ///     shard: u32,
+    /// }
+    ///
+    /// let identity = getmyid::get_identity()?;
+    /// let placement: Placement = identity.runner.extra_as()?;
+    /// println!("{} shard {}", placement.region, placement.shard);
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn extra_as<T: DeserializeOwned>(&self) -> Result<T> {
+        let fields = self.extra.iter().map(|(key, value)| (key.as_str(), value));
+        T::deserialize(MapDeserializer::new(fields)).map_err(GetMyIdError::invalid_json)
+    }
+
+    /// Record `local`, the hostname of the client's UTS namespace, if it
+    /// differs from the daemon-reported hostname, and apply `policy`.
+    pub(crate) fn apply_hostname_policy(&mut self, policy: HostnamePolicy, local: Option<String>) {
+        self.client_hostname = local.filter(|local| *local != self.hostname);
+        if let (HostnamePolicy::Client, Some(local)) = (policy, &self.client_hostname) {
+            self.hostname = local.clone();
+        }
+    }
+
+    /// Merge client-provided context into this runner, the way the daemon
+    /// does: client fields are added, server-injected fields are kept.
+    pub(crate) fn merge_request(&mut self, request: RunnerRequest) {
+        if request.instance_id.is_some() {
+            self.instance_id = request.instance_id;
+        }
+        if request.timestamp.is_some() {
+            self.timestamp = request.timestamp;
+        }
+        for (key, value) in request.well_known_fields() {
+            if let Some(value) = value {
+                self.extra.insert(key.to_string(), value.clone().into());
+            }
+        }
+        self.extra.extend(request.extra);
+    }
+}
+
 /// Client-provided runner context to send to whoami daemon.
 ///
 /// These fields will be merged with server-injected identity fields
@@ -62,6 +287,28 @@ pub struct RunnerRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timestamp: Option<u64>,
 
+    /// Region the instance runs in, e.g. `eu-west-1`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+
+    /// Environment the instance belongs to, e.g. `production`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+
+    /// Version of the service, e.g. `1.4.2`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_version: Option<String>,
+
+    /// Deployment the instance was rolled out by, e.g. `canary`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+
+    /// Identifier of this lookup, e.g. a UUID set by
+    /// [`with_request_id`](RunnerRequest::with_request_id), correlating
+    /// client, daemon and config-server logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+
     /// Additional custom fields.
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
@@ -73,41 +320,321 @@ impl RunnerRequest {
         Self::default()
     }
 
+    /// Create a runner request describing where the process runs, for
+    /// workloads on Kubernetes or in containers.
+    ///
+    /// Fills the custom fields `pod_name`, `pod_namespace` and `node_name`
+    /// from the `POD_NAME`, `POD_NAMESPACE` and `NODE_NAME` environment
+    /// variables, which the pod spec maps from the downward API, and adds
+    /// the [container context](RunnerRequest::with_container_context). On
+    /// Kubernetes, the pod name falls back to `HOSTNAME` and the namespace
+    /// to the service account's. Fields that cannot be determined are left
+    /// out.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use getmyid::{Client, RunnerRequest};
+    ///
+    /// let runner = RunnerRequest::from_environment().with_instance_id(1);
+    /// let identity = Client::new().get_identity_with_runner(Some(runner))?;
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn from_environment() -> Self {
+        let namespace = std::fs::read_to_string(SERVICE_ACCOUNT_NAMESPACE).ok();
+        Self::from_vars(|name| std::env::var(name).ok(), namespace).with_container_context()
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>, namespace: Option<String>) -> Self {
+        let var = |name: &str| {
+            var(name)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let mut request = Self::new();
+        for (field, name) in KUBERNETES_VARS {
+            if let Some(value) = var(name) {
+                request.extra.insert(field.to_string(), value.into());
+            }
+        }
+        if var("KUBERNETES_SERVICE_HOST").is_some() {
+            let fallbacks = [
+                ("pod_name", var("HOSTNAME")),
+                ("pod_namespace", namespace.map(|ns| ns.trim().to_string())),
+            ];
+            for (field, value) in fallbacks {
+                let extra = &mut request.extra;
+                if let Some(value) = value.filter(|value| !value.is_empty()) {
+                    extra.entry(field.to_string()).or_insert(value.into());
+                }
+            }
+        }
+        request
+    }
+
+    /// Add the custom fields `container_id` and `cgroup_path`, read from
+    /// `/proc` and container runtime files, so the daemon's rules and the
+    /// config server can tell apart containers sharing a UID.
+    ///
+    /// The container ID is the 64-digit hex ID Docker, containerd, CRI-O
+    /// and Podman assign. Fields that cannot be determined, e.g. outside a
+    /// container or behind a cgroup namespace, are left out.
+    pub fn with_container_context(mut self) -> Self {
+        let context = [
+            ("container_id", procfs::container_id()),
+            ("cgroup_path", procfs::cgroup_path()),
+        ];
+        for (field, value) in context {
+            if let Some(value) = value {
+                self.extra.insert(field.to_string(), value.into());
+            }
+        }
+        self
+    }
+
+    /// Create a runner request from an application's own context type.
+    ///
+    /// `context` must serialize to a JSON object. Its `instance_id`,
+    /// `timestamp`, `region`, `environment`, `service_version`, `deployment`
+    /// and `request_id` fields fill the typed fields, the others become custom
+    /// fields, so the daemon merges it as if it was built field by field.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidJson`] if `context` cannot be
+    /// serialized, is not an object or has a typed field of the wrong type.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use getmyid::RunnerRequest;
+    ///
+    /// #[derive(serde::Serialize)]
+    /// struct Context {
+    ///     instance_id: u64,
+    ///     shard: u32,
+    /// }
+    ///
+    /// let request = RunnerRequest::from_context(&Context { instance_id: 4, shard: 7 })?;
+    /// assert_eq!(request, RunnerRequest::new().with_instance_id(4).with_field("shard", 7));
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn from_context<T: Serialize + ?Sized>(context: &T) -> Result<Self> {
+        let value = serde_json::to_value(context).map_err(GetMyIdError::invalid_json)?;
+        serde_json::from_value(value).map_err(GetMyIdError::invalid_json)
+    }
+
     /// Set the instance ID.
     pub fn with_instance_id(mut self, id: u64) -> Self {
+        self.extra.remove("instance_id");
         self.instance_id = Some(id);
         self
     }
 
     /// Set the timestamp.
     pub fn with_timestamp(mut self, ts: u64) -> Self {
+        self.extra.remove("timestamp");
         self.timestamp = Some(ts);
         self
     }
 
+    /// Set the region.
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.extra.remove("region");
+        self.region = Some(region.into());
+        self
+    }
+
+    /// Set the environment.
+    pub fn with_environment(mut self, environment: impl Into<String>) -> Self {
+        self.extra.remove("environment");
+        self.environment = Some(environment.into());
+        self
+    }
+
+    /// Set the service version.
+    pub fn with_service_version(mut self, version: impl Into<String>) -> Self {
+        self.extra.remove("service_version");
+        self.service_version = Some(version.into());
+        self
+    }
+
+    /// Set the deployment.
+    pub fn with_deployment(mut self, deployment: impl Into<String>) -> Self {
+        self.extra.remove("deployment");
+        self.deployment = Some(deployment.into());
+        self
+    }
+
+    /// Set the request ID to a fresh random (version 4) UUID.
+    ///
+    /// The daemon echoes it in the response's `runner` object, read with
+    /// [`Runner::request_id`], and logs it, so the lookup can be traced
+    /// through the daemon and the config server. Set
+    /// [`request_id`](RunnerRequest::request_id) directly to use an ID of
+    /// your own.
+    pub fn with_request_id(mut self) -> Self {
+        self.extra.remove("request_id");
+        self.request_id = Some(new_uuid());
+        self
+    }
+
+    /// Give this request a fresh request ID unless it already carries one.
+    pub(crate) fn ensure_request_id(self) -> Self {
+        if self.carries("request_id") {
+            self
+        } else {
+            self.with_request_id()
+        }
+    }
+
     /// Set the timestamp to the current Unix timestamp.
-    pub fn with_current_timestamp(mut self) -> Self {
-        self.timestamp = Some(
+    pub fn with_current_timestamp(self) -> Self {
+        self.with_timestamp(
             std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .map(|d| d.as_secs())
                 .unwrap_or(0),
-        );
+        )
+    }
+
+    /// Rename the keys of the custom fields to `case`.
+    pub fn normalize_keys(mut self, case: KeyCase) -> Self {
+        case.normalize(&mut self.extra);
         self
     }
 
     /// Add a custom field.
-    pub fn with_field(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
-        self.extra.insert(key.into(), value.into());
+    ///
+    /// A typed field sent under the same key, e.g. `region`, is unset so the
+    /// key is sent once. Prefer the typed setters for these keys: a request
+    /// parsed back from JSON, e.g. by `FakeDaemon`, holds them in the typed
+    /// fields.
+    pub fn with_field(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Self {
+        let key = key.into();
+        self.clear_typed_field(&key);
+        self.extra.insert(key, value.into());
+        self
+    }
+
+    /// Unset the typed field sent under `key`, if any.
+    fn clear_typed_field(&mut self, key: &str) {
+        match key {
+            "instance_id" => self.instance_id = None,
+            "timestamp" => self.timestamp = None,
+            "region" => self.region = None,
+            "environment" => self.environment = None,
+            "service_version" => self.service_version = None,
+            "deployment" => self.deployment = None,
+            "request_id" => self.request_id = None,
+            _ => {}
+        }
+    }
+
+    /// Fill in the fields of `defaults` that this request does not set.
+    ///
+    /// Fields set on this request win, including custom fields with the
+    /// same key as a typed field of `defaults` and the other way around.
+    pub fn with_defaults(mut self, defaults: &RunnerRequest) -> Self {
+        let mut defaults = defaults.clone();
+        for key in self.extra.keys() {
+            defaults.clear_typed_field(key);
+        }
+        defaults.extra.retain(|key, _| !self.carries(key));
+        self.instance_id = self.instance_id.or(defaults.instance_id);
+        self.timestamp = self.timestamp.or(defaults.timestamp);
+        self.region = self.region.or_else(|| defaults.region.clone());
+        self.environment = self.environment.or_else(|| defaults.environment.clone());
+        self.service_version = self
+            .service_version
+            .or_else(|| defaults.service_version.clone());
+        self.deployment = self.deployment.or_else(|| defaults.deployment.clone());
+        self.request_id = self.request_id.or_else(|| defaults.request_id.clone());
+        for (key, value) in &defaults.extra {
+            self.extra
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
         self
     }
+
+    /// Check that no custom field collides with a field the daemon injects
+    /// into the `runner` object, listed in
+    /// [`RESERVED_RUNNER_FIELDS`](crate::protocol::RESERVED_RUNNER_FIELDS).
+    ///
+    /// The clients check every runner request before sending it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::ReservedField`] naming the first colliding
+    /// field.
+    pub fn check(&self) -> Result<()> {
+        match RESERVED_RUNNER_FIELDS
+            .iter()
+            .find(|field| self.extra.contains_key(**field))
+        {
+            Some(field) => Err(GetMyIdError::ReservedField {
+                field: field.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The typed string fields, by the key they are sent under.
+    pub(crate) fn well_known_fields(&self) -> [(&'static str, &Option<String>); 5] {
+        [
+            ("region", &self.region),
+            ("environment", &self.environment),
+            ("service_version", &self.service_version),
+            ("deployment", &self.deployment),
+            ("request_id", &self.request_id),
+        ]
+    }
+
+    /// Check if this request sends a field named `key`, which the daemon
+    /// then echoes in the response's `runner` object.
+    pub(crate) fn carries(&self, key: &str) -> bool {
+        self.extra.contains_key(key)
+            || self
+                .well_known_fields()
+                .iter()
+                .any(|(name, value)| *name == key && value.is_some())
+    }
+}
+
+/// A small configuration blob inlined in the daemon response, saving a
+/// round trip to the config server.
+///
+/// The payload is kept as sent; `Identity::bootstrap_config()` (requires
+/// `bootstrap` feature) decodes, verifies and parses it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct BootstrapPayload {
+    /// How `data` is encoded; `gzip+base64` is the only encoding defined.
+    pub encoding: String,
+    /// The encoded payload.
+    pub data: String,
+    /// Hex-encoded SHA-256 digest of the decoded payload.
+    pub sha256: String,
 }
 
 /// Identity information returned by the whoami daemon.
 ///
 /// This struct contains the application-level identity (name and URLs)
 /// and a `runner` object with process/host details and client context.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// The token is redacted in `Debug` output; log
+/// [`redacted`](Identity::redacted) when serializing.
+///
+/// Identities are normally parsed from daemon responses. Providers, tests
+/// and tooling build their own with [`Identity::new`] and the `with_*`
+/// methods, as fields may be added in minor releases.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct Identity {
     /// Application-level identity name (from rules.conf).
     pub identity: String,
@@ -119,41 +646,751 @@ pub struct Identity {
     pub config_url: String,
 
     /// Authentication token for this identity.
-    pub token: String,
+    pub token: SensitiveString,
+
+    /// When the token expires, in seconds since the Unix epoch.
+    ///
+    /// Read from the daemon's `expires_at` field, or computed from its `ttl`
+    /// (seconds) when the response was parsed. `None` if the daemon reports
+    /// no expiry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+
+    /// Configuration payload inlined by the daemon, if any.
+    ///
+    /// Decode it with `bootstrap_config()` (requires `bootstrap` feature).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bootstrap: Option<BootstrapPayload>,
 
     /// Runner information containing process details and client context.
     /// This object can be passed directly to a config server.
     pub runner: Runner,
 }
 
-/// Raw response from the whoami daemon.
-#[derive(Debug, Deserialize)]
-pub(crate) struct DaemonResponse {
-    pub status: String,
-    #[serde(flatten)]
-    pub data: ResponseData,
-}
-
-/// Response data variants.
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-pub(crate) enum ResponseData {
-    Success {
-        identity: String,
-        idm_url: String,
-        config_url: String,
-        token: String,
-        runner: Runner,
+/// An [`Identity`] together with the daemon response it was parsed from,
+/// returned by `get_identity_raw`.
+///
+/// The response is kept byte for byte as the daemon sent it, token
+/// included, so it can be forwarded or logged for audit without
+/// re-serializing. `Debug` output masks the token.
+#[derive(Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RawIdentity {
+    /// The parsed identity.
+    pub identity: Identity,
+    /// The daemon response as received.
+    pub raw: SensitiveString,
+    /// Versions the daemon reported in the response.
+    pub daemon_version: DaemonVersion,
+}
+
+impl RawIdentity {
+    /// Pair `identity` with `raw`, the response it was parsed from.
+    pub(crate) fn new(identity: Identity, raw: String) -> Self {
+        let daemon_version = DaemonVersion::from_response(&raw);
+        Self {
+            identity,
+            raw: raw.into(),
+            daemon_version,
+        }
+    }
+
+    /// Take the parsed identity, dropping the response.
+    pub fn into_identity(self) -> Identity {
+        self.identity
+    }
+
+    /// Parse the daemon response into a JSON value.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidJson`] if the response is not valid
+    /// JSON, which cannot happen for responses the identity was parsed from.
+    pub fn json(&self) -> Result<serde_json::Value> {
+        serde_json::from_str(&self.raw).map_err(GetMyIdError::invalid_json)
+    }
+}
+
+impl fmt::Debug for RawIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawIdentity")
+            .field("identity", &self.identity)
+            .field("raw", &mask_tokens(&self.raw))
+            .field("daemon_version", &self.daemon_version)
+            .finish()
+    }
+}
+
+/// Outcome of a long-poll with `Client::wait_for_change`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentityChange {
+    /// The identity changed, or differed from `since` already.
+    Changed {
+        /// The new identity.
+        identity: Box<Identity>,
+        /// Version to wait from next.
+        version: String,
     },
-    Error {
-        error_code: String,
-        message: String,
+    /// The wait timed out without a change.
+    Unchanged {
+        /// Version to wait from next, normally the one waited from.
+        version: String,
     },
 }
 
-impl DaemonResponse {
-    /// Check if the response indicates success.
-    pub fn is_ok(&self) -> bool {
-        self.status == "ok"
+impl IdentityChange {
+    /// Get the version to pass as `since` to the next wait.
+    pub fn version(&self) -> &str {
+        match self {
+            Self::Changed { version, .. } | Self::Unchanged { version } => version,
+        }
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("identity", &self.identity)
+            .field("idm_url", &self.idm_url)
+            .field("config_url", &self.config_url)
+            .field("token", &redact_token(&self.token))
+            .field("expires_at", &self.expires_at)
+            .field("bootstrap", &self.bootstrap)
+            .field("runner", &self.runner)
+            .finish()
+    }
+}
+
+impl Identity {
+    /// Create an identity named `identity` holding `token`, with empty URLs,
+    /// no expiry, no bootstrap payload and a default runner.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use getmyid::{Identity, Runner};
+    ///
+    /// let identity = Identity::new("BILLING_DEV", "dev-token")
+    ///     .with_idm_url("http://localhost:8443")
+    ///     .with_config_url("http://localhost:8080");
+    /// assert_eq!(identity.token, "dev-token");
+    /// assert_eq!(identity.runner, Runner::default());
+    /// ```
+    pub fn new(identity: impl Into<String>, token: impl Into<SensitiveString>) -> Self {
+        Self {
+            identity: identity.into(),
+            idm_url: String::new(),
+            config_url: String::new(),
+            token: token.into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner::default(),
+        }
+    }
+
+    /// Set the Identity Management URL.
+    pub fn with_idm_url(mut self, url: impl Into<String>) -> Self {
+        self.idm_url = url.into();
+        self
+    }
+
+    /// Set the configuration server URL.
+    pub fn with_config_url(mut self, url: impl Into<String>) -> Self {
+        self.config_url = url.into();
+        self
+    }
+
+    /// Set the token expiry, in seconds since the Unix epoch.
+    pub fn with_expires_at(mut self, expires_at: u64) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Set the inlined configuration payload.
+    pub fn with_bootstrap(mut self, bootstrap: BootstrapPayload) -> Self {
+        self.bootstrap = Some(bootstrap);
+        self
+    }
+
+    /// Set the runner information.
+    pub fn with_runner(mut self, runner: Runner) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Get a copy with the token replaced by `[redacted]`, to serialize
+    /// into logs. Identities without a token keep it empty.
+    pub fn redacted(&self) -> Identity {
+        let mut identity = self.clone();
+        if !identity.token.is_empty() {
+            identity.token = REDACTED.into();
+        }
+        identity
+    }
+
+    /// Get the `Authorization` header carrying the token, as a name and
+    /// value pair for any HTTP client.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let identity = getmyid::get_identity()?;
+    /// let (name, value) = identity.bearer_header();
+    /// assert_eq!(name, "Authorization");
+    /// assert!(value.starts_with("Bearer "));
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn bearer_header(&self) -> (&'static str, String) {
+        ("Authorization", self.auth_header_value())
+    }
+
+    /// Get the value of the `Authorization` header carrying the token,
+    /// `Bearer <token>`.
+    pub fn auth_header_value(&self) -> String {
+        format!("Bearer {}", self.token)
+    }
+
+    /// Get the value of the `Authorization` header carrying the token as
+    /// an [`http::HeaderValue`] marked sensitive, so HTTP stacks keep it out
+    /// of their logs (requires `headers` feature).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GetMyIdError::InvalidToken`] if the token contains
+    /// characters not allowed in header values.
+    #[cfg(feature = "headers")]
+    pub fn header_value(&self) -> Result<http::HeaderValue> {
+        let mut value = http::HeaderValue::try_from(self.auth_header_value()).map_err(|_| {
+            GetMyIdError::InvalidToken("token is not a valid header value".to_string())
+        })?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+
+    /// Get the token as a [`SecretString`](secrecy::SecretString), for code
+    /// handing credentials around with the `secrecy` crate (requires
+    /// `secrecy` feature).
+    ///
+    /// See [`SecretIdentity`](crate::SecretIdentity) to keep the whole
+    /// identity with its token zeroized on drop.
+    #[cfg(feature = "secrecy")]
+    pub fn token_secret(&self) -> secrecy::SecretString {
+        secrecy::SecretString::new(self.token.to_string())
+    }
+
+    /// Check whether the token has expired.
+    ///
+    /// Identities without an expiry never expire.
+    pub fn is_expired(&self) -> bool {
+        self.expires_in() == Some(Duration::ZERO)
+    }
+
+    /// Get the time left before the token expires, zero once it has.
+    ///
+    /// Returns `None` if the daemon reported no expiry, and
+    /// [`Duration::MAX`] for expiries too far ahead to represent.
+    pub fn expires_in(&self) -> Option<Duration> {
+        self.expires_at.map(time_until)
+    }
+
+    /// Check whether `other` is the same principal: same identity name,
+    /// runner uid, IDM URL and config URL.
+    ///
+    /// Unlike `==`, this ignores fields that change between lookups of the
+    /// same principal, such as the token and its expiry, the pid, the
+    /// timestamp and the inlined bootstrap configuration.
+    pub fn same_principal(&self, other: &Identity) -> bool {
+        self.identity == other.identity
+            && self.runner.uid == other.runner.uid
+            && self.idm_url == other.idm_url
+            && self.config_url == other.config_url
+    }
+
+    /// Render `template`, replacing `{placeholder}`s with identity fields.
+    ///
+    /// Available placeholders are `identity`, `idm_url`, `config_url`,
+    /// `token`, and the runner fields `hostname`, `client_hostname`,
+    /// `process`, `pid`, `uid`, `gid`, `instance_id` and `timestamp`. Any runner field, including
+    /// client-provided extras, can also be addressed as `runner.<name>`.
+    /// Write `{{` and `}}` for literal braces.
+    ///
+    /// Substitution is a single pass: braces inside substituted values are
+    /// copied verbatim and never expanded. Unknown placeholders, unset
+    /// optional fields and unbalanced braces are reported as
+    /// [`GetMyIdError::InvalidTemplate`] rather than rendered empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use getmyid::Identity;
+    ///
+    /// let identity = Identity::new("BILLING_PROD", "tok_xxx");
+    ///
+    /// let url = identity.render_template("postgres://{identity}:{token}@db/{identity}")?;
+    /// assert_eq!(url, "postgres://BILLING_PROD:tok_xxx@db/BILLING_PROD");
+    /// # Ok::<(), getmyid::GetMyIdError>(())
+    /// ```
+    pub fn render_template(&self, template: &str) -> Result<String> {
+        let mut rendered = String::with_capacity(template.len());
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    rendered.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    rendered.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| {
+                        GetMyIdError::InvalidTemplate("unclosed '{' in template".to_string())
+                    })?;
+                    let name = &rest[..end];
+                    rendered.push_str(&self.placeholder(name)?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err(GetMyIdError::InvalidTemplate(
+                        "unmatched '}' in template".to_string(),
+                    ))
+                }
+                c => rendered.push(c),
+            }
+        }
+        Ok(rendered)
+    }
+
+    /// Resolve a single template placeholder.
+    fn placeholder(&self, name: &str) -> Result<String> {
+        let runner = &self.runner;
+        let field = name.strip_prefix("runner.").unwrap_or(name);
+        let is_runner = field.len() != name.len();
+
+        let value = match field {
+            "identity" if is_runner => Some(runner.identity.clone()),
+            "identity" => Some(self.identity.clone()),
+            "idm_url" if !is_runner => Some(self.idm_url.clone()),
+            "config_url" if !is_runner => Some(self.config_url.clone()),
+            "token" if !is_runner => Some(self.token.to_string()),
+            "hostname" => Some(runner.hostname.clone()),
+            "client_hostname" => runner.client_hostname.clone(),
+            "process" => Some(runner.process.clone()),
+            "pid" => Some(runner.pid.to_string()),
+            "uid" => Some(runner.uid.to_string()),
+            "gid" => Some(runner.gid.to_string()),
+            "instance_id" => runner.instance_id.map(|id| id.to_string()),
+            "timestamp" => runner.timestamp.map(|ts| ts.to_string()),
+            _ if is_runner => match runner.extra.get(field) {
+                Some(serde_json::Value::String(s)) => Some(s.clone()),
+                Some(value) => Some(value.to_string()),
+                None => None,
+            },
+            _ => {
+                return Err(GetMyIdError::InvalidTemplate(format!(
+                    "unknown placeholder '{{{name}}}'"
+                )))
+            }
+        };
+
+        value.ok_or_else(|| {
+            GetMyIdError::InvalidTemplate(format!("placeholder '{{{name}}}' has no value"))
+        })
+    }
+}
+
+/// Time left until `expires_at`, in seconds since the Unix epoch: zero once
+/// it passed, [`Duration::MAX`] if it lies beyond what `SystemTime` can
+/// represent.
+pub(crate) fn time_until(expires_at: u64) -> Duration {
+    match UNIX_EPOCH.checked_add(Duration::from_secs(expires_at)) {
+        Some(expires_at) => expires_at
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+        None => Duration::MAX,
+    }
+}
+
+/// Expiry of a token valid for `ttl` seconds from now, in seconds since the
+/// Unix epoch.
+pub(crate) fn expiry_from_ttl(ttl: u64) -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+        .saturating_add(ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_identity() -> Identity {
+        Identity {
+            identity: "BILLING_PROD".to_string(),
+            idm_url: "https://auth.example.com".to_string(),
+            config_url: "https://config.example.com".to_string(),
+            token: "tok_{identity}".into(),
+            expires_at: None,
+            bootstrap: None,
+            runner: Runner {
+                identity: "BILLING_PROD".to_string(),
+                hostname: "worker-01".to_string(),
+                pid: 1234,
+                instance_id: Some(2),
+                extra: HashMap::from([
+                    ("region".to_string(), "eu-west-1".into()),
+                    ("shard".to_string(), 7.into()),
+                ]),
+                ..Runner::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_apply_hostname_policy() {
+        let daemon_runner = || Runner {
+            hostname: "node-7".to_string(),
+            ..Runner::default()
+        };
+
+        let mut runner = daemon_runner();
+        runner.apply_hostname_policy(HostnamePolicy::Daemon, Some("node-7".to_string()));
+        assert_eq!(runner, daemon_runner());
+        runner.apply_hostname_policy(HostnamePolicy::Client, None);
+        assert_eq!(runner, daemon_runner());
+
+        let mut runner = daemon_runner();
+        runner.apply_hostname_policy(HostnamePolicy::Daemon, Some("app-5f9c".to_string()));
+        assert_eq!(runner.hostname, "node-7");
+        assert_eq!(runner.client_hostname.as_deref(), Some("app-5f9c"));
+
+        let mut runner = daemon_runner();
+        runner.apply_hostname_policy(HostnamePolicy::Client, Some("app-5f9c".to_string()));
+        assert_eq!(runner.hostname, "app-5f9c");
+        assert_eq!(runner.client_hostname.as_deref(), Some("app-5f9c"));
+        assert!(!serde_json::to_string(&daemon_runner())
+            .unwrap()
+            .contains("client_hostname"));
+    }
+
+    #[test]
+    fn test_key_case() {
+        assert_eq!(KeyCase::Snake.apply("instanceGroup"), "instance_group");
+        assert_eq!(KeyCase::Snake.apply("HTTPServer2Port"), "http_server2_port");
+        assert_eq!(KeyCase::Snake.apply("config-url"), "config_url");
+        assert_eq!(KeyCase::Snake.apply("already_snake"), "already_snake");
+        assert_eq!(KeyCase::Camel.apply("instance_group"), "instanceGroup");
+        assert_eq!(KeyCase::Camel.apply("tls_CA_path"), "tlsCAPath");
+        assert_eq!(KeyCase::Camel.apply("URL_prefix"), "urlPrefix");
+        assert_eq!(KeyCase::Camel.apply("_private_key"), "_privateKey");
+        assert_eq!(KeyCase::Camel.apply("alreadyCamel"), "alreadyCamel");
+        assert_eq!(KeyCase::Preserve.apply("Mixed_Key"), "Mixed_Key");
+    }
+
+    #[test]
+    fn test_normalize_keys() {
+        let request = RunnerRequest::new()
+            .with_instance_id(1)
+            .with_field("deployRegion", "eu")
+            .with_field("build_sha", "abc")
+            .normalize_keys(KeyCase::Snake);
+        assert_eq!(request.extra.get("deploy_region").unwrap(), "eu");
+        assert_eq!(request.extra.get("build_sha").unwrap(), "abc");
+        assert_eq!(request.extra.len(), 2);
+
+        let mut runner = Runner::default();
+        runner.extra.insert("shard_id".to_string(), 1.into());
+        runner.extra.insert("shardId".to_string(), 2.into());
+        runner.normalize_keys(KeyCase::Camel);
+        assert_eq!(runner.extra.len(), 1);
+        assert_eq!(runner.extra.get("shardId").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_runner_request_with_defaults() {
+        let defaults = RunnerRequest::new()
+            .with_instance_id(1)
+            .with_timestamp(100)
+            .with_field("region", "eu")
+            .with_field("shard", 3);
+        let request = RunnerRequest::new()
+            .with_instance_id(2)
+            .with_field("shard", 7)
+            .with_defaults(&defaults);
+
+        assert_eq!(request.instance_id, Some(2));
+        assert_eq!(request.timestamp, Some(100));
+        assert_eq!(request.extra.get("region").unwrap(), "eu");
+        assert_eq!(request.extra.get("shard").unwrap(), 7);
+
+        let request = RunnerRequest::new()
+            .with_region("us")
+            .with_defaults(&defaults);
+        assert_eq!(request.region.as_deref(), Some("us"));
+        assert!(!request.extra.contains_key("region"));
+        let request = RunnerRequest::new()
+            .with_field("instance_id", "blue")
+            .with_defaults(&defaults);
+        assert_eq!(request.instance_id, None);
+        assert_eq!(request.extra["instance_id"], "blue");
+    }
+
+    #[test]
+    fn test_runner_request_from_vars() {
+        let vars = HashMap::from([
+            ("POD_NAME", "api-7d9f-x2k"),
+            ("NODE_NAME", "node-3 "),
+            ("POD_NAMESPACE", ""),
+            ("HOSTNAME", "api-host"),
+        ]);
+        let var = |name: &str| vars.get(name).map(|value| value.to_string());
+
+        let request = RunnerRequest::from_vars(var, Some("billing\n".to_string()));
+        assert_eq!(request.extra.get("pod_name").unwrap(), "api-7d9f-x2k");
+        assert_eq!(request.extra.get("node_name").unwrap(), "node-3");
+        assert_eq!(request.extra.len(), 2);
+
+        let mut vars = vars;
+        vars.remove("POD_NAME");
+        vars.insert("KUBERNETES_SERVICE_HOST", "10.0.0.1");
+        let var = |name: &str| vars.get(name).map(|value| value.to_string());
+        let request = RunnerRequest::from_vars(var, Some("billing\n".to_string()));
+        assert_eq!(request.extra.get("pod_name").unwrap(), "api-host");
+        assert_eq!(request.extra.get("pod_namespace").unwrap(), "billing");
+    }
+
+    #[test]
+    fn test_runner_extra_as() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Placement {
+            region: String,
+            shard: u32,
+            #[serde(default)]
+            zone: Option<String>,
+        }
+
+        let runner = sample_identity().runner.clone();
+        let placement: Placement = runner.extra_as().unwrap();
+        assert_eq!(
+            placement,
+            Placement {
+                region: "eu-west-1".to_string(),
+                shard: 7,
+                zone: None,
+            }
+        );
+        let fields: HashMap<String, serde_json::Value> = runner.extra_as().unwrap();
+        assert_eq!(fields, runner.extra);
+
+        let result = runner.extra_as::<HashMap<String, String>>();
+        assert!(matches!(result, Err(GetMyIdError::InvalidJson { .. })));
+    }
+
+    #[test]
+    fn test_runner_request_from_context() {
+        use serde_json::json;
+
+        #[derive(Serialize)]
+        struct Context<'a> {
+            region: &'a str,
+            labels: HashMap<&'a str, &'a str>,
+        }
+
+        let context = Context {
+            region: "eu-west-1",
+            labels: HashMap::from([("tier", "gold")]),
+        };
+        let request = RunnerRequest::from_context(&context).unwrap();
+        assert_eq!(request.region.as_deref(), Some("eu-west-1"));
+        assert_eq!(request.extra["labels"], json!({"tier": "gold"}));
+
+        for context in [json!("eu"), json!({"instance_id": "x"})] {
+            assert!(matches!(
+                RunnerRequest::from_context(&context),
+                Err(GetMyIdError::InvalidJson { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_runner_request_well_known_fields() {
+        let request = RunnerRequest::new()
+            .with_field("region", "us-east-1")
+            .with_region("eu-west-1")
+            .with_environment("production");
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"region": "eu-west-1", "environment": "production"})
+        );
+        let parsed: RunnerRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, request);
+        assert!(request.carries("region"));
+        assert!(!request.carries("deployment"));
+        let overwritten = request.clone().with_field("region", "us-east-1");
+        assert_eq!(overwritten.region, None);
+        assert_eq!(
+            serde_json::to_string(&overwritten.with_instance_id(3)).unwrap(),
+            r#"{"instance_id":3,"environment":"production","region":"us-east-1"}"#
+        );
+
+        let defaults = RunnerRequest::new()
+            .with_environment("staging")
+            .with_service_version("1.4.2");
+        let request = request.with_defaults(&defaults);
+        assert_eq!(request.environment.as_deref(), Some("production"));
+        assert_eq!(request.service_version.as_deref(), Some("1.4.2"));
+
+        let mut runner = Runner::default();
+        runner.merge_request(request.with_deployment("canary"));
+        assert_eq!(runner.extra["region"], "eu-west-1");
+        assert_eq!(runner.extra["deployment"], "canary");
+        assert_eq!(runner.extra.len(), 4);
+    }
+
+    #[test]
+    fn test_request_id() {
+        let request = RunnerRequest::new().with_request_id();
+        let id = request.request_id.clone().unwrap();
+        let groups: Vec<_> = id.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"));
+        let other = RunnerRequest::new().with_request_id().request_id;
+        assert_ne!(other, Some(id.clone()));
+        assert_eq!(request.clone().ensure_request_id(), request);
+        let custom = RunnerRequest::new().with_field("request_id", "req-1");
+        assert_eq!(custom.clone().ensure_request_id(), custom);
+
+        let mut runner = Runner::default();
+        runner.merge_request(request);
+        assert_eq!(runner.request_id(), Some(id.as_str()));
+    }
+
+    #[test]
+    fn test_token_redacted() {
+        let identity = sample_identity();
+        assert!(!format!("{identity:?}").contains("tok_"));
+        assert!(format!("{identity:#?}").contains("[redacted]"));
+        assert_eq!(identity.redacted().token, "[redacted]");
+        assert_eq!(identity.redacted().runner, identity.runner);
+
+        let raw = r#"{"status":"ok","token":"tok_secret","identity":"BILLING_PROD"}"#;
+        let raw = RawIdentity::new(identity, raw.to_string());
+        let debug = format!("{raw:?}");
+        assert!(!debug.contains("tok_") && debug.contains("BILLING_PROD"));
+        assert_eq!(raw.into_identity(), sample_identity());
+    }
+
+    #[test]
+    fn test_auth_headers() {
+        let identity = sample_identity();
+        let value = "Bearer tok_{identity}";
+        assert_eq!(identity.auth_header_value(), value);
+        let header = ("Authorization", value.to_string());
+        assert_eq!(identity.bearer_header(), header);
+
+        #[cfg(feature = "headers")]
+        {
+            let header = identity.header_value().unwrap();
+            assert_eq!(header, value);
+            assert!(header.is_sensitive());
+            let mut identity = sample_identity();
+            identity.token = "tok\nInjected: 1".into();
+            assert!(matches!(
+                identity.header_value(),
+                Err(GetMyIdError::InvalidToken(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_render_template() {
+        let identity = sample_identity();
+
+        assert_eq!(
+            identity
+                .render_template("postgres://{identity}:{token}@db/{identity}")
+                .unwrap(),
+            "postgres://BILLING_PROD:tok_{identity}@db/BILLING_PROD"
+        );
+        assert_eq!(
+            identity
+                .render_template("{hostname}-{pid}-{instance_id}/{runner.region}/{runner.shard}")
+                .unwrap(),
+            "worker-01-1234-2/eu-west-1/7"
+        );
+        assert_eq!(
+            identity.render_template("{{identity}}").unwrap(),
+            "{identity}"
+        );
+    }
+
+    #[test]
+    fn test_render_template_errors() {
+        let identity = sample_identity();
+
+        for template in [
+            "{unknown}",
+            "{timestamp}",
+            "{runner.missing}",
+            "{identity",
+            "oops}",
+        ] {
+            assert!(
+                matches!(
+                    identity.render_template(template),
+                    Err(GetMyIdError::InvalidTemplate(_))
+                ),
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut identity = sample_identity();
+        assert_eq!(identity.expires_in(), None);
+        assert!(!identity.is_expired());
+
+        identity.expires_at = Some(expiry_from_ttl(3600));
+        let left = identity.expires_in().unwrap();
+        assert!(left > Duration::from_secs(3590) && left <= Duration::from_secs(3600));
+        assert!(!identity.is_expired());
+
+        identity.expires_at = Some(1_000_000_000);
+        assert_eq!(identity.expires_in(), Some(Duration::ZERO));
+        assert!(identity.is_expired());
+
+        // Expiries beyond `SystemTime` are far in the future
+        identity.expires_at = Some(u64::MAX);
+        assert_eq!(identity.expires_in(), Some(Duration::MAX));
+        assert!(!identity.is_expired());
+        identity.expires_at = Some(expiry_from_ttl(u64::MAX));
+        assert!(!identity.is_expired());
+    }
+
+    #[test]
+    fn test_same_principal() {
+        let identity = sample_identity();
+
+        let mut relaunched = identity.clone();
+        relaunched.token = "tok_rotated".into();
+        relaunched.expires_at = Some(expiry_from_ttl(3600));
+        relaunched.runner.pid = 4242;
+        relaunched.runner.timestamp = Some(1_700_000_000);
+        assert_ne!(identity, relaunched);
+        assert!(identity.same_principal(&relaunched));
+
+        let mut other_user = identity.clone();
+        other_user.runner.uid += 1;
+        assert!(!identity.same_principal(&other_user));
+
+        let mut moved = identity.clone();
+        moved.config_url = "https://config.example.com/v2".to_string();
+        assert!(!identity.same_principal(&moved));
     }
 }
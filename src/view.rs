@@ -0,0 +1,273 @@
+//! Borrowed, allocation-free views of daemon responses.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+use crate::error::{GetMyIdError, Result};
+use crate::protocol::parse_response;
+use crate::support::redact_token;
+use crate::types::{expiry_from_ttl, Identity, Runner};
+
+/// A successful daemon response borrowing its strings from the response
+/// buffer.
+///
+/// Proxies that inspect only a field or two per request can parse with
+/// [`IdentityView::parse`] (or [`Client::get_identity_view`](crate::Client::get_identity_view))
+/// instead of building an owned [`Identity`], and call
+/// [`to_owned`](IdentityView::to_owned) only when they need to keep it.
+///
+/// Parsing is strict: strings are never unescaped into new allocations, so
+/// a field containing a JSON escape sequence (`\"`, `\n`, `\u00e9`, ...) is
+/// rejected with [`GetMyIdError::InvalidJson`]. Use the owned API for
+/// daemons that emit such values.
+///
+/// # Example
+///
+/// ```
+/// use getmyid::IdentityView;
+///
+/// let response = r#"{"status":"ok","identity":"BILLING_PROD","idm_url":"","config_url":"","token":"tok","runner":{"pid":42,"region":"eu"}}"#;
+/// let view = IdentityView::parse(response)?;
+/// assert_eq!(view.identity, "BILLING_PROD");
+/// assert_eq!(view.runner.pid, 42);
+///
+/// let identity = view.to_owned();
+/// assert_eq!(identity.runner.extra["region"], "eu");
+/// # Ok::<(), getmyid::GetMyIdError>(())
+/// ```
+#[derive(Clone, Deserialize)]
+pub struct IdentityView<'a> {
+    /// Application-level identity name.
+    pub identity: &'a str,
+    /// Identity Management (OAuth2/OIDC) URL.
+    pub idm_url: &'a str,
+    /// Configuration/API server URL.
+    pub config_url: &'a str,
+    /// Authentication token.
+    pub token: &'a str,
+    /// Token expiry in seconds since the Unix epoch, from `expires_at` or
+    /// `ttl`.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// Runner information.
+    #[serde(borrow)]
+    pub runner: RunnerView<'a>,
+}
+
+/// Borrowed counterpart of [`Runner`].
+#[derive(Debug, Clone, Default)]
+pub struct RunnerView<'a> {
+    /// Application-level identity name (injected by whoami).
+    pub identity: &'a str,
+    /// Hostname where the process is running.
+    pub hostname: &'a str,
+    /// Process name.
+    pub process: &'a str,
+    /// Process ID.
+    pub pid: u32,
+    /// User ID.
+    pub uid: u32,
+    /// Group ID.
+    pub gid: u32,
+    /// Client-provided instance identifier.
+    pub instance_id: Option<u64>,
+    /// Client-provided timestamp.
+    pub timestamp: Option<u64>,
+    /// Additional client-provided fields as unparsed JSON, in response order.
+    pub extra: Vec<(&'a str, &'a RawValue)>,
+}
+
+impl fmt::Debug for IdentityView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityView")
+            .field("identity", &self.identity)
+            .field("idm_url", &self.idm_url)
+            .field("config_url", &self.config_url)
+            .field("token", &redact_token(self.token))
+            .field("expires_at", &self.expires_at)
+            .field("runner", &self.runner)
+            .finish()
+    }
+}
+
+impl<'a> IdentityView<'a> {
+    /// Parse a daemon response without copying its strings.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as the clients for error and degraded
+    /// responses, and [`GetMyIdError::InvalidJson`] if the response is
+    /// malformed or a string contains an escape sequence.
+    pub fn parse(response: &'a str) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Status<'s> {
+            #[serde(borrow)]
+            status: Cow<'s, str>,
+            #[serde(default)]
+            ttl: Option<u64>,
+        }
+
+        let status: Status = serde_json::from_str(response)
+            .map_err(|e| GetMyIdError::invalid_response(e, response))?;
+        if status.status != "ok" {
+            return parse_response(response)
+                .and(Err(GetMyIdError::MissingField { field: "identity" }));
+        }
+        let mut view: Self = serde_json::from_str(response)
+            .map_err(|e| GetMyIdError::invalid_response(e, response))?;
+        view.expires_at = view.expires_at.or(status.ttl.map(expiry_from_ttl));
+        Ok(view)
+    }
+
+    /// Copy the view into an owned [`Identity`].
+    ///
+    /// Views do not parse inlined bootstrap payloads, so
+    /// [`Identity::bootstrap`] is always `None`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn to_owned(&self) -> Identity {
+        Identity {
+            identity: self.identity.to_string(),
+            idm_url: self.idm_url.to_string(),
+            config_url: self.config_url.to_string(),
+            token: self.token.into(),
+            expires_at: self.expires_at,
+            bootstrap: None,
+            runner: self.runner.to_owned(),
+        }
+    }
+}
+
+impl RunnerView<'_> {
+    /// Get the unparsed JSON of the custom field `key`.
+    pub fn extra(&self, key: &str) -> Option<&RawValue> {
+        self.extra
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| *value)
+    }
+
+    /// Copy the view into an owned [`Runner`].
+    #[allow(clippy::should_implement_trait)]
+    pub fn to_owned(&self) -> Runner {
+        Runner {
+            identity: self.identity.to_string(),
+            hostname: self.hostname.to_string(),
+            client_hostname: None,
+            process: self.process.to_string(),
+            pid: self.pid,
+            uid: self.uid,
+            gid: self.gid,
+            instance_id: self.instance_id,
+            timestamp: self.timestamp,
+            extra: self
+                .extra
+                .iter()
+                .map(|(key, value)| {
+                    let value = serde_json::from_str(value.get()).unwrap_or_default();
+                    (key.to_string(), value)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<IdentityView<'_>> for Identity {
+    fn from(view: IdentityView<'_>) -> Self {
+        view.to_owned()
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for RunnerView<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_map(RunnerVisitor(std::marker::PhantomData))
+    }
+}
+
+/// Visits the runner object, keeping unknown fields as raw JSON.
+///
+/// `#[serde(flatten)]` cannot collect borrowed raw values, hence the manual
+/// implementation.
+struct RunnerVisitor<'a>(std::marker::PhantomData<&'a ()>);
+
+impl<'de: 'a, 'a> Visitor<'de> for RunnerVisitor<'a> {
+    type Value = RunnerView<'a>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a runner object")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(
+        self,
+        mut map: M,
+    ) -> std::result::Result<Self::Value, M::Error> {
+        let mut runner = RunnerView::default();
+        while let Some(key) = map.next_key::<&'de str>()? {
+            match key {
+                "identity" => runner.identity = map.next_value()?,
+                "hostname" => runner.hostname = map.next_value()?,
+                "process" => runner.process = map.next_value()?,
+                "pid" => runner.pid = map.next_value()?,
+                "uid" => runner.uid = map.next_value()?,
+                "gid" => runner.gid = map.next_value()?,
+                "instance_id" => runner.instance_id = map.next_value()?,
+                "timestamp" => runner.timestamp = map.next_value()?,
+                _ => runner.extra.push((key, map.next_value()?)),
+            }
+        }
+        Ok(runner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DaemonErrorCode;
+
+    const RESPONSE: &str = r#"{"status":"ok","identity":"TRUSTEE_AGENT","idm_url":"https://auth.example.com","config_url":"https://config.example.com","token":"tok_xxx","runner":{"instance_id":42,"identity":"TRUSTEE_AGENT","hostname":"worker-03","process":"trustee","pid":26567,"uid":1000,"gid":1000,"region":"eu-west-1","labels":{"tier":"gold"}}}"#;
+
+    #[test]
+    fn test_identity_view_matches_owned_parse() {
+        let view = IdentityView::parse(RESPONSE).unwrap();
+
+        assert_eq!(view.identity, "TRUSTEE_AGENT");
+        assert_eq!(view.runner.hostname, "worker-03");
+        assert_eq!(view.runner.instance_id, Some(42));
+        assert_eq!(
+            view.runner.extra("labels").unwrap().get(),
+            r#"{"tier":"gold"}"#
+        );
+        assert_eq!(view.to_owned(), parse_response(RESPONSE).unwrap());
+        assert!(!format!("{view:?}").contains("tok_xxx"));
+
+        let with_ttl = RESPONSE.replace(r#""token""#, r#""ttl":60,"token""#);
+        let view = IdentityView::parse(&with_ttl).unwrap();
+        assert!(view.to_owned().expires_in().unwrap() > std::time::Duration::from_secs(50));
+    }
+
+    #[test]
+    fn test_identity_view_errors() {
+        assert!(matches!(
+            IdentityView::parse(
+                r#"{"status":"error","error_code":"E_NO_MATCH","message":"no \"match\""}"#
+            ),
+            Err(GetMyIdError::DaemonError {
+                code: DaemonErrorCode::NoMatch,
+                ..
+            })
+        ));
+        assert!(matches!(
+            IdentityView::parse(r#"{"status":"degraded","message":"reload"}"#),
+            Err(GetMyIdError::DaemonDegraded { .. })
+        ));
+
+        let escaped = RESPONSE.replace("tok_xxx", r"tok\nxxx");
+        assert!(matches!(
+            IdentityView::parse(&escaped),
+            Err(GetMyIdError::InvalidJson { .. })
+        ));
+    }
+}
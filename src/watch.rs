@@ -0,0 +1,166 @@
+//! Polling subscriptions to identity changes (requires `tokio` feature).
+
+use std::time::Duration;
+
+use crate::async_client::AsyncClient;
+use crate::error::Result;
+use crate::types::{Identity, RunnerRequest};
+
+/// How many intervals the delay between polls can grow to while the daemon
+/// keeps failing.
+const MAX_BACKOFF_INTERVALS: u32 = 8;
+
+/// Subscription to changes of the resolved identity, created by
+/// [`AsyncClient::watch_identity`].
+///
+/// The daemon is polled every interval, bypassing the client's identity
+/// and negative caches so a rotated token is seen on the next poll; the
+/// answers refresh the identity cache. [`changed`](IdentityWatch::changed)
+/// resolves with the first identity, then whenever it is no longer the
+/// [same principal](Identity::same_principal) as the last one returned or
+/// its token changed. Failures are
+/// returned as they happen, and polling backs off while they persist (up to
+/// eight intervals between polls), returning to the interval once the daemon
+/// answers again.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use getmyid::AsyncClient;
+///
+/// # async fn example() {
+/// let mut watch = AsyncClient::new().watch_identity(Duration::from_secs(30));
+/// loop {
+///     match watch.changed().await {
+///         Ok(identity) => println!("now running as {}", identity.identity),
+///         Err(e) => eprintln!("identity unavailable: {e}"),
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct IdentityWatch {
+    client: AsyncClient,
+    runner: Option<RunnerRequest>,
+    interval: Duration,
+    wait: Option<Duration>,
+    last: Option<Identity>,
+}
+
+impl IdentityWatch {
+    pub(crate) fn new(
+        client: AsyncClient,
+        runner: Option<RunnerRequest>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            client,
+            runner,
+            interval,
+            wait: None,
+            last: None,
+        }
+    }
+
+    /// Wait for the next changed identity or failure.
+    ///
+    /// The first call returns as soon as the daemon answers. Dropping the
+    /// returned future before it completes loses no state.
+    ///
+    /// # Errors
+    ///
+    /// Returns the client's error for each failed poll; the watch stays
+    /// usable afterwards.
+    pub async fn changed(&mut self) -> Result<Identity> {
+        loop {
+            if let Some(wait) = self.wait {
+                tokio::time::sleep(wait).await;
+            }
+            match self.client.refresh_identity(self.runner.clone()).await {
+                Ok(identity) => {
+                    self.wait = Some(self.interval);
+                    if self.last.as_ref().is_some_and(|last| same(last, &identity)) {
+                        continue;
+                    }
+                    self.last = Some(identity.clone());
+                    return Ok(identity);
+                }
+                Err(e) => {
+                    let max = self.interval.saturating_mul(MAX_BACKOFF_INTERVALS);
+                    self.wait = Some(
+                        self.wait
+                            .map_or(self.interval, |wait| wait.saturating_mul(2).min(max)),
+                    );
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Get the last identity returned by [`changed`](IdentityWatch::changed).
+    pub fn current(&self) -> Option<&Identity> {
+        self.last.as_ref()
+    }
+}
+
+/// Whether `b` is the same principal as `a`, holding the same token.
+fn same(a: &Identity, b: &Identity) -> bool {
+    a.same_principal(b) && a.token == b.token
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeDaemon, FakeResponse};
+
+    #[tokio::test]
+    async fn test_watch_identity_reports_changes() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let mut watch = client.watch_identity(Duration::from_millis(10));
+
+        assert_eq!(watch.changed().await.unwrap().token, "tok_test");
+
+        let mut rotated = FakeDaemon::sample_identity();
+        rotated.token = "tok_rotated".into();
+        daemon.respond_with(FakeResponse::identity(rotated));
+        assert_eq!(watch.changed().await.unwrap().token, "tok_rotated");
+        assert!(daemon.requests().len() >= 2);
+
+        daemon.respond_with(FakeResponse::no_match());
+        assert!(watch.changed().await.is_err());
+        assert_eq!(watch.current().unwrap().token, "tok_rotated");
+    }
+
+    #[tokio::test]
+    async fn test_watch_identity_bypasses_cache() {
+        let daemon = FakeDaemon::start().unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .cache_ttl(Duration::from_secs(300))
+            .build();
+        assert_eq!(client.get_identity().await.unwrap().token, "tok_test");
+        let mut watch = client.watch_identity(Duration::from_millis(10));
+        assert_eq!(watch.changed().await.unwrap().token, "tok_test");
+
+        let mut rotated = FakeDaemon::sample_identity();
+        rotated.token = "tok_rotated".into();
+        daemon.respond_with(FakeResponse::identity(rotated));
+        assert_eq!(watch.changed().await.unwrap().token, "tok_rotated");
+        assert_eq!(client.get_identity().await.unwrap().token, "tok_rotated");
+    }
+
+    #[tokio::test]
+    async fn test_watch_backoff_saturates() {
+        let daemon = FakeDaemon::with_response(FakeResponse::no_match()).unwrap();
+        let client = AsyncClient::builder()
+            .socket_path(daemon.socket_path())
+            .build();
+        let mut watch = client.watch_identity(Duration::MAX);
+        assert!(watch.changed().await.is_err());
+        assert_eq!(watch.wait, Some(Duration::MAX));
+    }
+}